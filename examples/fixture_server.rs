@@ -0,0 +1,16 @@
+//! Serve the test fixtures as a stand-in for the google calendar and pagerduty apis, to try the tool
+//! without real accounts. See the Demo section of the README
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use actix_web::web::Data;
+use common::{start_fixture_server, Fixtures};
+
+const PORT: u16 = 8090;
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    let (server, port) = start_fixture_server(PORT, Data::new(Fixtures::load()))?;
+    println!("Serving the fixtures on http://127.0.0.1:{}", port);
+    server.await
+}
@@ -0,0 +1,419 @@
+use crate::calendar::local_midnight;
+use crate::config::AvailabilityCommand;
+use crate::solver::{BusyInterval, FinalEntity, OncallSlot};
+use anyhow::{anyhow, ensure, Context, Result as AnyhowResult};
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate};
+use chrono_tz::Tz;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::Duration as StdDuration;
+use tokio::process::Command;
+use tracing::instrument;
+
+/// A range of time as written in the availability file. Both ends are either rfc3339 times or
+/// YYYY-MM-DD dates, in which case the end date is included
+#[derive(Deserialize, Debug)]
+struct DeclaredRange {
+    start: String,
+    end: String,
+    reason: Option<String>,
+}
+
+/// A row of a csv availability file: a range the person is unavailable for
+#[derive(Deserialize, Debug)]
+struct CsvRange {
+    email: String,
+    start: String,
+    end: String,
+    reason: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct DeclaredEntry {
+    available: Vec<DeclaredRange>,
+    unavailable: Vec<DeclaredRange>,
+}
+
+/// Availability a person declared outside of their calendar
+#[derive(Debug, Default, Clone)]
+pub struct DeclaredAvailability {
+    /// if not empty, the person can only be oncall within these ranges
+    pub available: Vec<(DateTime<FixedOffset>, DateTime<FixedOffset>)>,
+    /// treated like out of office events on the person's calendar
+    pub unavailable: Vec<BusyInterval>,
+    /// slots overlapping these are preferred, like prefer-oncall events on the person's calendar
+    pub preferred: Vec<(DateTime<FixedOffset>, DateTime<FixedOffset>)>,
+}
+
+impl DeclaredAvailability {
+    pub fn allows(&self, slot: &OncallSlot) -> bool {
+        let within_available = self.available.is_empty()
+            || self
+                .available
+                .iter()
+                .any(|(start, end)| *start <= slot.start_time && slot.end_time <= *end);
+        within_available
+            && !self
+                .unavailable
+                .iter()
+                .any(|x| x.overlaps(slot.start_time, slot.end_time))
+    }
+
+    /// Add the ranges of `other`, e.g. from another availability file
+    pub fn merge(&mut self, other: DeclaredAvailability) {
+        self.available.extend(other.available);
+        self.unavailable.extend(other.unavailable);
+        self.preferred.extend(other.preferred);
+    }
+
+    /// Merge into the availability derived from the person's calendar
+    pub fn apply(&self, entity: &mut FinalEntity) {
+        entity.available_slots.retain(|x| self.allows(x));
+        entity.preferred_slots.retain(|x| self.allows(x));
+        entity.soft_conflict_slots.retain(|x| self.allows(x));
+        entity.busy.extend(self.unavailable.iter().cloned());
+        let preferred: Vec<OncallSlot> = entity
+            .available_slots
+            .iter()
+            .filter(|slot| {
+                self.preferred
+                    .iter()
+                    .any(|(start, end)| *start < slot.end_time && slot.start_time < *end)
+            })
+            .filter(|slot| !entity.prefers(slot.start_time, slot.end_time))
+            .cloned()
+            .collect();
+        entity.preferred_slots.extend(preferred);
+        entity.preferred_slots.sort_by_key(|x| x.start_time);
+    }
+}
+
+/// Load per person availability, keyed by email, from a yaml file like
+///
+/// ```yaml
+/// alice@example.com:
+///   unavailable:
+///     - start: 2022-08-29
+///       end: 2022-09-02
+///       reason: vacation
+///   available:
+///     - start: 2022-08-22T00:00:00+08:00
+///       end: 2022-09-05T00:00:00+08:00
+/// ```
+///
+/// or, for files ending in .csv, from rows of ranges people are unavailable for, with an
+/// email,start,end,reason header and an optional reason
+pub fn load_availability(
+    path: &Path,
+    timezone: Tz,
+) -> AnyhowResult<HashMap<String, DeclaredAvailability>> {
+    let contents = fs::read_to_string(path).context(format!(
+        "Failed to read availability file {}",
+        path.display()
+    ))?;
+    match path.extension().and_then(|x| x.to_str()) {
+        Some("csv") => parse_csv_availability(&contents, timezone),
+        _ => parse_availability(&contents, timezone),
+    }
+    .context(format!(
+        "Failed to parse availability file {}",
+        path.display()
+    ))
+}
+
+fn parse_csv_availability(
+    contents: &str,
+    timezone: Tz,
+) -> AnyhowResult<HashMap<String, DeclaredAvailability>> {
+    let mut declared: HashMap<String, DeclaredAvailability> = HashMap::new();
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(contents.as_bytes());
+    for (i, row) in reader.deserialize::<CsvRange>().enumerate() {
+        let row = row.context("Failed to parse csv")?;
+        let range = DeclaredRange {
+            start: row.start,
+            end: row.end,
+            // an empty reason column reads as an empty string
+            reason: row.reason.filter(|x| !x.is_empty()),
+        };
+        // the header is line 1
+        let busy = parse_busy(&[range], "declared unavailable", timezone)
+            .context(format!("Invalid range on line {}", i + 2))?;
+        declared
+            .entry(row.email)
+            .or_default()
+            .unavailable
+            .extend(busy);
+    }
+    Ok(declared)
+}
+
+fn parse_availability(
+    contents: &str,
+    timezone: Tz,
+) -> AnyhowResult<HashMap<String, DeclaredAvailability>> {
+    let entries: HashMap<String, DeclaredEntry> = serde_yaml::from_str(contents)?;
+    entries
+        .into_iter()
+        .map(|(email, entry)| {
+            let available = entry
+                .available
+                .iter()
+                .map(|x| parse_range(x, timezone))
+                .collect::<AnyhowResult<Vec<_>>>()?;
+            let unavailable = parse_busy(&entry.unavailable, "declared unavailable", timezone)?;
+            Ok((
+                email,
+                DeclaredAvailability {
+                    available,
+                    unavailable,
+                    preferred: Vec::new(),
+                },
+            ))
+        })
+        .collect()
+}
+
+fn parse_busy(
+    ranges: &[DeclaredRange],
+    default_reason: &str,
+    timezone: Tz,
+) -> AnyhowResult<Vec<BusyInterval>> {
+    ranges
+        .iter()
+        .map(|range| {
+            let (start, end) = parse_range(range, timezone)?;
+            Ok(BusyInterval {
+                summary: range
+                    .reason
+                    .clone()
+                    .unwrap_or_else(|| default_reason.to_string()),
+                start,
+                end,
+            })
+        })
+        .collect()
+}
+
+/// Run the availability commands that apply to `email`, and gather the busy times they print. Dates
+/// they print are whole days in `timezone`
+#[instrument(skip(commands))]
+pub async fn external_availability(
+    commands: &[AvailabilityCommand],
+    email: &str,
+    start: DateTime<FixedOffset>,
+    end: DateTime<FixedOffset>,
+    timezone: Tz,
+) -> AnyhowResult<DeclaredAvailability> {
+    let mut availability = DeclaredAvailability::default();
+    for command in commands
+        .iter()
+        .filter(|x| x.users.is_empty() || x.users.iter().any(|user| user == email))
+    {
+        let busy = run_availability_command(command, email, start, end, timezone)
+            .await
+            .context(format!(
+                "Availability command {:?} failed for {}",
+                command.command, email
+            ))?;
+        availability.unavailable.extend(busy);
+    }
+    Ok(availability)
+}
+
+async fn run_availability_command(
+    command: &AvailabilityCommand,
+    email: &str,
+    start: DateTime<FixedOffset>,
+    end: DateTime<FixedOffset>,
+    timezone: Tz,
+) -> AnyhowResult<Vec<BusyInterval>> {
+    let args: Vec<String> = command
+        .command
+        .iter()
+        .map(|x| {
+            x.replace("{email}", email)
+                .replace("{start}", &start.to_rfc3339())
+                .replace("{end}", &end.to_rfc3339())
+        })
+        .collect();
+    let (program, args) = args
+        .split_first()
+        .ok_or_else(|| anyhow!("Empty availability command"))?;
+    let output = tokio::time::timeout(
+        StdDuration::from_secs(command.timeout_seconds),
+        Command::new(program).args(args).kill_on_drop(true).output(),
+    )
+    .await
+    .map_err(|_| anyhow!("Timed out after {} seconds", command.timeout_seconds))?
+    .context(format!("Failed to run {}", program))?;
+    ensure!(
+        output.status.success(),
+        "Exited with {}: {}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+    let ranges: Vec<DeclaredRange> =
+        serde_json::from_slice(&output.stdout).context("Expected a json array of busy times")?;
+    parse_busy(&ranges, &format!("busy according to {}", program), timezone)
+}
+
+/// A range someone is unavailable for, e.g. leave from an HR system, read like the ranges of the
+/// availability file
+pub fn unavailable_range(
+    start: &str,
+    end: &str,
+    reason: &str,
+    timezone: Tz,
+) -> AnyhowResult<BusyInterval> {
+    let range = DeclaredRange {
+        start: start.to_string(),
+        end: end.to_string(),
+        reason: Some(reason.to_string()),
+    };
+    Ok(parse_busy(&[range], reason, timezone)?.remove(0))
+}
+
+/// A range someone would like to be oncall in, read like the ranges of the availability file
+pub fn preferred_range(
+    start: &str,
+    end: &str,
+    timezone: Tz,
+) -> AnyhowResult<(DateTime<FixedOffset>, DateTime<FixedOffset>)> {
+    parse_range(
+        &DeclaredRange {
+            start: start.to_string(),
+            end: end.to_string(),
+            reason: None,
+        },
+        timezone,
+    )
+}
+
+fn parse_range(
+    range: &DeclaredRange,
+    timezone: Tz,
+) -> AnyhowResult<(DateTime<FixedOffset>, DateTime<FixedOffset>)> {
+    Ok((
+        parse_time(&range.start, false, timezone)?,
+        parse_time(&range.end, true, timezone)?,
+    ))
+}
+
+/// Dates are whole days in `timezone`, the schedule's, however long DST makes them
+fn parse_time(value: &str, is_end: bool, timezone: Tz) -> AnyhowResult<DateTime<FixedOffset>> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(if is_end {
+            local_midnight(date + Duration::days(1), timezone)
+        } else {
+            local_midnight(date, timezone)
+        });
+    }
+    DateTime::parse_from_rfc3339(value).context(format!(
+        "Invalid time {}, expected YYYY-MM-DD or rfc3339",
+        value
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::tests::{test_entity, test_slot};
+    use chrono_tz::Asia::Singapore;
+
+    #[test]
+    fn test_parse_availability() -> AnyhowResult<()> {
+        let declared = parse_availability(
+            r#"
+            a@x.com:
+              unavailable:
+                - start: 2022-08-30
+                  end: 2022-08-30
+                  reason: vacation
+            b@x.com:
+              available:
+                - start: 2022-08-29T00:00:00+08:00
+                  end: 2022-08-30T00:00:00+08:00
+            "#,
+            Singapore,
+        )?;
+        let days = ["2022-08-29T03:00:00+08:00", "2022-08-30T03:00:00+08:00"];
+        let mut a = test_entity("a@x.com", days[0], &days);
+        declared["a@x.com"].apply(&mut a);
+        assert_eq!(a.available_slots.len(), 1);
+        assert_eq!(a.busy[0].summary, "vacation");
+        assert!(declared["b@x.com"].allows(&test_slot(days[0])));
+        assert!(!declared["b@x.com"].allows(&test_slot(days[1])));
+
+        let preferred = DeclaredAvailability {
+            preferred: vec![(test_slot(days[1]).start_time, test_slot(days[1]).end_time)],
+            ..Default::default()
+        };
+        let mut c = test_entity("c@x.com", days[0], &days);
+        preferred.apply(&mut c);
+        let (tuesday, monday) = (test_slot(days[1]), test_slot(days[0]));
+        assert!(c.prefers(tuesday.start_time, tuesday.end_time));
+        assert!(!c.prefers(monday.start_time, monday.end_time));
+
+        let invalid = "a@x.com:\n  unavailable:\n    - {start: monday, end: tuesday}";
+        assert!(parse_availability(invalid, Singapore).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_csv_availability() -> AnyhowResult<()> {
+        let declared = parse_csv_availability(
+            "email,start,end,reason\n\
+             a@x.com, 2022-08-30, 2022-08-30, dentist\n\
+             a@x.com,2022-08-31T09:00:00+08:00,2022-08-31T12:00:00+08:00,\n",
+            Singapore,
+        )?;
+        let busy = &declared["a@x.com"].unavailable;
+        assert_eq!(busy.len(), 2);
+        assert_eq!(busy[0].summary, "dentist");
+        assert_eq!(busy[0].end.to_rfc3339(), "2022-08-31T00:00:00+08:00");
+        assert_eq!(busy[1].summary, "declared unavailable");
+
+        let error = parse_csv_availability("email,start,end\na@x.com,monday,tuesday\n", Singapore)
+            .unwrap_err();
+        assert_eq!(error.to_string(), "Invalid range on line 2");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_external_availability() -> AnyhowResult<()> {
+        let command = |script: &str, users: &[&str]| AvailabilityCommand {
+            command: vec!["sh".to_string(), "-c".to_string(), script.to_string()],
+            users: users.iter().map(|x| x.to_string()).collect(),
+            timeout_seconds: 5,
+        };
+        let commands = [
+            command(
+                r#"echo '[{"start": "2022-08-30", "end": "2022-08-30", "reason": "leave of {email}"}]'"#,
+                &["a@x.com"],
+            ),
+            command("echo '[]'", &[]),
+        ];
+        let days = ["2022-08-29T03:00:00+08:00", "2022-08-30T03:00:00+08:00"];
+        let (start, end) = (test_slot(days[0]).start_time, test_slot(days[1]).end_time);
+
+        let external = external_availability(&commands, "a@x.com", start, end, Singapore).await?;
+        let mut a = test_entity("a@x.com", days[0], &days);
+        external.apply(&mut a);
+        assert_eq!(a.available_slots.len(), 1);
+        assert_eq!(a.busy[0].summary, "leave of a@x.com");
+        let external = external_availability(&commands, "b@x.com", start, end, Singapore).await?;
+        assert!(external.unavailable.is_empty());
+
+        let failing = [command("echo oops >&2; exit 3", &[])];
+        let error = external_availability(&failing, "a@x.com", start, end, Singapore)
+            .await
+            .unwrap_err();
+        assert!(format!("{:#}", error).contains("oops"), "{:#}", error);
+        Ok(())
+    }
+}
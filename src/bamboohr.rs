@@ -0,0 +1,176 @@
+use crate::availability::{unavailable_range, DeclaredAvailability};
+use crate::http::HttpClient;
+use anyhow::{ensure, Context, Result as AnyhowResult};
+use chrono::{DateTime, FixedOffset};
+use chrono_tz::Tz;
+use reqwest::RequestBuilder;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::HashMap;
+use tracing::instrument;
+
+/// Root of the bamboohr api
+pub const BAMBOOHR_API_URL: &str = "https://api.bamboohr.com";
+
+#[derive(Deserialize, Debug)]
+struct Directory {
+    employees: Vec<Employee>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Employee {
+    id: String,
+    work_email: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct TimeOffRequest {
+    employee_id: String,
+    status: TimeOffStatus,
+    /// YYYY-MM-DD, both ends included
+    start: String,
+    end: String,
+    #[serde(rename = "type")]
+    kind: Option<TimeOffType>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TimeOffStatus {
+    status: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct TimeOffType {
+    name: String,
+}
+
+/// Approved time off from BambooHR, for teams whose leave is tracked in HR rather than on calendars
+pub struct BambooHr<'a> {
+    pub client: &'a HttpClient,
+    /// BAMBOOHR_API_URL, unless --base-url points elsewhere
+    pub base_url: &'a str,
+    pub company: &'a str,
+    pub api_key: &'a str,
+}
+
+impl BambooHr<'_> {
+    fn get(&self, path: &str) -> RequestBuilder {
+        self.client
+            .get(format!(
+                "{}/api/gateway.php/{}/v1/{}",
+                self.base_url, self.company, path
+            ))
+            // the key is the username, and the password can be anything
+            .basic_auth(self.api_key, Some("x"))
+            .header("Accept", "application/json")
+    }
+
+    async fn fetch<T: DeserializeOwned>(&self, request: RequestBuilder) -> AnyhowResult<T> {
+        let response = self.client.send(request).await?;
+        ensure!(
+            response.status().is_success(),
+            "Unexpected status {} from the bamboohr api",
+            response.status()
+        );
+        response
+            .json()
+            .await
+            .context("Failed to parse the bamboohr response as json")
+    }
+
+    /// Approved time off overlapping `start` to `end`, by work email. Its dates are whole days in
+    /// `timezone`
+    #[instrument(skip(self))]
+    pub async fn time_off(
+        &self,
+        start: DateTime<FixedOffset>,
+        end: DateTime<FixedOffset>,
+        timezone: Tz,
+    ) -> AnyhowResult<HashMap<String, DeclaredAvailability>> {
+        let directory: Directory = self
+            .fetch(self.get("employees/directory"))
+            .await
+            .context("Failed to read the bamboohr employee directory")?;
+        let requests: Vec<TimeOffRequest> = self
+            .fetch(self.get("time_off/requests/").query(&[
+                ("start", start.date_naive().to_string()),
+                ("end", end.date_naive().to_string()),
+                ("status", "approved".to_string()),
+            ]))
+            .await
+            .context("Failed to read the bamboohr time off requests")?;
+        to_availability(directory, requests, timezone)
+    }
+}
+
+fn to_availability(
+    directory: Directory,
+    requests: Vec<TimeOffRequest>,
+    timezone: Tz,
+) -> AnyhowResult<HashMap<String, DeclaredAvailability>> {
+    let emails: HashMap<String, String> = directory
+        .employees
+        .into_iter()
+        .filter_map(|x| Some((x.id, x.work_email?)))
+        .collect();
+    let mut time_off: HashMap<String, DeclaredAvailability> = HashMap::new();
+    // the status filter is applied again, in case the api ignored it
+    for request in requests
+        .into_iter()
+        .filter(|x| x.status.status == "approved")
+    {
+        let Some(email) = emails.get(&request.employee_id) else {
+            continue;
+        };
+        let reason = match &request.kind {
+            Some(kind) => format!("{} in bamboohr", kind.name.to_lowercase()),
+            None => "time off in bamboohr".to_string(),
+        };
+        let busy =
+            unavailable_range(&request.start, &request.end, &reason, timezone).context(format!(
+                "Invalid bamboohr time off of {} from {} to {}",
+                email, request.start, request.end
+            ))?;
+        time_off
+            .entry(email.clone())
+            .or_default()
+            .unavailable
+            .push(busy);
+    }
+    Ok(time_off)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_availability() -> AnyhowResult<()> {
+        let directory: Directory = serde_json::from_str(
+            r#"{"employees": [
+                {"id": "4", "workEmail": "a@x.com"},
+                {"id": "5", "workEmail": null}
+            ]}"#,
+        )?;
+        let requests: Vec<TimeOffRequest> = serde_json::from_str(
+            r#"[
+                {"employeeId": "4", "status": {"status": "approved"}, "start": "2022-08-30",
+                 "end": "2022-08-31", "type": {"name": "Vacation"}},
+                {"employeeId": "4", "status": {"status": "denied"}, "start": "2022-09-05",
+                 "end": "2022-09-05"},
+                {"employeeId": "5", "status": {"status": "approved"}, "start": "2022-08-30",
+                 "end": "2022-08-30"}
+            ]"#,
+        )?;
+        let time_off = to_availability(directory, requests, chrono_tz::Asia::Singapore)?;
+        assert_eq!(time_off.len(), 1);
+        let busy = &time_off["a@x.com"].unavailable;
+        assert_eq!(busy.len(), 1);
+        assert_eq!(busy[0].summary, "vacation in bamboohr");
+        // the end date is included
+        assert_eq!(busy[0].end.to_rfc3339(), "2022-09-01T00:00:00+08:00");
+        Ok(())
+    }
+}
@@ -0,0 +1,182 @@
+use crate::availability::DeclaredAvailability;
+use crate::caldav::parse_events;
+use crate::calendar::{convert_time_wrapper, CalendarEvent};
+use crate::config::Config;
+use crate::http::HttpClient;
+use crate::solver::BusyInterval;
+use anyhow::{anyhow, ensure, Context, Result as AnyhowResult};
+use chrono::{DateTime, Duration, FixedOffset, SecondsFormat, Utc};
+use chrono_tz::Tz;
+use serde::Deserialize;
+use std::collections::{BTreeSet, HashMap};
+use tracing::instrument;
+
+/// Root of the calendly api
+pub const CALENDLY_API_URL: &str = "https://api.calendly.com";
+
+#[derive(Deserialize, Debug)]
+struct ScheduledEvents {
+    collection: Vec<ScheduledEvent>,
+    pagination: Pagination,
+}
+
+#[derive(Deserialize, Debug)]
+struct ScheduledEvent {
+    name: String,
+    start_time: DateTime<FixedOffset>,
+    end_time: DateTime<FixedOffset>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Pagination {
+    next_page: Option<String>,
+}
+
+/// Calls people have been booked into outside their calendar, from the bookings feed or the
+/// calendly user of their config. Booked calls are conflicts, like out of office events
+pub struct Bookings<'a> {
+    pub client: &'a HttpClient,
+    pub config: &'a Config,
+    pub calendly_url: &'a str,
+    /// from CALENDLY_API_TOKEN, when someone has a calendly user
+    pub calendly_token: Option<&'a str>,
+}
+
+impl Bookings<'_> {
+    /// Bookings of each of `emails` overlapping `start` to `end`, by email
+    #[instrument(skip(self, emails))]
+    pub async fn bookings(
+        &self,
+        emails: &BTreeSet<&str>,
+        start: DateTime<FixedOffset>,
+        end: DateTime<FixedOffset>,
+    ) -> AnyhowResult<HashMap<String, DeclaredAvailability>> {
+        let mut bookings = HashMap::new();
+        for email in emails {
+            let user = self.config.user(email);
+            let mut unavailable = Vec::new();
+            if let Some(url) = &user.bookings_feed {
+                let events = self
+                    .feed(url, email)
+                    .await
+                    .context(format!("Failed to read the bookings feed of {}", email))?;
+                unavailable.extend(booked(&events, self.config.user_timezone(email)?));
+            }
+            if let Some(uri) = &user.calendly_user {
+                let token = self
+                    .calendly_token
+                    .ok_or_else(|| anyhow!("CALENDLY_API_TOKEN is needed for {}", email))?;
+                unavailable.extend(
+                    self.calendly(uri, token, start, end)
+                        .await
+                        .context(format!("Failed to read the calendly bookings of {}", email))?,
+                );
+            }
+            unavailable.retain(|x| x.overlaps(start, end));
+            if !unavailable.is_empty() {
+                let availability = DeclaredAvailability {
+                    unavailable,
+                    ..Default::default()
+                };
+                bookings.insert(email.to_string(), availability);
+            }
+        }
+        Ok(bookings)
+    }
+
+    async fn feed(&self, url: &str, email: &str) -> AnyhowResult<Vec<CalendarEvent>> {
+        let response = self.client.send(self.client.get(url)).await?;
+        ensure!(
+            response.status().is_success(),
+            "Unexpected status {} from the feed",
+            response.status()
+        );
+        let ics = response.text().await?;
+        Ok(parse_events(&ics, self.config.user_timezone(email)?))
+    }
+
+    /// Active scheduled events of the calendly user overlapping `start` to `end`, give or take those
+    /// longer than a day
+    async fn calendly(
+        &self,
+        uri: &str,
+        token: &str,
+        start: DateTime<FixedOffset>,
+        end: DateTime<FixedOffset>,
+    ) -> AnyhowResult<Vec<BusyInterval>> {
+        let time = |x: DateTime<FixedOffset>| {
+            x.with_timezone(&Utc)
+                .to_rfc3339_opts(SecondsFormat::Secs, true)
+        };
+        let mut request = self
+            .client
+            .get(format!("{}/scheduled_events", self.calendly_url))
+            .query(&[
+                ("user", uri),
+                // calendly filters on start times, so meetings running into the range start earlier
+                ("min_start_time", &time(start - Duration::days(1))),
+                ("max_start_time", &time(end)),
+                ("status", "active"),
+                ("count", "100"),
+            ]);
+        let mut busy = Vec::new();
+        loop {
+            let response = self.client.send(request.bearer_auth(token)).await?;
+            ensure!(
+                response.status().is_success(),
+                "Unexpected status {} from the calendly api",
+                response.status()
+            );
+            let page: ScheduledEvents = response
+                .json()
+                .await
+                .context("Failed to parse the calendly scheduled events")?;
+            busy.extend(page.collection.into_iter().map(|x| BusyInterval {
+                summary: format!("{} (booked in calendly)", x.name),
+                start: x.start_time,
+                end: x.end_time,
+            }));
+            match page.pagination.next_page {
+                Some(url) => request = self.client.get(url),
+                None => return Ok(busy),
+            }
+        }
+    }
+}
+
+/// The events of a bookings feed as busy intervals, leaving out those without valid times. All day
+/// bookings last from midnight to midnight in `timezone`
+fn booked(events: &[CalendarEvent], timezone: Tz) -> Vec<BusyInterval> {
+    events
+        .iter()
+        .filter_map(|x| {
+            Some(BusyInterval {
+                summary: format!(
+                    "{} (external booking)",
+                    x.summary.as_deref().unwrap_or("(no title)")
+                ),
+                start: convert_time_wrapper(x.start.as_ref()?, timezone).ok()?,
+                end: convert_time_wrapper(x.end.as_ref()?, timezone).ok()?,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_booked() {
+        let ics = "BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nSUMMARY:Demo with Acme\r\n\
+                   DTSTART:20220830T020000Z\r\nDTEND:20220830T023000Z\r\nEND:VEVENT\r\n\
+                   BEGIN:VEVENT\r\nSUMMARY:Broken\r\nDTSTART:soon\r\nEND:VEVENT\r\n\
+                   END:VCALENDAR\r\n";
+        let singapore = chrono_tz::Asia::Singapore;
+        let busy = booked(&parse_events(ics, singapore), singapore);
+        assert_eq!(busy.len(), 1);
+        assert_eq!(busy[0].summary, "Demo with Acme (external booking)");
+        assert_eq!(busy[0].start.to_rfc3339(), "2022-08-30T02:00:00+00:00");
+        assert_eq!(busy[0].end.to_rfc3339(), "2022-08-30T02:30:00+00:00");
+    }
+}
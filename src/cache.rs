@@ -0,0 +1,218 @@
+use anyhow::{Context, Result as AnyhowResult};
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Kinds of api responses kept in the cache, each with its own time to live
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Namespace {
+    /// the email of each pagerduty user, which hardly ever changes
+    UserEmails,
+    /// events on people's calendars
+    Calendars,
+    /// rendered pagerduty schedules
+    Schedules,
+}
+
+impl Namespace {
+    fn name(&self) -> &'static str {
+        match self {
+            Namespace::UserEmails => "user_emails",
+            Namespace::Calendars => "calendars",
+            Namespace::Schedules => "schedules",
+        }
+    }
+}
+
+/// How long each namespace is kept, from `[cache]` in the config file. 0 turns caching off
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CacheTtls {
+    pub user_emails_minutes: i64,
+    pub calendars_minutes: i64,
+    pub schedules_minutes: i64,
+}
+
+impl Default for CacheTtls {
+    fn default() -> Self {
+        CacheTtls {
+            user_emails_minutes: 7 * 24 * 60,
+            calendars_minutes: 5,
+            schedules_minutes: 5,
+        }
+    }
+}
+
+impl CacheTtls {
+    fn of(&self, namespace: Namespace) -> Duration {
+        Duration::minutes(match namespace {
+            Namespace::UserEmails => self.user_emails_minutes,
+            Namespace::Calendars => self.calendars_minutes,
+            Namespace::Schedules => self.schedules_minutes,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Entry {
+    /// unix timestamp
+    stored_at: i64,
+    key: String,
+    value: String,
+}
+
+/// Api responses kept on disk between runs, so re-running a plan minutes later doesn't fetch every
+/// calendar again. Entries are files under `dir`, one directory per namespace. Failing to read or
+/// write the cache only means fetching again
+pub struct Cache {
+    /// None when caching is off, e.g. with --no-cache
+    dir: Option<PathBuf>,
+    ttls: CacheTtls,
+}
+
+impl Cache {
+    pub fn new(dir: PathBuf, ttls: CacheTtls) -> Cache {
+        Cache {
+            dir: Some(dir),
+            ttls,
+        }
+    }
+
+    pub fn disabled() -> Cache {
+        Cache {
+            dir: None,
+            ttls: CacheTtls::default(),
+        }
+    }
+
+    fn path(&self, namespace: Namespace, key: &str) -> Option<PathBuf> {
+        let dir = self.dir.as_ref()?;
+        let hash = hex::encode(Sha256::digest(key.as_bytes()));
+        Some(dir.join(namespace.name()).join(format!("{}.json", hash)))
+    }
+
+    /// The value stored under `key`, unless it is older than the namespace's time to live
+    pub fn get(&self, namespace: Namespace, key: &str) -> Option<String> {
+        let contents = fs::read_to_string(self.path(namespace, key)?).ok()?;
+        let entry: Entry = serde_json::from_str(&contents).ok()?;
+        let age = Duration::seconds(Utc::now().timestamp() - entry.stored_at);
+        let fresh = age < self.ttls.of(namespace);
+        (fresh && entry.key == key).then_some(entry.value)
+    }
+
+    pub fn put(&self, namespace: Namespace, key: &str, value: &str) {
+        let Some(path) = self.path(namespace, key) else {
+            return;
+        };
+        if self.ttls.of(namespace) <= Duration::zero() {
+            return;
+        }
+        let entry = Entry {
+            stored_at: Utc::now().timestamp(),
+            key: key.to_string(),
+            value: value.to_string(),
+        };
+        let written = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&path, serde_json::to_string(&entry).unwrap()));
+        if let Err(e) = written {
+            say!("Warning. Failed to cache {}: {}", path.display(), e);
+        }
+    }
+
+    /// Drop every entry of `namespace`, e.g. the schedules once overrides changed them, so the next
+    /// run renders them afresh
+    pub fn forget(&self, namespace: Namespace) {
+        let Some(dir) = &self.dir else {
+            return;
+        };
+        let path = dir.join(namespace.name());
+        if let Err(e) = fs::remove_dir_all(&path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                say!("Warning. Failed to clear {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+/// Delete everything cached under `dir`, returning how many entries there were
+pub fn clear_cache(dir: &Path) -> AnyhowResult<usize> {
+    if !dir.exists() {
+        return Ok(0);
+    }
+    let mut removed = 0;
+    for namespace in fs::read_dir(dir).context(format!("Failed to read {}", dir.display()))? {
+        let namespace = namespace?.path();
+        if namespace.is_dir() {
+            removed += fs::read_dir(&namespace)?.count();
+        }
+    }
+    fs::remove_dir_all(dir).context(format!("Failed to delete {}", dir.display()))?;
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache() -> AnyhowResult<()> {
+        let dir = std::env::temp_dir().join(format!("gcal-pagerduty-cache-{}", std::process::id()));
+        let cache = Cache::new(
+            dir.clone(),
+            CacheTtls {
+                calendars_minutes: 0,
+                ..Default::default()
+            },
+        );
+        cache.put(Namespace::UserEmails, "https://pd/users/P1", "a@x.com");
+        assert_eq!(
+            cache.get(Namespace::UserEmails, "https://pd/users/P1"),
+            Some("a@x.com".to_string())
+        );
+        assert_eq!(
+            cache.get(Namespace::UserEmails, "https://pd/users/P2"),
+            None
+        );
+        assert_eq!(cache.get(Namespace::Schedules, "https://pd/users/P1"), None);
+        // a time to live of 0 keeps nothing
+        cache.put(Namespace::Calendars, "https://gcal/a", "[]");
+        assert_eq!(cache.get(Namespace::Calendars, "https://gcal/a"), None);
+
+        let expired = Cache::new(
+            dir.clone(),
+            CacheTtls {
+                user_emails_minutes: -1,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            expired.get(Namespace::UserEmails, "https://pd/users/P1"),
+            None
+        );
+        assert_eq!(
+            Cache::disabled().get(Namespace::UserEmails, "https://pd/users/P1"),
+            None
+        );
+
+        cache.put(Namespace::Schedules, "https://pd/schedules/P1", "{}");
+        cache.forget(Namespace::Schedules);
+        assert_eq!(
+            cache.get(Namespace::Schedules, "https://pd/schedules/P1"),
+            None
+        );
+        // forgetting a namespace that was never written is fine
+        cache.forget(Namespace::Calendars);
+
+        assert_eq!(clear_cache(&dir)?, 1);
+        assert_eq!(
+            cache.get(Namespace::UserEmails, "https://pd/users/P1"),
+            None
+        );
+        assert_eq!(clear_cache(&dir)?, 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,439 @@
+use crate::cache::{Cache, Namespace};
+use crate::calendar::{AvailabilityProvider, CalendarError, CalendarEvent, TimeWrapper};
+use crate::config::{CalDavAccount, Config};
+use crate::http::HttpClient;
+use crate::ldap::LdapDirectory;
+use anyhow::{anyhow, Context, Result as AnyhowResult};
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use reqwest::Method;
+use std::env;
+use tracing::instrument;
+
+/// Namespace of the elements holding the events in a calendar-query response
+const CALDAV_NAMESPACE: &str = "urn:ietf:params:xml:ns:caldav";
+
+/// Calendars on CalDAV servers, e.g. Fastmail or Nextcloud, for people with a `caldav` account in
+/// the config file
+pub struct CalDav<'a> {
+    pub client: &'a HttpClient,
+    pub config: &'a Config,
+    pub cache: &'a Cache,
+}
+
+impl AvailabilityProvider for CalDav<'_> {
+    #[instrument(skip_all, fields(%email, start = %start, end = %end))]
+    async fn fetch_events(
+        &self,
+        email: &str,
+        start: DateTime<FixedOffset>,
+        end: DateTime<FixedOffset>,
+    ) -> AnyhowResult<Vec<CalendarEvent>> {
+        let account = self
+            .config
+            .user(email)
+            .caldav
+            .ok_or_else(|| anyhow!("No caldav account for {} in the config file", email))?;
+        // recurring events are expanded by the server, and come back in UTC
+        let format_time = |time: DateTime<FixedOffset>| {
+            time.with_timezone(&Utc)
+                .format("%Y%m%dT%H%M%SZ")
+                .to_string()
+        };
+        let body = calendar_query(&format_time(start), &format_time(end));
+        let key = format!("{}\n{}", account.url, body);
+        let result = match self.cache.get(Namespace::Calendars, &key) {
+            Some(value) => value,
+            None => {
+                let result = self.report(email, &account, body).await?;
+                self.cache.put(Namespace::Calendars, &key, &result);
+                result
+            }
+        };
+        let timezone = self.config.user_timezone(email)?;
+        let mut events = Vec::new();
+        for ics in calendar_data(&result)? {
+            events.extend(parse_events(&ics, timezone));
+        }
+        Ok(events)
+    }
+}
+
+impl CalDav<'_> {
+    async fn report(
+        &self,
+        email: &str,
+        account: &CalDavAccount,
+        body: String,
+    ) -> AnyhowResult<String> {
+        let password = env::var(&account.password_env).context(format!(
+            "Expected environment variable {} to be set, for the calendar of {}",
+            account.password_env, email
+        ))?;
+        self.client.keep_secret(&password);
+        let request = self
+            .client
+            .request(Method::from_bytes(b"REPORT").unwrap(), &account.url)
+            .basic_auth(&account.username, Some(password))
+            .header("Depth", "1")
+            .header("Content-Type", "application/xml; charset=utf-8")
+            .body(body);
+        let response = self
+            .client
+            .send(request)
+            .await
+            .context("Request to the caldav server failed")?;
+        let email = email.to_string();
+        match response.status().as_u16() {
+            401 => return Err(CalendarError::Unauthorized { email }.into()),
+            403 | 404 => return Err(CalendarError::Forbidden { email }.into()),
+            429 => return Err(CalendarError::RateLimited.into()),
+            _ => {}
+        }
+        let status = response.status();
+        let result = response
+            .text()
+            .await
+            .context("Failed to read the caldav response")?;
+        match status.is_success() {
+            true => Ok(result),
+            false => Err(anyhow!(
+                "Unexpected status {} from the caldav server: {}",
+                status,
+                result
+            )),
+        }
+    }
+}
+
+/// Reads the calendars of people with a caldav account from their CalDAV server, and everyone
+/// else's from google, which is None with caldav_only. With a directory, google calendars are
+/// read at the address it has for each person instead of their email
+pub struct Calendars<'a, G> {
+    pub caldav: CalDav<'a>,
+    pub google: Option<G>,
+    pub directory: Option<&'a LdapDirectory<'a>>,
+}
+
+impl<G: AvailabilityProvider> AvailabilityProvider for Calendars<'_, G> {
+    async fn fetch_events(
+        &self,
+        email: &str,
+        start: DateTime<FixedOffset>,
+        end: DateTime<FixedOffset>,
+    ) -> AnyhowResult<Vec<CalendarEvent>> {
+        match (&self.caldav.config.user(email).caldav, &self.google) {
+            (Some(_), _) => self.caldav.fetch_events(email, start, end).await,
+            (None, Some(google)) => {
+                let normalised = self.caldav.config.normalise_email(email);
+                let address = match self.directory {
+                    Some(directory) => directory.calendar_address(&normalised).await?,
+                    None => normalised,
+                };
+                let events = google.fetch_events(&address, start, end).await;
+                match address == email {
+                    true => events,
+                    false => events.context(format!(
+                        "Read the calendar of {} as {}, see email_aliases in the config file",
+                        email, address
+                    )),
+                }
+            }
+            (None, None) => Err(anyhow!(
+                "{} has no caldav account in the config file, which caldav_only needs",
+                email
+            )),
+        }
+    }
+}
+
+/// A calendar-query REPORT for the events between `start` and `end`, as UTC iCalendar times
+fn calendar_query(start: &str, end: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<C:calendar-query xmlns:D="DAV:" xmlns:C="{namespace}">
+  <D:prop>
+    <C:calendar-data>
+      <C:expand start="{start}" end="{end}"/>
+    </C:calendar-data>
+  </D:prop>
+  <C:filter>
+    <C:comp-filter name="VCALENDAR">
+      <C:comp-filter name="VEVENT">
+        <C:time-range start="{start}" end="{end}"/>
+      </C:comp-filter>
+    </C:comp-filter>
+  </C:filter>
+</C:calendar-query>"#,
+        namespace = CALDAV_NAMESPACE,
+        start = start,
+        end = end
+    )
+}
+
+/// The iCalendar documents of a multistatus response, one per calendar object
+fn calendar_data(xml: &str) -> AnyhowResult<Vec<String>> {
+    let document =
+        roxmltree::Document::parse(xml).context("Failed to parse the caldav response as xml")?;
+    Ok(document
+        .descendants()
+        .filter(|x| x.has_tag_name((CALDAV_NAMESPACE, "calendar-data")))
+        .map(|x| {
+            x.descendants()
+                .filter_map(|x| x.is_text().then(|| x.text()).flatten())
+                .collect()
+        })
+        .collect())
+}
+
+/// The VEVENTs of an iCalendar document, shaped like google's events. Times without a timezone of
+/// their own are in `timezone`, and cancelled events are left out
+pub(crate) fn parse_events(ics: &str, timezone: Tz) -> Vec<CalendarEvent> {
+    // long lines are folded onto the next ones, which start with a space or a tab
+    let unfolded = ics
+        .replace("\r\n", "\n")
+        .replace("\n ", "")
+        .replace("\n\t", "");
+    let mut events = Vec::new();
+    // components the current line is in, e.g. VCALENDAR, VEVENT, VALARM
+    let mut components: Vec<&str> = Vec::new();
+    let mut event = IcsEvent::default();
+    for line in unfolded.lines() {
+        let Some((name, params, value)) = split_property(line) else {
+            continue;
+        };
+        match name {
+            "BEGIN" => {
+                if value == "VEVENT" {
+                    event = IcsEvent::default();
+                }
+                components.push(value);
+                continue;
+            }
+            "END" => {
+                if components.pop() == Some("VEVENT") && !event.cancelled {
+                    events.push(event.into_calendar_event(timezone));
+                    event = IcsEvent::default();
+                }
+                continue;
+            }
+            _ => {}
+        }
+        // properties of alarms inside the event are not the event's
+        if components.last() != Some(&"VEVENT") {
+            continue;
+        }
+        match name {
+            "SUMMARY" => event.summary = Some(unescape(value)),
+            "CLASS" => event.private = matches!(value, "PRIVATE" | "CONFIDENTIAL"),
+            "STATUS" => event.cancelled = value == "CANCELLED",
+            "DTSTART" => event.start = Some(IcsTime::parse(params, value)),
+            "DTEND" => event.end = Some(IcsTime::parse(params, value)),
+            _ => {}
+        }
+    }
+    events
+}
+
+#[derive(Default)]
+struct IcsEvent {
+    summary: Option<String>,
+    private: bool,
+    cancelled: bool,
+    start: Option<IcsTime>,
+    end: Option<IcsTime>,
+}
+
+impl IcsEvent {
+    fn into_calendar_event(self, timezone: Tz) -> CalendarEvent {
+        // an event without an end lasts a day if it is on a date, and no time at all otherwise
+        let end = self.end.or_else(|| match &self.start {
+            Some(IcsTime::Date(date)) => Some(IcsTime::Date(*date + Duration::days(1))),
+            other => other.clone(),
+        });
+        CalendarEvent {
+            visibility: Some(if self.private { "private" } else { "default" }.to_string()),
+            summary: self.summary,
+            start: self.start.and_then(|x| x.to_time_wrapper(timezone)),
+            end: end.and_then(|x| x.to_time_wrapper(timezone)),
+            event_type: None,
+            status: None,
+            pagerduty: None,
+        }
+    }
+}
+
+/// A DTSTART or DTEND
+#[derive(Clone, Debug, PartialEq)]
+enum IcsTime {
+    Date(NaiveDate),
+    Utc(NaiveDateTime),
+    /// in the timezone of the TZID parameter, or floating when there is none
+    Local(NaiveDateTime, Option<Tz>),
+    Invalid,
+}
+
+impl IcsTime {
+    fn parse(params: &str, value: &str) -> IcsTime {
+        if let Ok(date) = NaiveDate::parse_from_str(value, "%Y%m%d") {
+            return IcsTime::Date(date);
+        }
+        let (value, utc) = match value.strip_suffix('Z') {
+            Some(value) => (value, true),
+            None => (value, false),
+        };
+        let Ok(time) = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S") else {
+            return IcsTime::Invalid;
+        };
+        if utc {
+            return IcsTime::Utc(time);
+        }
+        let tzid = params
+            .split(';')
+            .find_map(|x| x.strip_prefix("TZID="))
+            .and_then(|x| x.trim_matches('"').parse().ok());
+        IcsTime::Local(time, tzid)
+    }
+
+    fn to_time_wrapper(&self, timezone: Tz) -> Option<TimeWrapper> {
+        let date_time = match self {
+            IcsTime::Date(date) => {
+                return Some(TimeWrapper {
+                    date_string: Some(date.format("%Y-%m-%d").to_string()),
+                    date_time_string: None,
+                })
+            }
+            IcsTime::Utc(time) => Utc.from_utc_datetime(time).to_rfc3339(),
+            IcsTime::Local(time, tzid) => {
+                let timezone = tzid.unwrap_or(timezone);
+                // a time skipped by a DST change is read as if the clocks hadn't changed yet
+                timezone
+                    .from_local_datetime(time)
+                    .earliest()
+                    .unwrap_or_else(|| timezone.from_utc_datetime(time))
+                    .to_rfc3339()
+            }
+            IcsTime::Invalid => return None,
+        };
+        Some(TimeWrapper {
+            date_string: None,
+            date_time_string: Some(date_time),
+        })
+    }
+}
+
+/// The name, parameters and value of a content line, e.g. DTSTART;TZID=Europe/Berlin:20220829T090000
+fn split_property(line: &str) -> Option<(&str, &str, &str)> {
+    // the value starts after the first colon outside quoted parameters
+    let mut quoted = false;
+    let colon = line.char_indices().find_map(|(i, x)| match x {
+        '"' => {
+            quoted = !quoted;
+            None
+        }
+        ':' if !quoted => Some(i),
+        _ => None,
+    })?;
+    let (head, value) = (&line[..colon], &line[colon + 1..]);
+    let (name, params) = head.split_once(';').unwrap_or((head, ""));
+    Some((name, params, value))
+}
+
+fn unescape(value: &str) -> String {
+    let mut result = String::new();
+    let mut chars = value.chars();
+    while let Some(x) = chars.next() {
+        match (x, x == '\\') {
+            (_, true) => match chars.next() {
+                Some('n') | Some('N') => result.push('\n'),
+                Some(other) => result.push(other),
+                None => {}
+            },
+            _ => result.push(x),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calendar::convert_time_wrapper;
+    use chrono_tz::Asia::Singapore;
+
+    #[test]
+    fn test_parse_events() -> AnyhowResult<()> {
+        let xml = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:multistatus xmlns:d="DAV:" xmlns:cal="urn:ietf:params:xml:ns:caldav">
+  <d:response>
+    <d:href>/calendars/alice/default/1.ics</d:href>
+    <d:propstat><d:prop><cal:calendar-data>BEGIN:VCALENDAR
+BEGIN:VEVENT
+SUMMARY:Out of office\, back
+  monday
+DTSTART;TZID=Europe/Berlin:20220829T090000
+DTEND:20220829T150000Z
+BEGIN:VALARM
+SUMMARY:Reminder
+END:VALARM
+END:VEVENT
+BEGIN:VEVENT
+SUMMARY:Dentist
+CLASS:PRIVATE
+DTSTART:20220830T090000
+DTEND:20220830T100000
+END:VEVENT
+END:VCALENDAR
+</cal:calendar-data></d:prop></d:propstat>
+  </d:response>
+  <d:response>
+    <d:href>/calendars/alice/default/2.ics</d:href>
+    <d:propstat><d:prop><cal:calendar-data><![CDATA[BEGIN:VCALENDAR
+BEGIN:VEVENT
+SUMMARY:Holiday
+DTSTART;VALUE=DATE:20220831
+END:VEVENT
+BEGIN:VEVENT
+SUMMARY:Moved
+STATUS:CANCELLED
+DTSTART;VALUE=DATE:20220831
+END:VEVENT
+END:VCALENDAR
+]]></cal:calendar-data></d:prop></d:propstat>
+  </d:response>
+</d:multistatus>"#;
+        let documents = calendar_data(xml)?;
+        assert_eq!(documents.len(), 2);
+        let events: Vec<CalendarEvent> = documents
+            .iter()
+            .flat_map(|x| parse_events(x, Singapore))
+            .collect();
+        assert_eq!(events.len(), 3);
+
+        assert_eq!(
+            events[0].summary.as_deref(),
+            Some("Out of office, back monday")
+        );
+        let start = convert_time_wrapper(events[0].start.as_ref().unwrap(), Singapore).unwrap();
+        assert_eq!(start.to_rfc3339(), "2022-08-29T09:00:00+02:00");
+        let end = convert_time_wrapper(events[0].end.as_ref().unwrap(), Singapore).unwrap();
+        assert_eq!(end.to_rfc3339(), "2022-08-29T15:00:00+00:00");
+
+        // floating times are in the person's timezone
+        assert_eq!(events[1].visibility.as_deref(), Some("private"));
+        let start = convert_time_wrapper(events[1].start.as_ref().unwrap(), Singapore).unwrap();
+        assert_eq!(start.to_rfc3339(), "2022-08-30T09:00:00+08:00");
+
+        // a date without an end lasts the day
+        let holiday = &events[2];
+        assert_eq!(
+            holiday.start.as_ref().unwrap().date_string.as_deref(),
+            Some("2022-08-31")
+        );
+        assert_eq!(
+            holiday.end.as_ref().unwrap().date_string.as_deref(),
+            Some("2022-09-01")
+        );
+        Ok(())
+    }
+}
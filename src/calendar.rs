@@ -0,0 +1,515 @@
+use crate::config::EmailAliases;
+use crate::oncall::FinalPagerDutySchedule;
+use anyhow::{anyhow, Context, Result as AnyhowResult};
+use chrono::{DateTime, Duration, FixedOffset, LocalResult, NaiveDate, Offset, TimeZone};
+use chrono_tz::Tz;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use thiserror::Error;
+use tracing::instrument;
+
+#[derive(Deserialize, Debug)]
+pub struct CalendarEvent {
+    pub visibility: Option<String>,
+    /// "cancelled" for events that were deleted, which have no times
+    pub status: Option<String>,
+    pub summary: Option<String>,
+    // creator: Option<EventCreator>,
+    pub start: Option<TimeWrapper>,
+    pub end: Option<TimeWrapper>,
+    #[serde(rename = "eventType")]
+    pub event_type: Option<String>,
+    // extra metadata after joining
+    pub pagerduty: Option<FinalPagerDutySchedule>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TimeWrapper {
+    #[serde(rename = "date")]
+    pub date_string: Option<String>,
+    #[serde(rename = "dateTime")]
+    pub date_time_string: Option<String>,
+    // #[serde(rename = "timeZone")]
+    // timezone: Option<String>,
+}
+
+/// All day events start at midnight in `timezone`, the timezone of whoever's calendar they are on
+pub fn convert_time_wrapper(
+    input: &TimeWrapper,
+    timezone: Tz,
+) -> AnyhowResult<DateTime<FixedOffset>> {
+    match (&input.date_string, &input.date_time_string) {
+        (Some(value), _) => {
+            let date = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .context(format!("Invalid date {}", value))?;
+            Ok(local_midnight(date, timezone))
+        }
+        (None, Some(value)) => DateTime::<FixedOffset>::parse_from_rfc3339(value)
+            .context(format!("Invalid time {}", value)),
+        (None, None) => Err(anyhow!("Neither a date nor a time")),
+    }
+}
+
+impl CalendarEvent {
+    /// Start and end of the event, all day events lasting from midnight to midnight in `timezone`
+    pub fn times(
+        &self,
+        timezone: Tz,
+    ) -> AnyhowResult<(DateTime<FixedOffset>, DateTime<FixedOffset>)> {
+        let start = self.start.as_ref().ok_or_else(|| anyhow!("No start"))?;
+        let end = self.end.as_ref().ok_or_else(|| anyhow!("No end"))?;
+        Ok((
+            convert_time_wrapper(start, timezone).context("Invalid start")?,
+            convert_time_wrapper(end, timezone).context("Invalid end")?,
+        ))
+    }
+}
+
+/// `time` with the offset `timezone` has at that instant, e.g. +01:00 or +02:00 in Europe/Berlin
+/// depending on the time of year, so its local date and time are right across DST changes
+pub fn local_time<T: TimeZone>(time: DateTime<T>, timezone: Tz) -> DateTime<FixedOffset> {
+    let local = time.with_timezone(&timezone);
+    local.with_timezone(&local.offset().fix())
+}
+
+/// The start of `date` in `timezone`. Where a DST change skips midnight, e.g. in America/Sao_Paulo,
+/// the day starts when the clocks go forward
+pub fn local_midnight(date: NaiveDate, timezone: Tz) -> DateTime<FixedOffset> {
+    let midnight = date.and_hms(0, 0, 0);
+    match timezone.from_local_datetime(&midnight) {
+        LocalResult::Single(x) | LocalResult::Ambiguous(x, _) => local_time(x, timezone),
+        LocalResult::None => {
+            // the offset before the change, a day earlier, puts midnight at the instant it was skipped
+            let before = timezone.offset_from_utc_datetime(&(midnight - Duration::days(1)));
+            let skipped = before.fix().from_local_datetime(&midnight).unwrap();
+            local_time(skipped, timezone)
+        }
+    }
+}
+
+/// Timezone times are shown in by tables and messages, independent of the schedule's
+#[derive(Debug, Clone)]
+pub enum DisplayTimezone {
+    /// the same for every row, e.g. that of the manager reviewing the plan
+    Zone(Tz),
+    /// that of the person a row is about, keyed by normalised email, and `fallback` for rows about
+    /// a slot rather than a person
+    PerUser {
+        timezones: HashMap<String, Tz>,
+        aliases: EmailAliases,
+        fallback: Tz,
+    },
+}
+
+impl DisplayTimezone {
+    fn of(&self, email: Option<&str>) -> Tz {
+        match (self, email) {
+            (DisplayTimezone::Zone(timezone), _) => *timezone,
+            (
+                DisplayTimezone::PerUser {
+                    timezones,
+                    aliases,
+                    fallback,
+                },
+                Some(email),
+            ) => *timezones.get(&aliases.normalise(email)).unwrap_or(fallback),
+            (DisplayTimezone::PerUser { fallback, .. }, None) => *fallback,
+        }
+    }
+}
+
+static DISPLAY_TIMEZONE: OnceLock<DisplayTimezone> = OnceLock::new();
+
+/// Set once from --display-timezone or the config file, before anything is shown
+pub fn set_display_timezone(display: DisplayTimezone) {
+    let _ = DISPLAY_TIMEZONE.set(display);
+}
+
+/// `time` as tables and messages show it, for a row about `email` if it is about one person.
+/// Without a display timezone it keeps its own offset, otherwise it is converted and labelled with
+/// the timezone's abbreviation so nobody mistakes it for their local time
+pub fn display_time(time: DateTime<FixedOffset>, email: Option<&str>) -> String {
+    match DISPLAY_TIMEZONE.get() {
+        Some(display) => format_in(time, display.of(email)),
+        None => time.format("%c").to_string(),
+    }
+}
+
+fn format_in(time: DateTime<FixedOffset>, timezone: Tz) -> String {
+    time.with_timezone(&timezone).format("%c %Z").to_string()
+}
+
+/// Title of private events kept as conflicts
+const PRIVATE_SUMMARY: &str = "Private event";
+
+/// A calendar backend: google calendar or CalDAV. Others (Outlook, ICS files, ...) only need to
+/// return their events in the same shape
+// the futures are awaited where they are created, so nothing needs them to be Send
+#[allow(async_fn_in_trait)]
+pub trait AvailabilityProvider {
+    /// Events on the calendar of `email` between `start` and `end`, private ones included
+    async fn fetch_events(
+        &self,
+        email: &str,
+        start: DateTime<FixedOffset>,
+        end: DateTime<FixedOffset>,
+    ) -> AnyhowResult<Vec<CalendarEvent>>;
+}
+
+/// Errors reading a calendar, common to every AvailabilityProvider
+#[derive(Error, Debug)]
+pub enum CalendarError {
+    /// the calendar isn't shared with whoever runs the tool, or doesn't exist
+    #[error(
+        "No access to the calendar of {email}: it isn't shared with whoever runs the tool, or \
+        doesn't exist. Ask them to share it, with at least their free/busy times"
+    )]
+    Forbidden { email: String },
+    #[error("Rate limited by the calendar api")]
+    RateLimited,
+    /// the calendar server refused the credentials configured for the person
+    #[error("The calendar server rejected the credentials for {email}")]
+    Unauthorized { email: String },
+}
+
+/// The events on a person's calendar that matter for scheduling
+#[derive(Debug)]
+pub struct UserCalendar {
+    pub pd_user: FinalPagerDutySchedule,
+    /// of the person, all day events on their calendar last from midnight to midnight in it
+    pub timezone: Tz,
+    /// events that mean the person can't be oncall, e.g. xoncall or out of office
+    pub unavailable: Vec<CalendarEvent>,
+    /// meetings matching the soft conflict keywords, which the person can be oncall through at a cost
+    pub soft_unavailable: Vec<CalendarEvent>,
+    /// events asking to be put oncall, e.g. prefer-oncall or oncall-ok
+    pub preferred: Vec<CalendarEvent>,
+}
+
+/// Fetch the calendar of the person holding `pd_user` from `provider`, and sort out the events
+/// that matter for scheduling
+#[instrument(skip_all, fields(email = %pd_user.email))]
+pub async fn get_user_calendar(
+    provider: &impl AvailabilityProvider,
+    pd_user: FinalPagerDutySchedule,
+    start_time_local: DateTime<FixedOffset>,
+    end_time_local: DateTime<FixedOffset>,
+    soft_conflict_keywords: &[String],
+    private_events_busy: bool,
+    timezone: Tz,
+) -> AnyhowResult<UserCalendar> {
+    let events = provider
+        .fetch_events(&pd_user.email, start_time_local, end_time_local)
+        .await?;
+    let events = private_events(events, private_events_busy);
+    let events = valid_events(events, &pd_user.email, timezone);
+    let (xoncall_calendar_events, other_events): (Vec<CalendarEvent>, Vec<CalendarEvent>) = events
+        .into_iter()
+        .map(|mut x| {
+            x.pagerduty = Some(pd_user.clone());
+            x
+        })
+        .partition(should_not_be_oncall);
+    let (soft_conflict_events, other_events): (Vec<CalendarEvent>, Vec<CalendarEvent>) =
+        other_events
+            .into_iter()
+            .partition(|x| is_soft_conflict(x, soft_conflict_keywords));
+    let preferred_events = other_events.into_iter().filter(prefers_oncall).collect();
+    Ok(UserCalendar {
+        pd_user,
+        timezone,
+        unavailable: xoncall_calendar_events,
+        soft_unavailable: soft_conflict_events,
+        preferred: preferred_events,
+    })
+}
+
+/// Private events are left out, unless `busy`: then they are conflicts whatever they are about,
+/// with their title hidden
+fn private_events(events: Vec<CalendarEvent>, busy: bool) -> Vec<CalendarEvent> {
+    events
+        .into_iter()
+        .filter_map(|mut x| match is_private(&x) {
+            false => Some(x),
+            true if busy => {
+                x.summary = Some(PRIVATE_SUMMARY.to_string());
+                Some(x)
+            }
+            true => None,
+        })
+        .collect()
+}
+
+/// Events that aren't visible to whoever runs the tool, google leaves the visibility out of
+/// events only shared as free/busy
+fn is_private(event: &CalendarEvent) -> bool {
+    !matches!(&event.visibility, Some(v) if v != "private")
+}
+
+/// The events with a start and end, leaving out cancelled ones and warning about the rest, so one
+/// malformed event on the calendar of `email` doesn't stop the run
+fn valid_events(events: Vec<CalendarEvent>, email: &str, timezone: Tz) -> Vec<CalendarEvent> {
+    events
+        .into_iter()
+        .filter(|x| x.status.as_deref() != Some("cancelled"))
+        .filter(|x| match x.times(timezone) {
+            Ok(_) => true,
+            Err(e) => {
+                say!(
+                    "Warning. Skipping the event \"{}\" on the calendar of {}: {:#}",
+                    x.summary.as_deref().unwrap_or("(no title)"),
+                    email,
+                    e
+                );
+                false
+            }
+        })
+        .collect()
+}
+
+/// Regular meetings whose summary contains one of `keywords`, case insensitive
+fn is_soft_conflict(event: &CalendarEvent, keywords: &[String]) -> bool {
+    match &event.summary {
+        Some(value) => {
+            let summary = value.to_lowercase();
+            keywords
+                .iter()
+                .any(|keyword| summary.contains(&keyword.to_lowercase()))
+        }
+        None => false,
+    }
+}
+
+fn prefers_oncall(event: &CalendarEvent) -> bool {
+    match &event.summary {
+        Some(value) => {
+            let summary = value.to_lowercase();
+            summary.contains("prefer-oncall") || summary.contains("oncall-ok")
+        }
+        None => false,
+    }
+}
+
+#[allow(clippy::match_like_matches_macro)]
+fn should_not_be_oncall(event: &CalendarEvent) -> bool {
+    match &event.summary {
+        // only kept with private_events_busy
+        _ if is_private(event) => true,
+        Some(value) if value.to_lowercase().contains("xoncall") => true,
+        Some(value) if value.to_lowercase().contains("out of") => true,
+        Some(_) if event.event_type.is_some() => match &event.event_type {
+            Some(event_type) if event_type.to_lowercase() == "outofoffice" => true,
+            _ => false,
+        },
+        // Some(value) if value.to_lowercase().contains("ooo") => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_local_midnight() {
+        let date = |x: &str| NaiveDate::parse_from_str(x, "%Y-%m-%d").unwrap();
+        let berlin = chrono_tz::Europe::Berlin;
+        // either side of the end of summer time, on 2022-10-30
+        let saturday = local_midnight(date("2022-10-29"), berlin);
+        let sunday = local_midnight(date("2022-10-30"), berlin);
+        let monday = local_midnight(date("2022-10-31"), berlin);
+        assert_eq!(saturday.to_rfc3339(), "2022-10-29T00:00:00+02:00");
+        assert_eq!(monday.to_rfc3339(), "2022-10-31T00:00:00+01:00");
+        assert_eq!(monday - sunday, Duration::hours(25));
+        assert_eq!(sunday - saturday, Duration::hours(24));
+
+        // sao paulo skipped from 00:00 to 01:00 on 2018-11-04
+        let skipped = local_midnight(date("2018-11-04"), chrono_tz::America::Sao_Paulo);
+        assert_eq!(skipped.to_rfc3339(), "2018-11-04T01:00:00-02:00");
+
+        let all_day = TimeWrapper {
+            date_string: Some("2022-10-31".to_string()),
+            date_time_string: None,
+        };
+        assert_eq!(convert_time_wrapper(&all_day, berlin).unwrap(), monday);
+        let summer = DateTime::parse_from_rfc3339("2022-10-29T22:00:00Z").unwrap();
+        assert_eq!(
+            local_time(summer, berlin).to_rfc3339(),
+            "2022-10-30T00:00:00+02:00"
+        );
+    }
+
+    #[test]
+    #[allow(clippy::bool_assert_comparison)]
+    fn test_should_not_be_oncall() {
+        let ooo = CalendarEvent {
+            visibility: Some("public".to_string()),
+            summary: Some("Out of Office".to_string()),
+            start: None,
+            end: None,
+            pagerduty: None,
+            event_type: None,
+            status: None,
+        };
+        assert_eq!(should_not_be_oncall(&ooo), true);
+        let xoncall = CalendarEvent {
+            visibility: Some("public".to_string()),
+            summary: Some("xoncall".to_string()),
+            start: None,
+            end: None,
+            pagerduty: None,
+            event_type: None,
+            status: None,
+        };
+        assert_eq!(should_not_be_oncall(&xoncall), true);
+    }
+
+    #[test]
+    fn test_prefers_oncall() {
+        let event = |summary: &str| CalendarEvent {
+            visibility: Some("public".to_string()),
+            summary: Some(summary.to_string()),
+            start: None,
+            end: None,
+            pagerduty: None,
+            event_type: None,
+            status: None,
+        };
+        assert!(prefers_oncall(&event("Prefer-Oncall")));
+        assert!(prefers_oncall(&event("oncall-ok this week")));
+        assert!(!prefers_oncall(&event("xoncall")));
+        assert!(!should_not_be_oncall(&event("prefer-oncall")));
+    }
+
+    #[test]
+    fn test_is_soft_conflict() {
+        let event = |summary: &str| CalendarEvent {
+            visibility: Some("public".to_string()),
+            summary: Some(summary.to_string()),
+            start: None,
+            end: None,
+            pagerduty: None,
+            event_type: None,
+            status: None,
+        };
+        let keywords = ["standup".to_string(), "1:1".to_string()];
+        assert!(is_soft_conflict(&event("Team Standup"), &keywords));
+        assert!(is_soft_conflict(&event("1:1 with manager"), &keywords));
+        assert!(!is_soft_conflict(&event("Offsite"), &keywords));
+        assert!(!is_soft_conflict(&event("Team Standup"), &[]));
+    }
+
+    #[test]
+    fn test_display_timezone() {
+        let time = DateTime::parse_from_rfc3339("2022-08-29T03:00:00+08:00").unwrap();
+        assert_eq!(
+            format_in(time, chrono_tz::Europe::Berlin),
+            "Sun Aug 28 21:00:00 2022 CEST"
+        );
+        assert_eq!(
+            format_in(time, chrono_tz::Asia::Singapore),
+            "Mon Aug 29 03:00:00 2022 +08"
+        );
+
+        let per_user = DisplayTimezone::PerUser {
+            timezones: HashMap::from([(
+                "alice@example.com".to_string(),
+                chrono_tz::Europe::Berlin,
+            )]),
+            aliases: EmailAliases::default(),
+            fallback: chrono_tz::Asia::Singapore,
+        };
+        assert_eq!(
+            per_user.of(Some("Alice+oncall@example.com")),
+            chrono_tz::Europe::Berlin
+        );
+        assert_eq!(
+            per_user.of(Some("bob@example.com")),
+            chrono_tz::Asia::Singapore
+        );
+        assert_eq!(per_user.of(None), chrono_tz::Asia::Singapore);
+        let zone = DisplayTimezone::Zone(chrono_tz::America::New_York);
+        assert_eq!(
+            zone.of(Some("alice@example.com")),
+            chrono_tz::America::New_York
+        );
+    }
+
+    #[test]
+    fn test_valid_events() {
+        let time = |x: &str| {
+            Some(TimeWrapper {
+                date_string: None,
+                date_time_string: Some(x.to_string()),
+            })
+        };
+        let event = |summary: &str, start, end, status: Option<&str>| CalendarEvent {
+            visibility: None,
+            summary: Some(summary.to_string()),
+            start,
+            end,
+            pagerduty: None,
+            event_type: None,
+            status: status.map(|x| x.to_string()),
+        };
+        let events = vec![
+            event(
+                "Leave",
+                time("2022-08-22T09:00:00+08:00"),
+                time("2022-08-22T18:00:00+08:00"),
+                None,
+            ),
+            event("Cancelled", None, None, Some("cancelled")),
+            event("No end", time("2022-08-22T09:00:00+08:00"), None, None),
+            event(
+                "Garbled",
+                time("22/08/2022 09:00"),
+                time("2022-08-22T18:00:00+08:00"),
+                None,
+            ),
+        ];
+        let singapore = chrono_tz::Asia::Singapore;
+        assert_eq!(
+            format!("{:#}", events[2].times(singapore).unwrap_err()),
+            "No end"
+        );
+        assert!(format!("{:#}", events[3].times(singapore).unwrap_err())
+            .starts_with("Invalid start: Invalid time 22/08/2022 09:00"));
+        let valid = valid_events(events, "alice@example.com", singapore);
+        assert_eq!(valid.len(), 1);
+        assert_eq!(valid[0].summary.as_deref(), Some("Leave"));
+    }
+
+    #[test]
+    fn test_private_events() {
+        let event = |visibility: Option<&str>| CalendarEvent {
+            visibility: visibility.map(|x| x.to_string()),
+            summary: Some("Dentist".to_string()),
+            start: None,
+            end: None,
+            pagerduty: None,
+            event_type: None,
+            status: None,
+        };
+        let events = || vec![event(Some("default")), event(Some("private")), event(None)];
+
+        let kept = private_events(events(), false);
+        assert_eq!(kept.len(), 1);
+        assert!(!should_not_be_oncall(&kept[0]));
+
+        let busy = private_events(events(), true);
+        let summaries: Vec<_> = busy.iter().map(|x| x.summary.as_deref()).collect();
+        assert_eq!(
+            summaries,
+            vec![
+                Some("Dentist"),
+                Some("Private event"),
+                Some("Private event")
+            ]
+        );
+        assert!(!should_not_be_oncall(&busy[0]));
+        assert!(should_not_be_oncall(&busy[1]));
+        assert!(should_not_be_oncall(&busy[2]));
+    }
+}
@@ -0,0 +1,1123 @@
+use crate::cache::CacheTtls;
+use crate::calendar::DisplayTimezone;
+use anyhow::{anyhow, Context, Result as AnyhowResult};
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveTime};
+use chrono_tz::Tz;
+use serde::Deserialize;
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::iter::zip;
+use std::path::{Path, PathBuf};
+use toml::Value;
+
+/// Config file read when --config isn't given, if it exists
+pub const DEFAULT_CONFIG_FILE: &str = "gcal-pagerduty.toml";
+
+/// Settings of the config file. A `[profile.<name>]` table holds settings laid over the others
+/// with `--profile <name>`, so one file can serve several rotations
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct Config {
+    /// pagerduty schedule used when --pd-schedule isn't given
+    pub schedule: Option<String>,
+    /// secondary schedule used when --secondary-schedule isn't given
+    pub secondary_schedule: Option<String>,
+    /// IANA timezone of the schedule, its window and the dates people declare, and of people without
+    /// one of their own. Defaults to DEFAULT_TIMEZONE
+    pub timezone: Option<String>,
+    /// IANA timezone tables and messages show times in, or "user" for the timezone of the person
+    /// each row is about. Defaults to the offsets of the schedule. --display-timezone takes
+    /// precedence
+    pub display_timezone: Option<String>,
+    /// per user settings, keyed by pagerduty email
+    pub users: HashMap<String, UserConfig>,
+    /// how emails of the oncall provider are normalised before they are matched to `users` and
+    /// calendars
+    pub email_aliases: EmailAliases,
+    /// penalty weights of the soft constraints, used to score plans
+    pub weights: Weights,
+    /// groups of people who must never be swapped with each other, e.g. for compliance reasons
+    pub blocked_swaps: Vec<Vec<String>>,
+    /// public holidays as YYYY-MM-DD. Their slots are spread evenly and flagged in the report
+    pub holidays: Vec<String>,
+    /// url of the public holiday ICS feed of each region, e.g. SG or MY. Everyone's regional
+    /// holidays are conflicts, like out of office events
+    pub holiday_feeds: HashMap<String, String>,
+    /// region of people without one of their own, a key of `holiday_feeds`
+    pub region: Option<String>,
+    /// primary and secondary pairs, used with --secondary-schedule
+    pub pairings: Pairings,
+    /// meetings whose summary contains one of these (case insensitive) are soft conflicts: avoided
+    /// when possible, but someone may be kept oncall through them when no clean plan exists.
+    /// Out of office and xoncall events are always hard conflicts
+    pub soft_conflict_keywords: Vec<String>,
+    /// private events are conflicts, whatever they are about, instead of being left out. For teams
+    /// whose calendars are private by default, hiding their out of office events
+    pub private_events_busy: bool,
+    /// named shifts, each starting at a local time in its own timezone. Entries of the schedule
+    /// that match none of them form a shift named after their start time
+    pub shifts: Vec<ShiftDefinition>,
+    /// how many slots each shift is expected to have in the window
+    pub coverage: Coverage,
+    /// slots under way or starting within this many hours are never changed, so nobody is swapped
+    /// out while they could be paged. --freeze-hours takes precedence
+    pub freeze_hours: Option<u32>,
+    /// relay for the emails of --email-affected
+    pub smtp: Option<SmtpConfig>,
+    /// receiver of a signed json request for each plan and apply
+    pub webhook: Option<WebhookConfig>,
+    /// slack statuses read as conflicts, and the user group kept pointing at whoever is oncall
+    pub slack: Option<SlackConfig>,
+    /// approved time off read from bamboohr
+    pub bamboohr: Option<BambooHrConfig>,
+    /// absences read from a workday report
+    pub workday: Option<WorkdayConfig>,
+    /// google sheets the plans are written to
+    pub sheets: Option<SheetsConfig>,
+    /// jira project given a ticket when conflicts can't be resolved
+    pub jira: Option<JiraConfig>,
+    /// confluence page kept showing the rotation after each apply
+    pub confluence: Option<ConfluenceConfig>,
+    /// grafana instance given an annotation for each override applied
+    pub grafana_annotations: Option<GrafanaAnnotationsConfig>,
+    /// directory mapping pagerduty emails to the addresses of google calendars
+    pub ldap: Option<LdapConfig>,
+    /// bucket given an audit record of each apply
+    pub audit_log: Option<AuditLogConfig>,
+    /// how long api responses are cached
+    pub cache: CacheTtls,
+    /// commands reporting when people are busy, for sources other than their calendar
+    pub availability_commands: Vec<AvailabilityCommand>,
+    /// Tera templates replacing the built-in wording of the reports
+    pub templates: TemplateFiles,
+    /// read every calendar over CalDAV, so runs never sign in to google. Everyone on the schedule
+    /// then needs a caldav account
+    pub caldav_only: bool,
+    /// the paging system holding the schedules
+    pub oncall_provider: OncallBackend,
+    /// root of the oncall provider's api, e.g. for the EU instance of opsgenie. Required for
+    /// grafana oncall, which has no single url. --base-url wins over it
+    pub oncall_api_url: Option<String>,
+    /// api id of splunk on-call, sent along the key
+    pub oncall_api_id: Option<String>,
+}
+
+/// How emails of the oncall provider are normalised: lower case, without a +tag, and with legacy
+/// domains replaced by the domain calendars live under now
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct EmailAliases {
+    /// legacy domain and the current domain it maps to, e.g. "oldcorp.com" = "example.com"
+    pub domains: HashMap<String, String>,
+    /// keep the +tag of alice+oncall@example.com, for calendars that are really under it
+    pub keep_plus_tags: bool,
+}
+
+impl EmailAliases {
+    /// `email` lowercased, without its +tag and with a legacy domain replaced
+    pub fn normalise(&self, email: &str) -> String {
+        let email = email.trim().to_lowercase();
+        let Some((local, domain)) = email.rsplit_once('@') else {
+            return email;
+        };
+        let local = match self.keep_plus_tags {
+            true => local,
+            false => local.split('+').next().unwrap_or(local),
+        };
+        let domain = self
+            .domains
+            .iter()
+            .find(|(legacy, _)| legacy.to_lowercase() == domain)
+            .map(|(_, current)| current.to_lowercase())
+            .unwrap_or_else(|| domain.to_string());
+        format!("{}@{}", local, domain)
+    }
+}
+
+/// Paging systems the schedules can live in, chosen with `oncall_provider`
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OncallBackend {
+    #[default]
+    PagerDuty,
+    Opsgenie,
+    Grafana,
+    Splunk,
+}
+
+impl OncallBackend {
+    /// Environment variable holding the api key
+    pub fn api_key_variable(&self) -> &'static str {
+        match self {
+            OncallBackend::PagerDuty => "PD_API_KEY",
+            OncallBackend::Opsgenie => "OPSGENIE_API_KEY",
+            OncallBackend::Grafana => "GRAFANA_ONCALL_TOKEN",
+            OncallBackend::Splunk => "SPLUNK_ONCALL_API_KEY",
+        }
+    }
+}
+
+/// Template files for the reports, each rendered with the plan in place of the built-in wording.
+/// Paths are relative to the current directory
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct TemplateFiles {
+    /// the plan posted to slack, as mrkdwn
+    pub slack_plan: Option<PathBuf>,
+    /// the body of the emails of --email-affected
+    pub email: Option<PathBuf>,
+    /// the report written with --markdown-report
+    pub markdown: Option<PathBuf>,
+}
+
+/// An external command printing when a person is busy, e.g. from an HR tool. It is run once per
+/// person, and prints a json array of `{"start", "end", "reason"}` objects, with rfc3339 times or
+/// YYYY-MM-DD dates as in the availability file. Those times are treated like out of office events
+#[derive(Deserialize, Debug, Clone)]
+pub struct AvailabilityCommand {
+    /// program and its arguments. `{email}`, `{start}` and `{end}` are replaced with the person
+    /// and the rfc3339 window being planned
+    pub command: Vec<String>,
+    /// emails of the people it is run for, everyone if empty
+    #[serde(default)]
+    pub users: Vec<String>,
+    /// how long a run may take before the whole run fails
+    #[serde(default = "default_command_timeout")]
+    pub timeout_seconds: u64,
+}
+
+fn default_command_timeout() -> u64 {
+    30
+}
+
+/// An SMTP relay. The password, if any, is read from the SMTP_PASSWORD environment variable
+#[derive(Deserialize, Debug, Clone)]
+pub struct SmtpConfig {
+    pub host: String,
+    #[serde(default = "default_smtp_port")]
+    pub port: u16,
+    /// sender address, e.g. "Oncall <oncall@example.com>"
+    pub from: String,
+    pub username: Option<String>,
+    /// upgrade the connection with STARTTLS. Only turn off for relays on a trusted network
+    #[serde(default = "default_starttls")]
+    pub starttls: bool,
+}
+
+/// An http endpoint told about conflicts, plans and applies. The HMAC secret signing the requests
+/// is read from the WEBHOOK_SECRET environment variable
+#[derive(Deserialize, Debug, Clone)]
+pub struct WebhookConfig {
+    pub url: String,
+}
+
+/// The slack workspace, read and changed through the web api with the bot token in
+/// SLACK_BOT_TOKEN. Separate from SLACK_WEBHOOK_URL, which only posts messages
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct SlackConfig {
+    /// statuses marking someone as away, matched against the status emoji, e.g. ":palm_tree:", or
+    /// contained in its text, ignoring case. People with one are busy until it expires. Empty
+    /// turns the status checks off
+    pub away_statuses: Vec<String>,
+    /// id of the user group, e.g. S0123ABCD, set to whoever is oncall after overrides are applied
+    /// and on every check of --watch
+    pub oncall_group: Option<String>,
+}
+
+/// A BambooHR account, read with the api key in BAMBOOHR_API_KEY
+#[derive(Deserialize, Debug, Clone)]
+pub struct BambooHrConfig {
+    /// the company's subdomain, e.g. acme for acme.bamboohr.com
+    pub company: String,
+}
+
+/// Google sheets, reached with the google sign in of the calendars, which then asks for the
+/// spreadsheets scope too
+#[derive(Deserialize, Debug, Clone)]
+pub struct SheetsConfig {
+    /// id of the spreadsheet, from its url, given a tab per run with its conflicts and overrides
+    pub plan_spreadsheet: Option<String>,
+    /// id of a spreadsheet where people mark slots they prefer or are unavailable for
+    pub preferences_spreadsheet: Option<String>,
+    /// range of the preferences, with an email, start, end, preference and reason header
+    #[serde(default = "default_preferences_range")]
+    pub preferences_range: String,
+    /// id of the spreadsheet a google form of swap requests saves its responses to
+    pub swap_requests_spreadsheet: Option<String>,
+    /// range of the swap requests, with an email address, slot, taken by and optional in return
+    /// header
+    #[serde(default = "default_swap_requests_range")]
+    pub swap_requests_range: String,
+}
+
+impl SheetsConfig {
+    /// Every spreadsheet the google token must reach
+    pub fn spreadsheets(&self) -> Vec<&str> {
+        [
+            &self.plan_spreadsheet,
+            &self.preferences_spreadsheet,
+            &self.swap_requests_spreadsheet,
+        ]
+        .into_iter()
+        .flatten()
+        .map(|x| x.as_str())
+        .collect()
+    }
+}
+
+fn default_preferences_range() -> String {
+    "Preferences".to_string()
+}
+
+/// The tab google forms saves responses to
+fn default_swap_requests_range() -> String {
+    "Form Responses 1".to_string()
+}
+
+/// A jira project where conflicts the plan can't resolve are tracked. The api token of `email` is
+/// read from JIRA_API_TOKEN
+#[derive(Deserialize, Debug, Clone)]
+pub struct JiraConfig {
+    /// e.g. https://acme.atlassian.net
+    pub url: String,
+    pub email: String,
+    /// key of the project, e.g. OPS
+    pub project: String,
+    #[serde(default = "default_jira_issue_type")]
+    pub issue_type: String,
+    #[serde(default)]
+    pub labels: Vec<String>,
+}
+
+fn default_jira_issue_type() -> String {
+    "Task".to_string()
+}
+
+/// A confluence page rewritten with the rotation, the latest overrides and the shift counts after
+/// each apply. The api token of `email` is read from CONFLUENCE_API_TOKEN
+#[derive(Deserialize, Debug, Clone)]
+pub struct ConfluenceConfig {
+    /// e.g. https://acme.atlassian.net/wiki
+    pub url: String,
+    pub email: String,
+    /// key of the space holding the page, e.g. OPS
+    pub space: String,
+    /// title of the page, created on the first apply if no page has it
+    #[serde(default = "default_confluence_title")]
+    pub title: String,
+    /// page the page is created under, by id, when it has to be created
+    pub parent_id: Option<String>,
+}
+
+fn default_confluence_title() -> String {
+    "Oncall rotation".to_string()
+}
+
+/// A grafana instance where each override applied becomes an annotation over the time it covers,
+/// to tell who held the pager when looking back at an incident. The service account token is
+/// read from GRAFANA_API_TOKEN
+#[derive(Deserialize, Debug, Clone)]
+pub struct GrafanaAnnotationsConfig {
+    /// e.g. https://grafana.acme.com
+    pub url: String,
+    /// uid of the dashboard the annotations are on. Without one they are organization wide, and
+    /// shown by dashboards querying annotations by tag
+    pub dashboard_uid: Option<String>,
+    /// panel of the dashboard the annotations are on, all of them if not set
+    pub panel_id: Option<i64>,
+    /// tags of every annotation, along with the id of the schedule
+    #[serde(default = "default_grafana_annotation_tags")]
+    pub tags: Vec<String>,
+}
+
+fn default_grafana_annotation_tags() -> Vec<String> {
+    vec!["oncall".to_string()]
+}
+
+/// An S3 or GCS bucket where a record of each apply is kept, for change records on the rotation.
+/// Uploads are signed with the access key in AUDIT_LOG_ACCESS_KEY_ID and AUDIT_LOG_SECRET_ACCESS_KEY,
+/// which for GCS is an HMAC key of a service account
+#[derive(Deserialize, Debug, Clone)]
+pub struct AuditLogConfig {
+    /// bucket and key prefix, e.g. s3://acme-audit/oncall or gs://acme-audit/oncall
+    pub url: String,
+    /// region of the S3 bucket
+    #[serde(default = "default_audit_log_region")]
+    pub region: String,
+    /// root of an S3 compatible api to use instead, e.g. https://minio.acme.com, reached with the
+    /// bucket in the path
+    pub endpoint: Option<String>,
+}
+
+fn default_audit_log_region() -> String {
+    "us-east-1".to_string()
+}
+
+/// An LDAP or Active Directory server mapping the email people log in to pagerduty with to the
+/// address of their google calendar, for companies where the two differ. The password of
+/// `bind_dn` is read from LDAP_BIND_PASSWORD
+#[derive(Deserialize, Debug, Clone)]
+pub struct LdapConfig {
+    /// ldap:// or ldaps:// url of the server
+    pub url: String,
+    pub bind_dn: String,
+    /// where people are searched, e.g. ou=people,dc=acme,dc=com
+    pub base_dn: String,
+    /// attribute holding the pagerduty email
+    #[serde(default = "default_ldap_email_attribute")]
+    pub email_attribute: String,
+    /// attribute holding the calendar address, e.g. userPrincipalName. In proxyAddresses, the
+    /// primary SMTP: address is used
+    pub calendar_attribute: String,
+    /// extra condition on the entries searched, e.g. (objectClass=user)
+    pub filter: Option<String>,
+}
+
+fn default_ldap_email_attribute() -> String {
+    "mail".to_string()
+}
+
+/// A Workday custom report of absences, one row per absence, read as json by an integration
+/// system user whose password is in WORKDAY_PASSWORD
+#[derive(Deserialize, Debug, Clone)]
+pub struct WorkdayConfig {
+    /// RaaS url of the report, ending in ?format=json
+    pub report_url: String,
+    pub username: String,
+    /// columns of the report
+    #[serde(default = "default_workday_email_field")]
+    pub email_field: String,
+    #[serde(default = "default_workday_start_field")]
+    pub start_field: String,
+    #[serde(default = "default_workday_end_field")]
+    pub end_field: String,
+    #[serde(default = "default_workday_reason_field")]
+    pub reason_field: String,
+}
+
+fn default_workday_email_field() -> String {
+    "Email".to_string()
+}
+
+fn default_workday_start_field() -> String {
+    "Start_Date".to_string()
+}
+
+fn default_workday_end_field() -> String {
+    "End_Date".to_string()
+}
+
+fn default_workday_reason_field() -> String {
+    "Absence_Type".to_string()
+}
+
+fn default_smtp_port() -> u16 {
+    587
+}
+
+fn default_starttls() -> bool {
+    true
+}
+
+/// Timezone of people without one in the config file, the same as the schedule's
+pub const DEFAULT_TIMEZONE: Tz = chrono_tz::Asia::Singapore;
+
+/// A shift as written in the config file, e.g. the EU shift starting at 09:00 Europe/Berlin
+#[derive(Deserialize, Debug, Clone)]
+pub struct ShiftDefinition {
+    pub name: String,
+    /// local start time, HH:MM
+    pub start: String,
+    /// IANA timezone of the start time, e.g. Europe/Berlin
+    pub timezone: String,
+}
+
+/// A shift definition with its start time and timezone parsed
+#[derive(Debug, Clone)]
+pub struct Shift {
+    pub name: String,
+    pub start: NaiveTime,
+    pub timezone: Tz,
+}
+
+impl Shift {
+    /// Whether a slot starting at `start` belongs to this shift. The comparison is done in the
+    /// shift's timezone, so the shift keeps its local start time across DST changes
+    pub fn starts_at(&self, start: DateTime<FixedOffset>) -> bool {
+        start.with_timezone(&self.timezone).time() == self.start
+    }
+
+    /// How far a slot starting at `start` is from the start of this shift, in the shift's timezone
+    pub fn offset_from_start(&self, start: DateTime<FixedOffset>) -> chrono::Duration {
+        start.with_timezone(&self.timezone).time() - self.start
+    }
+}
+
+/// The name of the defined shift a slot starting at `start` belongs to. Slots outside the defined
+/// shifts are named after their start time of day, e.g. 03:00
+pub fn shift_of(start: DateTime<FixedOffset>, shifts: &[Shift]) -> String {
+    match shifts.iter().find(|x| x.starts_at(start)) {
+        Some(shift) => shift.name.clone(),
+        None => start.format("%H:%M").to_string(),
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct Pairings {
+    /// [primary, secondary] pairs that stay together in the slots they already share
+    pub keep: Vec<[String; 2]>,
+    /// [primary, secondary] pairs that are never put oncall together
+    pub never: Vec<[String; 2]>,
+    /// [primary, shadow] pairs where the shadow follows the primary's moves on the secondary
+    /// schedule, with --link-secondary
+    pub follow: Vec<[String; 2]>,
+}
+
+/// Slots each shift should have in the window, to catch a window that doesn't line up with the
+/// rotation, e.g. 14 days asked for but 12 slots rendered
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Coverage {
+    /// slots starting in the window per day of it, for every shift. 1 for daily shifts, 0 turns the
+    /// check off
+    pub slots_per_day: f64,
+    /// per shift, e.g. "03:00" or the name of a defined shift, overriding `slots_per_day`. 1/7 for
+    /// a weekly rotation
+    pub shifts: HashMap<String, f64>,
+    /// fail the run instead of warning when a shift has more or fewer slots than expected
+    pub strict: bool,
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Coverage {
+            slots_per_day: 1.0,
+            shifts: HashMap::new(),
+            strict: false,
+        }
+    }
+}
+
+impl Coverage {
+    /// Slots starting per day expected of `shift`, 0 when it isn't checked
+    pub fn slots_per_day(&self, shift: &str) -> f64 {
+        *self.shifts.get(shift).unwrap_or(&self.slots_per_day)
+    }
+}
+
+/// Hard constraints (calendar conflicts) always hold. Soft ones are traded off using these weights
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Weights {
+    /// cost of each override in the plan
+    pub overrides: f64,
+    /// cost per unit of weekend imbalance, the sum of squared deviations from the mean weekend count
+    pub weekend: f64,
+    /// reward for each slot held by someone who asked for it with a prefer-oncall event
+    pub preference: f64,
+    /// cost per unit of holiday imbalance, computed like the weekend imbalance
+    pub holiday: f64,
+    /// cost of each time someone holds two slots back to back
+    pub back_to_back: f64,
+    /// cost per unit of recent load (see --history-weeks) of people taking overrides or weekend slots
+    pub history: f64,
+    /// multiplier of the costs from --cost-matrix
+    pub cost: f64,
+    /// cost of each slot held through soft conflicts, scaled by the fraction of the slot they
+    /// cover. Only used when no clean plan exists
+    pub soft_conflict: f64,
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        Weights {
+            overrides: 1.0,
+            weekend: 1.0,
+            holiday: 1.0,
+            preference: 1.0,
+            back_to_back: 1.0,
+            history: 1.0,
+            cost: 1.0,
+            soft_conflict: 10.0,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct UserConfig {
+    /// the only shift this person may be moved into by cross-shift swaps, named after its start
+    /// time of day, e.g. "03:00"
+    pub preferred_shift: Option<String>,
+    /// IANA timezone times are shown in to this person, e.g. in emails. Defaults to the timezone
+    /// of the config file
+    pub timezone: Option<String>,
+    /// CalDAV calendar read for this person instead of their google calendar
+    pub caldav: Option<CalDavAccount>,
+    /// region whose public holidays this person takes, a key of `holiday_feeds`. Defaults to the
+    /// region of the config file
+    pub region: Option<String>,
+    /// url of an ICS feed of this person's external bookings, e.g. customer calls booked through
+    /// a scheduling tool that never reach their calendar
+    pub bookings_feed: Option<String>,
+    /// calendly user uri of this person, e.g. https://api.calendly.com/users/AAAA, whose scheduled
+    /// events are read with CALENDLY_API_TOKEN
+    pub calendly_user: Option<String>,
+}
+
+/// A calendar on a CalDAV server, e.g. Fastmail or Nextcloud. The password is read from the
+/// environment variable named by `password_env`, so it stays out of the config file
+#[derive(Deserialize, Debug, Clone)]
+pub struct CalDavAccount {
+    /// url of the calendar collection, e.g.
+    /// https://caldav.fastmail.com/dav/calendars/user/alice@example.com/Default/
+    pub url: String,
+    pub username: String,
+    /// environment variable holding the password, usually an app password
+    pub password_env: String,
+}
+
+impl Config {
+    /// Settings of `email`, also found when their key in `users` is only written differently
+    pub fn user(&self, email: &str) -> UserConfig {
+        if let Some(user) = self.users.get(email) {
+            return user.clone();
+        }
+        let normalised = self.normalise_email(email);
+        self.users
+            .iter()
+            .find(|(key, _)| self.normalise_email(key) == normalised)
+            .map(|(_, user)| user.clone())
+            .unwrap_or_default()
+    }
+
+    /// `email` as calendars know it, following `email_aliases`
+    pub fn normalise_email(&self, email: &str) -> String {
+        self.email_aliases.normalise(email)
+    }
+
+    /// Keys of `users` that match none of `emails`, with the email they are a typo or two away
+    /// from, e.g. alise@example.com and alice@example.com. Their settings would otherwise be
+    /// silently ignored
+    pub fn near_misses(&self, emails: &[&str]) -> Vec<(String, String)> {
+        let normalised: Vec<String> = emails.iter().map(|x| self.normalise_email(x)).collect();
+        let mut misses: Vec<(String, String)> = self
+            .users
+            .keys()
+            .filter(|key| !normalised.contains(&self.normalise_email(key)))
+            .filter_map(|key| {
+                let key_normalised = self.normalise_email(key);
+                zip(emails, &normalised)
+                    .map(|(email, x)| (edit_distance(&key_normalised, x), email))
+                    .filter(|(distance, _)| *distance <= MAX_NEAR_MISS)
+                    .min()
+                    .map(|(_, email)| (key.clone(), email.to_string()))
+            })
+            .collect();
+        misses.sort();
+        misses
+    }
+
+    /// Timezone the window starts and ends in, and slots are shown in
+    pub fn schedule_timezone(&self) -> AnyhowResult<Tz> {
+        match &self.timezone {
+            Some(value) => value
+                .parse()
+                .map_err(|e| anyhow!("Invalid timezone {}: {}", value, e)),
+            None => Ok(DEFAULT_TIMEZONE),
+        }
+    }
+
+    pub fn user_timezone(&self, email: &str) -> AnyhowResult<Tz> {
+        match self.user(email).timezone.or_else(|| self.timezone.clone()) {
+            Some(value) => value
+                .parse()
+                .map_err(|e| anyhow!("Invalid timezone {} of {}: {}", value, email, e)),
+            None => Ok(DEFAULT_TIMEZONE),
+        }
+    }
+
+    /// How tables and messages show times, from `value` of --display-timezone or
+    /// `display_timezone`: an IANA timezone, or "user" for everyone's own
+    pub fn display_timezone(&self, value: &str) -> AnyhowResult<DisplayTimezone> {
+        if value != "user" {
+            return value
+                .parse()
+                .map(DisplayTimezone::Zone)
+                .map_err(|e| anyhow!("Invalid display timezone {}: {}", value, e));
+        }
+        let mut timezones = HashMap::new();
+        for email in self.users.keys() {
+            timezones.insert(self.normalise_email(email), self.user_timezone(email)?);
+        }
+        Ok(DisplayTimezone::PerUser {
+            timezones,
+            aliases: self.email_aliases.clone(),
+            fallback: self.schedule_timezone()?,
+        })
+    }
+
+    /// Whether anyone's bookings are read from calendly
+    pub fn uses_calendly(&self) -> bool {
+        self.users.values().any(|x| x.calendly_user.is_some())
+    }
+
+    /// Region whose holiday feed applies to `email`, if any
+    pub fn user_region(&self, email: &str) -> Option<String> {
+        self.user(email).region.or_else(|| self.region.clone())
+    }
+
+    pub fn holiday_dates(&self) -> AnyhowResult<BTreeSet<NaiveDate>> {
+        self.holidays
+            .iter()
+            .map(|x| {
+                NaiveDate::parse_from_str(x, "%Y-%m-%d")
+                    .context(format!("Invalid holiday {}, expected YYYY-MM-DD", x))
+            })
+            .collect()
+    }
+
+    pub fn shift_definitions(&self) -> AnyhowResult<Vec<Shift>> {
+        self.shifts
+            .iter()
+            .map(|x| {
+                Ok(Shift {
+                    name: x.name.clone(),
+                    start: NaiveTime::parse_from_str(&x.start, "%H:%M").context(format!(
+                        "Invalid start {} of shift {}, expected HH:MM",
+                        x.start, x.name
+                    ))?,
+                    timezone: x.timezone.parse().map_err(|e| {
+                        anyhow!("Invalid timezone {} of shift {}: {}", x.timezone, x.name, e)
+                    })?,
+                })
+            })
+            .collect()
+    }
+
+    /// Every `preferred_shift` has to name one of `shifts`, or the start time of day slots outside
+    /// them are named after. Otherwise, e.g. with the AM and PM of older versions, cross-shift
+    /// swaps would silently never move the person
+    fn check_preferred_shifts(&self) -> AnyhowResult<()> {
+        let names: Vec<&str> = self.shifts.iter().map(|x| x.name.as_str()).collect();
+        let mut users: Vec<(&String, &UserConfig)> = self.users.iter().collect();
+        users.sort_by_key(|(email, _)| *email);
+        for (email, user) in users {
+            let Some(preferred) = &user.preferred_shift else {
+                continue;
+            };
+            if names.contains(&preferred.as_str())
+                || NaiveTime::parse_from_str(preferred, "%H:%M").is_ok()
+            {
+                continue;
+            }
+            return Err(match names.is_empty() {
+                true => anyhow!(
+                    "Unknown preferred_shift {} of users.\"{}\". No shifts are defined, so \
+                    it has to be a start time of day, e.g. \"03:00\"",
+                    preferred,
+                    email
+                ),
+                false => anyhow!(
+                    "Unknown preferred_shift {} of users.\"{}\", expected one of the shifts \
+                    {} or a start time of day, e.g. \"03:00\"",
+                    preferred,
+                    email,
+                    names.join(", ")
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    /// Everything wrong with the settings that would only surface halfway through a run, e.g. a
+    /// misspelt timezone. Empty when the config is fine
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if let Some(value) = &self.timezone {
+            if let Err(e) = value.parse::<Tz>() {
+                problems.push(format!("Invalid timezone {}: {}", value, e));
+            }
+        }
+        if let Some(value) = self.display_timezone.as_deref().filter(|x| *x != "user") {
+            if let Err(e) = value.parse::<Tz>() {
+                problems.push(format!("Invalid display timezone {}: {}", value, e));
+            }
+        }
+        let mut emails: Vec<&String> = self.users.keys().collect();
+        emails.sort();
+        for email in emails {
+            if let Some(value) = &self.users[email].timezone {
+                if let Err(e) = value.parse::<Tz>() {
+                    problems.push(format!("Invalid timezone {} of {}: {}", value, email, e));
+                }
+            }
+            if let Some(account) = &self.users[email].caldav {
+                if !account.url.starts_with("https://") && !account.url.starts_with("http://") {
+                    problems.push(format!("Invalid caldav url {} of {}", account.url, email));
+                }
+            }
+            if let Some(url) = &self.users[email].bookings_feed {
+                if !url.starts_with("https://") && !url.starts_with("http://") {
+                    problems.push(format!("Invalid bookings feed {} of {}", url, email));
+                }
+            }
+            if let Some(region) = &self.users[email].region {
+                if !self.holiday_feeds.contains_key(region) {
+                    problems.push(format!(
+                        "No holiday feed for region {} of {}",
+                        region, email
+                    ));
+                }
+            }
+        }
+        if let Some(region) = &self.region {
+            if !self.holiday_feeds.contains_key(region) {
+                problems.push(format!("No holiday feed for region {}", region));
+            }
+        }
+        let mut names = BTreeSet::new();
+        for shift in &self.shifts {
+            if !names.insert(&shift.name) {
+                problems.push(format!("Shift {} is defined twice", shift.name));
+            }
+        }
+        if let Err(e) = self.shift_definitions() {
+            problems.push(format!("{:#}", e));
+        }
+        if let Err(e) = self.holiday_dates() {
+            problems.push(format!("{:#}", e));
+        }
+        if self.coverage.slots_per_day < 0.0 {
+            problems.push("coverage.slots_per_day can't be negative".to_string());
+        }
+        let mut shifts: Vec<&String> = self.coverage.shifts.keys().collect();
+        shifts.sort();
+        for shift in shifts {
+            if self.coverage.shifts[shift] < 0.0 {
+                problems.push(format!("coverage.shifts.\"{}\" can't be negative", shift));
+            }
+        }
+        if let Some(audit_log) = &self.audit_log {
+            if !audit_log.url.starts_with("s3://") && !audit_log.url.starts_with("gs://") {
+                problems.push(format!(
+                    "Invalid audit log url {}, expected s3://bucket/prefix or gs://bucket/prefix",
+                    audit_log.url
+                ));
+            }
+        }
+        for command in &self.availability_commands {
+            if command.command.is_empty() {
+                problems.push("An availability command has no program".to_string());
+            }
+        }
+        problems
+    }
+}
+
+/// Most single character edits between a key of `users` and an email for it to count as a typo
+const MAX_NEAR_MISS: usize = 2;
+
+/// Levenshtein distance between `a` and `b`, in characters
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, x) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, y) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(x != *y);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Load the config file at `path` with `profile` laid over it, or an empty config without one
+pub fn load_config(path: Option<&Path>, profile: Option<&str>) -> AnyhowResult<Config> {
+    let Some(path) = path else {
+        return match profile {
+            Some(name) => Err(anyhow!("--profile {} needs a config file", name)),
+            None => Ok(Config::default()),
+        };
+    };
+    let contents = fs::read_to_string(path)
+        .context(format!("Failed to read config file {}", path.display()))?;
+    parse_config(&contents, profile)
+        .context(format!("Failed to parse config file {}", path.display()))
+}
+
+fn parse_config(contents: &str, profile: Option<&str>) -> AnyhowResult<Config> {
+    let mut root: Value = toml::from_str(contents)?;
+    let profiles = root.as_table_mut().and_then(|x| x.remove("profile"));
+    if let Some(name) = profile {
+        let overlay = profiles.as_ref().and_then(|x| x.get(name)).ok_or_else(|| {
+            let known: Vec<&String> = profiles
+                .as_ref()
+                .and_then(|x| x.as_table())
+                .map(|x| x.keys().collect())
+                .unwrap_or_default();
+            anyhow!("No profile {}, the profiles are {:?}", name, known)
+        })?;
+        merge(&mut root, overlay.clone());
+    }
+    let config: Config = root.try_into()?;
+    config.check_preferred_shifts()?;
+    Ok(config)
+}
+
+/// Lay `overlay` over `base`: tables are merged key by key, anything else is replaced
+fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Table(base), Value::Table(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() -> AnyhowResult<()> {
+        let config = parse_config(
+            r#"
+            blocked_swaps = [["a@x.com", "b@x.com"]]
+            holidays = ["2022-08-31"]
+            soft_conflict_keywords = ["standup"]
+
+            [users."a@x.com"]
+            preferred_shift = "03:00"
+            timezone = "Europe/Berlin"
+
+            [smtp]
+            host = "smtp.example.com"
+            from = "oncall@example.com"
+
+            [webhook]
+            url = "https://audit.example.com/oncall"
+
+            [weights]
+            back_to_back = 5.0
+
+            [pairings]
+            keep = [["a@x.com", "mentor@x.com"]]
+
+            [[shifts]]
+            name = "EU"
+            start = "09:00"
+            timezone = "Europe/Berlin"
+            "#,
+            None,
+        )?;
+        assert_eq!(
+            config.user("a@x.com").preferred_shift,
+            Some("03:00".to_string())
+        );
+        assert_eq!(config.user("b@x.com").preferred_shift, None);
+        assert_eq!(config.user_timezone("a@x.com")?, chrono_tz::Europe::Berlin);
+        assert_eq!(config.user_timezone("b@x.com")?, DEFAULT_TIMEZONE);
+        assert_eq!(config.schedule_timezone()?, DEFAULT_TIMEZONE);
+        let smtp = config.smtp.as_ref().unwrap();
+        assert_eq!((smtp.port, smtp.starttls), (587, true));
+        assert_eq!(
+            config.webhook.as_ref().unwrap().url,
+            "https://audit.example.com/oncall"
+        );
+        assert_eq!(config.weights.back_to_back, 5.0);
+        assert!(parse_config("", None)?.users.is_empty());
+        assert_eq!(config.oncall_provider, OncallBackend::PagerDuty);
+        let opsgenie = parse_config(r#"oncall_provider = "opsgenie""#, None)?;
+        assert_eq!(opsgenie.oncall_provider, OncallBackend::Opsgenie);
+        assert_eq!(config.weights.weekend, 1.0);
+        assert_eq!(config.blocked_swaps, vec![vec!["a@x.com", "b@x.com"]]);
+        assert!(config
+            .holiday_dates()?
+            .contains(&NaiveDate::from_ymd(2022, 8, 31)));
+        assert_eq!(config.pairings.keep[0][1], "mentor@x.com");
+        assert!(config.pairings.never.is_empty());
+        assert_eq!(config.soft_conflict_keywords, vec!["standup"]);
+        assert_eq!(config.weights.soft_conflict, 10.0);
+        let invalid_holiday = parse_config(r#"holidays = ["31/08/2022"]"#, None)?;
+        assert!(invalid_holiday.holiday_dates().is_err());
+
+        // 09:00 in Berlin is 16:00 in Singapore before the DST change and 15:00 after it
+        let shifts = config.shift_definitions()?;
+        let winter = DateTime::parse_from_rfc3339("2022-03-25T16:00:00+08:00")?;
+        let summer = DateTime::parse_from_rfc3339("2022-03-28T15:00:00+08:00")?;
+        assert!(shifts[0].starts_at(winter) && shifts[0].starts_at(summer));
+        assert!(!shifts[0].starts_at(summer + chrono::Duration::hours(1)));
+        let invalid_timezone = parse_config(
+            r#"shifts = [{ name = "EU", start = "09:00", timezone = "Europe/Atlantis" }]"#,
+            None,
+        )?;
+        assert!(invalid_timezone.shift_definitions().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_preferred_shift() -> AnyhowResult<()> {
+        let config = |shifts: &str, preferred: &str| {
+            parse_config(
+                &format!(
+                    "shifts = [{}]\n[users.\"a@x.com\"]\npreferred_shift = \"{}\"",
+                    shifts, preferred
+                ),
+                None,
+            )
+        };
+        let eu = r#"{ name = "EU", start = "09:00", timezone = "Europe/Berlin" }"#;
+        config(eu, "EU")?;
+        config(eu, "03:00")?;
+        config("", "03:00")?;
+        assert_eq!(
+            config(eu, "AM").unwrap_err().to_string(),
+            "Unknown preferred_shift AM of users.\"a@x.com\", expected one of the shifts EU \
+            or a start time of day, e.g. \"03:00\""
+        );
+        assert!(config("", "PM")
+            .unwrap_err()
+            .to_string()
+            .contains("No shifts are defined"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_email_aliases() -> AnyhowResult<()> {
+        let config = parse_config(
+            r#"
+            [email_aliases]
+            domains = { "OldCorp.com" = "example.com" }
+
+            [users."Alice@Example.com"]
+            timezone = "Europe/Berlin"
+
+            [users."alise@example.com"]
+            timezone = "Europe/London"
+            "#,
+            None,
+        )?;
+        assert_eq!(
+            config.normalise_email(" Bob+oncall@OLDCORP.com"),
+            "bob@example.com"
+        );
+        assert_eq!(config.normalise_email("not-an-email"), "not-an-email");
+        assert_eq!(
+            config.user("alice+pager@oldcorp.com").timezone.as_deref(),
+            Some("Europe/Berlin")
+        );
+        assert_eq!(edit_distance("alise", "alice"), 1);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(
+            config.near_misses(&["alice@oldcorp.com", "bob@example.com"]),
+            vec![(
+                "alise@example.com".to_string(),
+                "alice@oldcorp.com".to_string()
+            )]
+        );
+
+        let config = parse_config("email_aliases.keep_plus_tags = true", None)?;
+        assert_eq!(
+            config.normalise_email("Bob+oncall@example.com"),
+            "bob+oncall@example.com"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_problems() -> AnyhowResult<()> {
+        assert!(parse_config("", None)?.problems().is_empty());
+        let config = parse_config(
+            r#"
+            timezone = "Asia/Singapore"
+            display_timezone = "Berlin"
+            holidays = ["31/08/2022"]
+            coverage = { shifts = { "EU" = -1.0 } }
+
+            [users."a@x.com"]
+            timezone = "Europe/Atlantis"
+            caldav = { url = "caldav.example.com", username = "a", password_env = "A_PASSWORD" }
+            region = "MY"
+
+            [[shifts]]
+            name = "EU"
+            start = "09:00"
+            timezone = "Europe/Berlin"
+
+            [[shifts]]
+            name = "EU"
+            start = "9am"
+            timezone = "Europe/Berlin"
+
+            [[availability_commands]]
+            command = []
+            "#,
+            None,
+        )?;
+        let problems = config.problems();
+        assert_eq!(problems.len(), 9, "{:?}", problems);
+        assert!(problems[0].starts_with("Invalid display timezone Berlin"));
+        assert!(problems[1].contains("Europe/Atlantis of a@x.com"));
+        assert_eq!(
+            problems[2],
+            "Invalid caldav url caldav.example.com of a@x.com"
+        );
+        assert_eq!(problems[3], "No holiday feed for region MY of a@x.com");
+        assert_eq!(problems[4], "Shift EU is defined twice");
+        assert!(problems[5].contains("Invalid start 9am of shift EU"));
+        assert_eq!(problems[7], "coverage.shifts.\"EU\" can't be negative");
+        Ok(())
+    }
+
+    #[test]
+    fn test_profiles() -> AnyhowResult<()> {
+        let contents = r#"
+            schedule = "PDEFAULT"
+            holidays = ["2022-08-31"]
+
+            [weights]
+            weekend = 2.0
+
+            [profile.platform-team]
+            schedule = "PPLATFORM"
+            timezone = "Europe/Berlin"
+            shifts = [{ name = "EU", start = "09:00", timezone = "Europe/Berlin" }]
+
+            [profile.platform-team.weights]
+            back_to_back = 5.0
+
+            [profile.data-team]
+            schedule = "PDATA"
+            "#;
+        let default = parse_config(contents, None)?;
+        assert_eq!(default.schedule.as_deref(), Some("PDEFAULT"));
+        assert!(default.shifts.is_empty());
+
+        let platform = parse_config(contents, Some("platform-team"))?;
+        assert_eq!(platform.schedule.as_deref(), Some("PPLATFORM"));
+        assert_eq!(platform.shifts[0].name, "EU");
+        assert_eq!(
+            platform.user_timezone("a@x.com")?,
+            chrono_tz::Europe::Berlin
+        );
+        // tables are merged, so settings the profile leaves out still apply
+        assert_eq!(platform.weights.back_to_back, 5.0);
+        assert_eq!(platform.weights.weekend, 2.0);
+        assert_eq!(platform.holidays, vec!["2022-08-31"]);
+
+        let error = parse_config(contents, Some("sre")).unwrap_err();
+        assert!(error.to_string().contains("data-team"), "{}", error);
+        Ok(())
+    }
+}
@@ -0,0 +1,269 @@
+use crate::config::ConfluenceConfig;
+use crate::dashboard::escape;
+use crate::http::HttpClient;
+use crate::solver::{holiday_counts, shift_counts, weekend_counts, FinalEntity, FinalOverride};
+use crate::state::StoredRun;
+use anyhow::{ensure, Context, Result as AnyhowResult};
+use chrono::NaiveDate;
+use reqwest::Response;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::BTreeSet;
+use tracing::instrument;
+
+/// Runs looked at for the recent runs of the page, of any schedule
+pub const RECENT_RUNS: usize = 10;
+
+#[derive(Deserialize, Debug)]
+struct PageResults {
+    #[serde(default)]
+    results: Vec<Page>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Page {
+    id: String,
+    version: Option<PageVersion>,
+}
+
+#[derive(Deserialize, Debug)]
+struct PageVersion {
+    number: u64,
+}
+
+/// What the wiki page shows about the schedule once a plan is applied
+pub struct SchedulePage<'a> {
+    pub schedule_id: &'a str,
+    pub run_id: i64,
+    /// the schedule after the plan
+    pub rotation: &'a [FinalEntity],
+    pub overrides: &'a [FinalOverride],
+    pub holidays: &'a BTreeSet<NaiveDate>,
+}
+
+/// Confluence, where the team's wiki page of the schedule is kept in sync. Signs in with the email
+/// of the config file and the api token in CONFLUENCE_API_TOKEN
+pub struct Confluence<'a> {
+    pub client: &'a HttpClient,
+    pub config: &'a ConfluenceConfig,
+    pub token: &'a str,
+}
+
+impl Confluence<'_> {
+    fn url(&self, path: &str) -> String {
+        format!(
+            "{}/rest/api/content{}",
+            self.config.url.trim_end_matches('/'),
+            path
+        )
+    }
+
+    /// Replace the body of the page titled as in the config file, creating it if no page of the
+    /// space has that title yet. Returns the id of the page
+    #[instrument(skip(self, body))]
+    pub async fn publish(&self, body: &str) -> AnyhowResult<String> {
+        let request = self
+            .client
+            .get(self.url(""))
+            .basic_auth(&self.config.email, Some(self.token))
+            .query(&[
+                ("spaceKey", self.config.space.as_str()),
+                ("title", self.config.title.as_str()),
+                ("expand", "version"),
+            ]);
+        let found: PageResults = check(self.client.send(request).await?)
+            .await?
+            .json()
+            .await
+            .context("Failed to parse the confluence page search")?;
+
+        let storage = json!({ "storage": { "value": body, "representation": "storage" } });
+        let request = match found.results.into_iter().next() {
+            Some(page) => {
+                // confluence only takes the update that bumps the version it holds
+                let version = page.version.map(|x| x.number).unwrap_or(0) + 1;
+                self.client
+                    .put(self.url(&format!("/{}", page.id)))
+                    .json(&json!({
+                        "id": page.id,
+                        "type": "page",
+                        "title": self.config.title,
+                        "space": { "key": self.config.space },
+                        "version": { "number": version },
+                        "body": storage,
+                    }))
+            }
+            None => {
+                let ancestors: Vec<_> = self
+                    .config
+                    .parent_id
+                    .iter()
+                    .map(|id| json!({ "id": id }))
+                    .collect();
+                self.client.post(self.url("")).json(&json!({
+                    "type": "page",
+                    "title": self.config.title,
+                    "space": { "key": self.config.space },
+                    "ancestors": ancestors,
+                    "body": storage,
+                }))
+            }
+        };
+        let request = request.basic_auth(&self.config.email, Some(self.token));
+        let page: Page = check(self.client.send(request).await?)
+            .await?
+            .json()
+            .await
+            .context("Failed to parse the page saved by confluence")?;
+        Ok(page.id)
+    }
+}
+
+async fn check(response: Response) -> AnyhowResult<Response> {
+    let status = response.status();
+    ensure!(
+        status.is_success(),
+        "Unexpected status {} from confluence: {}",
+        status,
+        response.text().await.unwrap_or_default()
+    );
+    Ok(response)
+}
+
+fn table(header: &[&str], rows: Vec<Vec<String>>) -> String {
+    let mut html = String::from("<table><tbody>\n<tr>");
+    for name in header {
+        html.push_str(&format!("<th>{}</th>", name));
+    }
+    html.push_str("</tr>\n");
+    for row in rows {
+        html.push_str("<tr>");
+        for cell in row {
+            html.push_str(&format!("<td>{}</td>", escape(&cell)));
+        }
+        html.push_str("</tr>\n");
+    }
+    html.push_str("</tbody></table>\n");
+    html
+}
+
+/// The page in confluence's storage format, which is xhtml: the rotation, the overrides of the
+/// run, the recent runs of the schedule among `runs` and the shifts everyone holds
+pub fn render_page(page: &SchedulePage, runs: &[StoredRun]) -> String {
+    let mut rotation: Vec<&FinalEntity> = page.rotation.iter().collect();
+    rotation.sort_by_key(|x| x.pd_schedule.start);
+    let rotation = rotation
+        .into_iter()
+        .map(|x| {
+            vec![
+                x.pd_schedule.start.format("%a %d %b %H:%M").to_string(),
+                x.pd_schedule.end.format("%a %d %b %H:%M").to_string(),
+                x.pd_schedule.email.clone(),
+            ]
+        })
+        .collect();
+    let overrides: Vec<Vec<String>> = page
+        .overrides
+        .iter()
+        .map(|x| {
+            vec![
+                x.start_time_iso.clone(),
+                x.end_time_iso.clone(),
+                x.original_assignee.clone(),
+                x.final_override.clone(),
+            ]
+        })
+        .collect();
+    let recent = runs
+        .iter()
+        .filter(|x| x.schedule_id == page.schedule_id)
+        .map(|x| {
+            vec![
+                x.id.to_string(),
+                x.started_at.clone(),
+                format!("{} to {}", x.window_start, x.window_end),
+                x.outcome.clone(),
+                x.active_overrides.to_string(),
+            ]
+        })
+        .collect();
+    let shifts = shift_counts(page.rotation);
+    let weekend = weekend_counts(page.rotation);
+    let holiday = holiday_counts(page.rotation, page.holidays);
+    let counts = shifts
+        .iter()
+        .map(|(email, count)| {
+            vec![
+                email.clone(),
+                count.to_string(),
+                weekend.get(email).copied().unwrap_or(0).to_string(),
+                holiday.get(email).copied().unwrap_or(0).to_string(),
+            ]
+        })
+        .collect();
+
+    let mut html = format!(
+        "<p>Kept up to date by gcal-pagerduty, last by run {} of schedule {}. Edits made here \
+         are lost on the next apply.</p>\n",
+        page.run_id,
+        escape(page.schedule_id)
+    );
+    html.push_str("<h2>Rotation</h2>\n");
+    html.push_str(&table(&["Start", "End", "Oncall"], rotation));
+    html.push_str("<h2>Latest overrides</h2>\n");
+    match overrides.is_empty() {
+        true => html.push_str("<p>No overrides were needed.</p>\n"),
+        false => html.push_str(&table(&["Start", "End", "From", "To"], overrides)),
+    }
+    html.push_str("<h2>Recent runs</h2>\n");
+    html.push_str(&table(
+        &["Run", "Started", "Window", "Outcome", "Overrides in place"],
+        recent,
+    ));
+    html.push_str("<h2>Shifts per person</h2>\n");
+    html.push_str(&table(&["Email", "Shifts", "Weekend", "Holiday"], counts));
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::tests::test_entity;
+
+    #[test]
+    fn test_render_page() {
+        let days = ["2022-08-27T03:00:00+08:00", "2022-08-29T03:00:00+08:00"];
+        let rotation = vec![
+            test_entity("b@x.com", days[1], &[]),
+            test_entity("<a>@x.com", days[0], &[]),
+        ];
+        let run = |id, schedule_id: &str| StoredRun {
+            id,
+            started_at: "2022-08-28T10:00:00+08:00".to_string(),
+            schedule_id: schedule_id.to_string(),
+            window_start: "2022-08-29T00:00:00+08:00".to_string(),
+            window_end: "2022-09-05T00:00:00+08:00".to_string(),
+            seed: 1,
+            outcome: "applied".to_string(),
+            active_overrides: 0,
+        };
+        let html = render_page(
+            &SchedulePage {
+                schedule_id: "P1",
+                run_id: 4,
+                rotation: &rotation,
+                overrides: &[],
+                holidays: &BTreeSet::new(),
+            },
+            &[run(4, "P1"), run(3, "P2")],
+        );
+        assert!(html.contains("last by run 4 of schedule P1"));
+        assert!(html.contains("No overrides were needed"));
+        // the rotation is in order of start, and saturday is a weekend shift
+        let a = html.find("<td>&lt;a&gt;@x.com</td>").unwrap();
+        assert!(a < html.find("<td>b@x.com</td>").unwrap());
+        assert!(html.contains("<td>&lt;a&gt;@x.com</td><td>1</td><td>1</td><td>0</td>"));
+        assert!(html.contains("<td>4</td>"));
+        assert!(!html.contains("<td>3</td>"));
+    }
+}
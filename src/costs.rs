@@ -0,0 +1,80 @@
+use anyhow::{Context, Result as AnyhowResult};
+use chrono::{DateTime, FixedOffset};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+/// One cell of the cost matrix: the cost of `email` holding the slot starting at `slot`
+#[derive(Deserialize, Debug)]
+struct CostEntry {
+    email: String,
+    /// rfc3339 start time of the slot, e.g. 2022-08-29T03:00:00+08:00
+    slot: String,
+    cost: f64,
+}
+
+/// Slot costs per person, keyed by email and then slot start time
+pub type CostMatrix = HashMap<String, BTreeMap<DateTime<FixedOffset>, f64>>;
+
+/// Load a person x slot cost matrix from a .json file (a list of {email, slot, cost} objects) or
+/// from a csv file with an email,slot,cost header
+pub fn load_cost_matrix(path: &Path) -> AnyhowResult<CostMatrix> {
+    let contents = fs::read_to_string(path)
+        .context(format!("Failed to read cost matrix {}", path.display()))?;
+    let entries = match path.extension().and_then(|x| x.to_str()) {
+        Some("json") => serde_json::from_str(&contents).context("Failed to parse json")?,
+        _ => parse_csv(&contents)?,
+    };
+    to_matrix(entries).context(format!("Failed to parse cost matrix {}", path.display()))
+}
+
+fn parse_csv(contents: &str) -> AnyhowResult<Vec<CostEntry>> {
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(contents.as_bytes())
+        .deserialize()
+        .collect::<Result<Vec<CostEntry>, csv::Error>>()
+        .context("Failed to parse csv")
+}
+
+fn to_matrix(entries: Vec<CostEntry>) -> AnyhowResult<CostMatrix> {
+    let mut matrix = CostMatrix::new();
+    for entry in entries {
+        let slot = DateTime::parse_from_rfc3339(&entry.slot)
+            .context(format!("Invalid slot {} for {}", entry.slot, entry.email))?;
+        *matrix
+            .entry(entry.email)
+            .or_default()
+            .entry(slot)
+            .or_default() += entry.cost;
+    }
+    Ok(matrix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cost_matrix() -> AnyhowResult<()> {
+        let from_csv = to_matrix(parse_csv(
+            "email, slot, cost
+            a@x.com, 2022-08-29T03:00:00+08:00, 2.5
+            b@x.com, 2022-08-29T03:00:00+08:00, -1",
+        )?)?;
+        let from_json = to_matrix(serde_json::from_str(
+            r#"[
+                {"email": "a@x.com", "slot": "2022-08-29T03:00:00+08:00", "cost": 2.5},
+                {"email": "b@x.com", "slot": "2022-08-29T03:00:00+08:00", "cost": -1}
+            ]"#,
+        )?)?;
+        let slot = DateTime::parse_from_rfc3339("2022-08-29T03:00:00+08:00")?;
+        for matrix in [from_csv, from_json] {
+            assert_eq!(matrix["a@x.com"][&slot], 2.5);
+            assert_eq!(matrix["b@x.com"][&slot], -1.0);
+        }
+        assert!(to_matrix(parse_csv("email,slot,cost\na@x.com,monday,1")?).is_err());
+        Ok(())
+    }
+}
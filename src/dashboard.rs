@@ -0,0 +1,212 @@
+use crate::solver::{FinalEntity, FinalOverride};
+use crate::watch::find_conflicts;
+use actix_web::dev::Server;
+use actix_web::web::Data;
+use actix_web::{get, post, App, HttpResponse, HttpServer};
+use anyhow::{Context, Result as AnyhowResult};
+use std::collections::BTreeMap;
+use tokio::sync::{mpsc, oneshot};
+
+/// What the reviewer decided on the dashboard. The reply is shown to them once the decision was
+/// carried out
+pub struct Decision {
+    pub apply: bool,
+    pub reply: oneshot::Sender<String>,
+}
+
+struct DashboardState {
+    page: String,
+    sender: mpsc::Sender<Decision>,
+}
+
+/// Everything the dashboard shows about a plan
+pub struct PlanReview<'a> {
+    pub schedule_id: &'a str,
+    pub run_id: i64,
+    pub original: &'a [FinalEntity],
+    pub rescheduled: &'a [FinalEntity],
+    pub overrides: &'a [FinalOverride],
+}
+
+/// `value` as html text, also safe inside a quoted attribute
+pub fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The schedule grid with every slot's holder before and after the plan and the conflicts
+/// found, the overrides to apply, and Apply and Discard buttons
+pub fn render_review(review: &PlanReview) -> String {
+    let conflicts: BTreeMap<_, _> = find_conflicts(review.original)
+        .into_iter()
+        .map(|x| ((x.email.clone(), x.start), x))
+        .collect();
+    let holders_after: BTreeMap<_, _> = review
+        .rescheduled
+        .iter()
+        .map(|x| (x.pd_schedule.start, x.pd_schedule.email.as_str()))
+        .collect();
+    let mut slots: Vec<&FinalEntity> = review.original.iter().collect();
+    slots.sort_by_key(|x| x.pd_schedule.start);
+
+    let mut grid = String::new();
+    for entity in slots {
+        let slot = &entity.pd_schedule;
+        let after = holders_after.get(&slot.start).copied().unwrap_or("");
+        let conflict = conflicts.get(&(slot.email.clone(), slot.start));
+        let class = if conflict.is_some() {
+            " class=\"conflict\""
+        } else if after != slot.email {
+            " class=\"changed\""
+        } else {
+            ""
+        };
+        grid.push_str(&format!(
+            "<tr{}><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+            class,
+            slot.start.format("%a %d %b %H:%M"),
+            slot.end.format("%a %d %b %H:%M"),
+            escape(&slot.email),
+            escape(after),
+            escape(&match conflict {
+                Some(x) if x.soft => format!("soft conflict: {}", x.reasons.join(", ")),
+                Some(x) => x.reasons.join(", "),
+                None => String::new(),
+            })
+        ));
+    }
+    let mut overrides = String::new();
+    for entry in review.overrides {
+        overrides.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+            escape(&entry.start_time_iso),
+            escape(&entry.end_time_iso),
+            escape(&entry.original_assignee),
+            escape(&entry.final_override)
+        ));
+    }
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Plan for schedule {schedule}</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; }}
+table {{ border-collapse: collapse; margin-bottom: 2em; }}
+td, th {{ border: 1px solid #ccc; padding: 0.3em 0.8em; text-align: left; }}
+tr.conflict {{ background: #fdd; }}
+tr.changed {{ background: #dfd; }}
+</style>
+</head>
+<body>
+<h1>Plan for schedule {schedule} (run {run_id})</h1>
+<h2>Schedule</h2>
+<table>
+<tr><th>Start</th><th>End</th><th>Oncall now</th><th>Oncall after the plan</th><th>Conflict</th></tr>
+{grid}</table>
+<h2>Overrides</h2>
+<table>
+<tr><th>Start</th><th>End</th><th>From</th><th>To</th></tr>
+{overrides}</table>
+<form method="post" action="/apply" style="display: inline"><button type="submit">Apply</button></form>
+<form method="post" action="/discard" style="display: inline"><button type="submit">Discard</button></form>
+</body>
+</html>
+"#,
+        schedule = escape(review.schedule_id),
+        run_id = review.run_id,
+        grid = grid,
+        overrides = overrides,
+    )
+}
+
+#[get("/")]
+async fn review_page(state: Data<DashboardState>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(state.page.clone())
+}
+
+#[post("/apply")]
+async fn apply_plan(state: Data<DashboardState>) -> HttpResponse {
+    decide(&state, true).await
+}
+
+#[post("/discard")]
+async fn discard_plan(state: Data<DashboardState>) -> HttpResponse {
+    decide(&state, false).await
+}
+
+async fn decide(state: &DashboardState, apply: bool) -> HttpResponse {
+    let (reply, result) = oneshot::channel();
+    if state.sender.send(Decision { apply, reply }).await.is_err() {
+        return HttpResponse::Conflict().body("This plan was already applied or discarded");
+    }
+    let message = result
+        .await
+        .unwrap_or_else(|_| "The plan was dropped before it was carried out".to_string());
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(format!(
+            "<!DOCTYPE html><html><body><p>{}</p></body></html>",
+            escape(&message)
+        ))
+}
+
+/// Serve `page` on localhost until a decision comes in on the returned channel
+pub fn start_dashboard(
+    port: u16,
+    page: String,
+) -> AnyhowResult<(Server, mpsc::Receiver<Decision>)> {
+    let (sender, receiver) = mpsc::channel(1);
+    let state = Data::new(DashboardState { page, sender });
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(state.clone())
+            .service(review_page)
+            .service(apply_plan)
+            .service(discard_plan)
+    })
+    .workers(1)
+    .shutdown_timeout(5)
+    .bind(("127.0.0.1", port))
+    .context(format!("Failed to serve the dashboard on port {}", port))?
+    .run();
+    Ok((server, receiver))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::tests::test_entity;
+
+    #[test]
+    fn test_render_review() {
+        let days = ["2022-08-29T03:00:00+08:00", "2022-08-30T03:00:00+08:00"];
+        let original = vec![
+            test_entity("<a>@x.com", days[0], &days[1..]),
+            test_entity("b@x.com", days[1], &days),
+        ];
+        let mut rescheduled = original.clone();
+        rescheduled[0].pd_schedule.email = "b@x.com".to_string();
+        rescheduled[1].pd_schedule.email = "<a>@x.com".to_string();
+        let page = render_review(&PlanReview {
+            schedule_id: "P1",
+            run_id: 3,
+            original: &original,
+            rescheduled: &rescheduled,
+            overrides: &[],
+        });
+        assert!(page.contains("(run 3)"));
+        assert!(page.contains("&lt;a&gt;@x.com"));
+        assert!(!page.contains("<a>@x.com"));
+        assert_eq!(page.matches("class=\"conflict\"").count(), 1);
+        assert_eq!(page.matches("class=\"changed\"").count(), 1);
+        assert!(page.contains("action=\"/apply\""));
+    }
+}
@@ -0,0 +1,248 @@
+use crate::config::{Config, SmtpConfig};
+use crate::solver::FinalOverride;
+use crate::templates::{Templates, EMAIL};
+use anyhow::{anyhow, Context, Result as AnyhowResult};
+use chrono::{DateTime, FixedOffset, Utc};
+use chrono_tz::Tz;
+use lettre::message::header::ContentType;
+use lettre::message::{Attachment, MultiPart, SinglePart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+type Range = (DateTime<FixedOffset>, DateTime<FixedOffset>);
+
+/// The slots a person takes over and hands off through the overrides
+#[derive(Debug, Default, PartialEq)]
+pub struct ShiftChange {
+    pub email: String,
+    pub gained: Vec<Range>,
+    pub lost: Vec<Range>,
+}
+
+pub fn shift_changes(overrides: &[FinalOverride]) -> AnyhowResult<Vec<ShiftChange>> {
+    let mut changes: BTreeMap<String, ShiftChange> = BTreeMap::new();
+    for entry in overrides {
+        let range = (
+            DateTime::parse_from_rfc3339(&entry.start_time_iso)?,
+            DateTime::parse_from_rfc3339(&entry.end_time_iso)?,
+        );
+        for (email, gained) in [
+            (&entry.final_override, true),
+            (&entry.original_assignee, false),
+        ] {
+            let change = changes.entry(email.clone()).or_insert_with(|| ShiftChange {
+                email: email.clone(),
+                ..Default::default()
+            });
+            if gained {
+                change.gained.push(range);
+            } else {
+                change.lost.push(range);
+            }
+        }
+    }
+    Ok(changes.into_values().collect())
+}
+
+fn describe(range: &Range, timezone: Tz) -> String {
+    format!(
+        "{} to {} ({})",
+        range.0.with_timezone(&timezone).format("%a %d %b %Y %H:%M"),
+        range.1.with_timezone(&timezone).format("%a %d %b %Y %H:%M"),
+        timezone.name()
+    )
+}
+
+/// What the email template is rendered from. Times are worded as in the built-in email, in the
+/// person's timezone
+#[derive(Serialize)]
+struct EmailReport<'a> {
+    schedule_id: &'a str,
+    email: &'a str,
+    timezone: &'a str,
+    gained: Vec<String>,
+    lost: Vec<String>,
+}
+
+fn render_body(
+    change: &ShiftChange,
+    schedule_id: &str,
+    timezone: Tz,
+    templates: &Templates,
+) -> AnyhowResult<String> {
+    let describe_all = |ranges: &[Range]| ranges.iter().map(|x| describe(x, timezone)).collect();
+    let report = EmailReport {
+        schedule_id,
+        email: &change.email,
+        timezone: timezone.name(),
+        gained: describe_all(&change.gained),
+        lost: describe_all(&change.lost),
+    };
+    templates
+        .render(EMAIL, &report)
+        .unwrap_or_else(|| Ok(default_body(change, schedule_id, timezone)))
+}
+
+fn default_body(change: &ShiftChange, schedule_id: &str, timezone: Tz) -> String {
+    let mut body = format!(
+        "Hi,\n\nYour oncall shifts on pagerduty schedule {} have changed.\n",
+        schedule_id
+    );
+    if !change.gained.is_empty() {
+        body.push_str("\nYou are now oncall:\n");
+        for range in &change.gained {
+            body.push_str(&format!("  {}\n", describe(range, timezone)));
+        }
+    }
+    if !change.lost.is_empty() {
+        body.push_str("\nYou are no longer oncall:\n");
+        for range in &change.lost {
+            body.push_str(&format!("  {}\n", describe(range, timezone)));
+        }
+    }
+    if !change.gained.is_empty() {
+        body.push_str("\nThe attached invite adds your new shifts to your calendar.\n");
+    }
+    body
+}
+
+/// An iCalendar file with an event for each gained slot
+fn render_invite(change: &ShiftChange, schedule_id: &str) -> String {
+    let format_time = |time: &DateTime<FixedOffset>| {
+        time.with_timezone(&Utc)
+            .format("%Y%m%dT%H%M%SZ")
+            .to_string()
+    };
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        format!(
+            "PRODID:-//{}//{}//EN",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION")
+        ),
+        "METHOD:PUBLISH".to_string(),
+    ];
+    for (start, end) in &change.gained {
+        lines.extend([
+            "BEGIN:VEVENT".to_string(),
+            format!(
+                "UID:{}-{}-{}@{}",
+                schedule_id,
+                start.timestamp(),
+                change.email,
+                env!("CARGO_PKG_NAME")
+            ),
+            format!("DTSTAMP:{}", format_time(&Utc::now().into())),
+            format!("DTSTART:{}", format_time(start)),
+            format!("DTEND:{}", format_time(end)),
+            format!("SUMMARY:Oncall ({})", schedule_id),
+            "END:VEVENT".to_string(),
+        ]);
+    }
+    lines.push("END:VCALENDAR".to_string());
+    lines.join("\r\n") + "\r\n"
+}
+
+/// Emails people whose shifts changed, through the SMTP relay of the config file
+pub struct EmailNotifier<'a> {
+    pub smtp: &'a SmtpConfig,
+    /// from SMTP_PASSWORD
+    pub password: Option<String>,
+    /// the timezone of each person
+    pub config: &'a Config,
+    pub templates: &'a Templates,
+}
+
+impl EmailNotifier<'_> {
+    /// Email everyone affected by the overrides, returning how many were sent
+    pub async fn email_affected(
+        &self,
+        schedule_id: &str,
+        overrides: &[FinalOverride],
+    ) -> AnyhowResult<usize> {
+        let mut transport = if self.smtp.starttls {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&self.smtp.host)
+                .context(format!("Invalid smtp relay {}", self.smtp.host))?
+        } else {
+            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&self.smtp.host)
+        }
+        .port(self.smtp.port);
+        if let Some(username) = &self.smtp.username {
+            transport = transport.credentials(Credentials::new(
+                username.clone(),
+                self.password.clone().unwrap_or_default(),
+            ));
+        }
+        let transport = transport.build();
+
+        let changes = shift_changes(overrides)?;
+        for change in &changes {
+            let timezone = self.config.user_timezone(&change.email)?;
+            let body = render_body(change, schedule_id, timezone, self.templates)?;
+            let mut content = MultiPart::mixed().singlepart(SinglePart::plain(body));
+            if !change.gained.is_empty() {
+                content = content.singlepart(Attachment::new("oncall.ics".to_string()).body(
+                    render_invite(change, schedule_id),
+                    ContentType::parse("text/calendar; method=PUBLISH; charset=utf-8").unwrap(),
+                ));
+            }
+            let message = Message::builder()
+                .from(
+                    self.smtp
+                        .from
+                        .parse()
+                        .map_err(|e| anyhow!("Invalid sender {}: {}", self.smtp.from, e))?,
+                )
+                .to(change
+                    .email
+                    .parse()
+                    .map_err(|e| anyhow!("Invalid address {}: {}", change.email, e))?)
+                .subject(format!("Your oncall shifts on {} changed", schedule_id))
+                .multipart(content)
+                .context("Failed to build email")?;
+            transport
+                .send(message)
+                .await
+                .context(format!("Failed to email {}", change.email))?;
+        }
+        Ok(changes.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TemplateFiles;
+
+    #[test]
+    fn test_shift_change_email() -> AnyhowResult<()> {
+        let overrides = vec![FinalOverride {
+            original_slot: "".to_string(),
+            original_assignee: "a@x.com".to_string(),
+            final_override: "b@x.com".to_string(),
+            start_time_iso: "2022-08-29T03:00:00+08:00".to_string(),
+            end_time_iso: "2022-08-29T15:00:00+08:00".to_string(),
+            pd_user_id: "PB".to_string(),
+        }];
+        let changes = shift_changes(&overrides)?;
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].email, "a@x.com");
+        assert!(changes[0].gained.is_empty() && changes[0].lost.len() == 1);
+
+        let templates = Templates::load(&TemplateFiles::default())?;
+        let body = render_body(&changes[1], "P1", chrono_tz::Europe::Berlin, &templates)?;
+        assert!(body.contains(
+            "You are now oncall:\n  Sun 28 Aug 2022 21:00 to Mon 29 Aug 2022 09:00 (Europe/Berlin)"
+        ));
+        assert!(!body.contains("no longer"));
+
+        let invite = render_invite(&changes[1], "P1");
+        assert!(invite.contains("DTSTART:20220828T190000Z\r\n"));
+        assert!(invite.contains("UID:P1-1661713200-b@x.com@gcal-pagerduty\r\n"));
+        assert_eq!(invite.matches("BEGIN:VEVENT").count(), 1);
+        Ok(())
+    }
+}
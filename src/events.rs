@@ -0,0 +1,110 @@
+use chrono::Utc;
+#[cfg(feature = "cli")]
+use clap::ValueEnum;
+use serde::Serialize;
+use std::sync::OnceLock;
+
+/// How the tool reports what it does
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    /// tables and messages for people, on stdout
+    Text,
+    /// one json Event per line on stdout, with the human output moved to stderr
+    Json,
+}
+
+static FORMAT: OnceLock<LogFormat> = OnceLock::new();
+
+pub fn set_log_format(format: LogFormat) {
+    let _ = FORMAT.set(format);
+}
+
+pub fn log_format() -> LogFormat {
+    *FORMAT.get().unwrap_or(&LogFormat::Text)
+}
+
+/// Human output: println, or eprintln when stdout is taken by json events
+macro_rules! say {
+    ($($arg:tt)*) => {
+        if $crate::events::log_format() == $crate::events::LogFormat::Json {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+
+/// Something that happened during a run, for automation wrapping the tool. Only emitted with
+/// `--log-format json`
+#[derive(Serialize, Debug, Default)]
+pub struct Event {
+    pub timestamp: String,
+    /// step of the run, e.g. "conflict", "plan" or "apply"
+    pub phase: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// rfc3339 start and end of the slot, separated by a slash
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slot: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub decision: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Event {
+    pub fn new(phase: &'static str) -> Event {
+        Event {
+            timestamp: Utc::now().to_rfc3339(),
+            phase,
+            ..Default::default()
+        }
+    }
+
+    pub fn user(mut self, user: &str) -> Event {
+        self.user = Some(user.to_string());
+        self
+    }
+
+    pub fn slot(mut self, start: &str, end: &str) -> Event {
+        self.slot = Some(format!("{}/{}", start, end));
+        self
+    }
+
+    pub fn decision(mut self, decision: impl Into<String>) -> Event {
+        self.decision = Some(decision.into());
+        self
+    }
+
+    pub fn error(mut self, error: &anyhow::Error) -> Event {
+        self.error = Some(format!("{:#}", error));
+        self
+    }
+
+    pub fn emit(self) {
+        if log_format() == LogFormat::Json {
+            println!("{}", serde_json::to_string(&self).unwrap());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_json() {
+        let event = Event::new("plan")
+            .user("b@x.com")
+            .slot("2022-08-29T03:00:00+08:00", "2022-08-29T15:00:00+08:00")
+            .decision("override a@x.com");
+        let value = serde_json::to_value(&event).unwrap();
+        assert_eq!(value["phase"], "plan");
+        assert_eq!(
+            value["slot"],
+            "2022-08-29T03:00:00+08:00/2022-08-29T15:00:00+08:00"
+        );
+        assert!(value.get("error").is_none());
+    }
+}
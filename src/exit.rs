@@ -0,0 +1,84 @@
+use crate::calendar::CalendarError;
+use crate::gcal::AuthError;
+use crate::provider::{error_kind, ProviderErrorKind};
+use thiserror::Error;
+
+/// Nothing to do, or everything done
+pub const SUCCESS: u8 = 0;
+/// Any failure without a code of its own
+pub const FAILURE: u8 = 1;
+/// `--check` found conflicts
+pub const CONFLICTS_FOUND: u8 = 10;
+/// No plan resolves the conflicts
+pub const UNRESOLVABLE: u8 = 20;
+/// Pagerduty, opsgenie, grafana oncall, splunk on-call, google or a CalDAV server rejected the credentials
+pub const AUTH_FAILED: u8 = 30;
+/// Some overrides were applied and others rejected
+pub const PARTIALLY_APPLIED: u8 = 40;
+/// Someone oncall in the window couldn't be looked up, without --allow-skipped-users
+pub const USERS_SKIPPED: u8 = 50;
+
+/// Results of a run that get their own exit code, for wrappers and cron jobs to branch on
+#[derive(Error, Debug)]
+pub enum Outcome {
+    #[error("Found {0} conflicts")]
+    ConflictsFound(usize),
+    #[error("The schedule can't be resolved")]
+    Unresolvable,
+    #[error("Some overrides were rejected, see the warnings above")]
+    PartiallyApplied,
+    #[error(
+        "{0} slots are held by users who couldn't be looked up, see the skipped users above. \
+        Fix their accounts, or pass --allow-skipped-users to plan without their slots"
+    )]
+    UsersSkipped(usize),
+}
+
+/// The exit code documented in the README for an error, looking through its context
+pub fn exit_code(error: &anyhow::Error) -> u8 {
+    match error.downcast_ref::<Outcome>() {
+        Some(Outcome::ConflictsFound(_)) => return CONFLICTS_FOUND,
+        Some(Outcome::Unresolvable) => return UNRESOLVABLE,
+        Some(Outcome::PartiallyApplied) => return PARTIALLY_APPLIED,
+        Some(Outcome::UsersSkipped(_)) => return USERS_SKIPPED,
+        None => {}
+    }
+    if error_kind(error) == Some(ProviderErrorKind::Unauthorized)
+        || matches!(error.downcast_ref(), Some(AuthError::Unauthorized))
+        || matches!(
+            error.downcast_ref(),
+            Some(CalendarError::Unauthorized { .. })
+        )
+    {
+        AUTH_FAILED
+    } else {
+        FAILURE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::ProviderError;
+    use anyhow::{anyhow, Context};
+
+    #[test]
+    fn test_exit_code() {
+        let error = Err::<(), _>(ProviderError {
+            provider: "pagerduty",
+            kind: ProviderErrorKind::Unauthorized,
+        })
+        .context("Failed to get pd schedule")
+        .unwrap_err();
+        assert_eq!(exit_code(&error), AUTH_FAILED);
+
+        let error = anyhow!("No plan found").context(Outcome::Unresolvable);
+        assert_eq!(exit_code(&error), UNRESOLVABLE);
+        let error = anyhow::Error::from(Outcome::PartiallyApplied).context("Failed to apply");
+        assert_eq!(exit_code(&error), PARTIALLY_APPLIED);
+        let error = anyhow::Error::from(Outcome::UsersSkipped(1));
+        assert_eq!(exit_code(&error), USERS_SKIPPED);
+
+        assert_eq!(exit_code(&anyhow!("Unrecognised input x")), FAILURE);
+    }
+}
@@ -1,7 +1,10 @@
-use crate::pagerduty::FinalPagerDutySchedule;
+use crate::cache::{Cache, Namespace};
+use crate::calendar::{local_midnight, AvailabilityProvider, CalendarError, CalendarEvent};
+use crate::http::HttpClient;
 use crate::webserver::{start_webserver, Callback};
 use anyhow::{anyhow, Context, Result as AnyhowResult};
-use chrono::{DateTime, Duration, FixedOffset, NaiveDateTime};
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate};
+use chrono_tz::Tz;
 use oauth2::basic::BasicClient;
 use oauth2::reqwest::async_http_client;
 use oauth2::{
@@ -9,138 +12,206 @@ use oauth2::{
     Scope, TokenResponse, TokenUrl,
 };
 use reqwest::Url;
-use reqwest::{self, Client};
 use serde::Deserialize;
 use std::process::Command;
+use std::time::Duration as StdDuration;
+use thiserror::Error;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tracing::instrument;
 
 #[derive(Deserialize, Debug)]
 struct CalendarEventResponse {
     items: Vec<CalendarEvent>,
 }
 
-#[derive(Deserialize, Debug)]
-pub struct CalendarEvent {
-    pub visibility: Option<String>,
-    pub summary: Option<String>,
-    // creator: Option<EventCreator>,
-    pub start: Option<TimeWrapper>,
-    pub end: Option<TimeWrapper>,
-    #[serde(rename = "eventType")]
-    pub event_type: Option<String>,
-    // extra metadata after joining
-    pub pagerduty: Option<FinalPagerDutySchedule>,
+/// Body of an error of the google apis
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct ErrorResponse {
+    error: ErrorDetails,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct ErrorDetails {
+    errors: Vec<ErrorReason>,
 }
 
 #[derive(Deserialize, Debug)]
-pub struct TimeWrapper {
-    #[serde(rename = "date")]
-    pub date_string: Option<String>,
-    #[serde(rename = "dateTime")]
-    pub date_time_string: Option<String>,
-    // #[serde(rename = "timeZone")]
-    // timezone: Option<String>,
+struct ErrorReason {
+    reason: String,
 }
 
+/// Whether a 403 from google is over a quota rather than a calendar that isn't shared. Those pass,
+/// so they are retried
+fn is_quota_error(body: &str) -> bool {
+    let response: ErrorResponse = serde_json::from_str(body).unwrap_or_default();
+    response
+        .error
+        .errors
+        .iter()
+        .any(|x| x.reason == "rateLimitExceeded" || x.reason == "userRateLimitExceeded")
+}
+
+/// Retries of a calendar read over the google quota, waiting twice as long each time
+const QUOTA_RETRIES: u32 = 4;
+
+/// Wait before the first retry over the google quota
+const QUOTA_BACKOFF: StdDuration = StdDuration::from_secs(1);
+
+/// The window from midnight of `start_date` to midnight `duration_days` later in `timezone`. The
+/// days are calendar days, so a window over a DST change is an hour longer or shorter
+#[allow(clippy::needless_return)]
 pub fn get_start_end_time(
-    start_date: &str,
+    start_date: NaiveDate,
     duration_days: i64,
+    timezone: Tz,
 ) -> (DateTime<FixedOffset>, DateTime<FixedOffset>) {
-    let sgt_timezone = FixedOffset::east(8 * 60 * 60);
-    let start_time =
-        NaiveDateTime::parse_from_str(&format!("{} 00:00", start_date), "%Y-%m-%d %H:%M").unwrap();
-    let start_time_local = DateTime::<FixedOffset>::from_local(start_time, sgt_timezone);
-
-    let end_time_local = start_time_local
-        .checked_add_signed(Duration::days(duration_days))
-        .unwrap();
+    let start_time_local = local_midnight(start_date, timezone);
+    let end_time_local = local_midnight(start_date + Duration::days(duration_days), timezone);
 
     return (start_time_local, end_time_local);
 }
 
-pub async fn check_token_validity(client: &Client, token: &str) -> AnyhowResult<()> {
-    let url = "https://www.googleapis.com/calendar/v3/users/me/calendarList";
+/// Root of the google calendar api
+pub const GOOGLE_API_URL: &str = "https://www.googleapis.com";
+
+/// Scope of the google token needed to read calendars, always asked for
+pub const CALENDAR_SCOPE: &str = "https://www.googleapis.com/auth/calendar.readonly";
+
+/// Errors of the google oauth token
+#[derive(Error, Debug)]
+pub enum AuthError {
+    /// the token expired or was revoked, a new one is needed
+    #[error("Unauthorised")]
+    Unauthorized,
+}
+
+#[instrument(skip_all)]
+pub async fn check_token_validity(
+    client: &HttpClient,
+    base_url: &str,
+    token: &str,
+) -> AnyhowResult<()> {
+    let url = format!("{}/calendar/v3/users/me/calendarList", base_url);
     let request = client
         .get(url)
         .header("Authorization", format!("Bearer {}", token));
 
-    let response = request.send().await;
+    let response = client.send(request).await;
 
     match response {
-        Ok(inside) if inside.status() == 401 => Err(anyhow!("Unauthorised")),
+        Ok(inside) if inside.status() == 401 => Err(AuthError::Unauthorized.into()),
         Ok(_) => Ok(()),
-        Err(e) => Err(anyhow!(e).context("Error when making request to google apis")),
+        Err(e) => Err(e.context("Error when making request to google apis")),
     }
 }
 
-pub async fn get_user_calender(
-    client: &Client,
-    pd_user: FinalPagerDutySchedule,
-    token: &str,
-    start_time_local: DateTime<FixedOffset>,
-    end_time_local: DateTime<FixedOffset>,
-) -> AnyhowResult<(FinalPagerDutySchedule, Vec<CalendarEvent>)> {
-    let event_url = format!(
-        "https://www.googleapis.com/calendar/v3/calendars/{}/events",
-        pd_user.email
-    );
-
-    let params = vec![
-        ("timeMin", start_time_local.to_rfc3339()),
-        ("timeMax", end_time_local.to_rfc3339()),
-        ("timeZone", "Asia/Singapore".to_string()),
-    ];
-    let url = Url::parse_with_params(&event_url, params).unwrap();
+/// Google calendar, read with an oauth token from get_oauth_token
+pub struct GoogleCalendar<'a> {
+    pub client: &'a HttpClient,
+    /// GOOGLE_API_URL, unless --base-url points elsewhere
+    pub base_url: &'a str,
+    pub token: &'a str,
+    pub cache: &'a Cache,
+}
 
-    let request = client
-        .get(url)
-        .header("Authorization", format!("Bearer {}", token));
+impl AvailabilityProvider for GoogleCalendar<'_> {
+    #[instrument(skip_all, fields(%email, start = %start_time_local, end = %end_time_local))]
+    async fn fetch_events(
+        &self,
+        email: &str,
+        start_time_local: DateTime<FixedOffset>,
+        end_time_local: DateTime<FixedOffset>,
+    ) -> AnyhowResult<Vec<CalendarEvent>> {
+        let event_url = format!("{}/calendar/v3/calendars/{}/events", self.base_url, email);
+
+        // google returns the events overlapping the range, long leave starting before it included.
+        // Recurring events are expanded, so each occurrence has its own times like with CalDAV
+        let params = vec![
+            ("timeMin", start_time_local.to_rfc3339()),
+            ("timeMax", end_time_local.to_rfc3339()),
+            ("singleEvents", "true".to_string()),
+            ("timeZone", "Asia/Singapore".to_string()),
+        ];
+        let url = Url::parse_with_params(&event_url, params).unwrap();
+        let result = match self.cache.get(Namespace::Calendars, url.as_str()) {
+            Some(value) => value,
+            None => self.fetch_events_text(email, url).await?,
+        };
+
+        let parsed: CalendarEventResponse =
+            serde_json::from_str(&result).context("Failed to parse gcal api response as json")?;
 
-    let result = request
-        .send()
-        .await
-        .context("Request to gcal api failed")?
-        .text()
-        .await
-        .context("Failed to convert gcal api request to text")?;
-
-    let parsed: CalendarEventResponse =
-        serde_json::from_str(&result).context("Failed to parse gcal api response as json")?;
-
-    let public_events = parsed.items.into_iter().filter(|x| match &x.visibility {
-        Some(v) if v != "private" => true,
-        _ => false,
-    });
-
-    // let x = pd_user.clone();
-    // if x.email == "jialong.loh@grabtaxi.com" {
-    //     print!("jl: {:?}", &public_events);
-    // }
-
-    let xoncall_calendar_events: Vec<CalendarEvent> = public_events
-        .filter(should_not_be_oncall)
-        .map(|mut x| {
-            x.pagerduty = Some(pd_user.clone());
-            x
-        })
-        .collect();
-    return Ok((pd_user, xoncall_calendar_events));
+        Ok(parsed.items)
+    }
 }
 
-fn should_not_be_oncall(event: &CalendarEvent) -> bool {
-    match &event.summary {
-        Some(value) if value.to_lowercase().contains("xoncall") => true,
-        Some(value) if value.to_lowercase().contains("out of") => true,
-        Some(_) if event.event_type.is_some() => match &event.event_type {
-            Some(event_type) if event_type.to_lowercase() == "outofoffice" => true,
-            _ => false,
-        },
-        // Some(value) if value.to_lowercase().contains("ooo") => true,
-        _ => false,
+impl GoogleCalendar<'_> {
+    /// The events of `email` as google returns them. Reads over the quota are retried with
+    /// backoff, while calendars that aren't shared fail right away
+    async fn fetch_events_text(&self, email: &str, url: Url) -> AnyhowResult<String> {
+        let mut backoff = QUOTA_BACKOFF;
+        let mut retries = 0;
+        loop {
+            let request = self
+                .client
+                .get(url.clone())
+                .header("Authorization", format!("Bearer {}", self.token));
+            let response = self
+                .client
+                .send(request)
+                .await
+                .context("Request to gcal api failed")?;
+            let status = response.status();
+            let result = response
+                .text()
+                .await
+                .context("Failed to convert gcal api request to text")?;
+            let over_quota = match status.as_u16() {
+                200..=299 => {
+                    self.cache.put(Namespace::Calendars, url.as_str(), &result);
+                    return Ok(result);
+                }
+                401 => return Err(AuthError::Unauthorized.into()),
+                403 => is_quota_error(&result),
+                404 => false,
+                429 => true,
+                _ => {
+                    return Err(anyhow!(
+                        "Unexpected status {} from the gcal api: {}",
+                        status,
+                        result
+                    ))
+                }
+            };
+            if !over_quota {
+                return Err(CalendarError::Forbidden {
+                    email: email.to_string(),
+                }
+                .into());
+            }
+            if retries == QUOTA_RETRIES {
+                return Err(CalendarError::RateLimited.into());
+            }
+            say!(
+                "Warning. Over the google calendar quota reading the calendar of {}, retrying in {}s",
+                email,
+                backoff.as_secs()
+            );
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+            retries += 1;
+        }
     }
 }
 
-pub async fn get_oauth_token(client_id: &str, secret: &str) -> AnyhowResult<String> {
+/// A google token with `scopes`, from signing in with the browser
+pub async fn get_oauth_token(
+    client_id: &str,
+    secret: &str,
+    scopes: &[&str],
+) -> AnyhowResult<String> {
     let auth_url = "https://accounts.google.com/o/oauth2/auth".to_string();
     let token_url = "https://oauth2.googleapis.com/token".to_string();
     // let redirect_url = "urn:ietf:wg:oauth:2.0:oob".to_string();
@@ -159,9 +230,7 @@ pub async fn get_oauth_token(client_id: &str, secret: &str) -> AnyhowResult<Stri
 
     let (auth_url, _csrf_token) = oidcclient
         .authorize_url(CsrfToken::new_random)
-        .add_scope(Scope::new(
-            "https://www.googleapis.com/auth/calendar.readonly".to_string(),
-        ))
+        .add_scopes(scopes.iter().map(|x| Scope::new(x.to_string())))
         .set_pkce_challenge(pkce_challenge)
         .url();
 
@@ -171,14 +240,14 @@ pub async fn get_oauth_token(client_id: &str, secret: &str) -> AnyhowResult<Stri
     let webserver_to_start = start_webserver(sender);
     let mut handle = tokio::spawn(webserver_to_start.await);
 
-    println!("Attempting to open oauth url with browser: {}", auth_url);
+    say!("Attempting to open oauth url with browser: {}", auth_url);
     let _ = Command::new("open")
         .arg(auth_url.to_string())
         .output()
         .expect("Failed to open url with browswer");
 
     tokio::select! {
-        _ = &mut handle =>  {return Err(anyhow!("Not ok").context("Failed to complete auth flow"))}
+        _ = &mut handle =>  {Err(anyhow!("Not ok").context("Failed to complete auth flow"))}
         // x = server => {return Err(format!("Web server unexpectedly exited with reason: {:?}", x))}
 
         message = receiver.recv() => {
@@ -195,34 +264,7 @@ pub async fn get_oauth_token(client_id: &str, secret: &str) -> AnyhowResult<Stri
             .access_token()
             .secret()
             .clone();
-            return Ok(token)
+            Ok(token)
         }
-    };
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_should_not_be_oncall() {
-        let ooo = CalendarEvent {
-            visibility: Some("public".to_string()),
-            summary: Some("Out of Office".to_string()),
-            start: None,
-            end: None,
-            pagerduty: None,
-            event_type: None,
-        };
-        assert_eq!(should_not_be_oncall(&ooo), true);
-        let xoncall = CalendarEvent {
-            visibility: Some("public".to_string()),
-            summary: Some("xoncall".to_string()),
-            start: None,
-            end: None,
-            pagerduty: None,
-            event_type: None,
-        };
-        assert_eq!(should_not_be_oncall(&xoncall), true);
     }
 }
@@ -0,0 +1,341 @@
+use crate::cache::{Cache, Namespace};
+use crate::calendar::local_time;
+use crate::http::HttpClient;
+use crate::oncall::{AppliedOverride, FinalPagerDutySchedule, OncallProvider};
+use crate::provider::{check, error_kind, ProviderErrorKind};
+use crate::solver::FinalOverride;
+
+use anyhow::{Context, Result as AnyhowResult};
+use chrono::{DateTime, FixedOffset, Utc};
+use chrono_tz::Tz;
+use reqwest::{RequestBuilder, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::instrument;
+
+/// One page of the final shifts of a schedule
+#[derive(Deserialize, Debug)]
+struct FinalShiftsPage {
+    next: Option<String>,
+    results: Vec<FinalShift>,
+}
+
+#[derive(Deserialize, Debug)]
+struct FinalShift {
+    user_pk: String,
+    user_email: String,
+    shift_start: String,
+    shift_end: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct User {
+    email: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Schedule {
+    /// ids of the shifts making up the schedule. Schedules edited in the web ui have none
+    #[serde(default)]
+    shifts: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct OverrideShift {
+    name: String,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    time_zone: &'static str,
+    /// naive utc time, in the time_zone above
+    start: String,
+    /// seconds
+    duration: i64,
+    users: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CreatedShift {
+    id: String,
+}
+
+/// Grafana OnCall, cloud or self-hosted, accessed with an api token. Overrides are shifts of type
+/// override added to the schedule
+pub struct GrafanaOncall<'a> {
+    pub client: &'a HttpClient,
+    /// the api url shown in the oncall settings, without /api/v1, e.g.
+    /// https://oncall-prod-us-central-0.grafana.net/oncall
+    pub base_url: &'a str,
+    pub api_token: &'a str,
+    pub cache: &'a Cache,
+    /// of the schedule, slots are given the offset it has at their start
+    pub timezone: Tz,
+}
+
+impl OncallProvider for GrafanaOncall<'_> {
+    #[instrument(skip_all, fields(%schedule_id, %start, %end))]
+    async fn fetch_schedule(
+        &self,
+        schedule_id: &str,
+        start: DateTime<FixedOffset>,
+        end: DateTime<FixedOffset>,
+    ) -> AnyhowResult<Vec<FinalPagerDutySchedule>> {
+        say!(
+            "Retrieving grafana oncall schedule from {} to {}",
+            start,
+            end
+        );
+        // the final shifts are asked for by utc day, shifts outside the window are dropped below
+        let mut url = Url::parse_with_params(
+            &format!(
+                "{}/api/v1/schedules/{}/final_shifts",
+                self.base_url, schedule_id
+            ),
+            [
+                (
+                    "start_date",
+                    start.with_timezone(&Utc).date_naive().to_string(),
+                ),
+                ("end_date", end.with_timezone(&Utc).date_naive().to_string()),
+            ],
+        )
+        .context("Failed to parse url")?;
+        let mut entries = Vec::new();
+        loop {
+            let page: FinalShiftsPage = serde_json::from_str(&self.get_cached(&url).await?)
+                .context("Failed to parse json from grafana oncall api response")?;
+            for shift in page.results {
+                let entry = FinalPagerDutySchedule {
+                    pd_user_id: shift.user_pk,
+                    // shifts are in utc, and shown in the timezone of the schedule like the others
+                    start: local_time(
+                        DateTime::parse_from_rfc3339(&shift.shift_start)
+                            .context("Failed to parse shift_start as rfc3339")?,
+                        self.timezone,
+                    ),
+                    end: local_time(
+                        DateTime::parse_from_rfc3339(&shift.shift_end)
+                            .context("Failed to parse shift_end as rfc3339")?,
+                        self.timezone,
+                    ),
+                    email: shift.user_email,
+                };
+                if entry.start < end && entry.end > start {
+                    entries.push(entry);
+                }
+            }
+            match page.next {
+                Some(next) => url = Url::parse(&next).context("Failed to parse the next page")?,
+                None => break,
+            }
+        }
+        entries.sort_by_key(|x| x.start);
+        Ok(entries)
+    }
+
+    #[instrument(skip_all, fields(%schedule_id, overrides = overrides.len()))]
+    async fn apply_overrides(
+        &self,
+        schedule_id: &str,
+        overrides: &[FinalOverride],
+    ) -> AnyhowResult<Vec<AppliedOverride>> {
+        let mut applied = Vec::new();
+        for entry in overrides {
+            match self.create_override(entry).await {
+                Ok(id) => applied.push(AppliedOverride {
+                    id,
+                    start: entry.start_time_iso.clone(),
+                    end: entry.end_time_iso.clone(),
+                    pd_user_id: entry.pd_user_id.clone(),
+                }),
+                Err(e)
+                    if matches!(
+                        error_kind(&e),
+                        Some(ProviderErrorKind::Unauthorized | ProviderErrorKind::RateLimited)
+                    ) =>
+                {
+                    self.remove_all(schedule_id, &applied).await;
+                    return Err(e.context("Failed to override grafana oncall schedule"));
+                }
+                Err(e) => say!(
+                    "Warning. Grafana oncall rejected the override from {} to {}: {:#}",
+                    entry.start_time_iso,
+                    entry.end_time_iso,
+                    e
+                ),
+            }
+        }
+        if applied.is_empty() {
+            return Ok(applied);
+        }
+        // overrides only take effect once they are shifts of the schedule
+        if let Err(e) = self.add_shifts(schedule_id, &applied).await {
+            self.remove_all(schedule_id, &applied).await;
+            return Err(e.context(format!(
+                "Failed to add the overrides to grafana oncall schedule {}",
+                schedule_id
+            )));
+        }
+        Ok(applied)
+    }
+
+    #[instrument(skip(self))]
+    async fn remove_override(&self, _schedule_id: &str, override_id: &str) -> AnyhowResult<()> {
+        // deleting a shift also takes it off its schedules
+        let request = self.authorized(self.client.delete(format!(
+            "{}/api/v1/on_call_shifts/{}",
+            self.base_url, override_id
+        )));
+        let response = self.client.send(request).await?;
+        check("grafana oncall", &response).context(format!(
+            "Failed to delete grafana oncall override {}",
+            override_id
+        ))
+    }
+
+    #[instrument(skip(self), fields(email))]
+    async fn resolve_user(&self, user_id: &str) -> AnyhowResult<String> {
+        let url = format!("{}/api/v1/users/{}", self.base_url, user_id);
+        if let Some(email) = self.cache.get(Namespace::UserEmails, &url) {
+            return Ok(email);
+        }
+        let response = self
+            .client
+            .send(self.authorized(self.client.get(&url)))
+            .await
+            .context("Failed to call grafana oncall api to get user email")?;
+        check("grafana oncall", &response)?;
+        let user: User = response
+            .json()
+            .await
+            .context("Failed to parse the grafana oncall user as json")?;
+        self.cache.put(Namespace::UserEmails, &url, &user.email);
+        tracing::Span::current().record("email", &user.email);
+        Ok(user.email)
+    }
+}
+
+impl GrafanaOncall<'_> {
+    fn authorized(&self, request: RequestBuilder) -> RequestBuilder {
+        request.header("Authorization", self.api_token)
+    }
+
+    /// Whether the api token can read the schedule
+    #[instrument(skip(self))]
+    pub async fn check_schedule(&self, schedule_id: &str) -> AnyhowResult<()> {
+        self.schedule(schedule_id).await.map(|_| ())
+    }
+
+    async fn schedule(&self, schedule_id: &str) -> AnyhowResult<Schedule> {
+        let request = self.authorized(self.client.get(format!(
+            "{}/api/v1/schedules/{}",
+            self.base_url, schedule_id
+        )));
+        let response = self
+            .client
+            .send(request)
+            .await
+            .context("Failed to call grafana oncall api")?;
+        check("grafana oncall", &response)?;
+        response
+            .json()
+            .await
+            .context("Failed to parse the grafana oncall schedule as json")
+    }
+
+    async fn get_cached(&self, url: &Url) -> AnyhowResult<String> {
+        if let Some(value) = self.cache.get(Namespace::Schedules, url.as_str()) {
+            return Ok(value);
+        }
+        let response = self
+            .client
+            .send(self.authorized(self.client.get(url.clone())))
+            .await
+            .context("Failed to call grafana oncall api")?;
+        check("grafana oncall", &response)?;
+        let text = response
+            .text()
+            .await
+            .context("Failed to get text response from grafana oncall api call")?;
+        self.cache.put(Namespace::Schedules, url.as_str(), &text);
+        Ok(text)
+    }
+
+    /// Create a shift of type override for `entry`, returning its id
+    async fn create_override(&self, entry: &FinalOverride) -> AnyhowResult<String> {
+        let start = DateTime::parse_from_rfc3339(&entry.start_time_iso)
+            .context("Failed to parse the override start as rfc3339")?;
+        let end = DateTime::parse_from_rfc3339(&entry.end_time_iso)
+            .context("Failed to parse the override end as rfc3339")?;
+        let body = OverrideShift {
+            name: format!("gcal-pagerduty override {}", entry.start_time_iso),
+            kind: "override",
+            time_zone: "UTC",
+            start: start
+                .with_timezone(&Utc)
+                .format("%Y-%m-%dT%H:%M:%S")
+                .to_string(),
+            duration: (end - start).num_seconds(),
+            users: vec![entry.pd_user_id.clone()],
+        };
+        let request = self
+            .authorized(
+                self.client
+                    .post(format!("{}/api/v1/on_call_shifts/", self.base_url)),
+            )
+            .json(&body);
+        let response = self.client.send(request).await?;
+        check("grafana oncall", &response)?;
+        let created: CreatedShift = response
+            .json()
+            .await
+            .context("Failed to parse the shift created by grafana oncall")?;
+        Ok(created.id)
+    }
+
+    async fn add_shifts(&self, schedule_id: &str, applied: &[AppliedOverride]) -> AnyhowResult<()> {
+        let mut shifts = self.schedule(schedule_id).await?.shifts;
+        shifts.extend(applied.iter().map(|x| x.id.clone()));
+        let request = self
+            .authorized(self.client.put(format!(
+                "{}/api/v1/schedules/{}",
+                self.base_url, schedule_id
+            )))
+            .json(&json!({ "shifts": shifts }));
+        let response = self.client.send(request).await?;
+        check("grafana oncall", &response)?;
+        Ok(())
+    }
+
+    /// Delete overrides created by a failed apply, so no stray shifts are left behind
+    async fn remove_all(&self, schedule_id: &str, applied: &[AppliedOverride]) {
+        for entry in applied {
+            if let Err(e) = self.remove_override(schedule_id, &entry.id).await {
+                say!("Warning. {:#}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_final_shifts() -> AnyhowResult<()> {
+        let page: FinalShiftsPage = serde_json::from_str(
+            r#"{"count": 1, "next": null, "previous": null, "results": [{
+                "user_pk": "UALICE",
+                "user_email": "alice@example.com",
+                "user_username": "alice",
+                "shift_start": "2022-08-28T19:00:00Z",
+                "shift_end": "2022-08-29T19:00:00Z"
+            }]}"#,
+        )?;
+        assert!(page.next.is_none());
+        assert_eq!(page.results[0].user_pk, "UALICE");
+        assert_eq!(page.results[0].user_email, "alice@example.com");
+        let schedule: Schedule = serde_json::from_str(r#"{"id": "S1", "type": "web"}"#)?;
+        assert!(schedule.shifts.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+use crate::config::GrafanaAnnotationsConfig;
+use crate::http::HttpClient;
+use crate::solver::FinalOverride;
+use anyhow::{ensure, Context, Result as AnyhowResult};
+use chrono::DateTime;
+use serde_json::{json, Value};
+use tracing::instrument;
+
+/// Grafana, where applied overrides are annotated so incident reviews can line pages up with who
+/// held the pager. Signs in with the service account token in GRAFANA_API_TOKEN
+pub struct GrafanaAnnotations<'a> {
+    pub client: &'a HttpClient,
+    pub config: &'a GrafanaAnnotationsConfig,
+    pub token: &'a str,
+}
+
+impl GrafanaAnnotations<'_> {
+    /// Annotate the time each of `overrides` covers on `schedule_id`, returning how many were
+    #[instrument(skip(self, overrides))]
+    pub async fn annotate(
+        &self,
+        schedule_id: &str,
+        run_id: i64,
+        overrides: &[&FinalOverride],
+    ) -> AnyhowResult<usize> {
+        let url = format!("{}/api/annotations", self.config.url.trim_end_matches('/'));
+        for entry in overrides {
+            let request = self
+                .client
+                .post(&url)
+                .bearer_auth(self.token)
+                .json(&annotation(self.config, schedule_id, run_id, entry)?);
+            let response = self.client.send(request).await?;
+            let status = response.status();
+            ensure!(
+                status.is_success(),
+                "Unexpected status {} from grafana: {}",
+                status,
+                response.text().await.unwrap_or_default()
+            );
+        }
+        Ok(overrides.len())
+    }
+}
+
+/// A region annotation over the override, in the epoch milliseconds grafana takes
+fn annotation(
+    config: &GrafanaAnnotationsConfig,
+    schedule_id: &str,
+    run_id: i64,
+    entry: &FinalOverride,
+) -> AnyhowResult<Value> {
+    let millis = |value: &str| {
+        DateTime::parse_from_rfc3339(value)
+            .map(|x| x.timestamp_millis())
+            .context(format!("Invalid override time {}", value))
+    };
+    let mut tags = config.tags.clone();
+    tags.push(schedule_id.to_string());
+    let mut annotation = json!({
+        "time": millis(&entry.start_time_iso)?,
+        "timeEnd": millis(&entry.end_time_iso)?,
+        "tags": tags,
+        "text": format!(
+            "{} is oncall on {} instead of {} (gcal-pagerduty run {})",
+            entry.final_override, schedule_id, entry.original_assignee, run_id
+        ),
+    });
+    if let Some(uid) = &config.dashboard_uid {
+        annotation["dashboardUID"] = json!(uid);
+    }
+    if let Some(panel_id) = config.panel_id {
+        annotation["panelId"] = json!(panel_id);
+    }
+    Ok(annotation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_annotation() -> AnyhowResult<()> {
+        let config: GrafanaAnnotationsConfig = toml::from_str(
+            r#"
+            url = "https://grafana.acme.com"
+            dashboard_uid = "oncall"
+            "#,
+        )?;
+        let entry = FinalOverride {
+            original_slot: "Mon".to_string(),
+            original_assignee: "a@x.com".to_string(),
+            final_override: "b@x.com".to_string(),
+            start_time_iso: "2022-08-29T03:00:00+08:00".to_string(),
+            end_time_iso: "2022-08-30T03:00:00+08:00".to_string(),
+            pd_user_id: "PB".to_string(),
+        };
+        let annotation = annotation(&config, "P1", 2, &entry)?;
+        assert_eq!(annotation["time"], 1661713200000i64);
+        assert_eq!(annotation["timeEnd"], 1661799600000i64);
+        assert_eq!(annotation["tags"], json!(["oncall", "P1"]));
+        assert_eq!(annotation["dashboardUID"], "oncall");
+        assert!(annotation.get("panelId").is_none());
+        assert_eq!(
+            annotation["text"],
+            "b@x.com is oncall on P1 instead of a@x.com (gcal-pagerduty run 2)"
+        );
+        Ok(())
+    }
+}
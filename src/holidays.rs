@@ -0,0 +1,106 @@
+use crate::availability::DeclaredAvailability;
+use crate::caldav::parse_events;
+use crate::calendar::{convert_time_wrapper, CalendarEvent};
+use crate::config::Config;
+use crate::http::HttpClient;
+use crate::solver::BusyInterval;
+use anyhow::{anyhow, ensure, Context, Result as AnyhowResult};
+use chrono::{DateTime, FixedOffset};
+use chrono_tz::Tz;
+use std::collections::{BTreeSet, HashMap};
+use tracing::instrument;
+
+/// Public holidays read from the ICS feed of each region of the config file, e.g. the holiday
+/// calendars google publishes per country. People are unavailable on the holidays of their region
+pub struct HolidayFeeds<'a> {
+    pub client: &'a HttpClient,
+    pub config: &'a Config,
+}
+
+impl HolidayFeeds<'_> {
+    /// Holidays of the region of each of `emails` overlapping `start` to `end`, by email. Each feed
+    /// is fetched once, and only if someone is in its region
+    #[instrument(skip(self, emails))]
+    pub async fn holidays(
+        &self,
+        emails: &BTreeSet<&str>,
+        start: DateTime<FixedOffset>,
+        end: DateTime<FixedOffset>,
+    ) -> AnyhowResult<HashMap<String, DeclaredAvailability>> {
+        let mut feeds: HashMap<String, String> = HashMap::new();
+        let mut holidays = HashMap::new();
+        for email in emails {
+            let Some(region) = self.config.user_region(email) else {
+                continue;
+            };
+            if !feeds.contains_key(&region) {
+                feeds.insert(region.clone(), self.fetch(&region).await?);
+            }
+            let timezone = self.config.user_timezone(email)?;
+            let unavailable: Vec<BusyInterval> = parse_events(&feeds[&region], timezone)
+                .iter()
+                .filter_map(|x| holiday(x, &region, timezone))
+                .filter(|x| x.overlaps(start, end))
+                .collect();
+            if !unavailable.is_empty() {
+                let availability = DeclaredAvailability {
+                    unavailable,
+                    ..Default::default()
+                };
+                holidays.insert(email.to_string(), availability);
+            }
+        }
+        Ok(holidays)
+    }
+
+    async fn fetch(&self, region: &str) -> AnyhowResult<String> {
+        let url = self
+            .config
+            .holiday_feeds
+            .get(region)
+            .ok_or_else(|| anyhow!("No holiday feed for region {}", region))?;
+        let response = self.client.send(self.client.get(url)).await?;
+        ensure!(
+            response.status().is_success(),
+            "Unexpected status {} from the holiday feed of {}",
+            response.status(),
+            region
+        );
+        response
+            .text()
+            .await
+            .context(format!("Failed to read the holiday feed of {}", region))
+    }
+}
+
+/// The holiday as a busy interval. Holidays on dates last from midnight to midnight in
+/// `timezone`, where the person is, rather than wherever the feed was made
+fn holiday(event: &CalendarEvent, region: &str, timezone: Tz) -> Option<BusyInterval> {
+    Some(BusyInterval {
+        summary: format!(
+            "{} ({} public holiday)",
+            event.summary.as_deref().unwrap_or("(no title)"),
+            region
+        ),
+        start: convert_time_wrapper(event.start.as_ref()?, timezone).ok()?,
+        end: convert_time_wrapper(event.end.as_ref()?, timezone).ok()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_holiday() {
+        let ics = "BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nSUMMARY:Independence Day\r\n\
+                   DTSTART;VALUE=DATE:20220817\r\nDTEND;VALUE=DATE:20220818\r\nEND:VEVENT\r\n\
+                   END:VCALENDAR\r\n";
+        let events = parse_events(ics, chrono_tz::Asia::Jakarta);
+        let busy = holiday(&events[0], "ID", chrono_tz::Asia::Jakarta).unwrap();
+        assert_eq!(busy.summary, "Independence Day (ID public holiday)");
+        // midnight in jakarta, an hour behind singapore
+        assert_eq!(busy.start.to_rfc3339(), "2022-08-17T00:00:00+07:00");
+        assert_eq!(busy.end.to_rfc3339(), "2022-08-18T00:00:00+07:00");
+    }
+}
@@ -0,0 +1,215 @@
+use crate::recording::Tape;
+use anyhow::{ensure, Context, Result as AnyhowResult};
+use reqwest::{Client, IntoUrl, Method, RequestBuilder, Response};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{Mutex as AsyncMutex, Semaphore};
+use tokio::time::Instant;
+use tracing::{instrument, Span};
+
+/// Longest wait for a connection to either api
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How hard a single host may be hit
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    /// requests in flight at the same time
+    pub concurrency: usize,
+    /// requests started per second, on average. Up to a second's worth may start at once
+    pub per_second: f64,
+}
+
+/// The http client shared by the google calendar and pagerduty apis. Every request goes through
+/// `send`, which keeps each host within the RateLimit, and gives up after the client timeout
+/// instead of hanging on an unresponsive network. Proxies are taken from the HTTP_PROXY,
+/// HTTPS_PROXY and NO_PROXY environment variables. With a Tape, requests are recorded or replayed
+pub struct HttpClient {
+    client: Client,
+    limit: RateLimit,
+    hosts: Mutex<HashMap<String, Arc<HostBudget>>>,
+    tape: Option<Tape>,
+}
+
+/// What is left of the RateLimit of one host
+struct HostBudget {
+    in_flight: Semaphore,
+    bucket: AsyncMutex<TokenBucket>,
+}
+
+impl HttpClient {
+    pub fn get<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.client.get(url)
+    }
+
+    pub fn post<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.client.post(url)
+    }
+
+    pub fn put<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.client.put(url)
+    }
+
+    pub fn delete<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.client.delete(url)
+    }
+
+    /// A request with any method, e.g. REPORT for CalDAV
+    pub fn request<U: IntoUrl>(&self, method: Method, url: U) -> RequestBuilder {
+        self.client.request(method, url)
+    }
+
+    /// Record every exchange to, or replay them from, `tape` instead of only talking to the apis
+    pub fn with_tape(self, tape: Tape) -> HttpClient {
+        HttpClient {
+            tape: Some(tape),
+            ..self
+        }
+    }
+
+    /// Keep `secret` out of any recording
+    pub fn keep_secret(&self, secret: &str) {
+        if let Some(tape) = &self.tape {
+            tape.keep_secret(secret);
+        }
+    }
+
+    /// Send the request once its host has room for it. Its span covers the wait for room, and
+    /// latency_ms only the request itself
+    #[instrument(skip_all, fields(method, host, status, latency_ms))]
+    pub async fn send(&self, request: RequestBuilder) -> AnyhowResult<Response> {
+        let request = request.build().context("Failed to build request")?;
+        if let Some(replayed) = self.tape.as_ref().and_then(|x| x.replayed(&request)) {
+            return replayed;
+        }
+        let host = request.url().host_str().unwrap_or_default().to_string();
+        let span = Span::current();
+        span.record("method", request.method().as_str());
+        span.record("host", &host);
+        let budget = self.budget(&host);
+        let _permit = budget
+            .in_flight
+            .acquire()
+            .await
+            .context("Request scheduler closed")?;
+        loop {
+            let wait = budget.bucket.lock().await.take(Instant::now());
+            match wait {
+                Some(value) => tokio::time::sleep(value).await,
+                None => break,
+            }
+        }
+        let key = self.tape.as_ref().map(|x| x.key(&request));
+        let started = Instant::now();
+        let response = self
+            .client
+            .execute(request)
+            .await
+            .context(format!("Request to {} failed", host))?;
+        span.record("latency_ms", started.elapsed().as_millis() as u64);
+        span.record("status", response.status().as_u16());
+        match (&self.tape, key) {
+            (Some(tape), Some(key)) => tape.recorded(key, response).await,
+            _ => Ok(response),
+        }
+    }
+
+    fn budget(&self, host: &str) -> Arc<HostBudget> {
+        self.hosts
+            .lock()
+            .unwrap()
+            .entry(host.to_string())
+            .or_insert_with(|| {
+                Arc::new(HostBudget {
+                    in_flight: Semaphore::new(self.limit.concurrency.max(1)),
+                    bucket: AsyncMutex::new(TokenBucket::new(
+                        self.limit.per_second,
+                        Instant::now(),
+                    )),
+                })
+            })
+            .clone()
+    }
+}
+
+/// Tokens refill continuously at `per_second`, and each request takes one
+#[derive(Debug)]
+struct TokenBucket {
+    per_second: f64,
+    capacity: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(per_second: f64, now: Instant) -> TokenBucket {
+        let capacity = per_second.max(1.0);
+        TokenBucket {
+            per_second,
+            capacity,
+            tokens: capacity,
+            updated: now,
+        }
+    }
+
+    /// Take a token, or return how long to wait for the next one
+    fn take(&mut self, now: Instant) -> Option<Duration> {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_second).min(self.capacity);
+        self.updated = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            None
+        } else {
+            Some(Duration::from_secs_f64(
+                (1.0 - self.tokens) / self.per_second,
+            ))
+        }
+    }
+}
+
+pub fn build_http_client(timeout: Duration, limit: RateLimit) -> AnyhowResult<HttpClient> {
+    ensure!(
+        limit.per_second > 0.0,
+        "The request rate must be positive, got {}",
+        limit.per_second
+    );
+    let client = Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .timeout(timeout)
+        .user_agent(concat!(
+            env!("CARGO_PKG_NAME"),
+            "/",
+            env!("CARGO_PKG_VERSION")
+        ))
+        .build()
+        .context("Failed to build the http client")?;
+    Ok(HttpClient {
+        client,
+        limit,
+        hosts: Mutex::new(HashMap::new()),
+        tape: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(2.0, start);
+        // a second's worth of requests may start at once, then one every half second
+        assert_eq!(bucket.take(start), None);
+        assert_eq!(bucket.take(start), None);
+        let wait = bucket.take(start).unwrap();
+        assert!((wait.as_secs_f64() - 0.5).abs() < 1e-6);
+        assert_eq!(bucket.take(start + wait), None);
+        // idle time doesn't build up more than the burst
+        let later = start + Duration::from_secs(60);
+        assert_eq!(bucket.take(later), None);
+        assert_eq!(bucket.take(later), None);
+        assert!(bucket.take(later).is_some());
+    }
+}
@@ -0,0 +1,137 @@
+use crate::config::JiraConfig;
+use crate::http::HttpClient;
+use crate::watch::Conflict;
+use anyhow::{ensure, Context, Result as AnyhowResult};
+use chrono::{DateTime, FixedOffset};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::instrument;
+
+#[derive(Deserialize, Debug)]
+struct CreatedIssue {
+    key: String,
+}
+
+/// Jira, where conflicts the plan can't resolve are tracked as tickets. Signs in with the email of
+/// the config file and the api token in JIRA_API_TOKEN
+pub struct Jira<'a> {
+    pub client: &'a HttpClient,
+    pub config: &'a JiraConfig,
+    pub token: &'a str,
+}
+
+impl Jira<'_> {
+    /// Open a ticket listing `conflicts`, returning its key
+    #[instrument(skip(self, conflicts))]
+    pub async fn open_ticket(
+        &self,
+        schedule_id: &str,
+        start: DateTime<FixedOffset>,
+        end: DateTime<FixedOffset>,
+        conflicts: &[Conflict],
+    ) -> AnyhowResult<String> {
+        let request = self
+            .client
+            .post(format!(
+                "{}/rest/api/2/issue",
+                self.config.url.trim_end_matches('/')
+            ))
+            .basic_auth(&self.config.email, Some(self.token))
+            .json(&issue(self.config, schedule_id, start, end, conflicts));
+        let response = self.client.send(request).await?;
+        let status = response.status();
+        ensure!(
+            status.is_success(),
+            "Unexpected status {} from jira: {}",
+            status,
+            response.text().await.unwrap_or_default()
+        );
+        let created: CreatedIssue = response
+            .json()
+            .await
+            .context("Failed to parse the issue created by jira")?;
+        Ok(created.key)
+    }
+}
+
+/// Fields of the ticket. Version 2 of the api takes the description as wiki markup
+fn issue(
+    config: &JiraConfig,
+    schedule_id: &str,
+    start: DateTime<FixedOffset>,
+    end: DateTime<FixedOffset>,
+    conflicts: &[Conflict],
+) -> Value {
+    let mut description = format!(
+        "gcal-pagerduty could not find anyone free to take these slots of schedule {}, someone \
+         needs to sort them out by hand.\n\n||Oncall||Start||End||Busy with||\n",
+        schedule_id
+    );
+    for conflict in conflicts {
+        description.push_str(&format!(
+            "|{}|{}|{}|{}|\n",
+            conflict.email,
+            conflict.start.format("%a %d %b %H:%M"),
+            conflict.end.format("%a %d %b %H:%M"),
+            // pipes would end the cell
+            conflict.reasons.join(", ").replace('|', "/")
+        ));
+    }
+    json!({
+        "fields": {
+            "project": { "key": config.project },
+            "issuetype": { "name": config.issue_type },
+            "summary": format!(
+                "{} unresolved oncall conflicts on {} from {} to {}",
+                conflicts.len(),
+                schedule_id,
+                start.date_naive(),
+                end.date_naive()
+            ),
+            "description": description,
+            "labels": config.labels,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_issue() -> AnyhowResult<()> {
+        let config: JiraConfig = toml::from_str(
+            r#"
+            url = "https://acme.atlassian.net"
+            email = "bot@x.com"
+            project = "OPS"
+            "#,
+        )?;
+        let start = DateTime::parse_from_rfc3339("2022-08-29T00:00:00+08:00")?;
+        let conflicts = vec![Conflict {
+            email: "a@x.com".to_string(),
+            start: DateTime::parse_from_rfc3339("2022-08-29T03:00:00+08:00")?,
+            end: DateTime::parse_from_rfc3339("2022-08-30T03:00:00+08:00")?,
+            soft: false,
+            reasons: vec!["Out of office | Bali".to_string()],
+        }];
+        let issue = issue(
+            &config,
+            "P1",
+            start,
+            start + chrono::Duration::days(4),
+            &conflicts,
+        );
+        let fields = &issue["fields"];
+        assert_eq!(fields["issuetype"]["name"], "Task");
+        assert_eq!(
+            fields["summary"],
+            "1 unresolved oncall conflicts on P1 from 2022-08-29 to 2022-09-02"
+        );
+        assert!(fields["description"]
+            .as_str()
+            .unwrap()
+            .ends_with("|a@x.com|Mon 29 Aug 03:00|Tue 30 Aug 03:00|Out of office / Bali|\n"));
+        Ok(())
+    }
+}
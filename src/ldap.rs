@@ -0,0 +1,147 @@
+use crate::config::LdapConfig;
+use anyhow::{Context, Result as AnyhowResult};
+use ldap3::{drive, ldap_escape, LdapConnAsync, LdapConnSettings, Scope, SearchEntry};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::instrument;
+
+/// Looks up the calendar address of people in an LDAP or Active Directory server, by the email
+/// they have in the oncall provider. Each person is looked up once per run
+pub struct LdapDirectory<'a> {
+    pub config: &'a LdapConfig,
+    /// of `config.bind_dn`, from LDAP_BIND_PASSWORD
+    pub password: &'a str,
+    pub timeout: Duration,
+    /// calendar address of everyone looked up so far
+    found: Mutex<HashMap<String, String>>,
+}
+
+impl<'a> LdapDirectory<'a> {
+    pub fn new(config: &'a LdapConfig, password: &'a str, timeout: Duration) -> LdapDirectory<'a> {
+        LdapDirectory {
+            config,
+            password,
+            timeout,
+            found: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Address of the calendar of `email`. People missing from the directory, or without the
+    /// calendar attribute, keep their email
+    #[instrument(skip(self))]
+    pub async fn calendar_address(&self, email: &str) -> AnyhowResult<String> {
+        if let Some(address) = self.found.lock().unwrap().get(email) {
+            return Ok(address.clone());
+        }
+        let address = match self.search(email).await? {
+            Some(address) => address,
+            None => {
+                say!(
+                    "Warning. {} has no {} in the directory, reading the calendar of that email",
+                    email,
+                    self.config.calendar_attribute
+                );
+                email.to_string()
+            }
+        };
+        self.found
+            .lock()
+            .unwrap()
+            .insert(email.to_string(), address.clone());
+        Ok(address)
+    }
+
+    async fn search(&self, email: &str) -> AnyhowResult<Option<String>> {
+        let settings = LdapConnSettings::new().set_conn_timeout(self.timeout);
+        let (connection, mut ldap) = LdapConnAsync::with_settings(settings, &self.config.url)
+            .await
+            .context(format!("Failed to connect to {}", self.config.url))?;
+        drive!(connection);
+        ldap.with_timeout(self.timeout)
+            .simple_bind(&self.config.bind_dn, self.password)
+            .await
+            .and_then(|x| x.success())
+            .context(format!(
+                "Failed to bind to the directory as {}",
+                self.config.bind_dn
+            ))?;
+        let (entries, _) = ldap
+            .with_timeout(self.timeout)
+            .search(
+                &self.config.base_dn,
+                Scope::Subtree,
+                &filter(self.config, email),
+                vec![self.config.calendar_attribute.as_str()],
+            )
+            .await
+            .and_then(|x| x.success())
+            .context(format!("Failed to look up {} in the directory", email))?;
+        // the search is done either way, so a failed unbind changes nothing
+        let _ = ldap.unbind().await;
+        Ok(entries.into_iter().find_map(|entry| {
+            let entry = SearchEntry::construct(entry);
+            entry
+                .attrs
+                .get(&self.config.calendar_attribute)
+                .and_then(|values| primary_address(values))
+        }))
+    }
+}
+
+/// Entries whose email attribute is `email`, and that match the filter of the config file
+fn filter(config: &LdapConfig, email: &str) -> String {
+    let by_email = format!("({}={})", config.email_attribute, ldap_escape(email));
+    match &config.filter {
+        Some(extra) => format!("(&{}{})", by_email, extra),
+        None => by_email,
+    }
+}
+
+/// The address among the values of the calendar attribute. Active Directory's proxyAddresses
+/// prefix each one with its type, the primary one with SMTP: in capitals
+fn primary_address(values: &[String]) -> Option<String> {
+    let primary = values.iter().find(|x| x.starts_with("SMTP:"));
+    primary.or_else(|| values.first()).map(|x| {
+        match x.get(..5) {
+            Some(prefix) if prefix.eq_ignore_ascii_case("smtp:") => &x[5..],
+            _ => x.as_str(),
+        }
+        .to_string()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_and_primary_address() -> AnyhowResult<()> {
+        let mut config: LdapConfig = toml::from_str(
+            r#"
+            url = "ldaps://ldap.acme.com"
+            bind_dn = "cn=bot,dc=acme,dc=com"
+            base_dn = "ou=people,dc=acme,dc=com"
+            calendar_attribute = "proxyAddresses"
+            "#,
+        )?;
+        assert_eq!(filter(&config, "a*b@x.com"), "(mail=a\\2ab@x.com)");
+        config.filter = Some("(objectClass=user)".to_string());
+        assert_eq!(
+            filter(&config, "a@x.com"),
+            "(&(mail=a@x.com)(objectClass=user))"
+        );
+
+        let values = |x: &[&str]| x.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            primary_address(&values(&["smtp:old@x.com", "SMTP:a@corp.x.com"])),
+            Some("a@corp.x.com".to_string())
+        );
+        assert_eq!(
+            primary_address(&values(&["a@corp.x.com"])),
+            Some("a@corp.x.com".to_string())
+        );
+        assert_eq!(primary_address(&[]), None);
+        Ok(())
+    }
+}
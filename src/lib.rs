@@ -0,0 +1,21 @@
+//! The scheduling core of gcal-pagerduty: the solver, the plan and calendar types, and config
+//! validation. None of it talks to the network, so it builds without the `cli` feature for tools
+//! that bring their own calendars and schedules, e.g.
+//!
+//! ```toml
+//! gcal-pagerduty = { git = "https://github.com/jlloh/gcal-pagerduty", default-features = false }
+//! ```
+
+// first, so its say! macro is available to the other modules
+#[macro_use]
+mod events;
+
+pub mod cache;
+pub mod calendar;
+pub mod config;
+pub mod oncall;
+pub mod saved_plan;
+pub mod solver;
+pub mod watch;
+
+pub use events::{log_format, set_log_format, Event, LogFormat};
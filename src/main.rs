@@ -1,216 +1,2098 @@
-use crate::gcal::{check_token_validity, get_oauth_token, get_start_end_time};
-use crate::pagerduty::{schedule_overrides, OverrideEntry, OverrideUser};
+use crate::audit_log::{AccessKey, AuditInputs, AuditLog};
+use crate::availability::{external_availability, load_availability, DeclaredAvailability};
+use crate::bamboohr::{BambooHr, BAMBOOHR_API_URL};
+use crate::bookings::{Bookings, CALENDLY_API_URL};
+use crate::cache::{clear_cache, Cache, Namespace};
+use crate::caldav::{CalDav, Calendars};
+use crate::calendar::{
+    display_time, get_user_calendar, local_time, set_display_timezone, AvailabilityProvider,
+    CalendarEvent, UserCalendar,
+};
+use crate::config::{load_config, shift_of, Config, Coverage, Shift, Weights};
+use crate::confluence::{render_page, Confluence, SchedulePage, RECENT_RUNS};
+use crate::costs::load_cost_matrix;
+use crate::dashboard::{render_review, start_dashboard, PlanReview};
+use crate::email::EmailNotifier;
+use crate::events::{set_log_format, Event, LogFormat};
+use crate::exit::{exit_code, Outcome, CONFLICTS_FOUND, FAILURE, SUCCESS};
+use crate::gcal::{
+    check_token_validity, get_oauth_token, get_start_end_time, AuthError, GoogleCalendar,
+    CALENDAR_SCOPE, GOOGLE_API_URL,
+};
+use crate::grafana_annotations::GrafanaAnnotations;
+use crate::holidays::HolidayFeeds;
+use crate::http::{build_http_client, HttpClient, RateLimit};
+use crate::jira::Jira;
+use crate::ldap::LdapDirectory;
+use crate::oncall::{FinalPagerDutySchedule, OncallProvider, SkippedUsers};
+use crate::paths::Paths;
+use crate::provider::Oncall;
+use crate::recording::Tape;
+use crate::report::{print_overrides_by_week, print_table, set_table_options, table, TableOptions};
+use crate::saved_plan::{
+    load_plan, save_plan, ExportedOverride, ExportedPlan, PlanFile, PlanFormat, Provenance,
+    SavedPlan, PLAN_SCHEMA, PLAN_SCHEMA_VERSION,
+};
+use crate::sheets::{check_spreadsheet, GoogleSheets, SHEETS_API_URL, SHEETS_SCOPE};
+use crate::slack::{check_reply, failed_reply, plan_preview_reply, SlackNotifier};
+use crate::slack_workspace::{SlackWorkspace, SLACK_API_URL};
+use crate::slash_command::{reply, start_slash_commands, CommandRequest, SlashCommand};
+use crate::solver::{
+    apply_previous_plan, compact_swaps, freeze_slots, generate_candidate_plans,
+    generate_diff_of_shift, has_conflicts, holiday_counts, inputs_hash, is_holiday, is_weekend,
+    minimal_removal, same_slot, set_overlap, shift_counts, to_saved_plan, validate_plan,
+    weekend_counts, BusyInterval, BusyTimes, CandidatePlan, FinalEntity, FinalOverride, OncallSlot,
+    Overlap, SearchExhausted, SolverOptions,
+};
+use crate::state::{same_instant, NewRun, RunOutcome, StateStore, StoredRun};
+use crate::swap_requests::review_swap_requests;
+use crate::telemetry::Telemetry;
+use crate::templates::{PlanReport, Templates, MARKDOWN};
+use crate::watch::{find_conflicts, Conflict, ConflictWatch};
+use crate::webhook::WebhookNotifier;
+use crate::workday::Workday;
 use anyhow::{anyhow, Context, Result as AnyhowResult};
-use chrono::{DateTime, Duration, FixedOffset, NaiveDateTime, NaiveTime};
-use clap::Parser;
+use chrono::{DateTime, Datelike, Duration, FixedOffset, NaiveDate, Utc};
+use chrono_tz::Tz;
+use clap::{Parser, Subcommand};
 use futures::future::join_all;
-use gcal::{get_user_calender, CalendarEvent, TimeWrapper};
-use pagerduty::{get_pagerduty_schedule, FinalPagerDutySchedule};
-use rand::seq::SliceRandom;
-use reqwest::{self, Client};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::io;
 use std::iter::zip;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::str::FromStr;
+use std::time::Duration as StdDuration;
 use std::{env, fs};
-use tabled::{Table, Tabled};
+use tabled::Tabled;
+use tokio::sync::mpsc;
+use tracing::{info_span, instrument, Instrument};
 
+// first, so its say! macro is available to the other modules
+#[macro_use]
+mod events;
+
+mod audit_log;
+mod availability;
+mod bamboohr;
+mod bookings;
+mod cache;
+mod caldav;
+mod calendar;
+mod config;
+mod confluence;
+mod costs;
+mod dashboard;
+mod email;
+mod exit;
 mod gcal;
+mod grafana;
+mod grafana_annotations;
+mod holidays;
+mod http;
+mod jira;
+mod ldap;
+mod oncall;
+mod opsgenie;
 mod pagerduty;
+mod paths;
+mod provider;
+mod recording;
+mod report;
+mod saved_plan;
+mod sheets;
+mod slack;
+mod slack_workspace;
+mod slash_command;
+mod solver;
+mod splunk;
+mod state;
+mod swap_requests;
+mod telemetry;
+mod templates;
+mod watch;
+mod webhook;
 mod webserver;
+mod workday;
 
 /// Pagerduty and google calendar conflict resolver
 #[derive(Parser, Debug)]
-#[clap(author, version, about, long_about = None)]
+#[clap(author, version, about, long_about = None, subcommand_negates_reqs = true)]
 struct Args {
+    #[clap(subcommand)]
+    command: Option<Command>,
     /// date string to start from, in the form of YYYY-mm-dd
+    #[clap(short, long, value_parser = parse_date, required_unless_present_any = &["history", "undo", "watch"])]
+    start_date: Option<NaiveDate>,
+    #[clap(short, long, value_parser = positive::<i64>, required_unless_present_any = &["history", "undo", "watch"])]
+    duration_days: Option<i64>,
+    /// id of the pagerduty schedule. Defaults to the schedule of the config file, or of --profile
     #[clap(short, long, value_parser)]
-    start_date: String,
-    #[clap(short, long, value_parser)]
-    duration_days: i64,
-    #[clap(short, long, value_parser)]
-    pd_schedule: String,
+    pd_schedule: Option<String>,
+    /// seed for the solver's random number generator. A random seed is picked and printed if not set
+    #[clap(long, value_parser)]
+    seed: Option<u64>,
+    /// number of distinct candidate plans to generate and rank before picking one to apply
+    #[clap(long, value_parser = positive::<usize>, default_value_t = 1)]
+    candidates: usize,
+    /// number of solver attempts, each with its own seed, run in parallel. Defaults to 5 per candidate
+    #[clap(long, value_parser = positive::<usize>)]
+    attempts: Option<usize>,
+    /// maximum number of slots a person may hold in the window after swaps
+    #[clap(long, value_parser = positive::<usize>)]
+    max_shifts_per_person: Option<usize>,
+    /// most calendar days in a row a person may be oncall after swaps, counting every shift
+    #[clap(long, value_parser = positive::<usize>)]
+    max_consecutive_days: Option<usize>,
+    /// how strongly the solver prefers plans that spread weekend slots evenly. Overrides weights.weekend in the config file
+    #[clap(long, value_parser)]
+    weekend_weight: Option<f64>,
+    /// longest rotation of people (A takes B's slot, B takes C's, C takes A's) tried when no direct swap exists
+    #[clap(long, value_parser, default_value_t = 4)]
+    max_cycle_length: usize,
+    /// most simulated swaps a single solver run may make before giving up
+    #[clap(long, value_parser = positive::<usize>, default_value_t = 200)]
+    max_swaps: usize,
+    /// most search steps a single solver run may take before giving up
+    #[clap(long, value_parser = positive::<usize>, default_value_t = 1000)]
+    max_depth: usize,
+    /// wall clock budget in seconds for a single solver run
+    #[clap(long, value_parser = positive::<u64>)]
+    max_solve_seconds: Option<u64>,
+    /// total time budget in seconds. Solver attempts with fresh seeds keep running until it is spent, and the best plan found is used, even a partial one
+    #[clap(long, value_parser = positive::<u64>)]
+    max_seconds: Option<u64>,
+    /// allow moving people between shifts (e.g. 03:00 and 15:00 slots) when same-shift swaps can't resolve a conflict
+    #[clap(long, action)]
+    allow_cross_shift: bool,
+    /// count private events as conflicts instead of leaving them out, like `private_events_busy` of the config file
+    #[clap(long, action)]
+    private_events_busy: bool,
+    /// count events that end exactly when a slot starts, or start exactly when it ends, as conflicts
+    #[clap(long, action)]
+    touching_conflicts: bool,
+    /// show times in tables and messages in this IANA timezone, e.g. Europe/London, or "user" for the timezone of the person each row is about, instead of `display_timezone` of the config file
+    #[clap(long, value_parser = display_timezone)]
+    display_timezone: Option<String>,
+    /// when someone is busy for only part of their slot, hand just that part to someone free instead of swapping the whole slot
+    #[clap(long, action)]
+    split_shifts: bool,
+    /// shortest piece of a slot, in hours, that --split-shifts may create
+    #[clap(long, value_parser = positive::<i64>, default_value_t = 3)]
+    min_split_hours: i64,
+    /// only move people into slots within this many days of a slot they were originally assigned
+    #[clap(long, value_parser = not_negative::<i64>)]
+    swap_window_days: Option<i64>,
+    /// how strongly the solver favours slots people asked for with "prefer-oncall" or "oncall-ok" events. Overrides weights.preference in the config file
+    #[clap(long, value_parser)]
+    preference_weight: Option<f64>,
+    /// look back this many weeks of the schedule, so people who recently carried extra or weekend slots absorb fewer swaps
+    #[clap(long, value_parser = not_negative::<i64>)]
+    history_weeks: Option<i64>,
+    /// id of the secondary schedule paired with --pd-schedule, defaulting to the one of the config file. Enables the pairings in the config file. The secondary schedule itself is only changed with --link-secondary
+    #[clap(long, value_parser)]
+    secondary_schedule: Option<String>,
+    /// move shadows (pairings.follow in the config file) on the secondary schedule along with their primary, and apply those overrides too
+    #[clap(long, action)]
+    link_secondary: bool,
+    /// csv or json file of extra costs per person and slot, added to the plan score. See the README for the format
+    #[clap(long, value_parser)]
+    cost_matrix: Option<PathBuf>,
+    /// yaml or csv file of availability declared per person, merged with their calendars. Can be
+    /// given several times. See the README for the formats
+    #[clap(long, value_parser)]
+    availability_file: Vec<PathBuf>,
+    /// write the chosen plan to this json file, to re-solve against it later with --previous-plan
+    #[clap(long, value_parser)]
+    save_plan: Option<PathBuf>,
+    /// format of --save-plan. schema-json is the versioned format of schemas/plan.v1.json, for
+    /// approval systems to review and sign before handing it back to --plan
+    #[clap(long, value_enum, default_value_t = PlanFormat::Json)]
+    plan_format: PlanFormat,
+    /// write the plan as a markdown report to this file, laid out by the markdown template of the
+    /// config file if it has one
+    #[clap(long, value_parser)]
+    markdown_report: Option<PathBuf>,
+    /// start from a plan saved with --save-plan and only change the slots that conflict with the refreshed calendars
+    #[clap(long, value_parser)]
+    previous_plan: Option<PathBuf>,
+    /// when some conflicts can't be resolved, still output (and optionally apply) the overrides for the rest, and list the unresolved conflicts separately
+    #[clap(long, action)]
+    allow_unresolved: bool,
+    /// plan without the slots of people the oncall provider couldn't look up, e.g. deleted users, instead of failing. They are listed at the end either way
+    #[clap(long, action)]
+    allow_skipped_users: bool,
+    /// also plan slots that have already started, whose overrides reach into the past. Without it they keep their holder
+    #[clap(long, action)]
+    force_past: bool,
+    /// never change the slot under way or any starting within this many hours of now, instead of `freeze_hours` of the config file
+    #[clap(long, value_parser = not_negative::<i64>)]
+    freeze_hours: Option<i64>,
+    /// apply a plan saved with --save-plan, possibly edited by hand, instead of solving. It is checked against every constraint first, and a schema-json plan must also match the current schedule and calendars
+    #[clap(long, value_parser)]
+    plan: Option<PathBuf>,
+    /// give up on a request to either api after this many seconds
+    #[clap(long, value_parser = positive::<u64>, default_value_t = 30)]
+    http_timeout_seconds: u64,
+    /// most requests in flight at once to each api
+    #[clap(long, value_parser = positive::<usize>, default_value_t = 8)]
+    max_concurrent_requests: usize,
+    /// most requests started per second to each api, to stay clear of their rate limits
+    #[clap(long, value_parser = positive::<f64>, default_value_t = 10.0)]
+    max_requests_per_second: f64,
+    /// serve both the google calendar and pagerduty apis from this url instead, e.g. the fixture server of the demo
+    #[clap(long, value_parser)]
+    base_url: Option<String>,
+    /// once the overrides are applied, email everyone whose shifts changed, through the [smtp] relay of the config file
+    #[clap(long, action)]
+    email_affected: bool,
+    /// review and apply the plan on a web page served on this port, instead of answering the prompt in the terminal
+    #[clap(long, value_parser)]
+    serve: Option<u16>,
+    /// only report the conflicts in the window, exiting with 10 if there are any and 0 otherwise, instead of solving
+    #[clap(long, action, conflicts_with = "watch")]
+    check: bool,
+    /// keep checking the upcoming slots of --pd-schedule against calendars, and print conflicts as they appear, instead of solving
+    #[clap(long, action)]
+    watch: bool,
+    /// minutes between checks with --watch
+    #[clap(long, value_parser = positive::<u64>, default_value_t = 15)]
+    watch_interval_minutes: u64,
+    /// how many days ahead --watch looks for conflicts
+    #[clap(long, value_parser = positive::<i64>, default_value_t = 7)]
+    watch_horizon_days: i64,
+    /// with --watch, also answer the slack slash commands of an app whose signing secret is in SLACK_SIGNING_SECRET, posted to /slack/commands on this port
+    #[clap(long, value_parser, requires = "watch")]
+    slack_commands: Option<u16>,
+    /// sqlite database recording every run, its plan and the overrides it applied. Defaults to state.db in the state directory
+    #[clap(long, value_parser)]
+    state_db: Option<PathBuf>,
+    /// where the google oauth token is kept. Defaults to google_oidc_token in the cache directory
+    #[clap(long, value_parser)]
+    token_file: Option<PathBuf>,
+    /// list the most recent runs recorded in --state-db, then exit
+    #[clap(long, action)]
+    history: bool,
+    /// delete the overrides applied by this run, as listed by --history, then exit
+    #[clap(long, value_parser, conflicts_with = "history")]
+    undo: Option<i64>,
+    /// write every api request and response of the run to this directory, with the credentials left out, to reproduce it with --replay
+    #[clap(long, value_parser, conflicts_with = "replay")]
+    record: Option<PathBuf>,
+    /// answer api requests from a directory written by --record instead of the apis, to rerun that scenario offline. No credentials are needed, and runs are kept in memory instead of --state-db
+    #[clap(long, value_parser)]
+    replay: Option<PathBuf>,
+    /// fetch every schedule, user and calendar from the apis instead of the cache of recent responses
+    #[clap(long, action)]
+    no_cache: bool,
+    /// print every row of long tables, instead of the first 40
+    #[clap(long, action)]
+    full: bool,
+    /// show long tables in $PAGER (less by default) when run in a terminal
+    #[clap(long, action)]
+    pager: bool,
+    /// wrap the text of table cells wider than this, e.g. long emails and event summaries. 0 for no limit
+    #[clap(long, value_parser, default_value_t = 40)]
+    max_column_width: usize,
+    /// json prints one event per line on stdout for automation, and moves the human output to stderr
+    #[clap(long, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+    /// settings of [profile.<name>] in the config file to use on top of the others, e.g. the schedule and shifts of one team
+    #[clap(long, value_parser)]
+    profile: Option<String>,
+    /// export a trace of the run, with a span per api call, to this OTLP/http collector endpoint, e.g. http://localhost:4318/v1/traces
+    #[clap(long, value_parser)]
+    otlp_endpoint: Option<String>,
+    /// path to the config file. Defaults to gcal-pagerduty.toml in the current directory, or else in the config directory, if present
+    #[clap(long, value_parser)]
+    config: Option<PathBuf>,
 }
 
-#[tokio::main]
-async fn main() -> AnyhowResult<()> {
-    // Environment variables
-    const PD_API_KEY: &str = "PD_API_KEY";
-    const GOOGLE_CLIENT_ID: &str = "GOOGLE_CLIENT_ID";
-    const GOOGLE_CLIENT_SECRET: &str = "GOOGLE_CLIENT_SECRET";
-
-    let api_key = env::var(PD_API_KEY).context(format!(
-        "Expected environment variable {} to be set",
-        PD_API_KEY
-    ))?;
-    let google_client_id = env::var(GOOGLE_CLIENT_ID).context(format!(
-        "Expected environment variable {} to be set",
-        GOOGLE_CLIENT_ID
-    ))?;
-    let google_client_secret = env::var(GOOGLE_CLIENT_SECRET).context(format!(
-        "Expected environment variable {} to be set",
-        GOOGLE_CLIENT_SECRET
-    ))?;
+/// Housekeeping, instead of planning
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// manage the cache of api responses
+    Cache {
+        #[clap(subcommand)]
+        action: CacheAction,
+    },
+    /// check the config file
+    Config {
+        #[clap(subcommand)]
+        action: ConfigAction,
+    },
+    /// print the JSON Schema of plans saved with --plan-format schema-json
+    PlanSchema,
+}
+
+#[derive(Subcommand, Debug)]
+enum CacheAction {
+    /// delete every cached response
+    Clear,
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigAction {
+    /// report problems with the config file (or --profile), the credentials and the schedules,
+    /// without planning
+    Validate,
+}
+
+/// A date of the command line, e.g. --start-date 2022-08-22
+fn parse_date(value: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| {
+        format!(
+            "expected a date as YYYY-MM-DD, e.g. 2022-08-22, got {}",
+            value
+        )
+    })
+}
+
+/// A count or duration that only makes sense above zero, e.g. --duration-days
+fn positive<T: FromStr + PartialOrd + Default>(value: &str) -> Result<T, String> {
+    match value.parse::<T>() {
+        Ok(x) if x > T::default() => Ok(x),
+        Ok(_) => Err(format!("expected a number above zero, got {}", value)),
+        Err(_) => Err(format!("expected a number, got {}", value)),
+    }
+}
+
+/// A count or duration where zero turns something off, e.g. --history-weeks
+fn not_negative<T: FromStr + PartialOrd + Default>(value: &str) -> Result<T, String> {
+    match value.parse::<T>() {
+        Ok(x) if x >= T::default() => Ok(x),
+        Ok(_) => Err(format!("expected zero or more, got {}", value)),
+        Err(_) => Err(format!("expected a number, got {}", value)),
+    }
+}
+
+/// An IANA timezone, or "user" for everyone's own, e.g. --display-timezone
+fn display_timezone(value: &str) -> Result<String, String> {
+    match value == "user" || value.parse::<Tz>().is_ok() {
+        true => Ok(value.to_string()),
+        false => Err(format!(
+            "expected an IANA timezone like Europe/London, or user, got {}",
+            value
+        )),
+    }
+}
 
+#[tokio::main]
+async fn main() -> ExitCode {
     // Command line args
     let args = Args::parse();
-    let start_date = args.start_date;
-    let duration_days = args.duration_days;
-    let pd_schedule_id = args.pd_schedule;
+    set_log_format(args.log_format);
+    set_overlap(match args.touching_conflicts {
+        true => Overlap::Inclusive,
+        false => Overlap::HalfOpen,
+    });
+    set_table_options(TableOptions {
+        full: args.full,
+        pager: args.pager,
+        max_column_width: args.max_column_width,
+    });
+    let telemetry = match args.otlp_endpoint.as_deref().map(Telemetry::init) {
+        Some(Err(e)) => {
+            eprintln!("Error: {:?}", e);
+            return ExitCode::from(FAILURE);
+        }
+        Some(Ok(value)) => Some(value),
+        None => None,
+    };
+    let result = start(args).instrument(info_span!("run")).await;
+    if let Some(telemetry) = telemetry {
+        telemetry.shutdown().await;
+    }
+    match result {
+        Ok(_) => ExitCode::from(SUCCESS),
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::from(exit_code(&e))
+        }
+    }
+}
 
-    let (start_time, end_time) = get_start_end_time(&start_date, duration_days);
+/// Everything but turning the outcome into an exit code
+async fn start(args: Args) -> AnyhowResult<()> {
+    let paths = Paths::from_env()?;
+    if let Some(Command::Cache {
+        action: CacheAction::Clear,
+    }) = &args.command
+    {
+        let cache_dir = paths.responses_dir();
+        let removed = clear_cache(&cache_dir)?;
+        say!(
+            "Removed {} cached responses from {}",
+            removed,
+            cache_dir.display()
+        );
+        return Ok(());
+    }
+    if let Some(Command::Config {
+        action: ConfigAction::Validate,
+    }) = &args.command
+    {
+        return validate_setup(&args, &paths).await;
+    }
+    if let Some(Command::PlanSchema) = &args.command {
+        // the schema is data for other programs, so it goes to stdout whatever the log format
+        print!("{}", PLAN_SCHEMA);
+        return Ok(());
+    }
+    let state_db = paths.state_db(args.state_db.as_deref())?;
+    let store = match &args.replay {
+        Some(_) => StateStore::in_memory()?,
+        None => StateStore::open(&state_db)?,
+    };
+    if args.history {
+        say!("{}", table(summarise_runs(&store.runs(20)?)));
+        return Ok(());
+    }
+    let client = build_http_client(
+        StdDuration::from_secs(args.http_timeout_seconds),
+        RateLimit {
+            concurrency: args.max_concurrent_requests,
+            per_second: args.max_requests_per_second,
+        },
+    )?;
+    let client = match (&args.record, &args.replay) {
+        (Some(dir), _) => client.with_tape(Tape::record(dir)?),
+        (_, Some(dir)) => client.with_tape(Tape::replay(dir)?),
+        (None, None) => client,
+    };
+    let webhook_url = env::var("SLACK_WEBHOOK_URL").ok();
+    if let Some(value) = &webhook_url {
+        client.keep_secret(value);
+    }
+    let notifier = SlackNotifier {
+        client: &client,
+        webhook_url,
+    };
 
-    let client = reqwest::Client::new();
+    let skipped = SkippedUsers::default();
+    let result = run(args, &paths, &client, &store, &notifier, &skipped).await;
+    let skipped = skipped.list();
+    if !skipped.is_empty() {
+        say!("\n========Skipped users, whose slots were left out==============");
+        print_table(skipped);
+    }
+    match &result {
+        Ok(_) => Event::new("run").decision("success").emit(),
+        Err(e) => {
+            Event::new("run").error(e).emit();
+            // found conflicts are what --check is for, and already reported
+            if exit_code(e) != CONFLICTS_FOUND {
+                notifier.check_failed(e).await;
+            }
+        }
+    }
+    result
+}
 
-    // Google
-    let token_file = ".google_oidc_token";
-    let token = match fs::read_to_string(token_file) {
-        Err(_e) => {
-            println!(
-                "Local token file {} not found. Triggering oauth flow.",
-                &token_file
-            );
-            get_oauth_token(&google_client_id, &google_client_secret).await
+/// Check everything a run needs before it gets to planning: the settings of the config file, that
+/// the schedules can be read with PD_API_KEY, and that the google token is still accepted
+async fn validate_setup(args: &Args, paths: &Paths) -> AnyhowResult<()> {
+    let config_file = args.config.clone().or_else(|| paths.config_file());
+    let config = load_config(config_file.as_deref(), args.profile.as_deref())?;
+    match &config_file {
+        Some(path) => say!("Checking {}", path.display()),
+        None => say!("No config file found, checking the defaults"),
+    }
+    let mut problems = config.problems();
+    if let Err(e) = Templates::load(&config.templates) {
+        problems.push(format!("{:#}", e));
+    }
+    let client = build_http_client(
+        StdDuration::from_secs(args.http_timeout_seconds),
+        RateLimit {
+            concurrency: args.max_concurrent_requests,
+            per_second: args.max_requests_per_second,
+        },
+    )?;
+
+    // Oncall provider
+    let schedules: Vec<String> = [
+        args.pd_schedule.clone().or_else(|| config.schedule.clone()),
+        args.secondary_schedule
+            .clone()
+            .or_else(|| config.secondary_schedule.clone()),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    if schedules.is_empty() {
+        problems.push("No schedule, set one in the config file or pass --pd-schedule".to_string());
+    }
+    let api_key_variable = config.oncall_provider.api_key_variable();
+    let cache = Cache::disabled();
+    let skipped = SkippedUsers::default();
+    let api_key = env::var(api_key_variable);
+    let connected = api_key.as_deref().map(|api_key| {
+        Oncall::connect(
+            &config,
+            &client,
+            args.base_url.as_deref(),
+            api_key,
+            &cache,
+            &skipped,
+        )
+    });
+    match connected {
+        Err(_) => problems.push(format!("{} is not set", api_key_variable)),
+        Ok(Err(e)) => problems.push(format!("{:#}", e)),
+        Ok(Ok(oncall_provider)) => {
+            for schedule_id in &schedules {
+                match oncall_provider.check_schedule(schedule_id).await {
+                    Ok(_) => say!(
+                        "{} schedule {} is readable",
+                        oncall_provider.name(),
+                        schedule_id
+                    ),
+                    Err(e) => problems.push(format!(
+                        "Can't read {} schedule {}: {:#}",
+                        oncall_provider.name().to_lowercase(),
+                        schedule_id,
+                        e
+                    )),
+                }
+            }
         }
-        Ok(value) => Ok(value),
     }
-    .context("Failed to get token from oauth flow")?;
 
-    // check token expiry and trigger oauth if expired
-    let token = match check_token_validity(&client, &token).await {
-        Err(e) if e.root_cause().to_string() == "Unauthorised" => {
-            println!("Unauthorised. Trying to get new token.");
-            get_oauth_token(&google_client_id, &google_client_secret)
-                .await
-                .context("Failed to get oauth token when trying to refresh after unauthorised")?
+    // Slack web api and HR
+    if config.slack.is_some() && env::var("SLACK_BOT_TOKEN").is_err() {
+        problems.push("SLACK_BOT_TOKEN is not set, for the [slack] section".to_string());
+    }
+    if config.bamboohr.is_some() && env::var("BAMBOOHR_API_KEY").is_err() {
+        problems.push("BAMBOOHR_API_KEY is not set, for the [bamboohr] section".to_string());
+    }
+    if config.jira.is_some() && env::var("JIRA_API_TOKEN").is_err() {
+        problems.push("JIRA_API_TOKEN is not set, for the [jira] section".to_string());
+    }
+    if config.grafana_annotations.is_some() && env::var("GRAFANA_API_TOKEN").is_err() {
+        problems.push(
+            "GRAFANA_API_TOKEN is not set, for the [grafana_annotations] section".to_string(),
+        );
+    }
+    if config.ldap.is_some() && env::var("LDAP_BIND_PASSWORD").is_err() {
+        problems.push("LDAP_BIND_PASSWORD is not set, for the [ldap] section".to_string());
+    }
+    if config.confluence.is_some() && env::var("CONFLUENCE_API_TOKEN").is_err() {
+        problems.push("CONFLUENCE_API_TOKEN is not set, for the [confluence] section".to_string());
+    }
+    if config.workday.is_some() && env::var("WORKDAY_PASSWORD").is_err() {
+        problems.push("WORKDAY_PASSWORD is not set, for the [workday] section".to_string());
+    }
+    for name in ["AUDIT_LOG_ACCESS_KEY_ID", "AUDIT_LOG_SECRET_ACCESS_KEY"] {
+        if config.audit_log.is_some() && env::var(name).is_err() {
+            problems.push(format!("{} is not set, for the [audit_log] section", name));
         }
-        Err(e) => return Err(e).context("Non-unauthorised error, not refreshing token"),
-        Ok(_) => token,
-    };
-    fs::write(token_file, &token).context("Unable to write token file")?;
+    }
+    if config.uses_calendly() && env::var("CALENDLY_API_TOKEN").is_err() {
+        problems.push("CALENDLY_API_TOKEN is not set, for the calendly users".to_string());
+    }
 
-    //pagerduty
-    let pd_schedule =
-        get_pagerduty_schedule(&client, &api_key, &pd_schedule_id, start_time, end_time)
-            .await
-            .context("Failed to get pd schedule")?;
+    // CalDAV
+    let mut emails: Vec<&String> = config.users.keys().collect();
+    emails.sort();
+    for email in emails {
+        if let Some(account) = &config.users[email].caldav {
+            if env::var(&account.password_env).is_err() {
+                problems.push(format!(
+                    "{} is not set, for the caldav calendar of {}",
+                    account.password_env, email
+                ));
+            }
+        }
+    }
+
+    // Google, unless every calendar is read over CalDAV and there are no sheets
+    let spreadsheets = config
+        .sheets
+        .as_ref()
+        .map(|x| x.spreadsheets())
+        .unwrap_or_default();
+    if !config.caldav_only || !spreadsheets.is_empty() {
+        for name in ["GOOGLE_CLIENT_ID", "GOOGLE_CLIENT_SECRET"] {
+            if env::var(name).is_err() {
+                problems.push(format!("{} is not set", name));
+            }
+        }
+        let token_file = paths.token_file(args.token_file.as_deref())?;
+        match fs::read_to_string(&token_file) {
+            Err(_) => problems.push(format!(
+                "No google token in {}, a run will sign in first",
+                token_file.display()
+            )),
+            Ok(token) => match check_google_token(args, &client, &token, &spreadsheets).await {
+                Ok(_) => say!("The google token is valid"),
+                Err(e) => problems.push(format!(
+                    "The google token in {} was rejected, a run will sign in again: {:#}",
+                    token_file.display(),
+                    e
+                )),
+            },
+        }
+    }
+
+    for problem in &problems {
+        say!("Problem: {}", problem);
+    }
+    match problems.len() {
+        0 => {
+            say!("No problems found");
+            Ok(())
+        }
+        n => Err(anyhow!("Found {} problems", n)),
+    }
+}
 
-    let sg_am_shift: Vec<FinalPagerDutySchedule> = pd_schedule
+async fn run(
+    args: Args,
+    paths: &Paths,
+    client: &HttpClient,
+    store: &StateStore,
+    notifier: &SlackNotifier<'_>,
+    skipped: &SkippedUsers,
+) -> AnyhowResult<()> {
+    let replaying = args.replay.is_some();
+    let google_api_url = args.base_url.as_deref().unwrap_or(GOOGLE_API_URL);
+    let config = load_config(
+        args.config
+            .clone()
+            .or_else(|| paths.config_file())
+            .as_deref(),
+        args.profile.as_deref(),
+    )?;
+    if let Some(value) = args
+        .display_timezone
+        .as_deref()
+        .or(config.display_timezone.as_deref())
+    {
+        set_display_timezone(config.display_timezone(value)?);
+    }
+    let api_key = credential(config.oncall_provider.api_key_variable(), replaying)?;
+    client.keep_secret(&api_key);
+    let templates = Templates::load(&config.templates)?;
+    // recordings need the requests to reach the apis, and replays to reach the recording
+    let cache = if args.no_cache || args.record.is_some() || replaying {
+        Cache::disabled()
+    } else {
+        Cache::new(paths.responses_dir(), config.cache.clone())
+    };
+    let oncall_provider = Oncall::connect(
+        &config,
+        client,
+        args.base_url.as_deref(),
+        &api_key,
+        &cache,
+        skipped,
+    )?;
+    if let Some(run_id) = args.undo {
+        let result = undo_run(&oncall_provider, store, run_id).await;
+        cache.forget(Namespace::Schedules);
+        return result;
+    }
+
+    let seed = args.seed.unwrap_or_else(rand::random);
+    let pd_schedule_id = args
+        .pd_schedule
         .clone()
-        .into_iter()
-        .filter(|schedule| {
-            schedule.start.time() == NaiveTime::from_hms(3, 0, 0)
-            // && schedule.end.time() == NaiveTime::from_hms(15, 0, 0)
-        })
-        .collect();
-    // assert!(sg_am_shift.len() == 14, "AM shift not full");
-    println!(
-        "AM shift size is: {}. First shift is {:?}, last shift is {:?}",
-        sg_am_shift.len(),
-        sg_am_shift.first().unwrap().email,
-        sg_am_shift.last().unwrap().email
-    );
+        .or_else(|| config.schedule.clone())
+        .context("--pd-schedule is needed, or a schedule in the config file")?;
+    let secondary_schedule_id = args
+        .secondary_schedule
+        .clone()
+        .or_else(|| config.secondary_schedule.clone());
+    let mailer = match (&config.smtp, args.email_affected) {
+        (Some(smtp), true) => Some(EmailNotifier {
+            smtp,
+            password: env::var("SMTP_PASSWORD").ok(),
+            config: &config,
+            templates: &templates,
+        }),
+        (None, true) => {
+            return Err(anyhow!(
+                "--email-affected needs an [smtp] section in the config file"
+            ))
+        }
+        (_, false) => None,
+    };
+    let webhook = WebhookNotifier {
+        client,
+        url: config.webhook.as_ref().map(|x| x.url.clone()),
+        secret: env::var("WEBHOOK_SECRET").ok(),
+    };
+    let slack_token = secret(client, "SLACK_BOT_TOKEN", replaying, config.slack.is_some())?;
+    let workspace = config
+        .slack
+        .as_ref()
+        .zip(slack_token.as_deref())
+        .map(|(slack, token)| SlackWorkspace {
+            client,
+            base_url: args.base_url.as_deref().unwrap_or(SLACK_API_URL),
+            token,
+            config: slack,
+        });
+    let bamboohr_key = secret(
+        client,
+        "BAMBOOHR_API_KEY",
+        replaying,
+        config.bamboohr.is_some(),
+    )?;
+    let bamboohr =
+        config
+            .bamboohr
+            .as_ref()
+            .zip(bamboohr_key.as_deref())
+            .map(|(bamboohr, api_key)| BambooHr {
+                client,
+                base_url: args.base_url.as_deref().unwrap_or(BAMBOOHR_API_URL),
+                company: &bamboohr.company,
+                api_key,
+            });
+    let workday_password = secret(
+        client,
+        "WORKDAY_PASSWORD",
+        replaying,
+        config.workday.is_some(),
+    )?;
+    let workday = config
+        .workday
+        .as_ref()
+        .zip(workday_password.as_deref())
+        .map(|(workday, password)| Workday {
+            client,
+            config: workday,
+            password,
+        });
 
-    let sg_pm_shift: Vec<FinalPagerDutySchedule> = pd_schedule
-        .into_iter()
-        .filter(|schedule| {
-            schedule.start.time() == NaiveTime::from_hms(15, 0, 0)
-            // && schedule.end.time() == NaiveTime::from_hms(3, 0, 0)
-        })
-        .collect();
-    println!(
-        "PM shift size is: {}. First shift is {:?}, last shift is {:?}",
-        sg_pm_shift.len(),
-        sg_pm_shift.first().unwrap().email,
-        sg_pm_shift.last().unwrap().email
-    );
+    let jira_token = secret(client, "JIRA_API_TOKEN", replaying, config.jira.is_some())?;
+    let jira = config
+        .jira
+        .as_ref()
+        .zip(jira_token.as_deref())
+        .map(|(jira, token)| Jira {
+            client,
+            config: jira,
+            token,
+        });
+    let confluence_token = secret(
+        client,
+        "CONFLUENCE_API_TOKEN",
+        replaying,
+        config.confluence.is_some(),
+    )?;
+    let confluence = config
+        .confluence
+        .as_ref()
+        .zip(confluence_token.as_deref())
+        .map(|(confluence, token)| Confluence {
+            client,
+            config: confluence,
+            token,
+        });
+    let grafana_token = secret(
+        client,
+        "GRAFANA_API_TOKEN",
+        replaying,
+        config.grafana_annotations.is_some(),
+    )?;
+    let annotations = config
+        .grafana_annotations
+        .as_ref()
+        .zip(grafana_token.as_deref())
+        .map(|(grafana, token)| GrafanaAnnotations {
+            client,
+            config: grafana,
+            token,
+        });
+    let audit_log = match &config.audit_log {
+        Some(audit_log) => {
+            let key = AccessKey {
+                id: credential("AUDIT_LOG_ACCESS_KEY_ID", replaying)?,
+                secret: credential("AUDIT_LOG_SECRET_ACCESS_KEY", replaying)?,
+                session_token: env::var("AUDIT_LOG_SESSION_TOKEN").ok(),
+            };
+            client.keep_secret(&key.secret);
+            if let Some(token) = &key.session_token {
+                client.keep_secret(token);
+            }
+            Some(AuditLog {
+                client,
+                config: audit_log,
+                key,
+            })
+        }
+        None => None,
+    };
 
-    let available_shifts_futures = vec![(sg_am_shift, "AM"), (sg_pm_shift, "PM")]
-        .into_iter()
-        .map(|(shift, shift_type)| {
-            get_available_shifts_per_user(
-                shift,
-                &client,
-                &token,
-                start_time,
-                end_time,
-                duration_days,
-                shift_type,
+    // Google, for everyone without a caldav account and for the sheets
+    let spreadsheets = config
+        .sheets
+        .as_ref()
+        .map(|x| x.spreadsheets())
+        .unwrap_or_default();
+    let token = match config.caldav_only && spreadsheets.is_empty() {
+        true => None,
+        false => Some(sign_in_to_google(&args, paths, client, &spreadsheets).await?),
+    };
+    let sheets = token.as_deref().map(|token| GoogleSheets {
+        client,
+        base_url: args.base_url.as_deref().unwrap_or(SHEETS_API_URL),
+        token,
+    });
+    let plan_spreadsheet = config
+        .sheets
+        .as_ref()
+        .and_then(|x| x.plan_spreadsheet.as_deref());
+
+    let shift_definitions = config.shift_definitions()?;
+    let timezone = config.schedule_timezone()?;
+    let mut declared: HashMap<String, DeclaredAvailability> = HashMap::new();
+    for path in &args.availability_file {
+        for (email, availability) in load_availability(path, timezone)? {
+            declared.entry(email).or_default().merge(availability);
+        }
+    }
+    let holiday_feeds = match config.holiday_feeds.is_empty() {
+        true => None,
+        false => Some(HolidayFeeds {
+            client,
+            config: &config,
+        }),
+    };
+    let calendly_token = secret(
+        client,
+        "CALENDLY_API_TOKEN",
+        replaying,
+        config.uses_calendly(),
+    )?;
+    let has_bookings = config
+        .users
+        .values()
+        .any(|x| x.bookings_feed.is_some() || x.calendly_user.is_some());
+    let bookings = has_bookings.then(|| Bookings {
+        client,
+        config: &config,
+        calendly_url: args.base_url.as_deref().unwrap_or(CALENDLY_API_URL),
+        calendly_token: calendly_token.as_deref(),
+    });
+    let availability_options = AvailabilityOptions {
+        config: &config,
+        allow_cross_shift: args.allow_cross_shift,
+        shifts: &shift_definitions,
+        timezone,
+        declared: &declared,
+        slack: workspace.as_ref(),
+        bamboohr: bamboohr.as_ref(),
+        workday: workday.as_ref(),
+        sheets: sheets.as_ref(),
+        holidays: holiday_feeds.as_ref(),
+        bookings: bookings.as_ref(),
+        private_events_busy: args.private_events_busy || config.private_events_busy,
+    };
+    // the directory isn't part of recordings, so replays go without it and ask for the
+    // calendars under the emails of the oncall provider
+    let ldap_password = match &config.ldap {
+        Some(_) if !replaying => Some(credential("LDAP_BIND_PASSWORD", replaying)?),
+        _ => None,
+    };
+    let directory = config
+        .ldap
+        .as_ref()
+        .zip(ldap_password.as_deref())
+        .map(|(ldap, password)| {
+            LdapDirectory::new(
+                ldap,
+                password,
+                StdDuration::from_secs(args.http_timeout_seconds),
             )
         });
+    let calendar_provider = Calendars {
+        caldav: CalDav {
+            client,
+            config: &config,
+            cache: &cache,
+        },
+        google: token
+            .as_deref()
+            .filter(|_| !config.caldav_only)
+            .map(|token| GoogleCalendar {
+                client,
+                base_url: google_api_url,
+                token,
+                cache: &cache,
+            }),
+        directory: directory.as_ref(),
+    };
+    if args.watch {
+        let commands = match args.slack_commands {
+            Some(port) => {
+                let secret = credential("SLACK_SIGNING_SECRET", replaying)?;
+                let (server, commands) = start_slash_commands(port, secret)?;
+                tokio::spawn(server);
+                say!("Answering slack commands on port {}", port);
+                Some(commands)
+            }
+            None => None,
+        };
+        return watch_schedule(
+            &oncall_provider,
+            &calendar_provider,
+            notifier,
+            &pd_schedule_id,
+            &availability_options,
+            WatchOptions {
+                interval: StdDuration::from_secs(args.watch_interval_minutes * 60),
+                horizon: Duration::days(args.watch_horizon_days),
+                commands,
+            },
+        )
+        .await;
+    }
 
-    // let available_shifts: Vec<(FinalPagerDutySchedule, Vec<OncallSlot>)> =
-    let current_shifts: Vec<FinalEntity> = join_all(available_shifts_futures)
-        .await
-        .into_iter()
-        .collect::<AnyhowResult<Vec<Vec<FinalEntity>>>>()
-        .context("Join error when getting pd shifts")?
-        .into_iter()
-        .flatten()
+    // clap only lets these be missing along with --history, --undo or --watch
+    let (start_time, end_time) = get_start_end_time(
+        args.start_date.unwrap(),
+        args.duration_days.unwrap(),
+        timezone,
+    );
+    let mut current_shifts = fetch_current_shifts(
+        &oncall_provider,
+        &calendar_provider,
+        &pd_schedule_id,
+        start_time,
+        end_time,
+        &availability_options,
+    )
+    .await?;
+    let entries: Vec<FinalPagerDutySchedule> = current_shifts
+        .iter()
+        .map(|x| x.pd_schedule.clone())
         .collect();
-    println!("{:#?}", current_shifts.first().unwrap());
+    check_slots(
+        &entries,
+        &pd_schedule_id,
+        start_time,
+        end_time,
+        &shift_definitions,
+    )?;
+    let emails: Vec<&str> = entries.iter().map(|x| x.email.as_str()).collect();
+    for (key, email) in config.near_misses(&emails) {
+        say!(
+            "Warning. users.\"{}\" in the config file matches nobody on the schedule, did you mean {}?",
+            key,
+            email
+        );
+    }
+    // their slots are missing from the schedule, so a plan would silently leave them as they are
+    let skipped_users = skipped.list().len();
+    if skipped_users > 0 && !args.allow_skipped_users {
+        return Err(Outcome::UsersSkipped(skipped_users).into());
+    }
+    check_coverage(
+        &current_shifts,
+        &shift_definitions,
+        &config.coverage,
+        (start_time, end_time),
+    )?;
+    if let Some(first) = current_shifts.first() {
+        say!("{:#?}", first);
+    }
+
+    say!("Total number of shifts: {}", current_shifts.len());
+
+    if args.check {
+        return report_conflicts(&current_shifts, &pd_schedule_id, notifier, &webhook).await;
+    }
 
-    println!("Total number of shifts: {}", current_shifts.len());
+    if let Some(weeks) = args.history_weeks {
+        let history = oncall_provider
+            .fetch_schedule(
+                &pd_schedule_id,
+                start_time - Duration::weeks(weeks),
+                start_time,
+            )
+            .await
+            .context("Failed to get past pd schedule")?;
+        add_recent_loads(&mut current_shifts, &history, weeks);
+    }
+
+    if let Some(path) = &args.cost_matrix {
+        let cost_matrix = load_cost_matrix(path)?;
+        for entity in current_shifts.iter_mut() {
+            entity.slot_costs = cost_matrix
+                .get(&entity.pd_schedule.email)
+                .cloned()
+                .unwrap_or_default();
+        }
+    }
+
+    // the providers reject overrides in the past or cut them short, so started slots stay as they
+    // are, and so do those about to start, whose holder could be paged during the handover
+    let now = local_time(Utc::now(), timezone);
+    let freeze_hours = args
+        .freeze_hours
+        .or(config.freeze_hours.map(i64::from))
+        .unwrap_or(0);
+    let frozen = frozen_slots(&current_shifts, now, freeze_hours, args.force_past);
+    if !frozen.is_empty() {
+        say!(
+            "{} slots have started{} and keep their holder{}",
+            frozen.len(),
+            match freeze_hours {
+                0 => String::new(),
+                hours => format!(" or start within {} hours,", hours),
+            },
+            match args.force_past {
+                true => "",
+                false => ". Pass --force-past to plan those that have started too",
+            }
+        );
+        current_shifts = freeze_slots(&current_shifts, &frozen);
+    }
 
     let unavailable_folks: Vec<ZeroSwaps> = current_shifts
         .clone()
         .into_iter()
-        .filter(|shift| shift.available_slots.is_empty())
+        .filter(|shift| shift.available_slots.is_empty() && shift.soft_conflict_slots.is_empty())
         .map(|x| convert_to_zero_swaps(x.pd_schedule))
         .collect();
-    if !unavailable_folks.is_empty() {
-        println!(
+    if !unavailable_folks.is_empty() && args.allow_unresolved {
+        say!("\n========Folks with zero swaps found. Their slots are left unresolved=======");
+        say!("{}", table(unavailable_folks));
+    } else if !unavailable_folks.is_empty() {
+        say!(
             "\n========Folks with zero swaps found. Please remove them from the pd schedule======="
         );
-        println!("{}", Table::new(unavailable_folks));
-        return Err(anyhow!("Folks with zero slots available").context(
-            "Failed to generate schedule because there are folks who can't be scheduled",
-        ));
+        say!("{}", table(unavailable_folks));
+        let stuck: Vec<FinalEntity> = current_shifts
+            .iter()
+            .filter(|x| x.available_slots.is_empty() && x.soft_conflict_slots.is_empty())
+            .cloned()
+            .collect();
+        track_unresolved(
+            jira.as_ref(),
+            &pd_schedule_id,
+            start_time,
+            end_time,
+            &find_conflicts(&stuck),
+        )
+        .await;
+        return Err(anyhow!("Folks with zero slots available")
+            .context(Outcome::Unresolvable)
+            .context(
+                "Failed to generate schedule because there are folks who can't be scheduled",
+            ));
     };
 
-    let (rescheduled_shifts, swaps) = recursive_solution(&current_shifts, Vec::new())?;
+    for conflict in current_shifts
+        .iter()
+        .filter(|x| has_conflicts(&x.pd_schedule, &x.available_slots))
+    {
+        say!("Found conflict: {:?}", conflict.pd_schedule)
+    }
+    for conflict in find_conflicts(&current_shifts) {
+        Event::new("conflict")
+            .user(&conflict.email)
+            .slot(&conflict.start.to_rfc3339(), &conflict.end.to_rfc3339())
+            .decision(format!(
+                "{} with {}",
+                if conflict.soft { "soft busy" } else { "busy" },
+                conflict.reasons.join(", ")
+            ))
+            .emit();
+    }
+
+    say!(
+        "\nSolving with seed {}. Re-run with --seed {} to reproduce this plan.",
+        seed,
+        seed
+    );
+    let secondary_schedule = match &secondary_schedule_id {
+        Some(secondary_schedule_id) => oncall_provider
+            .fetch_schedule(secondary_schedule_id, start_time, end_time)
+            .await
+            .context("Failed to get secondary pd schedule")?,
+        None => Vec::new(),
+    };
+    let secondaries = secondaries_by_slot(&current_shifts, &secondary_schedule);
+    let swap_requests_sheet = config.sheets.as_ref().and_then(|x| {
+        x.swap_requests_spreadsheet
+            .as_deref()
+            .map(|id| (id, x.swap_requests_range.as_str()))
+    });
+    let approved_swaps = match sheets.as_ref().zip(swap_requests_sheet) {
+        Some((sheets, (id, range))) => {
+            let requests = sheets.swap_requests(id, range).await?;
+            let (reviews, approved) = review_swap_requests(&current_shifts, &requests);
+            if !reviews.is_empty() {
+                say!("\n====Swap requests, accepted ones are part of the plan======");
+                say!("{}", table(&reviews));
+            }
+            approved
+        }
+        None => BTreeMap::new(),
+    };
+    let solver_options =
+        solver_options(&args, &config, &current_shifts, secondaries, approved_swaps)?;
+    let generated = match &args.plan {
+        Some(path) => Ok(vec![load_checked_plan(
+            path,
+            &pd_schedule_id,
+            &current_shifts,
+            &solver_options,
+        )?]),
+        None => generate_candidate_plans(&current_shifts, seed, args.candidates, &solver_options),
+    };
+    let candidate_plans = match generated {
+        Ok(plans) => plans,
+        Err(e) => {
+            report_unsolvable(
+                &current_shifts,
+                &e,
+                seed,
+                &solver_options,
+                jira.as_ref(),
+                &pd_schedule_id,
+                (start_time, end_time),
+            )
+            .await;
+            return Err(e.context(Outcome::Unresolvable));
+        }
+    };
+    let chosen_plan = choose_plan(candidate_plans, &solver_options.weights)?;
+    if chosen_plan.seed != seed {
+        say!(
+            "Using candidate plan from seed {}. Re-run with --seed {} to reproduce it.",
+            chosen_plan.seed,
+            chosen_plan.seed
+        );
+    }
+    let diff = match &solver_options.previous_assignments {
+        Some(previous_assignments) => {
+            let previous_schedule = apply_previous_plan(&current_shifts, previous_assignments);
+            let diff = generate_diff_of_shift(previous_schedule, chosen_plan.schedule.clone());
+            say!("\n====Changes from the previous plan======");
+            print_overrides_by_week(&diff);
+            diff
+        }
+        None => Vec::new(),
+    };
+    if let Some(path) = &args.save_plan {
+        match args.plan_format {
+            PlanFormat::Json => save_plan(path, &to_saved_plan(&chosen_plan))?,
+            PlanFormat::SchemaJson => save_plan(
+                path,
+                &export_plan(
+                    &chosen_plan,
+                    &current_shifts,
+                    &solver_options,
+                    &pd_schedule_id,
+                    (start_time, end_time),
+                )?,
+            )?,
+        }
+        say!("Saved the plan to {}", path.display());
+    }
+
     // TODO: Util function to print this properly
-    println!(
-        "\n========Simulating swaps. Note that these are sequential and stateful=============="
+    say!("\n========Simulating swaps. Note that these are sequential and stateful==============");
+    print_table(&chosen_plan.swaps);
+
+    let mut final_overrides = chosen_plan.overrides.clone();
+    for entry in final_overrides.iter_mut() {
+        let start = DateTime::parse_from_rfc3339(&entry.start_time_iso)?;
+        if is_holiday(start, &solver_options.holidays) {
+            entry.original_slot.push_str(" (holiday)");
+        }
+    }
+    say!("\n====Generating final diff against current schedule======");
+    print_overrides_by_week(&final_overrides);
+
+    if !chosen_plan.splits.is_empty() {
+        say!("\n====Slots split because the assignee is only busy for part of them======");
+        print_table(&chosen_plan.splits);
+    }
+
+    for entry in &final_overrides {
+        Event::new("plan")
+            .user(&entry.final_override)
+            .slot(&entry.start_time_iso, &entry.end_time_iso)
+            .decision(format!("takes over from {}", entry.original_assignee))
+            .emit();
+    }
+    let unresolved = chosen_plan.unresolved_conflicts();
+    for entity in &unresolved {
+        Event::new("plan")
+            .user(&entity.pd_schedule.email)
+            .slot(
+                &entity.pd_schedule.start.to_rfc3339(),
+                &entity.pd_schedule.end.to_rfc3339(),
+            )
+            .decision("unresolved, left as is")
+            .emit();
+    }
+    if !unresolved.is_empty() {
+        say!("\n====Conflicts the plan could not resolve. These slots are left as they are======");
+        print_table(
+            unresolved
+                .iter()
+                .map(|x| convert_to_zero_swaps(x.pd_schedule.clone())),
+        );
+        let unresolved: Vec<FinalEntity> = unresolved.into_iter().cloned().collect();
+        track_unresolved(
+            jira.as_ref(),
+            &pd_schedule_id,
+            start_time,
+            end_time,
+            &find_conflicts(&unresolved),
+        )
+        .await;
+    }
+
+    let secondary_overrides = if args.link_secondary {
+        mirrored_secondary_overrides(
+            &current_shifts,
+            &chosen_plan.schedule,
+            &secondary_schedule,
+            &solver_options.secondaries,
+            &config.pairings.follow,
+        )
+    } else {
+        Vec::new()
+    };
+    print_plan_summary(
+        &current_shifts,
+        &chosen_plan,
+        &solver_options,
+        &secondary_overrides,
+    );
+
+    // e.g. a re-run after an apply, whose overrides are part of the schedule now. There is no run
+    // to record or announce
+    if final_overrides.is_empty() && secondary_overrides.is_empty() {
+        say!("Nothing to apply, the schedule already matches the plan");
+        Event::new("apply").decision("nothing to apply").emit();
+        return Ok(());
+    }
+
+    let saved_plan = to_saved_plan(&chosen_plan);
+    let (run_id, new_run) = record_run(
+        store,
+        &pd_schedule_id,
+        (start_time, end_time),
+        chosen_plan.seed,
+        &saved_plan,
+    )?;
+    let wiki_page = SchedulePage {
+        schedule_id: &pd_schedule_id,
+        run_id,
+        rotation: &chosen_plan.schedule,
+        overrides: &final_overrides,
+        holidays: &solver_options.holidays,
+    };
+    let notifiers = Notifiers {
+        slack: notifier,
+        webhook: &webhook,
+        mailer: mailer.as_ref(),
+        workspace: workspace.as_ref(),
+        wiki: confluence.as_ref().map(|x| (x, &wiki_page)),
+        annotations: annotations.as_ref(),
+        audit: audit_log.as_ref().map(|x| {
+            let inputs = AuditInputs {
+                run: &new_run,
+                inputs_hash: inputs_hash(&current_shifts),
+            };
+            (x, inputs)
+        }),
+    };
+    let conflicts = find_conflicts(&current_shifts);
+    let report = PlanReport {
+        schedule_id: &pd_schedule_id,
+        run_id,
+        conflicts: &conflicts,
+        swaps: &chosen_plan.swaps,
+        overrides: &final_overrides,
+        diff: &diff,
+    };
+    notify_plan(
+        &report,
+        &notifiers,
+        &templates,
+        args.markdown_report.as_deref(),
+        sheets.as_ref().zip(plan_spreadsheet),
+    )
+    .await?;
+
+    let mut schedules = vec![(pd_schedule_id.as_str(), final_overrides.as_slice())];
+    if let Some(secondary_schedule_id) = &secondary_schedule_id {
+        schedules.push((secondary_schedule_id, &secondary_overrides));
+    }
+    let review = PlanReview {
+        schedule_id: &pd_schedule_id,
+        run_id,
+        original: &current_shifts,
+        rescheduled: &chosen_plan.schedule,
+        overrides: &final_overrides,
+    };
+    apply_plan(
+        &oncall_provider,
+        store,
+        &cache,
+        &notifiers,
+        &review,
+        &schedules,
+        args.serve,
+    )
+    .await
+}
+
+/// Report the conflicts of the current schedule, to slack and the webhook too. They are an error,
+/// so --check fails while there are any
+async fn report_conflicts(
+    current_shifts: &[FinalEntity],
+    schedule_id: &str,
+    notifier: &SlackNotifier<'_>,
+    webhook: &WebhookNotifier<'_>,
+) -> AnyhowResult<()> {
+    let conflicts = find_conflicts(current_shifts);
+    for conflict in &conflicts {
+        say!(
+            "Found conflict: {} from {} to {}: {}",
+            conflict.email,
+            conflict.start,
+            conflict.end,
+            conflict.reasons.join(", ")
+        );
+    }
+    notifier.new_conflicts(schedule_id, &conflicts).await;
+    webhook.conflicts_found(schedule_id, &conflicts).await;
+    match conflicts.len() {
+        0 => {
+            say!("No conflicts found");
+            Ok(())
+        }
+        n => Err(Outcome::ConflictsFound(n).into()),
+    }
+}
+
+/// Print the size of each shift of the window from `start_time` to `end_time`, and warn about
+/// the rotation not covering it evenly. With `strict` coverage, that's an error
+fn check_coverage(
+    current_shifts: &[FinalEntity],
+    shifts: &[Shift],
+    coverage: &Coverage,
+    (start_time, end_time): (DateTime<FixedOffset>, DateTime<FixedOffset>),
+) -> AnyhowResult<()> {
+    let mut shift_entries: BTreeMap<String, Vec<&FinalPagerDutySchedule>> = BTreeMap::new();
+    for entity in current_shifts {
+        shift_entries
+            .entry(shift_of(entity.pd_schedule.start, shifts))
+            .or_default()
+            .push(&entity.pd_schedule);
+    }
+    for (shift_type, entries) in &shift_entries {
+        say!(
+            "{} shift size is: {}. First shift is {:?}, last shift is {:?}",
+            shift_type,
+            entries.len(),
+            entries.first().unwrap().email,
+            entries.last().unwrap().email
+        );
+    }
+    let problems = coverage_problems(&shift_entries, start_time, end_time, coverage);
+    if coverage.strict && !problems.is_empty() {
+        return Err(anyhow!(
+            "{}. Adjust the window, or `coverage` in the config file",
+            problems.join(". ")
+        ));
+    }
+    for problem in problems {
+        say!(
+            "Warning. {}. Check that --start-date and --duration-days line up with the rotation, \
+            or set `coverage` in the config file",
+            problem
+        );
+    }
+    Ok(())
+}
+
+/// Print how much oncall everyone on the schedule had over the last `weeks`, from their `history`,
+/// and weigh it in their slots
+fn add_recent_loads(
+    current_shifts: &mut [FinalEntity],
+    history: &[FinalPagerDutySchedule],
+    weeks: i64,
+) {
+    let people: BTreeSet<String> = current_shifts
+        .iter()
+        .map(|x| x.pd_schedule.email.clone())
+        .collect();
+    let recent_loads = summarise_history(history, &people);
+    say!("\n====Oncall load over the last {} weeks======", weeks);
+    say!("{}", table(&recent_loads));
+    for entity in current_shifts.iter_mut() {
+        if let Some(recent) = recent_loads
+            .iter()
+            .find(|x| x.email == entity.pd_schedule.email)
+        {
+            entity.recent_load = recent.load_value;
+        }
+    }
+}
+
+/// Explain why no plan was found: the best partial plan of a search that ran out, and the fewest
+/// slots to take out of the schedule to make it solvable. The conflicts are tracked in jira
+async fn report_unsolvable(
+    current_shifts: &[FinalEntity],
+    error: &anyhow::Error,
+    seed: u64,
+    options: &SolverOptions,
+    jira: Option<&Jira<'_>>,
+    schedule_id: &str,
+    (start_time, end_time): (DateTime<FixedOffset>, DateTime<FixedOffset>),
+) {
+    if let Some(exhausted) = error.downcast_ref::<SearchExhausted>() {
+        print_partial_plan(current_shifts, exhausted);
+    }
+    let removal = minimal_removal(current_shifts, seed, options);
+    if !removal.is_empty() {
+        say!("\n====Smallest set of slots to take out of the schedule to make it solvable======");
+        say!(
+            "{}",
+            table(
+                removal
+                    .into_iter()
+                    .map(|x| convert_to_zero_swaps(x.pd_schedule))
+            )
+        );
+        say!("Talk to these people, or pass --allow-unresolved to solve the rest");
+    }
+    track_unresolved(
+        jira,
+        schedule_id,
+        start_time,
+        end_time,
+        &find_conflicts(current_shifts),
+    )
+    .await;
+}
+
+/// The weights of the config file, with those given on the command line instead
+fn weights(args: &Args, config: &Config) -> Weights {
+    let mut weights = config.weights.clone();
+    if let Some(value) = args.weekend_weight {
+        weights.weekend = value;
+    }
+    if let Some(value) = args.preference_weight {
+        weights.preference = value;
+    }
+    weights
+}
+
+/// What the solver may do, from the command line and the config file
+fn solver_options(
+    args: &Args,
+    config: &Config,
+    current_shifts: &[FinalEntity],
+    secondaries: BTreeMap<DateTime<FixedOffset>, String>,
+    approved_swaps: BTreeMap<DateTime<FixedOffset>, String>,
+) -> AnyhowResult<SolverOptions> {
+    Ok(SolverOptions {
+        max_shifts_per_person: args.max_shifts_per_person,
+        max_consecutive_days: args.max_consecutive_days,
+        max_cycle_length: args.max_cycle_length,
+        weights: weights(args, config),
+        max_swaps: args.max_swaps,
+        max_depth: args.max_depth,
+        max_duration: args.max_solve_seconds.map(StdDuration::from_secs),
+        attempts: args.attempts,
+        min_split_segment: args
+            .split_shifts
+            .then(|| Duration::hours(args.min_split_hours)),
+        swap_window: args.swap_window_days.map(Duration::days),
+        blocked_swaps: config.blocked_swaps.clone(),
+        holidays: config.holiday_dates()?,
+        secondaries,
+        keep_paired: config.pairings.keep.clone(),
+        never_paired: config.pairings.never.clone(),
+        previous_assignments: match &args.previous_plan {
+            Some(path) => Some(load_plan(path)?.saved().assignments()?),
+            None => None,
+        },
+        approved_swaps,
+        allow_unresolved: args.allow_unresolved,
+        time_budget: args.max_seconds.map(StdDuration::from_secs),
+        shifts: config.shift_definitions()?,
+        preferred_shifts: current_shifts
+            .iter()
+            .filter_map(|x| {
+                let email = &x.pd_schedule.email;
+                let preferred = config.user(email).preferred_shift?;
+                Some((email.clone(), preferred))
+            })
+            .collect(),
+    })
+}
+
+/// The only candidate plan, or the one picked at the prompt among several, best first
+fn choose_plan(
+    mut candidate_plans: Vec<CandidatePlan>,
+    weights: &Weights,
+) -> AnyhowResult<CandidatePlan> {
+    if candidate_plans.len() == 1 {
+        return Ok(candidate_plans.remove(0));
+    }
+    say!("\n========Candidate plans, best first==============");
+    say!(
+        "{}",
+        table(summarise_candidate_plans(&candidate_plans, weights))
     );
-    println!("{}", Table::new(swaps));
+    let chosen = prompt_candidate_choice(candidate_plans.len())?;
+    Ok(candidate_plans.swap_remove(chosen))
+}
+
+/// Print what else there is to know about a plan: the soft conflicts it leaves, its mirrored
+/// secondary overrides, the pairings it breaks, why each override is needed, and its score
+fn print_plan_summary(
+    original: &[FinalEntity],
+    plan: &CandidatePlan,
+    options: &SolverOptions,
+    secondary_overrides: &[FinalOverride],
+) {
+    let soft_conflicts = soft_conflicts_left(&plan.schedule);
+    if !soft_conflicts.is_empty() {
+        say!("\n====Soft conflicts left in the plan======");
+        for soft_conflict in soft_conflicts {
+            say!("{}", soft_conflict);
+        }
+    }
+
+    if !secondary_overrides.is_empty() {
+        say!("\n====Mirrored overrides on the secondary schedule, so shadows follow their primary======");
+        print_overrides_by_week(secondary_overrides);
+    }
+
+    let broken = broken_pairings(original, &plan.schedule, options);
+    if !broken.is_empty() {
+        say!("\n====Pairings the plan could not keep======");
+        for pairing in broken {
+            say!("{}", pairing);
+        }
+    }
+
+    say!("\n====Why each override is needed======");
+    for explanation in explain_plan(original, &plan.schedule) {
+        say!("{}", explanation);
+    }
+
+    say!("\n====Shifts per person before and after the plan======");
+    print_table(summarise_shift_counts(
+        original,
+        &plan.schedule,
+        &options.holidays,
+    ));
+
+    say!("\n====Plan score, lower is better======");
+    say!("{}", table(summarise_plan_score(plan, &options.weights)));
+
+    if plan.stats.attempts > 0 {
+        say!("\n====Solver statistics======");
+        say!("{}", table(summarise_solver_stats(original, plan)));
+    }
+}
 
-    // TODO: Print this as a table for readability
-    let final_overrides = generate_diff_of_shift(current_shifts, rescheduled_shifts);
-    println!("\n====Generating final diff against current schedule======");
-    println!("{}", Table::new(&final_overrides));
+/// Record `plan` as a new run of `schedule_id` from `start_time` to `end_time`, returning its id and
+/// what was recorded
+fn record_run<'a>(
+    store: &StateStore,
+    schedule_id: &'a str,
+    (start_time, end_time): (DateTime<FixedOffset>, DateTime<FixedOffset>),
+    seed: u64,
+    plan: &'a SavedPlan,
+) -> AnyhowResult<(i64, NewRun<'a>)> {
+    let new_run = NewRun {
+        command_line: env::args().collect::<Vec<_>>().join(" "),
+        schedule_id,
+        window_start: start_time.to_rfc3339(),
+        window_end: end_time.to_rfc3339(),
+        seed,
+        plan,
+    };
+    let run_id = store.start_run(&new_run)?;
+    say!("Recorded as run {} in {}", run_id, store.location());
+    Ok((run_id, new_run))
+}
+
+/// Announce a recorded plan to slack and the webhook, write it to `markdown_report` and to a new
+/// tab of the plan spreadsheet. Only failing to write the markdown report is an error
+async fn notify_plan(
+    report: &PlanReport<'_>,
+    notifiers: &Notifiers<'_>,
+    templates: &Templates,
+    markdown_report: Option<&Path>,
+    plan_spreadsheet: Option<(&GoogleSheets<'_>, &str)>,
+) -> AnyhowResult<()> {
+    if let Some(path) = markdown_report {
+        // the markdown template always exists, there is a built-in one
+        let markdown = templates.render(MARKDOWN, report).unwrap()?;
+        fs::write(path, markdown).context(format!("Failed to write {}", path.display()))?;
+        say!("Wrote the markdown report to {}", path.display());
+    }
+    notifiers.slack.plan_ready(report, templates).await;
+    notifiers
+        .webhook
+        .plan_ready(
+            report.schedule_id,
+            report.run_id,
+            report.conflicts,
+            report.overrides,
+        )
+        .await;
+    if let Some((sheets, spreadsheet)) = plan_spreadsheet {
+        let tab = format!("{} run {}", report.schedule_id, report.run_id);
+        // like slack, the plan is already recorded, so failing to write it isn't fatal
+        match sheets
+            .write_plan(spreadsheet, &tab, report.conflicts, report.overrides)
+            .await
+        {
+            Ok(_) => say!("Wrote the plan to the tab {} of the spreadsheet", tab),
+            Err(e) => say!(
+                "Warning. Failed to write the plan to google sheets: {:#}",
+                e
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Ask whether to apply the overrides of a recorded run, on the dashboard at port `serve` if
+/// there is one and at the prompt otherwise, then apply them or record that they were declined
+async fn apply_plan(
+    provider: &impl OncallProvider,
+    store: &StateStore,
+    cache: &Cache,
+    notifiers: &Notifiers<'_>,
+    review: &PlanReview<'_>,
+    schedules: &[(&str, &[FinalOverride])],
+    serve: Option<u16>,
+) -> AnyhowResult<()> {
+    let run_id = review.run_id;
+    if let Some(port) = serve {
+        let (server, mut decisions) = start_dashboard(port, render_review(review))?;
+        let handle = server.handle();
+        tokio::spawn(server);
+        say!(
+            "Review the plan at http://127.0.0.1:{}, and apply or discard it there",
+            port
+        );
+        let decision = decisions
+            .recv()
+            .await
+            .context("Dashboard stopped without a decision")?;
+        let result = if decision.apply {
+            apply_run(provider, store, cache, notifiers, run_id, schedules).await
+        } else {
+            say!("Skipping scheduling of overrides");
+            store.set_outcome(run_id, RunOutcome::Declined)
+        };
+        let message = match (&result, decision.apply) {
+            (Ok(_), true) => format!("Applied the overrides of run {}", run_id),
+            (Ok(_), false) => format!("Discarded run {}", run_id),
+            (Err(e), _) => format!("Failed to apply the overrides of run {}: {:#}", run_id, e),
+        };
+        let _ = decision.reply.send(message);
+        handle.stop(true).await;
+        return result;
+    }
 
     // TODO: Prompt user whether they want the program to do the overrides
     let mut user_override_prompt = "".to_string();
-    println!("Do you want to automatically schedule the overrides? (y/n)");
+    say!("Do you want to automatically schedule the overrides? (y/n)");
     match io::stdin().read_line(&mut user_override_prompt) {
         Ok(_) => match user_override_prompt.as_str().trim() {
-            "y" => {
-                println!("Scheduling overrides...");
-                let formatted_override: Vec<OverrideEntry> = final_overrides
-                    .into_iter()
-                    .map(|x| OverrideEntry {
-                        start: x.start_time_iso,
-                        end: x.end_time_iso,
-                        user: OverrideUser {
-                            id: x.pd_user_id,
-                            r#type: "user_reference".to_string(),
-                        },
-                    })
-                    .collect();
-                schedule_overrides(&client, &api_key, &pd_schedule_id, formatted_override)
-                    .await
-                    .context("Failed to schedule overrides")?;
-
-                Ok(())
-            }
+            "y" => apply_run(provider, store, cache, notifiers, run_id, schedules).await,
             "n" => {
-                println!("Skipping scheduling of overrides");
+                say!("Skipping scheduling of overrides");
+                Event::new("apply").decision("declined").emit();
+                store.set_outcome(run_id, RunOutcome::Declined)?;
                 Ok(())
             }
             _ => Err(anyhow!("Unrecognised input {}", user_override_prompt)),
         },
         Err(e) => Err(e).context("Failed to accept user input"),
     }
-    // Ok(())
+}
+
+/// The [credential] `name` if it's `needed`, kept out of what `client` logs
+fn secret(
+    client: &HttpClient,
+    name: &str,
+    replaying: bool,
+    needed: bool,
+) -> AnyhowResult<Option<String>> {
+    if !needed {
+        return Ok(None);
+    }
+    let value = credential(name, replaying)?;
+    client.keep_secret(&value);
+    Ok(Some(value))
+}
+
+/// An environment variable holding a credential. A replay never reaches the apis, so it runs
+/// without credentials
+fn credential(name: &str, replaying: bool) -> AnyhowResult<String> {
+    match env::var(name) {
+        Err(_) if replaying => Ok("replay".to_string()),
+        value => value.context(format!("Expected environment variable {} to be set", name)),
+    }
+}
+
+/// Open a jira ticket for conflicts left to people, when the config file has a [jira] section.
+/// Like slack, failing to is only a warning
+async fn track_unresolved(
+    jira: Option<&Jira<'_>>,
+    schedule_id: &str,
+    start: DateTime<FixedOffset>,
+    end: DateTime<FixedOffset>,
+    conflicts: &[Conflict],
+) {
+    let Some(jira) = jira else {
+        return;
+    };
+    if conflicts.is_empty() {
+        return;
+    }
+    match jira.open_ticket(schedule_id, start, end, conflicts).await {
+        Ok(key) => say!("Opened {} for the unresolved conflicts", key),
+        Err(e) => say!("Warning. Failed to open a jira ticket: {:#}", e),
+    }
+}
+
+/// Whether google accepts `token`, and it reaches every one of `spreadsheets`
+async fn check_google_token(
+    args: &Args,
+    client: &HttpClient,
+    token: &str,
+    spreadsheets: &[&str],
+) -> AnyhowResult<()> {
+    let google_api_url = args.base_url.as_deref().unwrap_or(GOOGLE_API_URL);
+    check_token_validity(client, google_api_url, token).await?;
+    let sheets_api_url = args.base_url.as_deref().unwrap_or(SHEETS_API_URL);
+    for id in spreadsheets {
+        check_spreadsheet(client, sheets_api_url, token, id)
+            .await
+            .context(format!("Cannot reach the spreadsheet {}", id))?;
+    }
+    Ok(())
+}
+
+/// The google token of the last run, or a new one from the oauth flow when there is none or it
+/// expired. With `spreadsheets`, the token must also be able to reach them
+async fn sign_in_to_google(
+    args: &Args,
+    paths: &Paths,
+    client: &HttpClient,
+    spreadsheets: &[&str],
+) -> AnyhowResult<String> {
+    let replaying = args.replay.is_some();
+    let scopes = match spreadsheets.is_empty() {
+        true => vec![CALENDAR_SCOPE],
+        false => vec![CALENDAR_SCOPE, SHEETS_SCOPE],
+    };
+    let google_client_id = credential("GOOGLE_CLIENT_ID", replaying)?;
+    let google_client_secret = credential("GOOGLE_CLIENT_SECRET", replaying)?;
+    let token_file = paths.token_file(args.token_file.as_deref())?;
+    let token = match fs::read_to_string(&token_file) {
+        Err(_e) if replaying => Ok("replay".to_string()),
+        Err(_e) => {
+            say!(
+                "Local token file {} not found. Triggering oauth flow.",
+                token_file.display()
+            );
+            get_oauth_token(&google_client_id, &google_client_secret, &scopes).await
+        }
+        Ok(value) => Ok(value),
+    }
+    .context("Failed to get token from oauth flow")?;
+
+    // check token expiry and trigger oauth if expired
+    let token = match check_google_token(args, client, &token, spreadsheets).await {
+        Err(e) if matches!(e.downcast_ref(), Some(AuthError::Unauthorized)) => {
+            say!("Unauthorised. Trying to get new token.");
+            get_oauth_token(&google_client_id, &google_client_secret, &scopes)
+                .await
+                .context("Failed to get oauth token when trying to refresh after unauthorised")?
+        }
+        Err(e) => return Err(e).context("Non-unauthorised error, not refreshing token"),
+        Ok(_) => token,
+    };
+    if !replaying {
+        fs::write(&token_file, &token).context("Unable to write token file")?;
+    }
+    client.keep_secret(&token);
+    Ok(token)
+}
+
+/// Everyone told about an apply
+struct Notifiers<'a> {
+    slack: &'a SlackNotifier<'a>,
+    webhook: &'a WebhookNotifier<'a>,
+    /// emails everyone affected, with --email-affected
+    mailer: Option<&'a EmailNotifier<'a>>,
+    /// keeps the slack oncall group pointing at whoever is oncall
+    workspace: Option<&'a SlackWorkspace<'a>>,
+    /// keeps the team's wiki page of the schedule in sync
+    wiki: Option<(&'a Confluence<'a>, &'a SchedulePage<'a>)>,
+    /// marks the overrides applied on grafana dashboards
+    annotations: Option<&'a GrafanaAnnotations<'a>>,
+    /// keeps a record of each apply in a bucket
+    audit: Option<(&'a AuditLog<'a>, AuditInputs<'a>)>,
+}
+
+/// Apply the overrides of every schedule, record the outcome of the run and notify slack and the
+/// webhook of it. Once applied, everyone affected is emailed if there is a mailer, the slack
+/// oncall group is updated and the confluence page is rewritten. Whatever got applied, even when
+/// some overrides failed, is annotated in grafana and recorded in the audit log
+async fn apply_run(
+    provider: &impl OncallProvider,
+    store: &StateStore,
+    cache: &Cache,
+    notifiers: &Notifiers<'_>,
+    run_id: i64,
+    schedules: &[(&str, &[FinalOverride])],
+) -> AnyhowResult<()> {
+    let result = apply_schedules(provider, store, run_id, schedules).await;
+    // even a failed apply may have changed the schedules, so a re-run must see their overrides
+    cache.forget(Namespace::Schedules);
+    if let Some(annotations) = notifiers.annotations {
+        if let Err(e) = annotate_applied(annotations, store, run_id, schedules).await {
+            say!(
+                "Warning. Failed to annotate the overrides in grafana: {:#}",
+                e
+            );
+        }
+    }
+    if let Some((audit_log, inputs)) = &notifiers.audit {
+        let uploaded = match store.active_overrides(run_id) {
+            Ok(applied) => {
+                let error = result.as_ref().err().map(|e| format!("{:#}", e));
+                audit_log
+                    .upload(inputs, run_id, schedules, &applied, error)
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+        match uploaded {
+            Ok(key) => say!("Recorded the run in the audit log as {}", key),
+            Err(e) => say!(
+                "Warning. Failed to record the run in the audit log: {:#}",
+                e
+            ),
+        }
+    }
+    notifiers
+        .slack
+        .applied(schedules[0].0, run_id, &result)
+        .await;
+    notifiers.webhook.applied(schedules, run_id, &result).await;
+    if let (Ok(_), Some(workspace)) = (&result, notifiers.workspace) {
+        if let Err(e) = sync_oncall_group(provider, workspace, schedules[0].0).await {
+            say!("Warning. Failed to update the slack oncall group: {:#}", e);
+        }
+    }
+    if let (Ok(_), Some((confluence, page))) = (&result, notifiers.wiki) {
+        let published = match store.runs(RECENT_RUNS) {
+            Ok(runs) => confluence.publish(&render_page(page, &runs)).await,
+            Err(e) => Err(e),
+        };
+        match published {
+            Ok(id) => say!("Updated confluence page {}", id),
+            Err(e) => say!("Warning. Failed to update the confluence page: {:#}", e),
+        }
+    }
+    if let (Ok(_), Some(mailer)) = (&result, notifiers.mailer) {
+        for (schedule_id, overrides) in schedules {
+            // the overrides are in place already, so a failed email is only worth a warning
+            match mailer.email_affected(schedule_id, overrides).await {
+                Ok(sent) => say!(
+                    "Emailed {} people affected on schedule {}",
+                    sent,
+                    schedule_id
+                ),
+                Err(e) => say!("Warning. Failed to email the people affected: {:#}", e),
+            }
+        }
+    }
+    result
+}
+
+/// Annotate the overrides of `schedules` that the run put in place, leaving out those applied by
+/// earlier runs
+async fn annotate_applied(
+    annotations: &GrafanaAnnotations<'_>,
+    store: &StateStore,
+    run_id: i64,
+    schedules: &[(&str, &[FinalOverride])],
+) -> AnyhowResult<()> {
+    let applied = store.active_overrides(run_id)?;
+    for (schedule_id, overrides) in schedules {
+        let new: Vec<&FinalOverride> = overrides
+            .iter()
+            .filter(|entry| {
+                applied.iter().any(|(applied_schedule, x)| {
+                    applied_schedule == schedule_id
+                        && x.start == entry.start_time_iso
+                        && x.pd_user_id == entry.pd_user_id
+                })
+            })
+            .collect();
+        if new.is_empty() {
+            continue;
+        }
+        let count = annotations.annotate(schedule_id, run_id, &new).await?;
+        say!(
+            "Annotated {} overrides of schedule {} in grafana",
+            count,
+            schedule_id
+        );
+    }
+    Ok(())
+}
+
+/// Make whoever holds the current slot of `schedule_id` the members of the slack oncall group
+async fn sync_oncall_group(
+    provider: &impl OncallProvider,
+    workspace: &SlackWorkspace<'_>,
+    schedule_id: &str,
+) -> AnyhowResult<()> {
+    if workspace.config.oncall_group.is_none() {
+        return Ok(());
+    }
+    // only the instant matters, whoever holds it is the same in any timezone
+    let now: DateTime<FixedOffset> = Utc::now().into();
+    let entries = provider
+        .fetch_schedule(schedule_id, now, now + Duration::minutes(1))
+        .await?;
+    let emails: BTreeSet<String> = entries
+        .into_iter()
+        .filter(|x| x.start <= now && now < x.end)
+        .map(|x| x.email)
+        .collect();
+    workspace
+        .set_oncall_group(&emails.into_iter().collect::<Vec<_>>())
+        .await
+}
+
+async fn apply_schedules(
+    provider: &impl OncallProvider,
+    store: &StateStore,
+    run_id: i64,
+    schedules: &[(&str, &[FinalOverride])],
+) -> AnyhowResult<()> {
+    say!("Scheduling overrides...");
+    let mut outcome = RunOutcome::Applied;
+    for (schedule_id, overrides) in schedules {
+        let applied = apply_new_overrides(provider, store, run_id, schedule_id, overrides).await;
+        match applied {
+            Ok(true) => {}
+            Ok(false) => outcome = RunOutcome::PartiallyApplied,
+            Err(e) => {
+                store.set_outcome(run_id, RunOutcome::Failed)?;
+                return Err(e).context(format!(
+                    "Failed to schedule overrides on schedule {}",
+                    schedule_id
+                ));
+            }
+        }
+    }
+    store.set_outcome(run_id, outcome)?;
+    if outcome == RunOutcome::PartiallyApplied {
+        return Err(Outcome::PartiallyApplied.into());
+    }
+    Ok(())
+}
+
+/// Apply the overrides not already applied by an earlier run, and record them against `run_id`.
+/// Returns false when the provider rejected some of them
+async fn apply_new_overrides(
+    provider: &impl OncallProvider,
+    store: &StateStore,
+    run_id: i64,
+    schedule_id: &str,
+    overrides: &[FinalOverride],
+) -> AnyhowResult<bool> {
+    let mut new_overrides = Vec::new();
+    for entry in overrides {
+        match store.applied_by(schedule_id, entry)? {
+            Some(previous_run) => {
+                say!(
+                    "Skipping override of {} from {}, already applied by run {}",
+                    entry.final_override,
+                    entry.start_time_iso,
+                    previous_run
+                );
+                Event::new("apply")
+                    .user(&entry.final_override)
+                    .slot(&entry.start_time_iso, &entry.end_time_iso)
+                    .decision(format!("already applied by run {}", previous_run))
+                    .emit();
+            }
+            None => new_overrides.push(entry.clone()),
+        }
+    }
+    if new_overrides.is_empty() {
+        return Ok(true);
+    }
+    let applied = provider
+        .apply_overrides(schedule_id, &new_overrides)
+        .await?;
+    store.record_applied(run_id, schedule_id, &applied)?;
+    for entry in &new_overrides {
+        let decision = match applied
+            .iter()
+            .find(|x| same_instant(&x.start, &entry.start_time_iso))
+        {
+            Some(created) => format!("applied as override {}", created.id),
+            None => "rejected".to_string(),
+        };
+        Event::new("apply")
+            .user(&entry.final_override)
+            .slot(&entry.start_time_iso, &entry.end_time_iso)
+            .decision(decision)
+            .emit();
+    }
+    Ok(applied.len() == new_overrides.len())
+}
+
+/// Delete the overrides a run applied that are still in place
+async fn undo_run(
+    provider: &impl OncallProvider,
+    store: &StateStore,
+    run_id: i64,
+) -> AnyhowResult<()> {
+    let active = store.active_overrides(run_id)?;
+    if active.is_empty() {
+        say!("Run {} has no overrides left to undo", run_id);
+        return Ok(());
+    }
+    for (schedule_id, entry) in active {
+        provider
+            .remove_override(&schedule_id, &entry.id)
+            .await
+            .context(format!("Failed to undo run {}", run_id))?;
+        store.mark_undone(&schedule_id, &entry.id)?;
+        say!(
+            "Removed override {} on schedule {} from {} to {}",
+            entry.id,
+            schedule_id,
+            entry.start,
+            entry.end
+        );
+    }
+    store.set_outcome(run_id, RunOutcome::Undone)
 }
 
 // Final displays for table
@@ -223,566 +2105,1955 @@ struct ZeroSwaps {
 
 fn convert_to_zero_swaps(input: FinalPagerDutySchedule) -> ZeroSwaps {
     ZeroSwaps {
+        start: display_time(input.start, Some(&input.email)),
+        end: display_time(input.end, Some(&input.email)),
         email: input.email,
-        start: input.start.format("%c").to_string(),
-        end: input.end.format("%c").to_string(),
     }
 }
 
-#[derive(Tabled, Debug, Clone)]
-struct SimulatedSwap {
-    person_with_conflict: String,
-    original_slot: String,
-    swapped_with: String,
-    new_slot: String,
+#[derive(Tabled)]
+struct RunSummary {
+    run: i64,
+    started_at: String,
+    schedule: String,
+    window: String,
+    seed: u64,
+    outcome: String,
+    overrides_in_place: usize,
+}
+
+#[derive(Tabled)]
+struct CandidateSummary {
+    rank: usize,
+    seed: u64,
+    overrides: usize,
+    simulated_swaps: usize,
+    max_overrides_per_person: usize,
+    weekend_imbalance: String,
+    preferences_met: usize,
+    back_to_back: usize,
+    score: String,
+}
+
+#[derive(Tabled)]
+struct ScoreComponent {
+    component: String,
+    value: String,
+    weight: String,
+    contribution: String,
+}
+
+#[derive(Tabled)]
+struct SolverStatistic {
+    statistic: String,
+    value: String,
 }
 
 #[derive(Tabled)]
-struct FinalOverride {
-    original_slot: String,
-    original_assignee: String,
-    final_override: String,
-    start_time_iso: String,
-    end_time_iso: String,
-    pd_user_id: String,
+struct ShiftCountSummary {
+    email: String,
+    shifts_before: usize,
+    shifts_after: usize,
+    weekend_before: usize,
+    weekend_after: usize,
+    holiday_before: usize,
+    holiday_after: usize,
 }
 
 // End
 
-#[derive(Debug, Clone)]
-struct FinalEntity {
-    pd_schedule: FinalPagerDutySchedule,
-    available_slots: Vec<OncallSlot>,
+fn summarise_runs(runs: &[StoredRun]) -> Vec<RunSummary> {
+    runs.iter()
+        .map(|run| RunSummary {
+            run: run.id,
+            started_at: run.started_at.clone(),
+            schedule: run.schedule_id.clone(),
+            window: format!("{} to {}", run.window_start, run.window_end),
+            seed: run.seed,
+            outcome: run.outcome.clone(),
+            overrides_in_place: run.active_overrides,
+        })
+        .collect()
+}
+
+fn summarise_candidate_plans(plans: &[CandidatePlan], weights: &Weights) -> Vec<CandidateSummary> {
+    plans
+        .iter()
+        .enumerate()
+        .map(|(i, plan)| {
+            let soft_score = plan.soft_score();
+            CandidateSummary {
+                rank: i + 1,
+                seed: plan.seed,
+                overrides: plan.overrides.len(),
+                simulated_swaps: plan.swaps.len(),
+                max_overrides_per_person: plan.max_overrides_per_person(),
+                weekend_imbalance: format!("{:.2}", soft_score.weekend_imbalance),
+                preferences_met: soft_score.preferences_met,
+                back_to_back: soft_score.back_to_back,
+                score: format!("{:.2}", plan.score(weights)),
+            }
+        })
+        .collect()
+}
+
+/// Break the plan's score down into its weighted components, with the total last
+fn summarise_plan_score(plan: &CandidatePlan, weights: &Weights) -> Vec<ScoreComponent> {
+    let soft_score = plan.soft_score();
+    let components = [
+        ("overrides", plan.overrides.len() as f64, weights.overrides),
+        (
+            "weekend imbalance",
+            soft_score.weekend_imbalance,
+            weights.weekend,
+        ),
+        (
+            "holiday imbalance",
+            soft_score.holiday_imbalance,
+            weights.holiday,
+        ),
+        (
+            "preferences met",
+            soft_score.preferences_met as f64,
+            -weights.preference,
+        ),
+        (
+            "back to back slots",
+            soft_score.back_to_back as f64,
+            weights.back_to_back,
+        ),
+        (
+            "recent load of people taking overrides",
+            plan.absorbed_load(),
+            weights.history,
+        ),
+        (
+            "recent load of weekend holders",
+            soft_score.history_load,
+            weights.history,
+        ),
+        ("cost matrix", soft_score.external_cost, weights.cost),
+        (
+            "soft conflict coverage",
+            soft_score.soft_conflict_coverage,
+            weights.soft_conflict,
+        ),
+    ];
+    let mut summary: Vec<ScoreComponent> = components
+        .iter()
+        .map(|(component, value, weight)| ScoreComponent {
+            component: component.to_string(),
+            value: format!("{:.2}", value),
+            weight: format!("{:.2}", weight),
+            contribution: format!("{:.2}", value * weight),
+        })
+        .collect();
+    summary.push(ScoreComponent {
+        component: "total".to_string(),
+        value: "".to_string(),
+        weight: "".to_string(),
+        contribution: format!("{:.2}", plan.score(weights)),
+    });
+    summary
+}
+
+#[derive(Tabled)]
+struct RecentLoad {
+    email: String,
+    shifts: usize,
+    weekend_shifts: usize,
+    load: String,
+    #[tabled(skip)]
+    load_value: f64,
 }
 
-impl PartialEq for FinalEntity {
-    fn eq(&self, other: &Self) -> bool {
-        self.pd_schedule.email == other.pd_schedule.email
-            && self.pd_schedule.start == other.pd_schedule.start
-            && self.pd_schedule.end == other.pd_schedule.end
+/// Shifts each of `people` held in the past `history`, and their load: how many more shifts plus
+/// weekend shifts they carried than the average person
+fn summarise_history(
+    history: &[FinalPagerDutySchedule],
+    people: &BTreeSet<String>,
+) -> Vec<RecentLoad> {
+    if people.is_empty() {
+        return Vec::new();
     }
+    let count = |email: &str, weekend_only: bool| {
+        history
+            .iter()
+            .filter(|x| x.email == email && (!weekend_only || is_weekend(x.start)))
+            .count()
+    };
+    let counts: Vec<(String, usize, usize)> = people
+        .iter()
+        .map(|email| (email.clone(), count(email, false), count(email, true)))
+        .collect();
+    let mean_shifts = counts.iter().map(|x| x.1).sum::<usize>() as f64 / counts.len() as f64;
+    let mean_weekend = counts.iter().map(|x| x.2).sum::<usize>() as f64 / counts.len() as f64;
+    counts
+        .into_iter()
+        .map(|(email, shifts, weekend_shifts)| {
+            let load = (shifts as f64 - mean_shifts) + (weekend_shifts as f64 - mean_weekend);
+            RecentLoad {
+                email,
+                shifts,
+                weekend_shifts,
+                load: format!("{:.2}", load),
+                load_value: load,
+            }
+        })
+        .collect()
 }
 
-fn recursive_solution(
-    schedule: &Vec<FinalEntity>,
-    mut swaps: Vec<SimulatedSwap>,
-) -> AnyhowResult<(Vec<FinalEntity>, Vec<SimulatedSwap>)> {
-    let (most_restrictive_option, rest) = find_conflicts(schedule);
-    if swaps.is_empty() {
-        let mut conflicts = rest
-            .clone()
-            .into_iter()
-            .map(|x| x.pd_schedule)
-            .collect::<Vec<_>>();
-        let restrictive_formatted = most_restrictive_option.clone().unwrap().pd_schedule;
-        conflicts.push(restrictive_formatted);
-        for conflict in conflicts {
-            println!("Found conflict: {:?}", conflict)
-        }
-    }
-    // println!("most restrictive conflict: {:?}", &most_restrictive_option);
-
-    // if this doesn't exist, we assume it's already solved and this is the termination condition. else, proceed
-    let most_restrict_conflict = match most_restrictive_option {
-        None => return Ok((schedule.clone(), swaps)), // termination condition
-        Some(value) => {
-            assert_eq!(rest.len(), schedule.len() - 1);
-            value
+/// Secondary oncall at the start of every slot in `schedule`
+fn secondaries_by_slot(
+    schedule: &[FinalEntity],
+    secondary_schedule: &[FinalPagerDutySchedule],
+) -> BTreeMap<DateTime<FixedOffset>, String> {
+    schedule
+        .iter()
+        .filter_map(|entity| {
+            let start = entity.pd_schedule.start;
+            secondary_schedule
+                .iter()
+                .find(|x| x.start <= start && start < x.end)
+                .map(|x| (start, x.email.clone()))
+        })
+        .collect()
+}
+
+/// Load the plan at `path` for --plan, and fail if it breaks any constraint
+fn load_checked_plan(
+    path: &Path,
+    schedule_id: &str,
+    schedule: &[FinalEntity],
+    options: &SolverOptions,
+) -> AnyhowResult<CandidatePlan> {
+    let plan_file = load_plan(path)?;
+    let saved = plan_file.saved();
+    let assignments = saved.assignments()?;
+    let unknown: BTreeSet<&String> = assignments
+        .values()
+        .filter(|email| !schedule.iter().any(|x| &x.pd_schedule.email == *email))
+        .collect();
+    if !unknown.is_empty() {
+        return Err(anyhow!(
+            "{} assigns slots to people outside the schedule: {:?}",
+            path.display(),
+            unknown
+        ));
+    }
+    let missing: Vec<String> = assignments
+        .keys()
+        .filter(|start| {
+            !schedule
+                .iter()
+                .any(|x| same_slot(x.pd_schedule.start, **start))
+        })
+        .map(|start| display_time(*start, None))
+        .collect();
+    if !missing.is_empty() {
+        return Err(anyhow!(
+            "{} has slots that are not in the schedule, plan again: {:?}",
+            path.display(),
+            missing
+        ));
+    }
+    let rescheduled = apply_previous_plan(schedule, &assignments);
+    let violations = validate_plan(schedule, &rescheduled, options);
+    if !violations.is_empty() {
+        say!("\n====Constraints the plan breaks======");
+        for violation in &violations {
+            say!("{}", violation);
+        }
+        return Err(anyhow!(
+            "{} breaks {} constraints",
+            path.display(),
+            violations.len()
+        ));
+    }
+    let plan = CandidatePlan::new(
+        saved.seed,
+        schedule,
+        rescheduled,
+        Vec::new(),
+        &[],
+        &options.holidays,
+    );
+    if let PlanFile::Exported(exported) = &plan_file {
+        check_exported_plan(path, exported, schedule_id, schedule, &plan)?;
+    }
+    Ok(plan)
+}
+
+/// The chosen plan in the versioned format of schemas/plan.v1.json
+fn export_plan(
+    plan: &CandidatePlan,
+    schedule: &[FinalEntity],
+    options: &SolverOptions,
+    schedule_id: &str,
+    (start, end): (DateTime<FixedOffset>, DateTime<FixedOffset>),
+) -> AnyhowResult<ExportedPlan> {
+    // the slots of an exported plan can't say which part of a slot someone took
+    if !plan.splits.is_empty() {
+        return Err(anyhow!(
+            "Plans splitting shifts can't be exported as schema-json, save them as json or plan without --split-shifts"
+        ));
+    }
+    let saved = to_saved_plan(plan);
+    Ok(ExportedPlan {
+        schema_version: PLAN_SCHEMA_VERSION,
+        schedule_id: schedule_id.to_string(),
+        window_start: start.to_rfc3339(),
+        window_end: end.to_rfc3339(),
+        inputs_hash: inputs_hash(schedule),
+        constraints: options.exported_constraints(),
+        overrides: plan.overrides.iter().map(ExportedOverride::from).collect(),
+        slots: saved.slots,
+        provenance: Provenance {
+            generator: format!("gcal-pagerduty {}", env!("CARGO_PKG_VERSION")),
+            generated_at: Utc::now().to_rfc3339(),
+            seed: plan.seed,
+            command_line: env::args().collect::<Vec<_>>().join(" "),
+        },
+    })
+}
+
+/// An exported plan is applied as it was approved, so it must be for this schedule, solved from
+/// the same schedule and calendars, and its overrides must be exactly those its slots make
+fn check_exported_plan(
+    path: &Path,
+    exported: &ExportedPlan,
+    schedule_id: &str,
+    schedule: &[FinalEntity],
+    plan: &CandidatePlan,
+) -> AnyhowResult<()> {
+    if exported.schedule_id != schedule_id {
+        return Err(anyhow!(
+            "{} is a plan for schedule {}, not {}",
+            path.display(),
+            exported.schedule_id,
+            schedule_id
+        ));
+    }
+    if exported.inputs_hash != inputs_hash(schedule) {
+        return Err(anyhow!(
+            "The schedule or calendars changed since {} was exported, plan again",
+            path.display()
+        ));
+    }
+    let sorted = |overrides: Vec<ExportedOverride>| {
+        let mut overrides = overrides;
+        overrides.sort_by(|a, b| (&a.start, &a.to_user_id).cmp(&(&b.start, &b.to_user_id)));
+        overrides
+    };
+    let expected = sorted(plan.overrides.iter().map(ExportedOverride::from).collect());
+    if sorted(exported.overrides.clone()) != expected {
+        return Err(anyhow!(
+            "The overrides of {} don't match its slots",
+            path.display()
+        ));
+    }
+    Ok(())
+}
+
+/// Slots the plan leaves someone oncall through a soft conflict, in slot order
+fn soft_conflicts_left(rescheduled: &[FinalEntity]) -> Vec<String> {
+    let mut schedule = rescheduled.to_vec();
+    schedule.sort_by_key(|x| x.pd_schedule.start);
+    schedule
+        .iter()
+        .filter_map(|x| {
+            x.soft_conflict().map(|meeting| {
+                format!(
+                    "{}: {} is oncall during {} ({:.0}% of the slot in soft conflicts)",
+                    display_time(x.pd_schedule.start, Some(&x.pd_schedule.email)),
+                    x.pd_schedule.email,
+                    meeting.summary,
+                    x.soft_conflict_coverage() * 100.0
+                )
+            })
+        })
+        .collect()
+}
+
+/// Overrides on the secondary schedule that move each followed shadow along with their primary:
+/// when a primary takes a new slot, their shadow swaps secondary slots with whoever was secondary
+/// there. The shadows' calendars are not checked
+fn mirrored_secondary_overrides(
+    original: &[FinalEntity],
+    rescheduled: &[FinalEntity],
+    secondary_schedule: &[FinalPagerDutySchedule],
+    secondaries: &BTreeMap<DateTime<FixedOffset>, String>,
+    follow: &[[String; 2]],
+) -> Vec<FinalOverride> {
+    let mut mirrored = secondaries.clone();
+    for entity in rescheduled.iter().filter(|x| !original.contains(x)) {
+        let (primary, slot) = (&entity.pd_schedule.email, entity.pd_schedule.start);
+        for [_, shadow] in follow.iter().filter(|[x, _]| x == primary) {
+            // the slot the pair originally shared
+            let shared = original.iter().find(|x| {
+                &x.pd_schedule.email == primary
+                    && x.pd_schedule.start != slot
+                    && secondaries.get(&x.pd_schedule.start) == Some(shadow)
+            });
+            let shared = match shared {
+                Some(value) if mirrored.get(&slot) != Some(shadow) => value.pd_schedule.start,
+                _ => continue,
+            };
+            if let (Some(at_slot), Some(at_shared)) =
+                (mirrored.get(&slot).cloned(), mirrored.get(&shared).cloned())
+            {
+                mirrored.insert(slot, at_shared);
+                mirrored.insert(shared, at_slot);
+            }
+        }
+    }
+    rescheduled
+        .iter()
+        .filter_map(|entity| {
+            let start = entity.pd_schedule.start;
+            let (before, after) = (secondaries.get(&start)?, mirrored.get(&start)?);
+            if before == after {
+                return None;
+            }
+            let pd_user_id = secondary_schedule
+                .iter()
+                .find(|x| &x.email == after)?
+                .pd_user_id
+                .clone();
+            Some(FinalOverride {
+                original_slot: display_time(start, Some(before)),
+                original_assignee: before.clone(),
+                final_override: after.clone(),
+                start_time_iso: start.to_rfc3339(),
+                end_time_iso: entity.pd_schedule.end.to_rfc3339(),
+                pd_user_id,
+            })
+        })
+        .collect()
+}
+
+/// Kept pairs the plan separates, e.g. because the primary had a conflict in the shared slot
+fn broken_pairings(
+    original: &[FinalEntity],
+    rescheduled: &[FinalEntity],
+    options: &SolverOptions,
+) -> Vec<String> {
+    let pairings = options.pairings();
+    original
+        .iter()
+        .filter(|x| !rescheduled.contains(x))
+        .filter_map(|x| {
+            pairings
+                .kept_with(&x.pd_schedule.email, x.pd_schedule.start)
+                .map(|secondary| {
+                    format!(
+                        "{}: {} is no longer paired with {}",
+                        display_time(x.pd_schedule.start, Some(&x.pd_schedule.email)),
+                        x.pd_schedule.email,
+                        secondary
+                    )
+                })
+        })
+        .collect()
+}
+
+fn summarise_shift_counts(
+    original: &[FinalEntity],
+    rescheduled: &[FinalEntity],
+    holidays: &BTreeSet<NaiveDate>,
+) -> Vec<ShiftCountSummary> {
+    let before = shift_counts(original);
+    let after = shift_counts(rescheduled);
+    let weekend_before = weekend_counts(original);
+    let weekend_after = weekend_counts(rescheduled);
+    let holiday_before = holiday_counts(original, holidays);
+    let holiday_after = holiday_counts(rescheduled, holidays);
+    let emails: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+    emails
+        .into_iter()
+        .map(|email| ShiftCountSummary {
+            email: email.clone(),
+            shifts_before: before.get(email).copied().unwrap_or(0),
+            shifts_after: after.get(email).copied().unwrap_or(0),
+            weekend_before: weekend_before.get(email).copied().unwrap_or(0),
+            weekend_after: weekend_after.get(email).copied().unwrap_or(0),
+            holiday_before: holiday_before.get(email).copied().unwrap_or(0),
+            holiday_after: holiday_after.get(email).copied().unwrap_or(0),
+        })
+        .collect()
+}
+
+/// Search statistics of the solve, and how the chosen plan changes everyone's slot count
+fn summarise_solver_stats(original: &[FinalEntity], plan: &CandidatePlan) -> Vec<SolverStatistic> {
+    let stats = &plan.stats;
+    let before = shift_counts(original);
+    let after = shift_counts(&plan.schedule);
+    let fairness_delta: Vec<String> = before
+        .keys()
+        .chain(after.keys())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .filter_map(|email| {
+            let delta = after.get(email).copied().unwrap_or(0) as i64
+                - before.get(email).copied().unwrap_or(0) as i64;
+            (delta != 0).then(|| format!("{} {:+}", email, delta))
+        })
+        .collect();
+    [
+        ("attempts", stats.attempts.to_string()),
+        ("failed attempts", stats.failed_attempts.to_string()),
+        ("iterations", stats.iterations.to_string()),
+        (
+            "candidate swaps evaluated",
+            stats.candidates_evaluated.to_string(),
+        ),
+        ("rotations", stats.rotations.to_string()),
+        ("dead ends", stats.dead_ends.to_string()),
+        (
+            "wall time",
+            format!("{:.2}s", stats.wall_time.as_secs_f64()),
+        ),
+        ("swaps in the plan", plan.swaps.len().to_string()),
+        ("slots gained or lost", fairness_delta.join(", ")),
+    ]
+    .into_iter()
+    .map(|(statistic, value)| SolverStatistic {
+        statistic: statistic.to_string(),
+        value,
+    })
+    .collect()
+}
+
+/// Show how far the solver got before its budget ran out
+fn print_partial_plan(original: &[FinalEntity], exhausted: &SearchExhausted) {
+    say!("\n{}", exhausted);
+    say!("\n====Swaps in the best partial plan======");
+    print_table(compact_swaps(&exhausted.best.swaps));
+    say!("\n====Best partial plan. It does NOT resolve every conflict======");
+    print_overrides_by_week(&generate_diff_of_shift(
+        original.to_vec(),
+        exhausted.best.schedule.clone(),
+    ));
+    say!("\n====Conflicts left in the partial plan======");
+    print_table(
+        exhausted
+            .best
+            .schedule
+            .iter()
+            .filter(|x| has_conflicts(&x.pd_schedule, &x.available_slots))
+            .map(|x| convert_to_zero_swaps(x.pd_schedule.clone())),
+    );
+    say!("Try raising --max-swaps, --max-depth or --max-solve-seconds, or another --seed");
+}
+
+/// Ask the user which ranked plan to use, returning its index
+fn prompt_candidate_choice(number_of_plans: usize) -> AnyhowResult<usize> {
+    let mut user_prompt = "".to_string();
+    say!(
+        "Which plan do you want to use? (1-{}, empty for 1)",
+        number_of_plans
+    );
+    io::stdin()
+        .read_line(&mut user_prompt)
+        .context("Failed to accept user input")?;
+    match user_prompt.trim() {
+        "" => Ok(0),
+        value => match value.parse::<usize>() {
+            Ok(rank) if rank >= 1 && rank <= number_of_plans => Ok(rank - 1),
+            _ => Err(anyhow!("Unrecognised plan {}", value)),
+        },
+    }
+}
+
+/// Who holds each slot of the schedule between `start_time` and `end_time`, and which slots
+/// their calendars and declared availability leave them free for
+#[instrument(skip_all, fields(%schedule_id))]
+async fn fetch_current_shifts(
+    oncall_provider: &impl OncallProvider,
+    calendar_provider: &impl AvailabilityProvider,
+    schedule_id: &str,
+    start_time: DateTime<FixedOffset>,
+    end_time: DateTime<FixedOffset>,
+    options: &AvailabilityOptions<'_>,
+) -> AnyhowResult<Vec<FinalEntity>> {
+    let pd_schedule = oncall_provider
+        .fetch_schedule(schedule_id, start_time, end_time)
+        .await
+        .context("Failed to get pd schedule")?;
+    let anomalies = schedule_anomalies(&pd_schedule, start_time, end_time);
+    if !anomalies.is_empty() {
+        say!(
+            "Warning. Schedule {} has entries that overlap, leave gaps or last no time at all, \
+            which can make plans look odd. Entries lasting no time are left out. Check the layers \
+            and overrides of the schedule:",
+            schedule_id
+        );
+        print_table(anomalies);
+    }
+    let pd_schedule = pd_schedule
+        .into_iter()
+        .filter(|x| x.end > x.start)
+        .collect();
+    let pd_schedule = merge_fragments(pd_schedule, options.shifts);
+    let (start_time, end_time) = covered_span(&pd_schedule, start_time, end_time);
+
+    // time off from HR is fetched for the whole window at once, and counts as declared
+    let timezone = options.timezone;
+    let mut declared = options.declared.clone();
+    if let Some(bamboohr) = options.bamboohr {
+        for (email, time_off) in bamboohr.time_off(start_time, end_time, timezone).await? {
+            declared.entry(email).or_default().merge(time_off);
+        }
+    }
+    if let Some(workday) = options.workday {
+        for (email, absences) in workday.absences(timezone).await? {
+            declared.entry(email).or_default().merge(absences);
+        }
+    }
+    let emails = pd_schedule.iter().map(|x| x.email.as_str()).collect();
+    if let Some(feeds) = options.holidays {
+        for (email, holidays) in feeds.holidays(&emails, start_time, end_time).await? {
+            declared.entry(email).or_default().merge(holidays);
+        }
+    }
+    if let Some(bookings) = options.bookings {
+        for (email, booked) in bookings.bookings(&emails, start_time, end_time).await? {
+            declared.entry(email).or_default().merge(booked);
+        }
+    }
+    let preferences_sheet = options.config.sheets.as_ref().and_then(|x| {
+        x.preferences_spreadsheet
+            .as_deref()
+            .map(|id| (id, x.preferences_range.as_str()))
+    });
+    if let Some((sheets, (id, range))) = options.sheets.zip(preferences_sheet) {
+        for (email, preferences) in sheets.preferences(id, range, timezone).await? {
+            declared.entry(email).or_default().merge(preferences);
         }
+    }
+    let options = &AvailabilityOptions {
+        declared: &declared,
+        ..*options
     };
 
-    // find best swap from remaining entries in schedule, and remove that from the list
-    let (best_swap_option, after_swap) =
-        find_potential_swap(&most_restrict_conflict, &rest, swaps.clone());
-    // println!("best swap: {:?}", &best_swap_option);
-    let best_swap = match best_swap_option {
-        None => {
-            let first_swap = &swaps.first().unwrap();
-            println!("No solution found. Suggestion, try removing {} with the leaast available slots and try again.", first_swap.person_with_conflict );
-            return Err(anyhow!("No solution"));
-        } // should panic? no swaps
-        Some(value) => {
-            assert_eq!(after_swap.len(), rest.len() - 1);
-            value
-        }
-    };
+    // slots come from the schedule itself, and entries starting at the same time of day form a shift
+    let oncall_slots = get_oncall_slots(&pd_schedule);
+    let mut shifts: BTreeMap<String, Vec<FinalPagerDutySchedule>> = BTreeMap::new();
+    for entry in pd_schedule {
+        shifts
+            .entry(shift_of(entry.start, options.shifts))
+            .or_default()
+            .push(entry);
+    }
 
-    // apply swap
-    let source_modified = FinalEntity {
-        pd_schedule: FinalPagerDutySchedule {
-            pd_user_id: most_restrict_conflict.pd_schedule.pd_user_id.clone(),
-            start: best_swap.pd_schedule.start,
-            end: best_swap.pd_schedule.end,
-            email: most_restrict_conflict.pd_schedule.email.clone(),
-        },
-        available_slots: most_restrict_conflict.clone().available_slots,
-    };
-    // println!("original conflicter: {:?}", most_restrict_conflict);
-    // println!("after modifed: {:?}", source_modified);
-    let destination_modified = FinalEntity {
-        pd_schedule: FinalPagerDutySchedule {
-            pd_user_id: best_swap.pd_schedule.pd_user_id.clone(),
-            start: most_restrict_conflict.pd_schedule.start,
-            end: most_restrict_conflict.pd_schedule.end,
-            email: best_swap.pd_schedule.email.clone(),
-        },
-        available_slots: best_swap.clone().available_slots,
-    };
-    // println!("original to swap: {:?}", best_swap);
-    // println!("swap modifed: {:?}", destination_modified);
-
-    let mut schedule_after_swapping = after_swap;
-    schedule_after_swapping.push(source_modified);
-    schedule_after_swapping.push(destination_modified);
-    assert_eq!(schedule_after_swapping.len(), schedule.len());
-    swaps.push(SimulatedSwap {
-        person_with_conflict: most_restrict_conflict.pd_schedule.email,
-        original_slot: most_restrict_conflict
-            .pd_schedule
-            .start
-            .format("%c")
-            .to_string(),
-        swapped_with: best_swap.pd_schedule.email,
-        new_slot: best_swap.pd_schedule.start.format("%c").to_string(),
+    let available_shifts_futures = shifts.into_iter().map(|(shift_type, shift)| {
+        get_available_shifts_per_user(
+            shift,
+            calendar_provider,
+            start_time,
+            end_time,
+            shift_type,
+            &oncall_slots,
+            options,
+        )
     });
-    if swaps.len() > 200 {
-        for swap in swaps.clone() {
-            println!("{:?}", swap);
-        }
-        // println!("No solution found. Suggestion, try removing {} with the leaast available slots and try again.", swaps.first().unwrap.person_with_conflict );
+    Ok(join_all(available_shifts_futures)
+        .await
+        .into_iter()
+        .collect::<AnyhowResult<Vec<Vec<FinalEntity>>>>()
+        .context("Join error when getting pd shifts")?
+        .into_iter()
+        .flatten()
+        .collect())
+}
 
-        return Err(anyhow!("No solution found. Suggestion, try removing {} with the least available slots and try again.", swaps.first().unwrap().person_with_conflict ));
-    }
-    // println!("{}", &swap_string);
-    recursive_solution(&schedule_after_swapping, swaps)
+/// How --watch goes about it
+struct WatchOptions {
+    interval: StdDuration,
+    /// how far ahead slots are checked
+    horizon: Duration,
+    /// slash commands to answer between checks, with --slack-commands
+    commands: Option<mpsc::Receiver<CommandRequest>>,
 }
 
-/// find the most restrictive conflict, and return: (most_restrictive_conflict, rest_with_conflict_removed)
-fn find_conflicts(available_shifts: &[FinalEntity]) -> (Option<FinalEntity>, Vec<FinalEntity>) {
-    let (mut remaining_pool, mut conflict_pool) =
-        available_shifts
-            .iter()
-            .fold((Vec::new(), Vec::new()), |acc, x| {
-                let current_slot = (&x.pd_schedule).clone();
-                let available_slots = (&x.available_slots).clone();
-                let mut pool = acc.0;
-                let mut conflicts = acc.1;
-                if has_conflicts(&current_slot, &available_slots) {
-                    conflicts.push(FinalEntity {
-                        pd_schedule: current_slot,
-                        available_slots,
-                    });
-                } else {
-                    pool.push(FinalEntity {
-                        pd_schedule: current_slot,
-                        available_slots,
-                    });
+/// Re-check the slots starting within `horizon` every `interval`, and print conflicts as they
+/// appear. A failed check is reported and retried on the next one
+async fn watch_schedule(
+    oncall_provider: &impl OncallProvider,
+    calendar_provider: &impl AvailabilityProvider,
+    notifier: &SlackNotifier<'_>,
+    schedule_id: &str,
+    options: &AvailabilityOptions<'_>,
+    watch_options: WatchOptions,
+) -> AnyhowResult<()> {
+    let WatchOptions {
+        interval,
+        horizon,
+        mut commands,
+    } = watch_options;
+    say!(
+        "Watching schedule {} for conflicts over the next {} days, checking every {} minutes",
+        schedule_id,
+        horizon.num_days(),
+        interval.as_secs() / 60
+    );
+    let mut watch = ConflictWatch::default();
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        let command = tokio::select! {
+            _ = ticker.tick() => None,
+            Some(request) = next_command(&mut commands) => Some(request),
+        };
+        if let Some(request) = command {
+            answer_command(
+                oncall_provider,
+                calendar_provider,
+                notifier.client,
+                schedule_id,
+                options,
+                horizon,
+                request,
+            )
+            .await;
+            continue;
+        }
+        let now = local_time(Utc::now(), options.timezone);
+        let checked = fetch_current_shifts(
+            oncall_provider,
+            calendar_provider,
+            schedule_id,
+            now,
+            now + horizon,
+            options,
+        )
+        .await;
+        match checked {
+            Ok(entities) => {
+                if let Some(workspace) = options.slack {
+                    if let Err(e) = sync_oncall_group(oncall_provider, workspace, schedule_id).await
+                    {
+                        say!("Warning. Failed to update the slack oncall group: {:#}", e);
+                    }
                 }
-                (pool, conflicts)
-            });
-    conflict_pool.sort_by(|a, b| a.available_slots.len().cmp(&b.available_slots.len()));
-    // remove first conflict and put the rest back into the pool
-    match conflict_pool.split_first() {
-        Some((most_restrictive, rest)) => {
-            let mut to_move = rest.to_vec();
-            remaining_pool.append(&mut to_move);
-            (Some(most_restrictive.clone()), remaining_pool)
+                let new_conflicts = watch.update(find_conflicts(&entities));
+                notifier.new_conflicts(schedule_id, &new_conflicts).await;
+                for conflict in new_conflicts {
+                    say!(
+                        "[{}] New {}: {} is oncall from {} to {} but busy with {}",
+                        display_time(now, None),
+                        if conflict.soft {
+                            "soft conflict"
+                        } else {
+                            "conflict"
+                        },
+                        conflict.email,
+                        display_time(conflict.start, Some(&conflict.email)),
+                        display_time(conflict.end, Some(&conflict.email)),
+                        conflict.reasons.join(", ")
+                    );
+                }
+            }
+            Err(e) => {
+                say!(
+                    "[{}] Warning. Check failed with error: {:#}. Retrying on the next check.",
+                    display_time(now, None),
+                    e
+                );
+                notifier.check_failed(&e).await;
+            }
         }
-        None => (None, remaining_pool),
     }
 }
 
-fn find_potential_swap(
-    // current_slot: &FinalPagerDutySchedule,
-    current_slot: &FinalEntity,
-    all_slots: &[FinalEntity],
-    swaps: Vec<SimulatedSwap>,
-) -> (Option<FinalEntity>, Vec<FinalEntity>) {
-    let mut potential_swaps: Vec<FinalEntity> = current_slot
-        .clone()
-        .available_slots
-        .into_iter()
-        .flat_map(|available_slot| {
-            all_slots.iter().filter(move |slot| {
-                slot.pd_schedule.start == available_slot.start_time
-                // && slot.pd_schedule.end == available_slot.end_time
-            })
-        })
-        .cloned()
-        .collect();
-    // potential_swaps.sort_by(|a, b| a.available_slots.len().cmp(&b.available_slots.len()));
-    let mut rng = rand::thread_rng();
-    potential_swaps.shuffle(&mut rng);
-    let last_swap = swaps.last();
-    if let Some(swap) = last_swap {
-        // println!("last_swap: {:?}", &last_swap);
-        // Remove the last swap from the pool to avoid a cyclic error
-        potential_swaps = potential_swaps
-            .into_iter()
-            .filter(|x| x.pd_schedule.email != swap.person_with_conflict)
-            .collect();
+/// The next slash command, or never without any
+async fn next_command(
+    commands: &mut Option<mpsc::Receiver<CommandRequest>>,
+) -> Option<CommandRequest> {
+    match commands {
+        Some(receiver) => receiver.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Carry out a slash command and reply in the channel it came from. Plans are only shown, applying
+/// them is left to a run
+async fn answer_command(
+    oncall_provider: &impl OncallProvider,
+    calendar_provider: &impl AvailabilityProvider,
+    client: &HttpClient,
+    schedule_id: &str,
+    options: &AvailabilityOptions<'_>,
+    horizon: Duration,
+    request: CommandRequest,
+) {
+    say!("Slack command from {}: {:?}", request.user, request.command);
+    let now = local_time(Utc::now(), options.timezone);
+    let (start, end) = match request.command {
+        SlashCommand::Plan { next_week: true } => {
+            let days_to_monday = 7 - now.weekday().num_days_from_monday() as i64;
+            let monday = now.date_naive() + Duration::days(days_to_monday);
+            get_start_end_time(monday, 7, options.timezone)
+        }
+        _ => (now, now + horizon),
     };
-    if swaps.len() >= 2 {
-        let last_last_swap = swaps.get(&swaps.len() - 2);
-        // println!("last_last_swap: {:?}", &last_last_swap);
-        if let Some(last_last_swap) = last_last_swap {
-            potential_swaps = potential_swaps
-                .into_iter()
-                .filter(|x| x.pd_schedule.email != last_last_swap.person_with_conflict)
-                .collect();
+    let answered = async {
+        let entities = fetch_current_shifts(
+            oncall_provider,
+            calendar_provider,
+            schedule_id,
+            start,
+            end,
+            options,
+        )
+        .await?;
+        let conflicts = find_conflicts(&entities);
+        if request.command == SlashCommand::Check {
+            return Ok(check_reply(schedule_id, &conflicts));
         }
+        let config = options.config;
+        let solver_options = SolverOptions {
+            weights: config.weights.clone(),
+            blocked_swaps: config.blocked_swaps.clone(),
+            holidays: config.holiday_dates()?,
+            keep_paired: config.pairings.keep.clone(),
+            never_paired: config.pairings.never.clone(),
+            // a preview shows what can be solved rather than nothing
+            allow_unresolved: true,
+            ..SolverOptions::default()
+        };
+        let overrides = match conflicts.is_empty() {
+            true => Vec::new(),
+            false => {
+                generate_candidate_plans(&entities, rand::random(), 1, &solver_options)?
+                    .remove(0)
+                    .overrides
+            }
+        };
+        Ok(plan_preview_reply(
+            schedule_id,
+            start,
+            end,
+            &conflicts,
+            &overrides,
+        ))
     }
-    // brute force for now and loop through another time
-    // TODO: Write the above as a fold and avoid another loop
-    let mut remaining_pool: Vec<FinalEntity> = all_slots
-        .iter()
-        .filter(|slot| !potential_swaps.contains(slot))
-        .cloned()
-        .collect();
-    match potential_swaps.split_first() {
-        Some((best_swap, rest)) => {
-            let mut to_move = rest.to_vec();
-            remaining_pool.append(&mut to_move);
-            (Some(best_swap.clone()), remaining_pool)
-        }
-        None => (None, remaining_pool),
+    .await
+    .unwrap_or_else(|e: anyhow::Error| failed_reply(&e.context("The slack command failed")));
+    if let Err(e) = reply(client, &request.response_url, answered).await {
+        say!("Warning. {:#}", e);
     }
-    // return potential_swaps;
 }
 
 async fn get_available_shifts_per_user(
     shifts: Vec<FinalPagerDutySchedule>,
-    client: &Client,
-    token: &str,
+    calendar_provider: &impl AvailabilityProvider,
     start_time_local: DateTime<FixedOffset>,
     end_time_local: DateTime<FixedOffset>,
-    duration_days: i64,
-    shift_type: &str,
+    shift_type: String,
+    oncall_slots: &[OncallSlot],
+    options: &AvailabilityOptions<'_>,
 ) -> AnyhowResult<Vec<FinalEntity>> {
-    let futures = shifts
-        .into_iter()
-        .map(|user_pd| get_user_calender(client, user_pd, token, start_time_local, end_time_local));
+    let futures = shifts.into_iter().map(|user_pd| async move {
+        let timezone = options.config.user_timezone(&user_pd.email)?;
+        get_user_calendar(
+            calendar_provider,
+            user_pd,
+            start_time_local,
+            end_time_local,
+            &options.config.soft_conflict_keywords,
+            options.private_events_busy,
+            timezone,
+        )
+        .await
+    });
 
-    let results: Vec<(FinalPagerDutySchedule, Vec<CalendarEvent>)> = join_all(futures)
+    let results: Vec<UserCalendar> = join_all(futures)
         .await
         .into_iter()
-        .collect::<AnyhowResult<Vec<(FinalPagerDutySchedule, Vec<CalendarEvent>)>>>()?;
+        .collect::<AnyhowResult<Vec<UserCalendar>>>()?;
+    let external = join_all(results.iter().map(|calendar| {
+        external_availability(
+            &options.config.availability_commands,
+            &calendar.pd_user.email,
+            start_time_local,
+            end_time_local,
+            options.timezone,
+        )
+    }))
+    .await
+    .into_iter()
+    .collect::<AnyhowResult<Vec<DeclaredAvailability>>>()?;
+    let now = local_time(Utc::now(), options.timezone);
+    let statuses = join_all(
+        results
+            .iter()
+            .map(|calendar| slack_status(options.slack, &calendar.pd_user.email, now)),
+    )
+    .await;
+    let external: Vec<DeclaredAvailability> = zip(external, statuses)
+        .map(|(mut external, status)| {
+            external.merge(status);
+            external
+        })
+        .collect();
 
     // availble oncall slots
 
-    let available_oncall_slots: Vec<Vec<OncallSlot>> = results
+    let available_oncall_slots: Vec<(Vec<OncallSlot>, Vec<OncallSlot>, Vec<OncallSlot>)> = results
         .iter()
-        .map(|(_user, user_events)| {
-            let available_slots = get_available_slots(
-                user_events,
-                shift_type,
-                start_time_local.date().format("%Y-%m-%d").to_string(),
-                duration_days,
+        .map(|calendar| {
+            let preferred_shift = options.config.user(&calendar.pd_user.email).preferred_shift;
+            // people can always move within their own shift, other shifts are opt-in
+            let allowed = |slot: &OncallSlot| {
+                let other_shift_type = shift_of(slot.start_time, options.shifts);
+                other_shift_type == shift_type
+                    || (options.allow_cross_shift
+                        && preferred_shift
+                            .as_ref()
+                            .is_none_or(|preferred| *preferred == other_shift_type))
+            };
+            let busy = |events: &[CalendarEvent]| BusyTimes::from_events(events, calendar.timezone);
+            let mut available_slots = get_available_slots(
+                &busy(&calendar.unavailable),
+                oncall_slots.iter().filter(|x| allowed(x)).cloned(),
             );
-            available_slots
+            available_slots.sort_by_key(|x| x.start_time);
+            let soft_busy = busy(&calendar.soft_unavailable);
+            let (soft_conflict_slots, available_slots): (Vec<OncallSlot>, Vec<OncallSlot>) =
+                available_slots
+                    .into_iter()
+                    .partition(|slot| slot_clashes(slot, &soft_busy));
+            let preferred = busy(&calendar.preferred);
+            let preferred_slots = available_slots
+                .iter()
+                .filter(|slot| slot_clashes(slot, &preferred))
+                .cloned()
+                .collect();
+            (available_slots, preferred_slots, soft_conflict_slots)
         })
-        .collect::<AnyhowResult<Vec<Vec<OncallSlot>>>>()?;
+        .collect();
 
-    let available_oncalls: Vec<FinalEntity> = zip(results, available_oncall_slots)
-        .map(|((user, _), available_slots)| FinalEntity {
-            pd_schedule: user,
-            available_slots,
-        })
+    let available_oncalls: Vec<FinalEntity> = zip(zip(results, available_oncall_slots), external)
+        .map(
+            |((calendar, (available_slots, preferred_slots, soft_conflict_slots)), external)| {
+                let mut entity = FinalEntity {
+                    busy: BusyInterval::from_events(&calendar.unavailable, calendar.timezone),
+                    soft_busy: BusyInterval::from_events(
+                        &calendar.soft_unavailable,
+                        calendar.timezone,
+                    ),
+                    soft_conflict_slots,
+                    recent_load: 0.0,
+                    slot_costs: BTreeMap::new(),
+                    pd_schedule: calendar.pd_user,
+                    available_slots,
+                    preferred_slots,
+                };
+                if let Some(declared) = options.declared.get(&entity.pd_schedule.email) {
+                    declared.apply(&mut entity);
+                }
+                external.apply(&mut entity);
+                entity
+            },
+        )
         .collect();
 
     Ok(available_oncalls)
 }
 
-#[derive(Debug, Clone)]
-struct OncallSlot {
-    start_time: DateTime<FixedOffset>,
-    end_time: DateTime<FixedOffset>,
+/// The time `email` is away according to their slack status. Statuses are a hint, so a failed
+/// lookup is only a warning
+async fn slack_status(
+    workspace: Option<&SlackWorkspace<'_>>,
+    email: &str,
+    now: DateTime<FixedOffset>,
+) -> DeclaredAvailability {
+    let Some(workspace) = workspace else {
+        return DeclaredAvailability::default();
+    };
+    workspace.away(email, now).await.unwrap_or_else(|e| {
+        say!(
+            "Warning. Failed to read the slack status of {}: {:#}",
+            email,
+            e
+        );
+        DeclaredAvailability::default()
+    })
 }
 
-/// Get oncall slots for a given shift for a date range
-fn get_oncall_slots(
-    shift_type: &str,
-    start_date: String,
-    duration_days: i64,
-) -> AnyhowResult<Vec<OncallSlot>> {
-    let start_time = match shift_type {
-        x if x == "AM" => "03:00",
-        x if x == "PM" => "15:00",
-        _ => "error",
-    };
-    let sgt_timezone = FixedOffset::east(8 * 60 * 60);
-    let start_datetime_string = format!("{} {}", start_date, start_time);
-    let start_time = NaiveDateTime::parse_from_str(&start_datetime_string, "%Y-%m-%d %H:%M")
-        .context(format!("Error parsing {}", &start_datetime_string))?;
-    let start_time_local = DateTime::<FixedOffset>::from_local(start_time, sgt_timezone);
-    let mut final_vec = Vec::new();
-    for i in 0..duration_days {
-        let shift_start_time = start_time_local
-            .checked_add_signed(Duration::days(i))
-            .unwrap();
-        let shift_end_time = shift_start_time
-            .checked_add_signed(Duration::hours(12))
-            .unwrap();
-        let slot = OncallSlot {
-            start_time: shift_start_time,
-            end_time: shift_end_time,
+/// Settings that decide which slots count as available for a person
+struct AvailabilityOptions<'a> {
+    config: &'a Config,
+    /// also offer slots from other shifts, limited to the person's preferred shift if they have one
+    allow_cross_shift: bool,
+    /// shifts defined in the config file
+    shifts: &'a [Shift],
+    /// of the schedule, from the config file
+    timezone: Tz,
+    /// availability from --availability-file, by email
+    declared: &'a HashMap<String, DeclaredAvailability>,
+    /// slack statuses, read when the config file has a [slack] section
+    slack: Option<&'a SlackWorkspace<'a>>,
+    /// approved time off, read when the config file has a [bamboohr] section
+    bamboohr: Option<&'a BambooHr<'a>>,
+    /// absences, read when the config file has a [workday] section
+    workday: Option<&'a Workday<'a>>,
+    /// preferences, read when the [sheets] section has a preferences_spreadsheet
+    sheets: Option<&'a GoogleSheets<'a>>,
+    /// regional public holidays, read when the config file has holiday_feeds
+    holidays: Option<&'a HolidayFeeds<'a>>,
+    /// external bookings, read when someone has a bookings feed or a calendly user
+    bookings: Option<&'a Bookings<'a>>,
+    /// private events are conflicts instead of being left out
+    private_events_busy: bool,
+}
+
+/// The window widened to the slots of `entries`, which can start before it or end after it.
+/// Calendars and declared availability are read over all of it, so busy times in the part of a
+/// slot outside the window count too
+fn covered_span(
+    entries: &[FinalPagerDutySchedule],
+    start: DateTime<FixedOffset>,
+    end: DateTime<FixedOffset>,
+) -> (DateTime<FixedOffset>, DateTime<FixedOffset>) {
+    entries.iter().fold((start, end), |(start, end), x| {
+        (start.min(x.start), end.max(x.end))
+    })
+}
+
+/// Every distinct slot of the schedule, taken from its entries so shifts of any length work
+fn get_oncall_slots(entries: &[FinalPagerDutySchedule]) -> Vec<OncallSlot> {
+    let slots: BTreeSet<(DateTime<FixedOffset>, DateTime<FixedOffset>)> =
+        entries.iter().map(|x| (x.start, x.end)).collect();
+    slots
+        .into_iter()
+        .map(|(start_time, end_time)| OncallSlot {
+            start_time,
+            end_time,
+        })
+        .collect()
+}
+
+/// Fail with guidance when the schedule has no slots in the window, and warn about slots that
+/// match none of the shifts defined in the config file
+fn check_slots(
+    entries: &[FinalPagerDutySchedule],
+    schedule_id: &str,
+    start: DateTime<FixedOffset>,
+    end: DateTime<FixedOffset>,
+    shifts: &[Shift],
+) -> AnyhowResult<()> {
+    let defined = shifts
+        .iter()
+        .map(|x| format!("{} at {} {}", x.name, x.start.format("%H:%M"), x.timezone))
+        .collect::<Vec<_>>();
+    if entries.is_empty() {
+        let filters = match defined.is_empty() {
+            true => {
+                "No shifts are defined in the config file, so any start time counts".to_string()
+            }
+            false => format!("Shifts defined in the config file: {}", defined.join(", ")),
         };
-        final_vec.push(slot);
+        return Err(anyhow!(
+            "Schedule {} has nobody oncall from {} to {}. {}. Check the schedule id, \
+            --start-date and --duration-days, and that the schedule has layers covering the window",
+            schedule_id,
+            start,
+            end,
+            filters
+        ));
+    }
+    let unmatched = unmatched_start_times(entries, shifts);
+    if !shifts.is_empty() && !unmatched.is_empty() {
+        say!(
+            "Warning. Slots starting at {} match none of the shifts defined in the config file \
+            ({}), and are grouped by their start time instead. Check `shifts` in the config file",
+            unmatched.join(", "),
+            defined.join(", ")
+        );
+    }
+    for (entry, shift) in drifted_slots(entries, shifts) {
+        say!(
+            "Warning. The slot from {} to {} held by {} starts an hour off the {} shift at {} {}, \
+            so the schedule's layer doesn't follow the DST changes of {}. Set the layer's \
+            timezone to {} in the oncall provider",
+            entry.start,
+            entry.end,
+            entry.email,
+            shift.name,
+            shift.start.format("%H:%M"),
+            shift.timezone,
+            shift.timezone,
+            shift.timezone
+        );
+    }
+    let starts = detect_shift_starts(entries);
+    say!(
+        "Shifts detected from the schedule: {}",
+        starts
+            .iter()
+            .map(|(start, slots)| format!("{} ({} slots)", start, slots))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    for entry in irregular_slots(entries, &starts) {
+        say!(
+            "Warning. The slot from {} to {} held by {} starts at {}, unlike the rest of the \
+            schedule, e.g. because of an override. It is planned as a shift of its own",
+            entry.start,
+            entry.end,
+            entry.email,
+            shift_of(entry.start, &[])
+        );
     }
-    Ok(final_vec)
+    Ok(())
 }
 
-// For every user, generate a list of "available shifts"
-fn get_available_slots(
-    user_events: &Vec<CalendarEvent>,
-    shift_type: &str,
-    start_date: String,
-    duration_days: i64,
-) -> AnyhowResult<Vec<OncallSlot>> {
-    let slots = get_oncall_slots(shift_type, start_date, duration_days)
-        .context("Failed to get oncall slots")?;
-    let available_slots: Vec<OncallSlot> = slots
+/// How many slots start at each time of day. Those shared by several slots are the shift
+/// boundaries of the schedule's layers
+fn detect_shift_starts(entries: &[FinalPagerDutySchedule]) -> BTreeMap<String, usize> {
+    let mut starts = BTreeMap::new();
+    for entry in entries {
+        *starts.entry(shift_of(entry.start, &[])).or_default() += 1;
+    }
+    starts
+}
+
+/// Shifts with more or fewer slots starting between `start` and `end` than `coverage` expects,
+/// e.g. when the window doesn't line up with the rotation. Slots straddling `start` don't count
+fn coverage_problems(
+    shift_entries: &BTreeMap<String, Vec<&FinalPagerDutySchedule>>,
+    start: DateTime<FixedOffset>,
+    end: DateTime<FixedOffset>,
+    coverage: &Coverage,
+) -> Vec<String> {
+    let days = (end - start).num_seconds() as f64 / 86400.0;
+    let mut shifts: BTreeSet<&String> = shift_entries.keys().collect();
+    shifts.extend(coverage.shifts.keys());
+    shifts
         .into_iter()
-        .filter(|oncall_slot| !slot_clashes(oncall_slot, user_events))
-        .collect();
-    Ok(available_slots)
+        .filter_map(|shift| {
+            let per_day = coverage.slots_per_day(shift);
+            if per_day <= 0.0 {
+                return None;
+            }
+            let expected = (days * per_day).round() as usize;
+            let slots = shift_entries.get(shift).map_or(0, |entries| {
+                entries
+                    .iter()
+                    .filter(|x| x.start >= start && x.start < end)
+                    .count()
+            });
+            // e.g. 1 or 0.14, for a weekly rotation
+            let rate = format!("{:.2}", per_day);
+            let rate = rate.trim_end_matches('0').trim_end_matches('.');
+            (slots != expected).then(|| {
+                format!(
+                    "Shift {} has {} slots starting in the {:.0} day window, {} expected at {} a day",
+                    shift, slots, days, expected, rate
+                )
+            })
+        })
+        .collect()
 }
 
-fn slot_clashes(oncall_slot: &OncallSlot, events: &Vec<CalendarEvent>) -> bool {
-    for event in events {
-        let event_start = convert_time_wrapper(event.start.as_ref().unwrap());
-        let event_end = convert_time_wrapper(event.end.as_ref().unwrap());
-        let oncall_start = oncall_slot.start_time;
-        let oncall_end = oncall_slot.end_time;
-        //https://stackoverflow.com/questions/325933/determine-whether-two-date-ranges-overlap
-        if event_start <= oncall_end && event_end >= oncall_start {
-            return true;
-        }
-    }
-    false
+/// Starts of the slots no plan may change: those under way or starting within `freeze_hours` of
+/// `now`, and those that have started already unless `force_past`
+fn frozen_slots(
+    schedule: &[FinalEntity],
+    now: DateTime<FixedOffset>,
+    freeze_hours: i64,
+    force_past: bool,
+) -> BTreeSet<DateTime<FixedOffset>> {
+    let horizon = now + Duration::hours(freeze_hours);
+    schedule
+        .iter()
+        .map(|x| &x.pd_schedule)
+        .filter(|x| {
+            let started = x.start < now;
+            let under_way = started && x.end > now;
+            (started && !force_past)
+                || (under_way && freeze_hours > 0)
+                || (!started && x.start < horizon)
+        })
+        .map(|x| x.start)
+        .collect()
+}
+
+/// Something off in the fetched schedule, which would otherwise only show as baffling plans
+#[derive(Tabled, Debug)]
+struct Anomaly {
+    /// overlap, gap or zero-length
+    kind: &'static str,
+    start: String,
+    end: String,
+    /// holders of the entries involved, none for a gap
+    users: String,
 }
 
-fn convert_time_wrapper(input: &TimeWrapper) -> DateTime<FixedOffset> {
-    let standard_format = "%Y-%m-%d %H:%M";
-    let sgt_timezone = FixedOffset::east(8 * 60 * 60);
-    let final_time = match input.date_string.clone() {
-        Some(value) => {
-            let naive = NaiveDateTime::parse_from_str(&format!("{} 00:00", value), standard_format)
-                .unwrap();
-            DateTime::<FixedOffset>::from_local(naive, sgt_timezone)
+/// Entries oncall at the same time, times between `start` and `end` nobody is oncall, and entries
+/// ending no later than they start
+fn schedule_anomalies(
+    entries: &[FinalPagerDutySchedule],
+    start: DateTime<FixedOffset>,
+    end: DateTime<FixedOffset>,
+) -> Vec<Anomaly> {
+    let anomaly = |kind, from, to, users: &[&str]| Anomaly {
+        kind,
+        start: display_time(from, None),
+        end: display_time(to, None),
+        users: users.join(", "),
+    };
+    let mut sorted: Vec<&FinalPagerDutySchedule> = entries.iter().collect();
+    sorted.sort_by_key(|x| (x.start, x.end));
+    let mut anomalies = Vec::new();
+    // the entry ending last so far, which any later one overlaps or leaves a gap after
+    let mut latest: Option<&FinalPagerDutySchedule> = None;
+    for entry in sorted {
+        if entry.end <= entry.start {
+            anomalies.push(anomaly(
+                "zero-length",
+                entry.start,
+                entry.end,
+                &[&entry.email],
+            ));
+            continue;
+        }
+        let covered = latest.map_or(start, |x| x.end.max(start));
+        match latest {
+            Some(previous) if entry.start < previous.end => anomalies.push(anomaly(
+                "overlap",
+                entry.start,
+                entry.end.min(previous.end),
+                &[&previous.email, &entry.email],
+            )),
+            _ if entry.start.min(end) > covered => {
+                anomalies.push(anomaly("gap", covered, entry.start.min(end), &[]))
+            }
+            _ => {}
         }
-        None => {
-            let x = input.date_time_string.clone().unwrap();
-            DateTime::<FixedOffset>::parse_from_rfc3339(&x).unwrap()
+        if latest.is_none_or(|x| entry.end > x.end) {
+            latest = Some(entry);
         }
+    }
+    let covered = latest.map_or(start, |x| x.end.max(start));
+    if covered < end {
+        anomalies.push(anomaly("gap", covered, end, &[]));
+    }
+    anomalies
+}
+
+/// Join back-to-back entries of the same person into one slot, when the time between them isn't a
+/// shift boundary: neither the start of a defined shift nor a start time other slots share. PD
+/// splits a shift like that where a layer changes or DST starts or ends, and the fragments
+/// would otherwise be swapped separately
+fn merge_fragments(
+    mut entries: Vec<FinalPagerDutySchedule>,
+    shifts: &[Shift],
+) -> Vec<FinalPagerDutySchedule> {
+    entries.sort_by_key(|x| x.start);
+    let starts = detect_shift_starts(&entries);
+    let is_boundary = |time: DateTime<FixedOffset>| {
+        shifts.iter().any(|x| x.starts_at(time)) || starts[&shift_of(time, &[])] > 1
     };
-    final_time
+    let mut merged: Vec<FinalPagerDutySchedule> = Vec::new();
+    for entry in entries {
+        match merged.last_mut() {
+            Some(last)
+                if last.pd_user_id == entry.pd_user_id
+                    && last.end == entry.start
+                    && !is_boundary(entry.start) =>
+            {
+                last.end = entry.end
+            }
+            _ => merged.push(entry),
+        }
+    }
+    merged
+}
+
+/// Slots starting at a time of day no other slot starts at, when the rest of the schedule repeats.
+/// In a window too short for anything to repeat, every slot fits
+fn irregular_slots<'a>(
+    entries: &'a [FinalPagerDutySchedule],
+    starts: &BTreeMap<String, usize>,
+) -> Vec<&'a FinalPagerDutySchedule> {
+    if starts.values().all(|&slots| slots < 2) {
+        return Vec::new();
+    }
+    entries
+        .iter()
+        .filter(|x| starts[&shift_of(x.start, &[])] == 1)
+        .collect()
 }
 
-/// find conflicts. I.e. his initial scheduled slot is not in the vector of available slots a person has
-fn has_conflicts(current_slot: &FinalPagerDutySchedule, available_slots: &[OncallSlot]) -> bool {
-    available_slots
+/// Slots starting an hour off one of `shifts` in its timezone, with that shift. Their layer keeps a
+/// fixed offset the shift's timezone doesn't, so its slots leave the shift at a DST change. Slots
+/// follow each other, so one ending off the shift shows up as the next one starting off it
+fn drifted_slots<'a>(
+    entries: &'a [FinalPagerDutySchedule],
+    shifts: &'a [Shift],
+) -> Vec<(&'a FinalPagerDutySchedule, &'a Shift)> {
+    entries
         .iter()
-        .filter(|slot| slot.start_time == current_slot.start)
-        .count()
-        == 0
+        .filter(|x| !shifts.iter().any(|shift| shift.starts_at(x.start)))
+        .filter_map(|x| {
+            shifts
+                .iter()
+                .find(|shift| shift.offset_from_start(x.start).num_minutes().abs() == 60)
+                .map(|shift| (x, shift))
+        })
+        .collect()
 }
 
-/// Get diff a shift. A loop of a loop, pretty inefficient
-/// Can be made better by pre-sorting both and zipping?
-fn generate_diff_of_shift(
-    mut initial_shifts: Vec<FinalEntity>,
-    mut final_shifts: Vec<FinalEntity>,
-) -> Vec<FinalOverride> {
-    let mut final_overrides = Vec::new();
-    // println!("\n====Generating final diff against current schedule======");
-    initial_shifts.sort_by(|a, b| a.pd_schedule.start.cmp(&b.pd_schedule.start));
-    final_shifts.sort_by(|a, b| a.pd_schedule.start.cmp(&b.pd_schedule.start));
-    let zipped = zip(initial_shifts, final_shifts);
-    for pair in zipped {
-        let (original, new) = pair;
-        assert!(original.pd_schedule.start == new.pd_schedule.start);
-        if original.pd_schedule.email != new.pd_schedule.email {
-            final_overrides.push(FinalOverride {
-                original_assignee: original.pd_schedule.email,
-                original_slot: original.pd_schedule.start.format("%c").to_string(),
-                final_override: new.pd_schedule.email,
-                start_time_iso: original.pd_schedule.start.format("%+").to_string(),
-                end_time_iso: original.pd_schedule.end.format("%+").to_string(),
-                pd_user_id: new.pd_schedule.pd_user_id,
-            });
-        }
-    }
-    final_overrides
+/// The distinct start times of the slots that belong to none of `shifts`, e.g. 09:00 +08:00
+fn unmatched_start_times(entries: &[FinalPagerDutySchedule], shifts: &[Shift]) -> Vec<String> {
+    let unmatched: BTreeSet<String> = entries
+        .iter()
+        .filter(|x| !shifts.iter().any(|shift| shift.starts_at(x.start)))
+        .map(|x| x.start.format("%H:%M %:z").to_string())
+        .collect();
+    unmatched.into_iter().collect()
+}
+
+// For every user, keep the slots they are available for
+fn get_available_slots(
+    busy: &BusyTimes,
+    slots: impl Iterator<Item = OncallSlot>,
+) -> Vec<OncallSlot> {
+    slots
+        .filter(|oncall_slot| !slot_clashes(oncall_slot, busy))
+        .collect()
+}
+
+fn slot_clashes(oncall_slot: &OncallSlot, busy: &BusyTimes) -> bool {
+    busy.overlaps(oncall_slot.start_time, oncall_slot.end_time)
+}
+
+/// Explain every override in the plan: why the original assignee had to give up the slot, and why
+/// the replacement is allowed to take it
+fn explain_plan(original: &[FinalEntity], rescheduled: &[FinalEntity]) -> Vec<String> {
+    let mut initial_shifts = original.to_vec();
+    let mut final_shifts = rescheduled.to_vec();
+    initial_shifts.sort_by_key(|a| a.pd_schedule.start);
+    final_shifts.sort_by_key(|a| a.pd_schedule.start);
+    // slots each person gains in the plan, to say where people were moved to
+    let gained_slots = |email: &str| -> Vec<String> {
+        zip(&initial_shifts, &final_shifts)
+            .filter(|(before, after)| {
+                after.pd_schedule.email == email && before.pd_schedule.email != email
+            })
+            .map(|(_, after)| display_time(after.pd_schedule.start, Some(email)))
+            .collect()
+    };
+    zip(&initial_shifts, &final_shifts)
+        .filter(|(before, after)| before.pd_schedule.email != after.pd_schedule.email)
+        .map(|(before, after)| {
+            let start = before.pd_schedule.start;
+            let end = before.pd_schedule.end;
+            let conflicting_events: Vec<String> = before
+                .busy
+                .iter()
+                .filter(|event| event.overlaps(start, end))
+                .map(|event| {
+                    format!(
+                        "\"{}\" ({} to {})",
+                        event.summary,
+                        display_time(event.start, Some(&before.pd_schedule.email)),
+                        display_time(event.end, Some(&before.pd_schedule.email))
+                    )
+                })
+                .collect();
+            let needed = if conflicting_events.is_empty() {
+                format!(
+                    "{} has no conflict here, but was moved to {} to make room for other swaps",
+                    before.pd_schedule.email,
+                    gained_slots(&before.pd_schedule.email).join(", ")
+                )
+            } else {
+                format!(
+                    "{} is busy with {}",
+                    before.pd_schedule.email,
+                    conflicting_events.join(", ")
+                )
+            };
+            let mut valid = if after.is_available_at(start, end) {
+                format!(
+                    "{} has no conflicting events in this slot",
+                    after.pd_schedule.email
+                )
+            } else {
+                format!(
+                    "{} is NOT free in this slot, please check",
+                    after.pd_schedule.email
+                )
+            };
+            if after.prefers(start, end) {
+                valid.push_str(" and asked for it with a prefer-oncall event");
+            }
+            format!(
+                "{}: {} -> {}\n  needed: {}\n  valid: {}",
+                display_time(start, Some(&before.pd_schedule.email)),
+                before.pd_schedule.email,
+                after.pd_schedule.email,
+                needed,
+                valid
+            )
+        })
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::calendar::TimeWrapper;
+    use crate::solver::tests::{test_entity, test_slot};
+    use crate::solver::{check_shift_limit, swap_penalty};
 
     #[test]
     fn test_get_oncall_slot() -> AnyhowResult<()> {
-        let slots = get_oncall_slots("AM", "2022-08-22".to_string(), 14)?;
-        assert!(slots.len() == 14);
+        let entry = |email: &str, start: &str, end: &str| FinalPagerDutySchedule {
+            pd_user_id: format!("id-{}", email),
+            start: DateTime::parse_from_rfc3339(start).unwrap(),
+            end: DateTime::parse_from_rfc3339(end).unwrap(),
+            email: email.to_string(),
+        };
+        // an 8 hour shift, a 24 hour shift, and the same slot twice
+        let slots = get_oncall_slots(&[
+            entry(
+                "b@x.com",
+                "2022-08-23T09:00:00+08:00",
+                "2022-08-24T09:00:00+08:00",
+            ),
+            entry(
+                "a@x.com",
+                "2022-08-22T09:00:00+08:00",
+                "2022-08-22T17:00:00+08:00",
+            ),
+            entry(
+                "a@x.com",
+                "2022-08-22T09:00:00+08:00",
+                "2022-08-22T17:00:00+08:00",
+            ),
+        ]);
+        assert!(slots.len() == 2);
         let first = slots.first().unwrap();
         assert_eq!(
             first.start_time.to_string(),
-            "2022-08-22 07:00:00 +08:00".to_string()
+            "2022-08-22 09:00:00 +08:00".to_string()
         );
         assert_eq!(
             first.end_time.to_string(),
-            "2022-08-22 15:00:00 +08:00".to_string()
+            "2022-08-22 17:00:00 +08:00".to_string()
         );
         let last = slots.last().unwrap();
+        assert_eq!(last.end_time - last.start_time, Duration::hours(24));
+        assert_eq!(shift_of(last.start_time, &[]), "09:00");
+        let eu = Shift {
+            name: "EU".to_string(),
+            start: chrono::NaiveTime::from_hms(3, 0, 0),
+            timezone: chrono_tz::Europe::Berlin,
+        };
+        assert_eq!(shift_of(last.start_time, &[eu]), "EU");
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_slots() {
+        let (start, end) = get_start_end_time(
+            parse_date("2022-08-29").unwrap(),
+            4,
+            chrono_tz::Asia::Singapore,
+        );
+        let shifts = [Shift {
+            name: "EU".to_string(),
+            start: chrono::NaiveTime::from_hms(3, 0, 0),
+            timezone: chrono_tz::Europe::Berlin,
+        }];
+        let error = check_slots(&[], "PPRIMARY", start, end, &shifts).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("Schedule PPRIMARY has nobody oncall"));
+        assert!(error.to_string().contains("EU at 03:00 Europe/Berlin"));
+        assert!(check_slots(&[], "PPRIMARY", start, end, &[]).is_err());
+
+        let entry = |start: &str| FinalPagerDutySchedule {
+            pd_user_id: "id-a".to_string(),
+            start: DateTime::parse_from_rfc3339(start).unwrap(),
+            end: DateTime::parse_from_rfc3339(start).unwrap() + Duration::hours(8),
+            email: "a@x.com".to_string(),
+        };
+        let entries = [
+            entry("2022-08-29T09:00:00+02:00"),
+            entry("2022-08-29T03:00:00+02:00"),
+            entry("2022-08-30T09:00:00+02:00"),
+        ];
+        assert!(check_slots(&entries, "PPRIMARY", start, end, &shifts).is_ok());
         assert_eq!(
-            last.start_time.to_string(),
-            "2022-09-04 07:00:00 +08:00".to_string()
+            unmatched_start_times(&entries, &shifts),
+            vec!["09:00 +02:00"]
+        );
+        assert!(drifted_slots(&entries, &shifts).is_empty());
+
+        // a layer kept at +08:00 starts the 09:00 Berlin shift at 08:00 once DST ends
+        let eu = [Shift {
+            start: chrono::NaiveTime::from_hms(9, 0, 0),
+            ..shifts[0].clone()
+        }];
+        let entries = [
+            entry("2022-10-28T15:00:00+08:00"),
+            entry("2022-10-31T15:00:00+08:00"),
+        ];
+        let drifted = drifted_slots(&entries, &eu);
+        assert_eq!(drifted.len(), 1);
+        assert_eq!(drifted[0].0.start, entries[1].start);
+    }
+
+    #[test]
+    fn test_frozen_slots() {
+        let days = [
+            "2022-08-29T03:00:00+08:00",
+            "2022-08-30T03:00:00+08:00",
+            "2022-08-31T03:00:00+08:00",
+            "2022-09-01T03:00:00+08:00",
+        ];
+        let schedule: Vec<FinalEntity> = days
+            .iter()
+            .map(|x| test_entity("a@x.com", x, &days))
+            .collect();
+        let starts = |frozen: BTreeSet<DateTime<FixedOffset>>| {
+            frozen
+                .into_iter()
+                .map(|x| x.to_rfc3339())
+                .collect::<Vec<_>>()
+        };
+        // the second slot is under way
+        let now = DateTime::parse_from_rfc3339("2022-08-30T12:00:00+08:00").unwrap();
+        assert_eq!(starts(frozen_slots(&schedule, now, 0, false)), days[..2]);
+        assert!(frozen_slots(&schedule, now, 0, true).is_empty());
+        // 18 hours reach past the start of the third slot, but not the fourth
+        assert_eq!(starts(frozen_slots(&schedule, now, 18, false)), days[..3]);
+        assert_eq!(starts(frozen_slots(&schedule, now, 18, true)), days[1..3]);
+    }
+
+    #[test]
+    fn test_schedule_anomalies() {
+        let entry = |email: &str, start: &str, hours: i64| FinalPagerDutySchedule {
+            pd_user_id: format!("id-{}", email),
+            start: DateTime::parse_from_rfc3339(start).unwrap(),
+            end: DateTime::parse_from_rfc3339(start).unwrap() + Duration::hours(hours),
+            email: email.to_string(),
+        };
+        let (start, end) = get_start_end_time(
+            parse_date("2022-08-29").unwrap(),
+            4,
+            chrono_tz::Asia::Singapore,
         );
+        let anomalies = |entries: &[FinalPagerDutySchedule]| {
+            schedule_anomalies(entries, start, end)
+                .into_iter()
+                .map(|x| format!("{} {} to {} {}", x.kind, x.start, x.end, x.users))
+                .collect::<Vec<_>>()
+        };
+        // back to back entries straddling the window are fine
+        let clean = [
+            entry("a@x.com", "2022-08-28T03:00:00+08:00", 48),
+            entry("b@x.com", "2022-08-30T03:00:00+08:00", 72),
+        ];
+        assert!(anomalies(&clean).is_empty());
+
+        let entries = [
+            entry("a@x.com", "2022-08-29T03:00:00+08:00", 24),
+            // b's override ends an hour into c's slot
+            entry("b@x.com", "2022-08-30T03:00:00+08:00", 25),
+            entry("c@x.com", "2022-08-31T03:00:00+08:00", 24),
+            entry("d@x.com", "2022-09-01T09:00:00+08:00", 0),
+        ];
         assert_eq!(
-            last.end_time.to_string(),
-            "2022-09-04 15:00:00 +08:00".to_string()
+            anomalies(&entries),
+            [
+                "gap Mon Aug 29 00:00:00 2022 to Mon Aug 29 03:00:00 2022 ",
+                "overlap Wed Aug 31 03:00:00 2022 to Wed Aug 31 04:00:00 2022 b@x.com, c@x.com",
+                "zero-length Thu Sep  1 09:00:00 2022 to Thu Sep  1 09:00:00 2022 d@x.com",
+                "gap Thu Sep  1 03:00:00 2022 to Fri Sep  2 00:00:00 2022 ",
+            ]
         );
-        Ok(())
     }
 
     #[test]
-    fn test_find_conflicts_false() {
-        let current_pd_shift = FinalPagerDutySchedule {
-            pd_user_id: "someid".to_string(),
-            start: DateTime::<FixedOffset>::parse_from_rfc3339("2022-08-30T07:00:00+08:00")
-                .unwrap(),
-            end: DateTime::<FixedOffset>::parse_from_rfc3339("2022-08-30T15:00:00+08:00").unwrap(),
-            email: "random.user@grabtaxi.com".to_string(),
+    fn test_coverage_problems() {
+        let entry = |start: &str| FinalPagerDutySchedule {
+            pd_user_id: "id-a".to_string(),
+            start: DateTime::parse_from_rfc3339(start).unwrap(),
+            end: DateTime::parse_from_rfc3339(start).unwrap() + Duration::hours(12),
+            email: "a@x.com".to_string(),
         };
-        let oncall_slots = vec![
-            OncallSlot {
-                start_time: DateTime::<FixedOffset>::parse_from_rfc3339(
-                    "2022-08-30T07:00:00+08:00",
-                )
-                .unwrap(),
-                end_time: DateTime::<FixedOffset>::parse_from_rfc3339("2022-08-30T15:00:00+08:00")
-                    .unwrap(),
-            },
-            OncallSlot {
-                start_time: DateTime::<FixedOffset>::parse_from_rfc3339(
-                    "2022-08-31T07:00:00+08:00",
-                )
-                .unwrap(),
-                end_time: DateTime::<FixedOffset>::parse_from_rfc3339("2022-08-31T15:00:00+08:00")
-                    .unwrap(),
-            },
+        let (start, end) = get_start_end_time(
+            parse_date("2022-08-29").unwrap(),
+            4,
+            chrono_tz::Asia::Singapore,
+        );
+        // the first slot starts before the window, and one day has no night slot
+        let entries = [
+            entry("2022-08-28T15:00:00+08:00"),
+            entry("2022-08-29T03:00:00+08:00"),
+            entry("2022-08-29T15:00:00+08:00"),
+            entry("2022-08-30T03:00:00+08:00"),
+            entry("2022-08-31T03:00:00+08:00"),
+            entry("2022-08-31T15:00:00+08:00"),
+            entry("2022-09-01T03:00:00+08:00"),
+            entry("2022-09-01T15:00:00+08:00"),
         ];
-        let result = has_conflicts(&current_pd_shift, &oncall_slots);
-        assert!(!result);
+        let mut shift_entries: BTreeMap<String, Vec<&FinalPagerDutySchedule>> = BTreeMap::new();
+        for entry in &entries {
+            shift_entries
+                .entry(shift_of(entry.start, &[]))
+                .or_default()
+                .push(entry);
+        }
+        let mut coverage = Coverage::default();
+        assert_eq!(
+            coverage_problems(&shift_entries, start, end, &coverage),
+            ["Shift 15:00 has 3 slots starting in the 4 day window, 4 expected at 1 a day"]
+        );
+
+        // a weekly handover expected, that never happens
+        coverage.shifts.insert("15:00".to_string(), 0.0);
+        coverage.shifts.insert("Weekly".to_string(), 1.0 / 7.0);
+        assert_eq!(
+            coverage_problems(&shift_entries, start, end, &coverage),
+            ["Shift Weekly has 0 slots starting in the 4 day window, 1 expected at 0.14 a day"]
+        );
+        coverage.slots_per_day = 0.0;
+        coverage.shifts.clear();
+        assert!(coverage_problems(&shift_entries, start, end, &coverage).is_empty());
     }
 
     #[test]
-    fn test_find_conflicts() {
-        let current_pd_shift = FinalPagerDutySchedule {
-            pd_user_id: "someid".to_string(),
-            start: DateTime::<FixedOffset>::parse_from_rfc3339("2022-08-30T07:00:00+08:00")
-                .unwrap(),
-            end: DateTime::<FixedOffset>::parse_from_rfc3339("2022-08-30T15:00:00+08:00").unwrap(),
-            email: "random.user@grabtaxi.com".to_string(),
+    fn test_merge_fragments() {
+        let entry = |email: &str, start: &str, hours: i64| FinalPagerDutySchedule {
+            pd_user_id: format!("id-{}", email),
+            start: DateTime::parse_from_rfc3339(start).unwrap(),
+            end: DateTime::parse_from_rfc3339(start).unwrap() + Duration::hours(hours),
+            email: email.to_string(),
         };
-        let oncall_slots = vec![
-            OncallSlot {
-                start_time: DateTime::<FixedOffset>::parse_from_rfc3339(
-                    "2022-08-29T07:00:00+08:00",
-                )
-                .unwrap(),
-                end_time: DateTime::<FixedOffset>::parse_from_rfc3339("2022-08-29T15:00:00+08:00")
-                    .unwrap(),
-            },
-            OncallSlot {
-                start_time: DateTime::<FixedOffset>::parse_from_rfc3339(
-                    "2022-08-31T07:00:00+08:00",
-                )
-                .unwrap(),
-                end_time: DateTime::<FixedOffset>::parse_from_rfc3339("2022-08-31T15:00:00+08:00")
-                    .unwrap(),
-            },
+        // a's shift on the 30th is split where a layer changes at 10:00
+        let entries = vec![
+            entry("b@x.com", "2022-08-31T03:00:00+08:00", 24),
+            entry("a@x.com", "2022-08-29T03:00:00+08:00", 24),
+            entry("a@x.com", "2022-08-30T03:00:00+08:00", 7),
+            entry("a@x.com", "2022-08-30T10:00:00+08:00", 17),
+        ];
+        let merged = merge_fragments(entries, &[]);
+        // a's two days stay separate slots, as other slots start at 03:00 too
+        assert_eq!(merged.len(), 3);
+        assert_eq!(merged[0].end, merged[1].start);
+        assert_eq!(merged[1].end - merged[1].start, Duration::hours(24));
+        assert_eq!(merged[2].email, "b@x.com");
+
+        // unless a shift is defined to start at 10:00
+        let entries = vec![
+            entry("a@x.com", "2022-08-30T03:00:00+08:00", 7),
+            entry("a@x.com", "2022-08-30T10:00:00+08:00", 17),
         ];
-        let result = has_conflicts(&current_pd_shift, &oncall_slots);
-        assert!(result);
+        let late = Shift {
+            name: "Late".to_string(),
+            start: chrono::NaiveTime::from_hms(10, 0, 0),
+            timezone: chrono_tz::Asia::Singapore,
+        };
+        assert_eq!(merge_fragments(entries.clone(), &[late]).len(), 2);
+        assert_eq!(merge_fragments(entries, &[]).len(), 1);
     }
 
     #[test]
-    fn test_recursive_solution_base_case() -> AnyhowResult<()> {
-        let schedule = vec![
-            FinalEntity {
-                pd_schedule: FinalPagerDutySchedule {
-                    pd_user_id: "someid".to_string(),
-                    start: DateTime::<FixedOffset>::parse_from_rfc3339("2022-08-30T07:00:00+08:00")
-                        .unwrap(),
-                    end: DateTime::<FixedOffset>::parse_from_rfc3339("2022-08-30T15:00:00+08:00")
-                        .unwrap(),
-                    email: "random.user@grabtaxi.com".to_string(),
-                },
-                available_slots: vec![
-                    OncallSlot {
-                        start_time: DateTime::<FixedOffset>::parse_from_rfc3339(
-                            "2022-08-29T07:00:00+08:00",
-                        )
-                        .unwrap(),
-                        end_time: DateTime::<FixedOffset>::parse_from_rfc3339(
-                            "2022-08-29T15:00:00+08:00",
-                        )
-                        .unwrap(),
-                    },
-                    OncallSlot {
-                        start_time: DateTime::<FixedOffset>::parse_from_rfc3339(
-                            "2022-08-31T07:00:00+08:00",
-                        )
-                        .unwrap(),
-                        end_time: DateTime::<FixedOffset>::parse_from_rfc3339(
-                            "2022-08-31T15:00:00+08:00",
-                        )
-                        .unwrap(),
-                    },
-                ],
-            },
-            FinalEntity {
-                pd_schedule: FinalPagerDutySchedule {
-                    pd_user_id: "someid".to_string(),
-                    start: DateTime::<FixedOffset>::parse_from_rfc3339("2022-08-31T07:00:00+08:00")
-                        .unwrap(),
-                    end: DateTime::<FixedOffset>::parse_from_rfc3339("2022-08-31T15:00:00+08:00")
-                        .unwrap(),
-                    email: "random.user2@grabtaxi.com".to_string(),
-                },
-                available_slots: vec![
-                    OncallSlot {
-                        start_time: DateTime::<FixedOffset>::parse_from_rfc3339(
-                            "2022-08-29T07:00:00+08:00",
-                        )
-                        .unwrap(),
-                        end_time: DateTime::<FixedOffset>::parse_from_rfc3339(
-                            "2022-08-29T15:00:00+08:00",
-                        )
-                        .unwrap(),
-                    },
-                    OncallSlot {
-                        start_time: DateTime::<FixedOffset>::parse_from_rfc3339(
-                            "2022-08-30T07:00:00+08:00",
-                        )
-                        .unwrap(),
-                        end_time: DateTime::<FixedOffset>::parse_from_rfc3339(
-                            "2022-08-30T15:00:00+08:00",
-                        )
-                        .unwrap(),
-                    },
-                ],
-            },
+    fn test_detect_shift_starts() {
+        let entry = |start: &str, hours: i64| FinalPagerDutySchedule {
+            pd_user_id: "id-a".to_string(),
+            start: DateTime::parse_from_rfc3339(start).unwrap(),
+            end: DateTime::parse_from_rfc3339(start).unwrap() + Duration::hours(hours),
+            email: "a@x.com".to_string(),
+        };
+        // 03:00 and 15:00 shifts, with an override from 10:00 splitting the second day
+        let entries = [
+            entry("2022-08-29T03:00:00+08:00", 12),
+            entry("2022-08-29T15:00:00+08:00", 12),
+            entry("2022-08-30T03:00:00+08:00", 7),
+            entry("2022-08-30T10:00:00+08:00", 5),
+            entry("2022-08-30T15:00:00+08:00", 12),
+        ];
+        let starts = detect_shift_starts(&entries);
+        assert_eq!(
+            starts.clone().into_iter().collect::<Vec<_>>(),
+            vec![
+                ("03:00".to_string(), 2),
+                ("10:00".to_string(), 1),
+                ("15:00".to_string(), 2)
+            ]
+        );
+        let irregular = irregular_slots(&entries, &starts);
+        assert_eq!(irregular.len(), 1);
+        assert_eq!(irregular[0].start, entries[3].start);
+
+        // a single day has nothing to compare against
+        let starts = detect_shift_starts(&entries[..2]);
+        assert!(irregular_slots(&entries[..2], &starts).is_empty());
+    }
+
+    #[test]
+    fn test_covered_span() {
+        let time = |x: &str| DateTime::parse_from_rfc3339(x).unwrap();
+        let entry = |start: &str, end: &str| FinalPagerDutySchedule {
+            pd_user_id: "id-a".to_string(),
+            start: time(start),
+            end: time(end),
+            email: "a@x.com".to_string(),
+        };
+        let (start, end) = get_start_end_time(
+            parse_date("2022-08-29").unwrap(),
+            4,
+            chrono_tz::Asia::Singapore,
+        );
+        assert_eq!(covered_span(&[], start, end), (start, end));
+        // the last slot ends three hours after the window
+        let entries = [
+            entry("2022-08-29T03:00:00+08:00", "2022-08-30T03:00:00+08:00"),
+            entry("2022-09-01T03:00:00+08:00", "2022-09-02T03:00:00+08:00"),
         ];
+        assert_eq!(
+            covered_span(&entries, start, end),
+            (start, time("2022-09-02T03:00:00+08:00"))
+        );
 
-        let (rescheduled, swaps) = recursive_solution(&schedule, Vec::new())?;
-        println!("\n========Simulating swaps==============");
-        println!("{}", Table::new(swaps));
+        // leave from before the window clashes with the slot straddling its start
+        let slot = OncallSlot {
+            start_time: time("2022-08-28T21:00:00+08:00"),
+            end_time: time("2022-08-29T09:00:00+08:00"),
+        };
+        let leave = vec![CalendarEvent {
+            visibility: None,
+            summary: Some("Leave".to_string()),
+            start: Some(TimeWrapper {
+                date_string: Some("2022-08-15".to_string()),
+                date_time_string: None,
+            }),
+            end: Some(TimeWrapper {
+                date_string: Some("2022-08-29".to_string()),
+                date_time_string: None,
+            }),
+            pagerduty: None,
+            event_type: None,
+            status: None,
+        }];
+        let busy = BusyTimes::from_events(&leave, chrono_tz::Asia::Singapore);
+        assert!(slot_clashes(&slot, &busy));
+    }
 
-        let final_overrides = generate_diff_of_shift(schedule, rescheduled);
-        println!("\n====Generating final diff against current schedule======");
-        println!("{}", Table::new(final_overrides));
-        Ok(())
+    #[test]
+    fn test_parse_inputs() {
+        assert_eq!(
+            parse_date("2022-08-22").map(|x| x.to_string()),
+            Ok("2022-08-22".to_string())
+        );
+        assert_eq!(
+            parse_date("22/08/2022"),
+            Err("expected a date as YYYY-MM-DD, e.g. 2022-08-22, got 22/08/2022".to_string())
+        );
+        assert!(parse_date("2022-02-30").is_err());
+        assert_eq!(positive::<i64>("7"), Ok(7));
+        assert_eq!(
+            positive::<i64>("-7"),
+            Err("expected a number above zero, got -7".to_string())
+        );
+        assert_eq!(positive::<f64>("0.5"), Ok(0.5));
+        assert!(positive::<f64>("0").is_err());
+        assert_eq!(
+            positive::<usize>("seven"),
+            Err("expected a number, got seven".to_string())
+        );
+        assert_eq!(not_negative::<i64>("0"), Ok(0));
+        assert!(not_negative::<i64>("-1").is_err());
+    }
+
+    #[test]
+    fn test_window_and_all_day_events_across_dst() {
+        let berlin = chrono_tz::Europe::Berlin;
+        // summer time ends on the sunday, which is 25 hours long
+        let (start, end) = get_start_end_time(parse_date("2022-10-29").unwrap(), 3, berlin);
+        assert_eq!(start.to_rfc3339(), "2022-10-29T00:00:00+02:00");
+        assert_eq!(end.to_rfc3339(), "2022-11-01T00:00:00+01:00");
+        assert_eq!(end - start, Duration::hours(73));
+
+        let slot = OncallSlot {
+            start_time: DateTime::parse_from_rfc3339("2022-10-30T18:00:00+01:00").unwrap(),
+            end_time: DateTime::parse_from_rfc3339("2022-10-30T23:30:00+01:00").unwrap(),
+        };
+        let day_off = vec![CalendarEvent {
+            visibility: None,
+            summary: Some("Out of office".to_string()),
+            start: Some(TimeWrapper {
+                date_string: Some("2022-10-31".to_string()),
+                date_time_string: None,
+            }),
+            end: Some(TimeWrapper {
+                date_string: Some("2022-11-01".to_string()),
+                date_time_string: None,
+            }),
+            pagerduty: None,
+            event_type: None,
+            status: None,
+        }];
+        // the day off starts at midnight in berlin, long after midnight in singapore
+        assert!(!slot_clashes(
+            &slot,
+            &BusyTimes::from_events(&day_off, berlin)
+        ));
+        assert!(slot_clashes(
+            &slot,
+            &BusyTimes::from_events(&day_off, chrono_tz::Asia::Singapore)
+        ));
+    }
+
+    #[test]
+    fn test_check_shift_limit() {
+        let days = ["2022-08-29T03:00:00+08:00", "2022-08-30T03:00:00+08:00"];
+        let original = vec![
+            test_entity("a@x.com", days[0], &days),
+            test_entity("b@x.com", days[1], &days),
+        ];
+        let rescheduled = vec![
+            test_entity("a@x.com", days[0], &days),
+            test_entity("a@x.com", days[1], &days),
+        ];
+        assert!(check_shift_limit(&original, &rescheduled, None).is_ok());
+        assert!(check_shift_limit(&original, &rescheduled, Some(2)).is_ok());
+        assert!(check_shift_limit(&original, &rescheduled, Some(1)).is_err());
+        // already over the limit before solving, and not made worse
+        assert!(check_shift_limit(&rescheduled, &rescheduled, Some(1)).is_ok());
+
+        let summary = summarise_shift_counts(&original, &rescheduled, &BTreeSet::new());
+        assert_eq!(summary.len(), 2);
+        assert_eq!((summary[0].shifts_before, summary[0].shifts_after), (1, 2));
+        assert_eq!((summary[1].shifts_before, summary[1].shifts_after), (1, 0));
+    }
+
+    #[test]
+    fn test_explain_plan() {
+        let days = ["2022-08-29T03:00:00+08:00", "2022-08-30T03:00:00+08:00"];
+        let mut busy = test_entity("a@x.com", days[0], &[days[1]]);
+        busy.busy = vec![BusyInterval {
+            summary: "Out of office".to_string(),
+            start: test_slot(days[0]).start_time,
+            end: test_slot(days[0]).end_time,
+        }];
+        let free = test_entity("b@x.com", days[1], &days);
+        let original = vec![busy.clone(), free.clone()];
+        let rescheduled = vec![busy.moved_to(&free), free.moved_to(&busy)];
+        let explanations = explain_plan(&original, &rescheduled);
+        assert_eq!(explanations.len(), 2);
+        assert!(explanations[0].contains("a@x.com is busy with \"Out of office\""));
+        assert!(explanations[0].contains("b@x.com has no conflicting events"));
+        assert!(explanations[1].contains("b@x.com has no conflict here, but was moved to"));
+    }
+
+    #[test]
+    fn test_summarise_history() {
+        // 2022-08-27 is a saturday
+        let past = |email: &str, start: &str| FinalPagerDutySchedule {
+            pd_user_id: format!("id-{}", email),
+            start: test_slot(start).start_time,
+            end: test_slot(start).end_time,
+            email: email.to_string(),
+        };
+        let history = vec![
+            past("a@x.com", "2022-08-26T03:00:00+08:00"),
+            past("a@x.com", "2022-08-27T03:00:00+08:00"),
+            past("b@x.com", "2022-08-25T03:00:00+08:00"),
+        ];
+        let people: BTreeSet<String> = ["a@x.com", "b@x.com", "c@x.com"]
+            .iter()
+            .map(|x| x.to_string())
+            .collect();
+        let loads = summarise_history(&history, &people);
+        assert_eq!(loads[0].shifts, 2);
+        assert_eq!(loads[0].weekend_shifts, 1);
+        // a: (2 - 1) + (1 - 1/3), c: (0 - 1) + (0 - 1/3)
+        assert!((loads[0].load_value - 5.0 / 3.0).abs() < 1e-9);
+        assert!((loads[2].load_value + 4.0 / 3.0).abs() < 1e-9);
+
+        // with everything else equal, the conflict goes to whoever carried less recently
+        let days = [
+            "2022-08-29T03:00:00+08:00",
+            "2022-08-30T03:00:00+08:00",
+            "2022-08-31T03:00:00+08:00",
+        ];
+        let conflict = test_entity("x@x.com", days[0], &days[1..]);
+        let mut busy_recently = test_entity("a@x.com", days[1], &days);
+        busy_recently.recent_load = loads[0].load_value;
+        let mut quiet_recently = test_entity("c@x.com", days[2], &days);
+        quiet_recently.recent_load = loads[2].load_value;
+        let mut schedule = vec![conflict, busy_recently, quiet_recently];
+        let weights = Weights {
+            back_to_back: 0.0,
+            ..Weights::default()
+        };
+        let options = SolverOptions {
+            weights,
+            ..SolverOptions::default()
+        };
+        assert!(
+            swap_penalty(&mut schedule, 0, 2, &options)
+                < swap_penalty(&mut schedule, 0, 1, &options)
+        );
+    }
+
+    #[test]
+    fn test_mirrored_secondary_overrides() {
+        let days = ["2022-08-29T03:00:00+08:00", "2022-08-30T03:00:00+08:00"];
+        let original = vec![
+            test_entity("a@x.com", days[0], &days),
+            test_entity("b@x.com", days[1], &days),
+        ];
+        let rescheduled = vec![
+            original[0].moved_to(&original[1]),
+            original[1].moved_to(&original[0]),
+        ];
+        let secondary = |email: &str, start: &str| FinalPagerDutySchedule {
+            pd_user_id: format!("id-{}", email),
+            start: test_slot(start).start_time,
+            end: test_slot(start).end_time,
+            email: email.to_string(),
+        };
+        let secondary_schedule = vec![
+            secondary("trainee@x.com", days[0]),
+            secondary("s@x.com", days[1]),
+        ];
+        let secondaries = secondaries_by_slot(&original, &secondary_schedule);
+        let follow = [["a@x.com".to_string(), "trainee@x.com".to_string()]];
+        // the trainee moves with a to the second day
+        let overrides = mirrored_secondary_overrides(
+            &original,
+            &rescheduled,
+            &secondary_schedule,
+            &secondaries,
+            &follow,
+        );
+        assert_eq!(overrides.len(), 2);
+        let moved = overrides
+            .iter()
+            .find(|x| x.final_override == "trainee@x.com")
+            .unwrap();
+        assert_eq!(
+            moved.start_time_iso,
+            test_slot(days[1]).start_time.to_rfc3339()
+        );
+        assert_eq!(moved.pd_user_id, "id-trainee@x.com");
+        assert!(mirrored_secondary_overrides(
+            &original,
+            &rescheduled,
+            &secondary_schedule,
+            &secondaries,
+            &[]
+        )
+        .is_empty());
     }
 }
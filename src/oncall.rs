@@ -0,0 +1,216 @@
+use crate::solver::FinalOverride;
+use anyhow::Result as AnyhowResult;
+use chrono::{DateTime, FixedOffset};
+use serde::Deserialize;
+use std::sync::Mutex;
+use tabled::Tabled;
+
+/// Who is oncall for one slot of a schedule
+#[derive(Deserialize, Debug, Clone)]
+pub struct FinalPagerDutySchedule {
+    pub pd_user_id: String,
+    pub start: DateTime<FixedOffset>,
+    pub end: DateTime<FixedOffset>,
+    pub email: String,
+}
+
+/// An override created on a schedule, with the id needed to remove it again
+#[derive(Debug, Clone, PartialEq)]
+pub struct AppliedOverride {
+    pub id: String,
+    /// rfc3339 start and end time, as in the FinalOverride it was created from
+    pub start: String,
+    pub end: String,
+    pub pd_user_id: String,
+}
+
+/// Someone oncall for a slot who couldn't be looked up, so the slot was left out of the schedule
+#[derive(Tabled, Debug, Clone, PartialEq)]
+pub struct SkippedUser {
+    /// name or id, as the provider gave it
+    pub user: String,
+    /// start and end time of the slot, as the provider gave them
+    pub start: String,
+    pub end: String,
+    pub reason: String,
+}
+
+/// The users skipped while fetching schedules, for a table at the end of the run
+#[derive(Default)]
+pub struct SkippedUsers(Mutex<Vec<SkippedUser>>);
+
+impl SkippedUsers {
+    /// Record a skipped user, once however often the same slot is fetched
+    pub fn push(&self, skipped: SkippedUser) {
+        let mut list = self.0.lock().unwrap();
+        if !list.contains(&skipped) {
+            list.push(skipped);
+        }
+    }
+
+    pub fn list(&self) -> Vec<SkippedUser> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+/// A paging system holding the oncall schedules: PagerDuty, Opsgenie, Grafana OnCall or Splunk
+/// On-Call. Others only need to render their schedules into the same entries and accept the same
+/// overrides
+// the futures are awaited where they are created, so nothing needs them to be Send
+#[allow(async_fn_in_trait)]
+pub trait OncallProvider {
+    /// Who is oncall on `schedule_id` between `start` and `end`, one entry per slot
+    async fn fetch_schedule(
+        &self,
+        schedule_id: &str,
+        start: DateTime<FixedOffset>,
+        end: DateTime<FixedOffset>,
+    ) -> AnyhowResult<Vec<FinalPagerDutySchedule>>;
+
+    /// Put the `final_override` person of every override oncall for its time range. Returns the
+    /// overrides created, leaving out any the provider rejected
+    async fn apply_overrides(
+        &self,
+        schedule_id: &str,
+        overrides: &[FinalOverride],
+    ) -> AnyhowResult<Vec<AppliedOverride>>;
+
+    /// Delete an override created by apply_overrides
+    async fn remove_override(&self, schedule_id: &str, override_id: &str) -> AnyhowResult<()>;
+
+    /// Email of the user with `user_id`
+    async fn resolve_user(&self, user_id: &str) -> AnyhowResult<String>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::generate_candidate_plans;
+    use crate::solver::tests::{test_entity, test_slot};
+    use crate::solver::SolverOptions;
+    use anyhow::anyhow;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    /// One schedule kept in memory, with overrides applied to its entries
+    struct InMemoryOncall {
+        entries: RefCell<Vec<FinalPagerDutySchedule>>,
+        emails: HashMap<String, String>,
+        /// entries as they were before each override, by override id
+        replaced: RefCell<HashMap<String, FinalPagerDutySchedule>>,
+    }
+
+    impl OncallProvider for InMemoryOncall {
+        async fn fetch_schedule(
+            &self,
+            _schedule_id: &str,
+            start: DateTime<FixedOffset>,
+            end: DateTime<FixedOffset>,
+        ) -> AnyhowResult<Vec<FinalPagerDutySchedule>> {
+            Ok(self
+                .entries
+                .borrow()
+                .iter()
+                .filter(|x| x.start < end && x.end > start)
+                .cloned()
+                .collect())
+        }
+
+        async fn apply_overrides(
+            &self,
+            _schedule_id: &str,
+            overrides: &[FinalOverride],
+        ) -> AnyhowResult<Vec<AppliedOverride>> {
+            let mut applied = Vec::new();
+            for entry in overrides {
+                let email = self.resolve_user(&entry.pd_user_id).await?;
+                let start = DateTime::parse_from_rfc3339(&entry.start_time_iso)?;
+                let mut entries = self.entries.borrow_mut();
+                let slot = entries
+                    .iter_mut()
+                    .find(|x| x.start == start)
+                    .ok_or_else(|| anyhow!("No slot starts at {}", start))?;
+                let id = format!("override-{}", self.replaced.borrow().len());
+                self.replaced.borrow_mut().insert(id.clone(), slot.clone());
+                slot.pd_user_id = entry.pd_user_id.clone();
+                slot.email = email;
+                applied.push(AppliedOverride {
+                    id,
+                    start: entry.start_time_iso.clone(),
+                    end: entry.end_time_iso.clone(),
+                    pd_user_id: entry.pd_user_id.clone(),
+                });
+            }
+            Ok(applied)
+        }
+
+        async fn remove_override(&self, _schedule_id: &str, override_id: &str) -> AnyhowResult<()> {
+            let previous = self
+                .replaced
+                .borrow_mut()
+                .remove(override_id)
+                .ok_or_else(|| anyhow!("Unknown override {}", override_id))?;
+            let mut entries = self.entries.borrow_mut();
+            if let Some(slot) = entries.iter_mut().find(|x| x.start == previous.start) {
+                *slot = previous;
+            }
+            Ok(())
+        }
+
+        async fn resolve_user(&self, user_id: &str) -> AnyhowResult<String> {
+            self.emails
+                .get(user_id)
+                .cloned()
+                .ok_or_else(|| anyhow!("Unknown user {}", user_id))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_solve_against_in_memory_provider() -> AnyhowResult<()> {
+        let days = ["2022-08-29T03:00:00+08:00", "2022-08-30T03:00:00+08:00"];
+        let (start, end) = (test_slot(days[0]).start_time, test_slot(days[1]).end_time);
+        let provider = InMemoryOncall {
+            entries: RefCell::new(vec![
+                test_entity("a@x.com", days[0], &[]).pd_schedule,
+                test_entity("b@x.com", days[1], &[]).pd_schedule,
+            ]),
+            emails: ["a@x.com", "b@x.com"]
+                .into_iter()
+                .map(|x| (format!("id-{}", x), x.to_string()))
+                .collect(),
+            replaced: RefCell::new(HashMap::new()),
+        };
+        // a is only free on the second day
+        let schedule: Vec<_> = provider
+            .fetch_schedule("primary", start, end)
+            .await?
+            .into_iter()
+            .map(|entry| {
+                let available = if entry.email == "a@x.com" {
+                    &days[1..]
+                } else {
+                    &days[..]
+                };
+                let mut entity = test_entity(&entry.email, days[0], available);
+                entity.pd_schedule = entry;
+                entity
+            })
+            .collect();
+        let plans = generate_candidate_plans(&schedule, 7, 1, &SolverOptions::default())?;
+        let applied = provider
+            .apply_overrides("primary", &plans[0].overrides)
+            .await?;
+        assert_eq!(applied.len(), 2);
+
+        let after = provider.fetch_schedule("primary", start, end).await?;
+        assert_eq!(after[0].email, "b@x.com");
+        assert_eq!(after[1].email, "a@x.com");
+
+        for entry in &applied {
+            provider.remove_override("primary", &entry.id).await?;
+        }
+        let undone = provider.fetch_schedule("primary", start, end).await?;
+        assert_eq!(undone[0].email, "a@x.com");
+        Ok(())
+    }
+}
@@ -0,0 +1,357 @@
+use crate::cache::{Cache, Namespace};
+use crate::calendar::local_time;
+use crate::http::HttpClient;
+use crate::oncall::{
+    AppliedOverride, FinalPagerDutySchedule, OncallProvider, SkippedUser, SkippedUsers,
+};
+use crate::provider::{check, error_kind, ProviderErrorKind};
+use crate::solver::FinalOverride;
+
+use anyhow::{anyhow, Context, Result as AnyhowResult};
+use chrono::{DateTime, FixedOffset};
+use chrono_tz::Tz;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+#[derive(Deserialize, Debug)]
+struct TimelineResponse {
+    data: Timeline,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Timeline {
+    final_timeline: FinalTimeline,
+}
+
+#[derive(Deserialize, Debug)]
+struct FinalTimeline {
+    #[serde(default)]
+    rotations: Vec<Rotation>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Rotation {
+    #[serde(default)]
+    periods: Vec<Period>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Period {
+    start_date: String,
+    end_date: String,
+    recipient: Recipient,
+}
+
+/// Who a period is for. Gaps nobody covers have a recipient of type "none"
+#[derive(Deserialize, Debug)]
+struct Recipient {
+    #[serde(rename = "type")]
+    kind: String,
+    id: Option<String>,
+    /// the username, which is the user's email
+    name: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct UserResponse {
+    data: User,
+}
+
+#[derive(Deserialize, Debug)]
+struct User {
+    username: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct OverrideRequest {
+    user: OverrideUser,
+    start_date: String,
+    end_date: String,
+}
+
+#[derive(Serialize, Debug)]
+struct OverrideUser {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    id: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct CreatedOverrideResponse {
+    data: CreatedOverride,
+}
+
+#[derive(Deserialize, Debug)]
+struct CreatedOverride {
+    alias: String,
+}
+
+/// Root of the opsgenie rest api. The EU instance is at https://api.eu.opsgenie.com
+pub const OPSGENIE_API_URL: &str = "https://api.opsgenie.com";
+
+/// Opsgenie, accessed with an api key of an integration allowed to read and change schedules
+pub struct Opsgenie<'a> {
+    pub client: &'a HttpClient,
+    /// OPSGENIE_API_URL, unless the config file or --base-url point elsewhere
+    pub base_url: &'a str,
+    pub api_key: &'a str,
+    pub cache: &'a Cache,
+    /// of the schedule, slots are given the offset it has at their start
+    pub timezone: Tz,
+    /// users that couldn't be looked up, whose slots are left out
+    pub skipped: &'a SkippedUsers,
+}
+
+impl OncallProvider for Opsgenie<'_> {
+    #[instrument(skip_all, fields(%schedule_id, %start, %end))]
+    async fn fetch_schedule(
+        &self,
+        schedule_id: &str,
+        start: DateTime<FixedOffset>,
+        end: DateTime<FixedOffset>,
+    ) -> AnyhowResult<Vec<FinalPagerDutySchedule>> {
+        say!("Retrieving opsgenie schedule from {} to {}", start, end);
+        // the timeline covers whole days from `date`, periods outside the window are dropped below
+        let days = (end - start).num_days() + 1;
+        let url = Url::parse_with_params(
+            &format!("{}/v2/schedules/{}/timeline", self.base_url, schedule_id),
+            [
+                ("identifierType", "id".to_string()),
+                ("date", start.to_rfc3339()),
+                ("interval", days.to_string()),
+                ("intervalUnit", "days".to_string()),
+            ],
+        )
+        .context("Failed to parse url")?;
+        let response_text = match self.cache.get(Namespace::Schedules, url.as_str()) {
+            Some(value) => value,
+            None => {
+                let response = self
+                    .client
+                    .send(self.authorized(self.client.get(url.clone())))
+                    .await
+                    .context("Failed to call opsgenie api")?;
+                check("opsgenie", &response)?;
+                let text = response
+                    .text()
+                    .await
+                    .context("Failed to get text response from opsgenie api call")?;
+                self.cache.put(Namespace::Schedules, url.as_str(), &text);
+                text
+            }
+        };
+        let timeline: TimelineResponse = serde_json::from_str(&response_text)
+            .context("Failed to parse json from opsgenie api response")?;
+
+        let mut entries = Vec::new();
+        let periods = timeline
+            .data
+            .final_timeline
+            .rotations
+            .into_iter()
+            .flat_map(|x| x.periods);
+        for period in periods {
+            let recipient = &period.recipient;
+            let is_user = recipient.kind == "user";
+            let user = recipient.name.clone().or_else(|| recipient.id.clone());
+            let (period_start, period_end) = (period.start_date.clone(), period.end_date.clone());
+            match self.to_final_schedule(period).await {
+                Ok(entry) if entry.start < end && entry.end > start => entries.push(entry),
+                Ok(_) => {}
+                Err(e) if error_kind(&e) == Some(ProviderErrorKind::RateLimited) => {
+                    return Err(e.context("Failed to look up the users of the opsgenie schedule"));
+                }
+                // gaps and periods of teams or escalations have nobody to look up
+                Err(e) if !is_user => say!("Warning. {:#}. Skipping.", e),
+                Err(e) => self.skipped.push(SkippedUser {
+                    user: user.unwrap_or_default(),
+                    start: period_start,
+                    end: period_end,
+                    reason: format!("{:#}", e),
+                }),
+            }
+        }
+        // each rotation lists its own periods, the solver wants them in time order
+        entries.sort_by_key(|x| x.start);
+        Ok(entries)
+    }
+
+    #[instrument(skip_all, fields(%schedule_id, overrides = overrides.len()))]
+    async fn apply_overrides(
+        &self,
+        schedule_id: &str,
+        overrides: &[FinalOverride],
+    ) -> AnyhowResult<Vec<AppliedOverride>> {
+        let url = format!(
+            "{}/v2/schedules/{}/overrides?scheduleIdentifierType=id",
+            self.base_url, schedule_id
+        );
+        // opsgenie takes one override per request
+        let mut applied = Vec::new();
+        for entry in overrides {
+            let body = OverrideRequest {
+                user: OverrideUser {
+                    kind: "user",
+                    id: entry.pd_user_id.clone(),
+                },
+                start_date: entry.start_time_iso.clone(),
+                end_date: entry.end_time_iso.clone(),
+            };
+            let request = self.authorized(self.client.post(&url)).json(&body);
+            let response = self.client.send(request).await?;
+            match check("opsgenie", &response) {
+                Ok(_) => {
+                    let created: CreatedOverrideResponse = response
+                        .json()
+                        .await
+                        .context("Failed to parse the override created by opsgenie")?;
+                    applied.push(AppliedOverride {
+                        id: created.data.alias,
+                        start: entry.start_time_iso.clone(),
+                        end: entry.end_time_iso.clone(),
+                        pd_user_id: entry.pd_user_id.clone(),
+                    });
+                }
+                // a bad key or rate limit fails every override after it too
+                Err(e)
+                    if matches!(
+                        e.kind,
+                        ProviderErrorKind::Unauthorized | ProviderErrorKind::RateLimited
+                    ) =>
+                {
+                    return Err(anyhow!(e).context("Failed to override opsgenie schedule"));
+                }
+                Err(e) => say!(
+                    "Warning. Opsgenie rejected the override from {} to {}: {}",
+                    entry.start_time_iso,
+                    entry.end_time_iso,
+                    e
+                ),
+            }
+        }
+        Ok(applied)
+    }
+
+    #[instrument(skip(self))]
+    async fn remove_override(&self, schedule_id: &str, override_id: &str) -> AnyhowResult<()> {
+        let request = self.authorized(self.client.delete(format!(
+            "{}/v2/schedules/{}/overrides/{}?scheduleIdentifierType=id",
+            self.base_url, schedule_id, override_id
+        )));
+        let response = self.client.send(request).await?;
+        check("opsgenie", &response).context(format!(
+            "Failed to delete opsgenie override {}",
+            override_id
+        ))
+    }
+
+    #[instrument(skip(self), fields(email))]
+    async fn resolve_user(&self, user_id: &str) -> AnyhowResult<String> {
+        let url = format!("{}/v2/users/{}", self.base_url, user_id);
+        if let Some(email) = self.cache.get(Namespace::UserEmails, &url) {
+            return Ok(email);
+        }
+        let response = self
+            .client
+            .send(self.authorized(self.client.get(&url)))
+            .await
+            .context("Failed to call opsgenie api to get user email")?;
+        check("opsgenie", &response)?;
+        let user: UserResponse = response
+            .json()
+            .await
+            .context("Failed to parse the opsgenie user as json")?;
+        self.cache
+            .put(Namespace::UserEmails, &url, &user.data.username);
+        tracing::Span::current().record("email", &user.data.username);
+        Ok(user.data.username)
+    }
+}
+
+impl Opsgenie<'_> {
+    fn authorized(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        request.header("Authorization", format!("GenieKey {}", self.api_key))
+    }
+
+    /// Whether the api key can read the schedule, without fetching its timeline
+    #[instrument(skip(self))]
+    pub async fn check_schedule(&self, schedule_id: &str) -> AnyhowResult<()> {
+        let request = self.authorized(self.client.get(format!(
+            "{}/v2/schedules/{}?identifierType=id",
+            self.base_url, schedule_id
+        )));
+        let response = self
+            .client
+            .send(request)
+            .await
+            .context("Failed to call opsgenie api")?;
+        check("opsgenie", &response)?;
+        Ok(())
+    }
+
+    async fn to_final_schedule(&self, period: Period) -> AnyhowResult<FinalPagerDutySchedule> {
+        let recipient = period.recipient;
+        let id = match (recipient.kind.as_str(), recipient.id) {
+            ("user", Some(id)) => id,
+            (kind, _) => {
+                return Err(anyhow!(
+                    "Opsgenie period from {} to {} is for a {}, not a user",
+                    period.start_date,
+                    period.end_date,
+                    kind
+                ))
+            }
+        };
+        // the timeline names users by username, which opsgenie requires to be their email
+        let email = match recipient.name {
+            Some(name) => name,
+            None => self.resolve_user(&id).await?,
+        };
+        Ok(FinalPagerDutySchedule {
+            pd_user_id: id,
+            // the timeline is in utc
+            start: local_time(
+                DateTime::parse_from_rfc3339(&period.start_date)
+                    .context("Failed to parse startDate as rfc3339")?,
+                self.timezone,
+            ),
+            end: local_time(
+                DateTime::parse_from_rfc3339(&period.end_date)
+                    .context("Failed to parse endDate as rfc3339")?,
+                self.timezone,
+            ),
+            email,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_timeline() -> AnyhowResult<()> {
+        let timeline: TimelineResponse = serde_json::from_str(
+            r#"{"data": {"finalTimeline": {"rotations": [
+                {"id": "r1", "periods": [{
+                    "startDate": "2022-08-29T03:00:00+08:00",
+                    "endDate": "2022-08-30T03:00:00+08:00",
+                    "type": "default",
+                    "recipient": {"id": "u1", "type": "user", "name": "a@x.com"}
+                }]},
+                {"id": "r2"}
+            ]}}}"#,
+        )?;
+        let rotations = timeline.data.final_timeline.rotations;
+        assert_eq!(rotations.len(), 2);
+        assert!(rotations[1].periods.is_empty());
+        let period = &rotations[0].periods[0];
+        assert_eq!(period.recipient.name.as_deref(), Some("a@x.com"));
+        assert_eq!(period.start_date, "2022-08-29T03:00:00+08:00");
+        Ok(())
+    }
+}
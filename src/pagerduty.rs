@@ -1,11 +1,20 @@
+use crate::cache::{Cache, Namespace};
+use crate::calendar::local_time;
+use crate::http::HttpClient;
+use crate::oncall::{
+    AppliedOverride, FinalPagerDutySchedule, OncallProvider, SkippedUser, SkippedUsers,
+};
+use crate::provider::{check, error_kind, ProviderErrorKind};
+use crate::solver::FinalOverride;
 use std::collections::HashMap;
 
 use anyhow::{anyhow, Context, Result as AnyhowResult};
 use chrono::{DateTime, FixedOffset};
+use chrono_tz::Tz;
 use futures::future::join_all;
 use reqwest::Url;
-use reqwest::{self, Client};
 use serde::{Deserialize, Serialize};
+use tracing::instrument;
 
 #[derive(Deserialize, Debug)]
 struct ScheduleResponse {
@@ -47,150 +56,273 @@ struct ScheduleEntry {
     user: PagerDutyUser,
 }
 
-#[derive(Deserialize, Debug, Clone)]
-pub struct FinalPagerDutySchedule {
-    pub pd_user_id: String,
-    pub start: DateTime<FixedOffset>,
-    pub end: DateTime<FixedOffset>,
-    pub email: String,
+#[derive(Serialize, Debug)]
+struct OverrideEntry {
+    start: String,
+    end: String,
+    user: OverrideUser,
 }
 
-#[derive(Serialize, Debug)]
-pub struct OverrideEntry {
-    pub start: String,
-    pub end: String,
-    pub user: OverrideUser,
+#[derive(Serialize, Deserialize, Debug)]
+struct OverrideUser {
+    id: String,
+    r#type: String,
 }
 
-#[derive(Serialize, Debug)]
-pub struct OverrideUser {
-    pub id: String,
-    pub r#type: String,
+/// Outcome of one override in a request creating several
+#[derive(Deserialize, Debug)]
+struct CreatedOverride {
+    status: u16,
+    #[serde(default)]
+    errors: Vec<String>,
+    #[serde(rename = "override")]
+    entry: CreatedOverrideEntry,
 }
 
-pub async fn schedule_overrides(
-    client: &Client,
-    api_key: &str,
-    schedule_id: &str,
-    overrides: Vec<OverrideEntry>,
-) -> AnyhowResult<()> {
-    let url_base = format!(
-        "https://api.pagerduty.com/schedules/{}/overrides",
-        schedule_id
-    );
-    let body = HashMap::from([("overrides".to_string(), overrides)]);
-    let response = client
-        .post(url_base)
-        .header("Authorization", format!("Token token={}", api_key))
-        .json(&body)
-        .send()
-        .await?;
-    if response.status() != 200 {
-        return Err(anyhow!(
-            "Non 200 status while trying to override pd schedule"
-        ));
-    } else {
-        Ok(())
-    }
+#[derive(Deserialize, Debug)]
+struct CreatedOverrideEntry {
+    id: Option<String>,
+    start: String,
+    end: String,
+    user: OverrideUser,
+}
+
+/// Root of the pagerduty rest api
+pub const PAGERDUTY_API_URL: &str = "https://api.pagerduty.com";
+
+/// PagerDuty, accessed with a REST api key
+pub struct PagerDuty<'a> {
+    pub client: &'a HttpClient,
+    /// PAGERDUTY_API_URL, unless --base-url points elsewhere
+    pub base_url: &'a str,
+    pub api_key: &'a str,
+    pub cache: &'a Cache,
+    /// of the schedule, slots are given the offset it has at their start
+    pub timezone: Tz,
+    /// users that couldn't be looked up, whose slots are left out
+    pub skipped: &'a SkippedUsers,
 }
 
-pub async fn get_pagerduty_schedule(
-    client: &Client,
-    api_key: &str,
-    schedule_id: &str,
-    start_time_local: DateTime<FixedOffset>,
-    end_time_local: DateTime<FixedOffset>,
-) -> AnyhowResult<Vec<FinalPagerDutySchedule>> {
-    let url_base = format!("https://api.pagerduty.com/schedules/{}", schedule_id);
-    println!(
-        "Retrieving pd schedule from {} to {}",
-        &start_time_local, &end_time_local
-    );
-    let params = vec![
-        ("since", start_time_local.to_rfc3339()),
-        ("until", end_time_local.to_rfc3339()),
-        ("time_zone", "Asia/Singapore".to_string()),
-    ];
-    let url = Url::parse_with_params(&url_base, params).context("Failed to parse url")?;
-
-    let request = client
-        .get(url)
-        .header("Authorization", format!("Token token={}", api_key));
-
-    let response_text = request
-        .send()
-        .await
-        .context("Failed to call pd api")?
-        .text()
-        .await;
-
-    let schedule: ScheduleResponse = serde_json::from_str(
-        &response_text.context("Failed to get text response from pd api call")?,
-    )
-    .context("Failed to parse json from pd api response")?;
-
-    // retrieve emails of usrs
-    let scheduled_entries = schedule.schedule.final_schedule.rendered_schedule_entries;
-    let futures = scheduled_entries
-        .into_iter()
-        .map(|entry| get_pd_user_email(client, &api_key, entry));
-
-    let results = join_all(futures).await;
-
-    let results_filtered = results
-        .into_iter()
-        .filter(|result| match result {
-            Ok(_) => true,
-            Err(e) => {
-                println!("Warning. Pd lookup failed with error: {}. Skipping.", e);
-                false
+impl OncallProvider for PagerDuty<'_> {
+    #[instrument(skip_all, fields(%schedule_id, start = %start_time_local, end = %end_time_local))]
+    async fn fetch_schedule(
+        &self,
+        schedule_id: &str,
+        start_time_local: DateTime<FixedOffset>,
+        end_time_local: DateTime<FixedOffset>,
+    ) -> AnyhowResult<Vec<FinalPagerDutySchedule>> {
+        let url_base = format!("{}/schedules/{}", self.base_url, schedule_id);
+        say!(
+            "Retrieving pd schedule from {} to {}",
+            &start_time_local,
+            &end_time_local
+        );
+        let params = vec![
+            ("since", start_time_local.to_rfc3339()),
+            ("until", end_time_local.to_rfc3339()),
+            ("time_zone", self.timezone.name().to_string()),
+        ];
+        let url = Url::parse_with_params(&url_base, params).context("Failed to parse url")?;
+        let response_text = match self.cache.get(Namespace::Schedules, url.as_str()) {
+            Some(value) => value,
+            None => {
+                let request = self
+                    .client
+                    .get(url.clone())
+                    .header("Authorization", format!("Token token={}", self.api_key));
+                let response = self
+                    .client
+                    .send(request)
+                    .await
+                    .context("Failed to call pd api")?;
+                check("pagerduty", &response)?;
+                let text = response
+                    .text()
+                    .await
+                    .context("Failed to get text response from pd api call")?;
+                self.cache.put(Namespace::Schedules, url.as_str(), &text);
+                text
             }
-        })
-        .flatten()
-        .collect();
+        };
+
+        let schedule: ScheduleResponse = serde_json::from_str(&response_text)
+            .context("Failed to parse json from pd api response")?;
+
+        // retrieve emails of usrs
+        let scheduled_entries = schedule.schedule.final_schedule.rendered_schedule_entries;
+        let futures = scheduled_entries.into_iter().map(|entry| async move {
+            let slot = (
+                entry.user.summary.clone(),
+                entry.start.clone(),
+                entry.end.clone(),
+            );
+            (slot, self.to_final_schedule(entry).await)
+        });
+
+        let results = join_all(futures).await;
+
+        let mut results_filtered = Vec::new();
+        for ((user, start, end), result) in results {
+            match result {
+                Ok(entry) => results_filtered.push(entry),
+                // skipping would silently drop the slot, so give up on the whole schedule instead
+                Err(e) if error_kind(&e) == Some(ProviderErrorKind::RateLimited) => {
+                    return Err(e.context("Failed to look up the users of the pd schedule"));
+                }
+                Err(e) => self.skipped.push(SkippedUser {
+                    user,
+                    start,
+                    end,
+                    reason: format!("{:#}", e),
+                }),
+            }
+        }
 
-    Ok(results_filtered)
+        Ok(results_filtered)
+    }
+
+    #[instrument(skip_all, fields(%schedule_id, overrides = overrides.len()))]
+    async fn apply_overrides(
+        &self,
+        schedule_id: &str,
+        overrides: &[FinalOverride],
+    ) -> AnyhowResult<Vec<AppliedOverride>> {
+        let url_base = format!("{}/schedules/{}/overrides", self.base_url, schedule_id);
+        let overrides: Vec<OverrideEntry> = overrides
+            .iter()
+            .map(|x| OverrideEntry {
+                start: x.start_time_iso.clone(),
+                end: x.end_time_iso.clone(),
+                user: OverrideUser {
+                    id: x.pd_user_id.clone(),
+                    r#type: "user_reference".to_string(),
+                },
+            })
+            .collect();
+        let body = HashMap::from([("overrides".to_string(), overrides)]);
+        let request = self
+            .client
+            .post(url_base)
+            .header("Authorization", format!("Token token={}", self.api_key))
+            .json(&body);
+        let response = self.client.send(request).await?;
+        check("pagerduty", &response).context("Failed to override pd schedule")?;
+        let created: Vec<CreatedOverride> = response
+            .json()
+            .await
+            .context("Failed to parse the overrides created by pd")?;
+
+        let mut applied = Vec::new();
+        for result in created {
+            match result.entry.id {
+                Some(id) if (200..300).contains(&result.status) => applied.push(AppliedOverride {
+                    id,
+                    start: result.entry.start,
+                    end: result.entry.end,
+                    pd_user_id: result.entry.user.id,
+                }),
+                _ => say!(
+                    "Warning. Pd rejected the override from {} to {} with status {}: {}",
+                    result.entry.start,
+                    result.entry.end,
+                    result.status,
+                    result.errors.join(", ")
+                ),
+            }
+        }
+        Ok(applied)
+    }
+
+    #[instrument(skip(self))]
+    async fn remove_override(&self, schedule_id: &str, override_id: &str) -> AnyhowResult<()> {
+        let request = self
+            .client
+            .delete(format!(
+                "{}/schedules/{}/overrides/{}",
+                self.base_url, schedule_id, override_id
+            ))
+            .header("Authorization", format!("Token token={}", self.api_key));
+        let response = self.client.send(request).await?;
+        check("pagerduty", &response)
+            .context(format!("Failed to delete pd override {}", override_id))
+    }
+
+    #[instrument(skip(self), fields(email))]
+    async fn resolve_user(&self, user_id: &str) -> AnyhowResult<String> {
+        let url = format!("{}/users/{}", self.base_url, user_id);
+        if let Some(email) = self.cache.get(Namespace::UserEmails, &url) {
+            return Ok(email);
+        }
+        let request = self
+            .client
+            .get(&url)
+            .header("Authorization", format!("Token token={}", self.api_key));
+
+        let response = self
+            .client
+            .send(request)
+            .await
+            .context("Failed to call pd api to get user email")?;
+        check("pagerduty", &response)?;
+        let response_text = response
+            .text()
+            .await
+            .context("Failed to convert pd api response to text")?;
+
+        let user_response: PagerDutyUserResponse = serde_json::from_str(&response_text)
+            .context("Failed to parse pagerdutyuserresponse as json")?;
+        self.cache
+            .put(Namespace::UserEmails, &url, &user_response.user.email);
+        tracing::Span::current().record("email", &user_response.user.email);
+        Ok(user_response.user.email)
+    }
 }
 
-async fn get_pd_user_email(
-    client: &Client,
-    api_key: &str,
-    entry: ScheduleEntry,
-) -> AnyhowResult<FinalPagerDutySchedule> {
-    let endpoint = match entry.user.api_url {
-        Some(value) => value,
-        None => {
+impl PagerDuty<'_> {
+    /// Whether the api key can read the schedule, without rendering it
+    #[instrument(skip(self))]
+    pub async fn check_schedule(&self, schedule_id: &str) -> AnyhowResult<()> {
+        let request = self
+            .client
+            .get(format!("{}/schedules/{}", self.base_url, schedule_id))
+            .header("Authorization", format!("Token token={}", self.api_key));
+        let response = self
+            .client
+            .send(request)
+            .await
+            .context("Failed to call pd api")?;
+        check("pagerduty", &response)?;
+        Ok(())
+    }
+
+    async fn to_final_schedule(
+        &self,
+        entry: ScheduleEntry,
+    ) -> AnyhowResult<FinalPagerDutySchedule> {
+        // deleted users are still rendered in the schedule, but without a link to their profile
+        if entry.user.api_url.is_none() {
             return Err(anyhow!(
                 "Possible invalid user in pagerduty: {}",
                 entry.user.summary
-            ))
+            ));
         }
-    };
-    let id = entry.user.id;
-    let request = client
-        .get(endpoint)
-        .header("Authorization", format!("Token token={}", api_key));
-
-    let response_text = request
-        .send()
-        .await
-        .context("Failed to call pd api to get user email")?
-        .text()
-        .await
-        .context("Failed to convert pd api response to text")?;
-
-    let user_response: PagerDutyUserResponse = serde_json::from_str(&response_text)
-        .context("Failed to parse pagerdutyuserresponse as json")?;
-
-    let start_time = DateTime::<FixedOffset>::parse_from_rfc3339(&entry.start)
-        .context("Failed to parse start_time as rfc3339")?;
-    let end_time = DateTime::<FixedOffset>::parse_from_rfc3339(&entry.end)
-        .context("Failed to parse end_time as rfc3339")?;
-
-    Ok(FinalPagerDutySchedule {
-        pd_user_id: id,
-        start: start_time,
-        end: end_time,
-        email: user_response.user.email,
-    })
+        let email = self.resolve_user(&entry.user.id).await?;
+
+        let start_time = DateTime::<FixedOffset>::parse_from_rfc3339(&entry.start)
+            .context("Failed to parse start_time as rfc3339")?;
+        let end_time = DateTime::<FixedOffset>::parse_from_rfc3339(&entry.end)
+            .context("Failed to parse end_time as rfc3339")?;
+        // pagerduty renders them in time_zone already, this only guards against it being ignored
+        let (start_time, end_time) = (
+            local_time(start_time, self.timezone),
+            local_time(end_time, self.timezone),
+        );
+
+        Ok(FinalPagerDutySchedule {
+            pd_user_id: entry.user.id,
+            start: start_time,
+            end: end_time,
+            email,
+        })
+    }
 }
@@ -0,0 +1,131 @@
+use crate::config::DEFAULT_CONFIG_FILE;
+use anyhow::{anyhow, Context, Result as AnyhowResult};
+use directories::ProjectDirs;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Files earlier versions wrote to the current directory
+const LEGACY_TOKEN_FILE: &str = ".google_oidc_token";
+const LEGACY_STATE_DB: &str = "gcal-pagerduty.db";
+
+/// Where files live, following the platform's conventions (XDG base directories on linux). Each
+/// directory can be moved with an environment variable
+#[derive(Debug, Clone)]
+pub struct Paths {
+    /// the config file is read from here unless one is in the current directory
+    pub config_dir: PathBuf,
+    /// the google token, which can always be fetched again
+    pub cache_dir: PathBuf,
+    /// the run history
+    pub state_dir: PathBuf,
+}
+
+impl Paths {
+    pub fn from_env() -> AnyhowResult<Paths> {
+        let dirs = ProjectDirs::from("", "", env!("CARGO_PKG_NAME"))
+            .ok_or_else(|| anyhow!("No home directory found, set HOME"))?;
+        let dir = |name: &str, default: &Path| {
+            env::var_os(name)
+                .map(PathBuf::from)
+                .unwrap_or_else(|| default.to_path_buf())
+        };
+        Ok(Paths {
+            config_dir: dir("GCAL_PAGERDUTY_CONFIG_DIR", dirs.config_dir()),
+            cache_dir: dir("GCAL_PAGERDUTY_CACHE_DIR", dirs.cache_dir()),
+            state_dir: dir(
+                "GCAL_PAGERDUTY_STATE_DIR",
+                dirs.state_dir().unwrap_or_else(|| dirs.data_local_dir()),
+            ),
+        })
+    }
+
+    /// `chosen` if given, or else google_oidc_token in the cache directory
+    pub fn token_file(&self, chosen: Option<&Path>) -> AnyhowResult<PathBuf> {
+        resolve(
+            chosen,
+            self.cache_dir.join("google_oidc_token"),
+            LEGACY_TOKEN_FILE,
+        )
+    }
+
+    /// Where api responses are cached
+    pub fn responses_dir(&self) -> PathBuf {
+        self.cache_dir.join("responses")
+    }
+
+    /// `chosen` if given, or else state.db in the state directory
+    pub fn state_db(&self, chosen: Option<&Path>) -> AnyhowResult<PathBuf> {
+        resolve(chosen, self.state_dir.join("state.db"), LEGACY_STATE_DB)
+    }
+
+    /// gcal-pagerduty.toml in the current directory, or else in the config directory
+    pub fn config_file(&self) -> Option<PathBuf> {
+        [
+            PathBuf::from(DEFAULT_CONFIG_FILE),
+            self.config_dir.join(DEFAULT_CONFIG_FILE),
+        ]
+        .into_iter()
+        .find(|x| x.exists())
+    }
+}
+
+/// The path chosen on the command line, or else `default`, where the file an earlier version
+/// left in the current directory is moved first unless something is there already
+fn resolve(chosen: Option<&Path>, default: PathBuf, legacy: &str) -> AnyhowResult<PathBuf> {
+    let path = match chosen {
+        Some(value) => value.to_path_buf(),
+        None => {
+            let legacy = Path::new(legacy);
+            if legacy.exists() && !default.exists() {
+                move_file(legacy, &default)?;
+                say!("Moved {} to {}", legacy.display(), default.display());
+            }
+            default
+        }
+    };
+    ensure_parent(&path)?;
+    Ok(path)
+}
+
+/// Rename, or copy and delete when the target is on another filesystem
+fn move_file(from: &Path, to: &Path) -> AnyhowResult<()> {
+    ensure_parent(to)?;
+    if fs::rename(from, to).is_err() {
+        fs::copy(from, to).context(format!(
+            "Failed to move {} to {}",
+            from.display(),
+            to.display()
+        ))?;
+        fs::remove_file(from).context(format!("Failed to remove {}", from.display()))?;
+    }
+    Ok(())
+}
+
+/// Create the directory `path` goes in, if it doesn't exist yet
+fn ensure_parent(path: &Path) -> AnyhowResult<()> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => fs::create_dir_all(parent)
+            .context(format!("Failed to create directory {}", parent.display())),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_move_file() -> AnyhowResult<()> {
+        let dir = env::temp_dir().join(format!("gcal-pagerduty-paths-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let from = dir.join(LEGACY_TOKEN_FILE);
+        fs::write(&from, "token")?;
+        let to = dir.join("cache/nested/google_oidc_token");
+        move_file(&from, &to)?;
+        assert!(!from.exists());
+        assert_eq!(fs::read_to_string(&to)?, "token");
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
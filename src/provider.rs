@@ -0,0 +1,190 @@
+use crate::cache::Cache;
+use crate::config::{Config, OncallBackend};
+use crate::grafana::GrafanaOncall;
+use crate::http::HttpClient;
+use crate::oncall::{AppliedOverride, FinalPagerDutySchedule, OncallProvider, SkippedUsers};
+use crate::opsgenie::{Opsgenie, OPSGENIE_API_URL};
+use crate::pagerduty::{PagerDuty, PAGERDUTY_API_URL};
+use crate::solver::FinalOverride;
+use crate::splunk::{SplunkOncall, SPLUNK_ONCALL_API_URL};
+use anyhow::{anyhow, Result as AnyhowResult};
+use chrono::{DateTime, FixedOffset};
+use reqwest::Response;
+use std::fmt;
+use thiserror::Error;
+
+/// An error response of the api of an oncall provider
+#[derive(Error, Debug)]
+#[error("{kind} the {provider} api")]
+pub struct ProviderError {
+    /// e.g. "pagerduty"
+    pub provider: &'static str,
+    pub kind: ProviderErrorKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProviderErrorKind {
+    RateLimited,
+    /// the api key or token is missing, wrong, or lacks the rights to the schedule
+    Unauthorized,
+    Status(u16),
+}
+
+impl fmt::Display for ProviderErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProviderErrorKind::RateLimited => write!(f, "Rate limited by"),
+            ProviderErrorKind::Unauthorized => write!(f, "Unauthorised credentials for"),
+            ProviderErrorKind::Status(status) => write!(f, "Unexpected status {} from", status),
+        }
+    }
+}
+
+/// Whether `response` of the api of `provider` succeeded
+pub fn check(provider: &'static str, response: &Response) -> Result<(), ProviderError> {
+    let kind = match response.status().as_u16() {
+        200..=299 => return Ok(()),
+        401 | 403 => ProviderErrorKind::Unauthorized,
+        429 => ProviderErrorKind::RateLimited,
+        status => ProviderErrorKind::Status(status),
+    };
+    Err(ProviderError { provider, kind })
+}
+
+/// The kind of provider error `error` is, looking through its context
+pub fn error_kind(error: &anyhow::Error) -> Option<ProviderErrorKind> {
+    error.downcast_ref::<ProviderError>().map(|x| x.kind)
+}
+
+/// The oncall provider chosen with `oncall_provider` in the config file
+pub enum Oncall<'a> {
+    PagerDuty(PagerDuty<'a>),
+    Opsgenie(Opsgenie<'a>),
+    Grafana(GrafanaOncall<'a>),
+    Splunk(SplunkOncall<'a>),
+}
+
+impl<'a> Oncall<'a> {
+    /// The provider of the config file, at `base_url` if given, or else at `oncall_api_url` or
+    /// the provider's own api. Users it can't look up are recorded in `skipped`
+    pub fn connect(
+        config: &'a Config,
+        client: &'a HttpClient,
+        base_url: Option<&'a str>,
+        api_key: &'a str,
+        cache: &'a Cache,
+        skipped: &'a SkippedUsers,
+    ) -> AnyhowResult<Oncall<'a>> {
+        let base_url = base_url.or(config.oncall_api_url.as_deref());
+        let timezone = config.schedule_timezone()?;
+        Ok(match config.oncall_provider {
+            OncallBackend::PagerDuty => Oncall::PagerDuty(PagerDuty {
+                client,
+                base_url: base_url.unwrap_or(PAGERDUTY_API_URL),
+                api_key,
+                cache,
+                timezone,
+                skipped,
+            }),
+            OncallBackend::Opsgenie => Oncall::Opsgenie(Opsgenie {
+                client,
+                base_url: base_url.unwrap_or(OPSGENIE_API_URL),
+                api_key,
+                cache,
+                timezone,
+                skipped,
+            }),
+            OncallBackend::Grafana => Oncall::Grafana(GrafanaOncall {
+                client,
+                base_url: base_url.map(|x| x.trim_end_matches('/')).ok_or_else(|| {
+                    anyhow!(
+                        "Set oncall_api_url to the api url of grafana oncall, from its settings"
+                    )
+                })?,
+                api_token: api_key,
+                cache,
+                timezone,
+            }),
+            OncallBackend::Splunk => Oncall::Splunk(SplunkOncall {
+                client,
+                base_url: base_url.unwrap_or(SPLUNK_ONCALL_API_URL),
+                api_id: config.oncall_api_id.as_deref().ok_or_else(|| {
+                    anyhow!(
+                        "Set oncall_api_id to the api id of splunk on-call, from its integrations"
+                    )
+                })?,
+                api_key,
+                cache,
+                timezone,
+                skipped,
+            }),
+        })
+    }
+
+    /// Name shown in messages
+    pub fn name(&self) -> &'static str {
+        match self {
+            Oncall::PagerDuty(_) => "Pagerduty",
+            Oncall::Opsgenie(_) => "Opsgenie",
+            Oncall::Grafana(_) => "Grafana oncall",
+            Oncall::Splunk(_) => "Splunk on-call",
+        }
+    }
+
+    /// Whether the api key can read the schedule
+    pub async fn check_schedule(&self, schedule_id: &str) -> AnyhowResult<()> {
+        match self {
+            Oncall::PagerDuty(x) => x.check_schedule(schedule_id).await,
+            Oncall::Opsgenie(x) => x.check_schedule(schedule_id).await,
+            Oncall::Grafana(x) => x.check_schedule(schedule_id).await,
+            Oncall::Splunk(x) => x.check_schedule(schedule_id).await,
+        }
+    }
+}
+
+impl OncallProvider for Oncall<'_> {
+    async fn fetch_schedule(
+        &self,
+        schedule_id: &str,
+        start: DateTime<FixedOffset>,
+        end: DateTime<FixedOffset>,
+    ) -> AnyhowResult<Vec<FinalPagerDutySchedule>> {
+        match self {
+            Oncall::PagerDuty(x) => x.fetch_schedule(schedule_id, start, end).await,
+            Oncall::Opsgenie(x) => x.fetch_schedule(schedule_id, start, end).await,
+            Oncall::Grafana(x) => x.fetch_schedule(schedule_id, start, end).await,
+            Oncall::Splunk(x) => x.fetch_schedule(schedule_id, start, end).await,
+        }
+    }
+
+    async fn apply_overrides(
+        &self,
+        schedule_id: &str,
+        overrides: &[FinalOverride],
+    ) -> AnyhowResult<Vec<AppliedOverride>> {
+        match self {
+            Oncall::PagerDuty(x) => x.apply_overrides(schedule_id, overrides).await,
+            Oncall::Opsgenie(x) => x.apply_overrides(schedule_id, overrides).await,
+            Oncall::Grafana(x) => x.apply_overrides(schedule_id, overrides).await,
+            Oncall::Splunk(x) => x.apply_overrides(schedule_id, overrides).await,
+        }
+    }
+
+    async fn remove_override(&self, schedule_id: &str, override_id: &str) -> AnyhowResult<()> {
+        match self {
+            Oncall::PagerDuty(x) => x.remove_override(schedule_id, override_id).await,
+            Oncall::Opsgenie(x) => x.remove_override(schedule_id, override_id).await,
+            Oncall::Grafana(x) => x.remove_override(schedule_id, override_id).await,
+            Oncall::Splunk(x) => x.remove_override(schedule_id, override_id).await,
+        }
+    }
+
+    async fn resolve_user(&self, user_id: &str) -> AnyhowResult<String> {
+        match self {
+            Oncall::PagerDuty(x) => x.resolve_user(user_id).await,
+            Oncall::Opsgenie(x) => x.resolve_user(user_id).await,
+            Oncall::Grafana(x) => x.resolve_user(user_id).await,
+            Oncall::Splunk(x) => x.resolve_user(user_id).await,
+        }
+    }
+}
@@ -0,0 +1,230 @@
+use anyhow::{anyhow, Context, Result as AnyhowResult};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Request, Response};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// File of a recording directory holding the exchanges, one json object per line
+const EXCHANGES_FILE: &str = "exchanges.jsonl";
+
+/// Stands in for every secret in a recording
+const REDACTED: &str = "REDACTED";
+
+/// A request and the response it got. Request headers are never kept, so api keys and tokens
+/// stay out of recordings
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Exchange {
+    pub method: String,
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_body: Option<String>,
+    pub status: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    pub body: String,
+}
+
+/// Method, url and body of a request
+pub type ExchangeKey = (String, String, Option<String>);
+
+impl Exchange {
+    fn key(&self) -> ExchangeKey {
+        (
+            self.method.clone(),
+            self.url.clone(),
+            self.request_body.clone(),
+        )
+    }
+
+    fn to_response(&self) -> AnyhowResult<Response> {
+        let mut response = http::Response::builder().status(self.status);
+        if let Some(content_type) = &self.content_type {
+            response = response.header(CONTENT_TYPE, content_type);
+        }
+        let response = response
+            .body(self.body.clone())
+            .context("Invalid recorded response")?;
+        Ok(Response::from(response))
+    }
+}
+
+/// Where the http client sends requests: to the apis, to the apis while writing what they
+/// answer to a directory with --record, or to such a directory alone with --replay
+pub enum Tape {
+    Record {
+        file: Mutex<File>,
+        /// values replaced with REDACTED before anything is written
+        secrets: Mutex<Vec<String>>,
+    },
+    /// recorded responses by request, served in the order they were recorded. The last one of
+    /// each request keeps being served, so a replay that asks more often than the recording still
+    /// gets an answer
+    Replay(Mutex<HashMap<ExchangeKey, VecDeque<Exchange>>>),
+}
+
+impl Tape {
+    pub fn record(dir: &Path) -> AnyhowResult<Tape> {
+        fs::create_dir_all(dir).context(format!(
+            "Failed to create recording directory {}",
+            dir.display()
+        ))?;
+        let path = dir.join(EXCHANGES_FILE);
+        let file = File::create(&path).context(format!("Failed to create {}", path.display()))?;
+        Ok(Tape::Record {
+            file: Mutex::new(file),
+            secrets: Mutex::new(Vec::new()),
+        })
+    }
+
+    pub fn replay(dir: &Path) -> AnyhowResult<Tape> {
+        let path = dir.join(EXCHANGES_FILE);
+        let text =
+            fs::read_to_string(&path).context(format!("Failed to read {}", path.display()))?;
+        let mut exchanges: HashMap<ExchangeKey, VecDeque<Exchange>> = HashMap::new();
+        for (number, line) in text.lines().enumerate() {
+            let exchange: Exchange = serde_json::from_str(line).context(format!(
+                "Failed to parse line {} of {}",
+                number + 1,
+                path.display()
+            ))?;
+            exchanges
+                .entry(exchange.key())
+                .or_default()
+                .push_back(exchange);
+        }
+        Ok(Tape::Replay(Mutex::new(exchanges)))
+    }
+
+    /// Keep `secret` out of the recording, wherever it shows up
+    pub fn keep_secret(&self, secret: &str) {
+        if let Tape::Record { secrets, .. } = self {
+            if !secret.is_empty() {
+                secrets.lock().unwrap().push(secret.to_string());
+            }
+        }
+    }
+
+    fn redact(&self, text: &str) -> String {
+        match self {
+            Tape::Record { secrets, .. } => secrets
+                .lock()
+                .unwrap()
+                .iter()
+                .fold(text.to_string(), |text, secret| {
+                    text.replace(secret, REDACTED)
+                }),
+            Tape::Replay(_) => text.to_string(),
+        }
+    }
+
+    /// What `request` is recorded under
+    pub fn key(&self, request: &Request) -> ExchangeKey {
+        let body = request
+            .body()
+            .and_then(|x| x.as_bytes())
+            .map(|x| self.redact(&String::from_utf8_lossy(x)));
+        (
+            request.method().to_string(),
+            self.redact(request.url().as_str()),
+            body,
+        )
+    }
+
+    /// The recorded response to `request`, when replaying
+    pub fn replayed(&self, request: &Request) -> Option<AnyhowResult<Response>> {
+        let Tape::Replay(exchanges) = self else {
+            return None;
+        };
+        let key = self.key(request);
+        let mut exchanges = exchanges.lock().unwrap();
+        let recorded = match exchanges.get_mut(&key) {
+            Some(queue) if queue.len() > 1 => queue.pop_front(),
+            Some(queue) => queue.front().cloned(),
+            None => None,
+        };
+        Some(match recorded {
+            Some(exchange) => exchange.to_response(),
+            None => Err(anyhow!(
+                "No recorded response to {} {}",
+                request.method(),
+                request.url()
+            )),
+        })
+    }
+
+    /// Write the exchange when recording, and hand back an equivalent response, since reading
+    /// the body used up the original
+    pub async fn recorded(&self, key: ExchangeKey, response: Response) -> AnyhowResult<Response> {
+        let Tape::Record { file, .. } = self else {
+            return Ok(response);
+        };
+        let status = response.status().as_u16();
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|x| x.to_str().ok())
+            .map(|x| x.to_string());
+        let body = response
+            .text()
+            .await
+            .context("Failed to read the response to record it")?;
+        let exchange = Exchange {
+            method: key.0,
+            url: key.1,
+            request_body: key.2,
+            status,
+            content_type,
+            body: self.redact(&body),
+        };
+        let mut file = file.lock().unwrap();
+        writeln!(file, "{}", serde_json::to_string(&exchange)?)
+            .context("Failed to write to the recording")?;
+        // the caller gets the body as it was, secrets included
+        Exchange { body, ..exchange }.to_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_record_and_replay() -> AnyhowResult<()> {
+        let dir = std::env::temp_dir().join(format!("gcal-pagerduty-tape-{}", std::process::id()));
+        let client = reqwest::Client::new();
+        let request = client
+            .post("http://pd.test/schedules/P1/overrides?token=s3cret")
+            .body("{\"user\":\"s3cret\"}")
+            .build()?;
+
+        let tape = Tape::record(&dir)?;
+        tape.keep_secret("s3cret");
+        let key = tape.key(&request);
+        let live: Response = http::Response::builder()
+            .status(201)
+            .body("created s3cret")?
+            .into();
+        let response = tape.recorded(key, live).await?;
+        assert_eq!(response.text().await?, "created s3cret");
+        drop(tape);
+        let written = fs::read_to_string(dir.join(EXCHANGES_FILE))?;
+        assert!(!written.contains("s3cret"), "{}", written);
+
+        // a replay sees the redacted request, as it runs without the secrets
+        let tape = Tape::replay(&dir)?;
+        let redacted = client
+            .post("http://pd.test/schedules/P1/overrides?token=REDACTED")
+            .body("{\"user\":\"REDACTED\"}")
+            .build()?;
+        let response = tape.replayed(&redacted).unwrap()?;
+        assert_eq!(response.status(), 201);
+        assert_eq!(response.text().await?, "created REDACTED");
+        assert!(tape.replayed(&request).unwrap().is_err());
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,239 @@
+use crate::events::{log_format, LogFormat};
+use crate::solver::FinalOverride;
+use anyhow::{anyhow, Context, Result as AnyhowResult};
+use chrono::{DateTime, Datelike, Duration, NaiveDate};
+use std::collections::BTreeMap;
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+use tabled::object::Segment;
+use tabled::{Format, Modify, Table, Tabled};
+
+/// Rows of a table shown before the rest is cut, without --full
+pub const MAX_ROWS: usize = 40;
+
+/// Pager used with --pager when PAGER isn't set. -F quits right away when the table fits on screen
+const DEFAULT_PAGER: &str = "less -FRX";
+
+/// How long tables are shown
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TableOptions {
+    /// print every row instead of cutting tables at MAX_ROWS
+    pub full: bool,
+    /// show long tables in PAGER, when stdout is a terminal
+    pub pager: bool,
+    /// widest a cell gets before its text is wrapped onto more lines, 0 for no limit
+    pub max_column_width: usize,
+}
+
+static OPTIONS: OnceLock<TableOptions> = OnceLock::new();
+
+pub fn set_table_options(options: TableOptions) {
+    let _ = OPTIONS.set(options);
+}
+
+/// Print `rows` as a table. Tables longer than MAX_ROWS are paged with --pager, printed whole with
+/// --full, and otherwise cut with a note of how many rows were left out
+pub fn print_table<T: Tabled>(rows: impl IntoIterator<Item = T>) {
+    let rows: Vec<T> = rows.into_iter().collect();
+    let options = OPTIONS.get().copied().unwrap_or_default();
+    if rows.len() > MAX_ROWS && options.pager && can_page() {
+        match page(&fitted_table(&rows, options.max_column_width).to_string()) {
+            Ok(_) => return,
+            Err(e) => say!("Warning. {:#}, printing instead", e),
+        }
+    }
+    say!("{}", render_table(rows, options));
+}
+
+/// `rows` as a table with cells wrapped at --max-column-width, for tables that are printed whole
+pub fn table<T: Tabled>(rows: impl IntoIterator<Item = T>) -> Table {
+    let options = OPTIONS.get().copied().unwrap_or_default();
+    fitted_table(rows, options.max_column_width)
+}
+
+/// Long emails, event summaries and timestamps are wrapped between words where they can be, so
+/// rows stay within the terminal instead of being wrapped by it in the middle of a cell
+fn fitted_table<T: Tabled>(rows: impl IntoIterator<Item = T>, max_column_width: usize) -> Table {
+    let table = Table::new(rows);
+    if max_column_width == 0 {
+        return table;
+    }
+    table.with(Modify::new(Segment::all()).with(Format::new(|x| wrap_cell(x, max_column_width))))
+}
+
+/// Wrap every line of `text` at `width` characters between words, and cut words longer than that
+fn wrap_cell(text: &str, width: usize) -> String {
+    let mut lines = Vec::new();
+    for line in text.lines() {
+        if line.chars().count() <= width {
+            lines.push(line.to_string());
+            continue;
+        }
+        let mut current = String::new();
+        for word in line.split_whitespace() {
+            let chars: Vec<char> = word.chars().collect();
+            for piece in chars.chunks(width) {
+                let piece: String = piece.iter().collect();
+                if current.is_empty() {
+                    current = piece;
+                } else if current.chars().count() + 1 + piece.chars().count() <= width {
+                    current.push(' ');
+                    current.push_str(&piece);
+                } else {
+                    lines.push(std::mem::replace(&mut current, piece));
+                }
+            }
+        }
+        lines.push(current);
+    }
+    lines.join("\n")
+}
+
+fn render_table<T: Tabled>(rows: Vec<T>, options: TableOptions) -> String {
+    if options.full || rows.len() <= MAX_ROWS {
+        return fitted_table(rows, options.max_column_width).to_string();
+    }
+    let hidden = rows.len() - MAX_ROWS;
+    format!(
+        "{}\n... {} more rows. Pass --full to see them all, or --pager to page through them",
+        fitted_table(rows.into_iter().take(MAX_ROWS), options.max_column_width),
+        hidden
+    )
+}
+
+/// Print overrides as a table per week, starting on mondays, once they span more than one
+pub fn print_overrides_by_week<'a>(overrides: impl IntoIterator<Item = &'a FinalOverride>) {
+    let weeks = group_by_week(overrides);
+    if weeks.len() <= 1 {
+        print_table(weeks.into_values().flatten());
+        return;
+    }
+    for (monday, rows) in weeks {
+        say!("Week of {} ({} overrides)", monday, rows.len());
+        print_table(rows);
+    }
+}
+
+fn group_by_week<'a>(
+    overrides: impl IntoIterator<Item = &'a FinalOverride>,
+) -> BTreeMap<NaiveDate, Vec<&'a FinalOverride>> {
+    let mut weeks: BTreeMap<NaiveDate, Vec<&FinalOverride>> = BTreeMap::new();
+    for entry in overrides {
+        // overrides carry their start in rfc3339, an unparseable one goes in the first week
+        let day = DateTime::parse_from_rfc3339(&entry.start_time_iso)
+            .map(|x| x.naive_local().date())
+            .unwrap_or(NaiveDate::MIN);
+        let monday = day - Duration::days(day.weekday().num_days_from_monday().into());
+        weeks.entry(monday).or_default().push(entry);
+    }
+    weeks
+}
+
+fn can_page() -> bool {
+    log_format() == LogFormat::Text && io::stdout().is_terminal()
+}
+
+/// Show `text` in PAGER, and wait for the user to leave it
+fn page(text: &str) -> AnyhowResult<()> {
+    let pager = env::var("PAGER").unwrap_or_else(|_| DEFAULT_PAGER.to_string());
+    let mut words = pager.split_whitespace();
+    let program = words.next().ok_or_else(|| anyhow!("PAGER is empty"))?;
+    let mut child = Command::new(program)
+        .args(words)
+        .stdin(Stdio::piped())
+        .spawn()
+        .context(format!("Failed to start the pager {}", program))?;
+    // the pager may be quit before reading everything, which is fine
+    let _ = child.stdin.take().unwrap().write_all(text.as_bytes());
+    child.wait().context("Failed to wait for the pager")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_override(start: &str) -> FinalOverride {
+        FinalOverride {
+            original_slot: start.to_string(),
+            original_assignee: "a@x.com".to_string(),
+            final_override: "b@x.com".to_string(),
+            start_time_iso: start.to_string(),
+            end_time_iso: start.to_string(),
+            pd_user_id: "PB".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_group_by_week() {
+        // 2022-08-29 and 2022-09-05 are mondays
+        let overrides = [
+            test_override("2022-08-29T03:00:00+08:00"),
+            test_override("2022-09-04T15:00:00+08:00"),
+            test_override("2022-09-05T03:00:00+08:00"),
+        ];
+        let weeks = group_by_week(&overrides);
+        let sizes: Vec<(String, usize)> = weeks
+            .iter()
+            .map(|(monday, rows)| (monday.to_string(), rows.len()))
+            .collect();
+        assert_eq!(
+            sizes,
+            vec![("2022-08-29".to_string(), 2), ("2022-09-05".to_string(), 1)]
+        );
+    }
+
+    #[test]
+    fn test_render_table() {
+        let overrides: Vec<FinalOverride> = (0..MAX_ROWS + 5)
+            .map(|_| test_override("2022-08-29T03:00:00+08:00"))
+            .collect();
+        let cut = render_table(overrides.clone(), TableOptions::default());
+        assert!(cut.ends_with(
+            "... 5 more rows. Pass --full to see them all, or --pager to page through them"
+        ));
+        let options = TableOptions {
+            full: true,
+            ..TableOptions::default()
+        };
+        let full = render_table(overrides, options);
+        assert_eq!(full.matches("b@x.com").count(), MAX_ROWS + 5);
+    }
+
+    #[test]
+    fn test_fitted_table() {
+        let mut entry = test_override("2022-08-29T03:00:00+08:00");
+        entry.original_assignee =
+            "someone.with.a.very.long.name@subsidiary.example.com".to_string();
+        entry.original_slot = "Mon Aug 29 03:00:00 2022 +08".to_string();
+        let widest = |text: &str| text.lines().map(|x| x.chars().count()).max().unwrap();
+
+        let unlimited = fitted_table([entry.clone()], 0).to_string();
+        assert!(unlimited.contains(&entry.original_assignee));
+
+        let fitted = fitted_table([entry], 20).to_string();
+        assert!(widest(&fitted) < widest(&unlimited));
+        for line in fitted.lines().filter(|x| x.starts_with('|')) {
+            for cell in line.split('|') {
+                assert!(cell.chars().count() <= 20 + 2, "{}", fitted);
+            }
+        }
+        // timestamps are wrapped between words
+        assert!(fitted.contains("| Mon Aug 29 03:00:00 "), "{}", fitted);
+        assert!(fitted.contains("| 2022 +08 "), "{}", fitted);
+    }
+
+    #[test]
+    fn test_wrap_cell() {
+        assert_eq!(wrap_cell("short", 10), "short");
+        assert_eq!(
+            wrap_cell("busy with Out of office", 10),
+            "busy with\nOut of\noffice"
+        );
+        assert_eq!(wrap_cell("alice@example.com", 10), "alice@exam\nple.com");
+        // lines already there are kept
+        assert_eq!(wrap_cell("a b\nc d", 10), "a b\nc d");
+    }
+}
@@ -0,0 +1,249 @@
+use anyhow::{bail, Context, Result as AnyhowResult};
+use chrono::{DateTime, FixedOffset, NaiveDate};
+#[cfg(feature = "cli")]
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Version of the exported plan format, bumped whenever a field changes meaning or goes away, so
+/// that approval systems and --plan can tell which plans they understand
+pub const PLAN_SCHEMA_VERSION: u32 = 1;
+
+/// JSON Schema of the exported plan format, for approval systems to validate plans against
+pub const PLAN_SCHEMA: &str = include_str!("../schemas/plan.v1.json");
+
+/// How --save-plan writes the plan
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlanFormat {
+    /// the seed and the holder of every slot, to re-solve against or edit by hand
+    Json,
+    /// an ExportedPlan, to be reviewed and signed outside the tool and applied verbatim
+    SchemaJson,
+}
+
+/// Who holds a slot in a saved plan
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SavedSlot {
+    /// rfc3339 start and end time of the slot
+    pub start: String,
+    pub end: String,
+    pub email: String,
+    pub pd_user_id: String,
+}
+
+/// A solved schedule written to disk, so a later run can re-solve against it
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SavedPlan {
+    pub seed: u64,
+    pub slots: Vec<SavedSlot>,
+}
+
+impl SavedPlan {
+    /// Email of the holder of every slot, keyed by slot start
+    pub fn assignments(&self) -> AnyhowResult<BTreeMap<DateTime<FixedOffset>, String>> {
+        self.slots
+            .iter()
+            .map(|slot| {
+                let start = DateTime::parse_from_rfc3339(&slot.start)
+                    .context(format!("Invalid slot start {}", slot.start))?;
+                Ok((start, slot.email.clone()))
+            })
+            .collect()
+    }
+}
+
+/// A plan in the versioned format of PLAN_SCHEMA, carrying everything an approval system needs to
+/// review it: what it was computed from, under which constraints, the overrides it makes and who
+/// made it. Handed back to --plan, its overrides are applied as they are, and only if the
+/// schedule and calendars still hash to `inputs_hash`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExportedPlan {
+    pub schema_version: u32,
+    pub schedule_id: String,
+    /// rfc3339 bounds of the planned window
+    pub window_start: String,
+    pub window_end: String,
+    /// hex sha256 of the schedule and everyone's availability the plan was solved against
+    pub inputs_hash: String,
+    pub constraints: PlanConstraints,
+    pub overrides: Vec<ExportedOverride>,
+    /// holder of every slot once the overrides are applied
+    pub slots: Vec<SavedSlot>,
+    pub provenance: Provenance,
+}
+
+/// The hard constraints the plan was solved under
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct PlanConstraints {
+    pub max_shifts_per_person: Option<usize>,
+    pub max_consecutive_days: Option<usize>,
+    pub swap_window_days: Option<i64>,
+    pub blocked_swaps: Vec<Vec<String>>,
+    pub keep_paired: Vec<[String; 2]>,
+    pub never_paired: Vec<[String; 2]>,
+    pub holidays: Vec<NaiveDate>,
+    /// whether conflicts were allowed to stay unresolved
+    pub allow_unresolved: bool,
+}
+
+/// A slot handed from one person to another
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ExportedOverride {
+    pub start: String,
+    pub end: String,
+    pub from: String,
+    pub to: String,
+    /// oncall provider id of `to`
+    pub to_user_id: String,
+}
+
+/// Where the plan came from
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Provenance {
+    /// gcal-pagerduty and its version
+    pub generator: String,
+    /// rfc3339 time the plan was exported
+    pub generated_at: String,
+    /// solver seed, which reproduces the plan from the same inputs
+    pub seed: u64,
+    pub command_line: String,
+}
+
+impl From<&ExportedPlan> for SavedPlan {
+    fn from(plan: &ExportedPlan) -> SavedPlan {
+        SavedPlan {
+            seed: plan.provenance.seed,
+            slots: plan.slots.clone(),
+        }
+    }
+}
+
+/// A plan read from disk, in either format of --save-plan
+pub enum PlanFile {
+    Saved(SavedPlan),
+    Exported(Box<ExportedPlan>),
+}
+
+impl PlanFile {
+    /// The seed and slots of the plan, whichever its format
+    pub fn saved(&self) -> SavedPlan {
+        match self {
+            PlanFile::Saved(plan) => SavedPlan {
+                seed: plan.seed,
+                slots: plan.slots.clone(),
+            },
+            PlanFile::Exported(plan) => plan.as_ref().into(),
+        }
+    }
+}
+
+pub fn save_plan<T: Serialize>(path: &Path, plan: &T) -> AnyhowResult<()> {
+    let contents = serde_json::to_string_pretty(plan).context("Failed to serialise plan")?;
+    fs::write(path, contents).context(format!("Failed to write plan to {}", path.display()))
+}
+
+/// Read a plan written by --save-plan in either format, told apart by the schema_version of
+/// exported plans
+pub fn load_plan(path: &Path) -> AnyhowResult<PlanFile> {
+    let contents =
+        fs::read_to_string(path).context(format!("Failed to read plan {}", path.display()))?;
+    parse_plan(&contents).context(format!("Failed to parse plan {}", path.display()))
+}
+
+fn parse_plan(contents: &str) -> AnyhowResult<PlanFile> {
+    let value: Value = serde_json::from_str(contents)?;
+    match value.get("schema_version") {
+        None => Ok(PlanFile::Saved(serde_json::from_value(value)?)),
+        Some(version) if version == PLAN_SCHEMA_VERSION => {
+            Ok(PlanFile::Exported(Box::new(serde_json::from_value(value)?)))
+        }
+        Some(version) => bail!(
+            "Unsupported plan schema version {}, this version of gcal-pagerduty reads version {}",
+            version,
+            PLAN_SCHEMA_VERSION
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exported_plan() -> ExportedPlan {
+        ExportedPlan {
+            schema_version: PLAN_SCHEMA_VERSION,
+            schedule_id: "P1".to_string(),
+            window_start: "2022-08-29T00:00:00+08:00".to_string(),
+            window_end: "2022-09-02T00:00:00+08:00".to_string(),
+            inputs_hash: "ab".repeat(32),
+            constraints: PlanConstraints::default(),
+            overrides: vec![ExportedOverride {
+                start: "2022-08-29T03:00:00+08:00".to_string(),
+                end: "2022-08-30T03:00:00+08:00".to_string(),
+                from: "a@x.com".to_string(),
+                to: "b@x.com".to_string(),
+                to_user_id: "PB".to_string(),
+            }],
+            slots: vec![SavedSlot {
+                start: "2022-08-29T03:00:00+08:00".to_string(),
+                end: "2022-08-30T03:00:00+08:00".to_string(),
+                email: "b@x.com".to_string(),
+                pd_user_id: "PB".to_string(),
+            }],
+            provenance: Provenance {
+                generator: "gcal-pagerduty 0.2.0".to_string(),
+                generated_at: "2022-08-28T10:00:00+00:00".to_string(),
+                seed: 7,
+                command_line: "gcal-pagerduty --save-plan plan.json".to_string(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_parse_plan() -> AnyhowResult<()> {
+        let exported = serde_json::to_string(&exported_plan())?;
+        let PlanFile::Exported(plan) = parse_plan(&exported)? else {
+            panic!("expected an exported plan");
+        };
+        assert_eq!(plan.overrides[0].to, "b@x.com");
+        assert_eq!(PlanFile::Exported(plan).saved().seed, 7);
+
+        let saved = r#"{"seed": 3, "slots": []}"#;
+        assert!(matches!(parse_plan(saved)?, PlanFile::Saved(x) if x.seed == 3));
+
+        let newer = exported.replace("\"schema_version\":1", "\"schema_version\":2");
+        let error = parse_plan(&newer).err().unwrap();
+        assert!(error.to_string().contains("version 2"), "{}", error);
+        Ok(())
+    }
+
+    /// Every field the schema requires is written, so plans validate against it
+    #[test]
+    fn test_schema_matches_exported_plan() -> AnyhowResult<()> {
+        let schema: Value = serde_json::from_str(PLAN_SCHEMA)?;
+        assert_eq!(
+            schema["properties"]["schema_version"]["const"],
+            PLAN_SCHEMA_VERSION
+        );
+        let plan = serde_json::to_value(exported_plan())?;
+        let required = |schema: &Value, value: &Value| {
+            for field in schema["required"].as_array().unwrap() {
+                assert!(value.get(field.as_str().unwrap()).is_some(), "{}", field);
+            }
+        };
+        required(&schema, &plan);
+        for (name, definition) in schema["$defs"].as_object().unwrap() {
+            let value = match name.as_str() {
+                "override" => &plan["overrides"][0],
+                "slot" => &plan["slots"][0],
+                other => &plan[other],
+            };
+            required(definition, value);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,360 @@
+use crate::availability::{preferred_range, unavailable_range, DeclaredAvailability};
+use crate::gcal::AuthError;
+use crate::http::HttpClient;
+use crate::solver::FinalOverride;
+use crate::swap_requests::{parse_swap_requests, SwapRequest};
+use crate::watch::Conflict;
+use anyhow::{anyhow, bail, Context, Result as AnyhowResult};
+use chrono_tz::Tz;
+use reqwest::{Response, Url};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use tracing::instrument;
+
+/// Root of the google sheets api
+pub const SHEETS_API_URL: &str = "https://sheets.googleapis.com";
+
+/// Scope of the google token needed to read and write spreadsheets
+pub const SHEETS_SCOPE: &str = "https://www.googleapis.com/auth/spreadsheets";
+
+#[derive(Deserialize, Debug)]
+struct Spreadsheet {
+    #[serde(default)]
+    sheets: Vec<Sheet>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Sheet {
+    properties: SheetProperties,
+}
+
+#[derive(Deserialize, Debug)]
+struct SheetProperties {
+    title: String,
+}
+
+/// Cells of a range as shown in the sheet, without the empty cells ending a row
+#[derive(Deserialize, Debug)]
+struct ValueRange {
+    #[serde(default)]
+    values: Vec<Vec<String>>,
+}
+
+/// Google sheets, written with the oauth token of the calendars
+pub struct GoogleSheets<'a> {
+    pub client: &'a HttpClient,
+    /// SHEETS_API_URL, unless --base-url points elsewhere
+    pub base_url: &'a str,
+    pub token: &'a str,
+}
+
+impl GoogleSheets<'_> {
+    /// `{base_url}/v4/spreadsheets/{spreadsheet_id}/{path}`, with each part of `path` escaped as
+    /// tab names may have spaces
+    fn url(&self, spreadsheet_id: &str, path: &[&str]) -> AnyhowResult<Url> {
+        let mut url = Url::parse(self.base_url).context("Invalid google sheets api url")?;
+        url.path_segments_mut()
+            .map_err(|_| anyhow!("Invalid google sheets api url"))?
+            .pop_if_empty()
+            .extend(["v4", "spreadsheets", spreadsheet_id])
+            .extend(path);
+        Ok(url)
+    }
+
+    /// Titles of the tabs of the spreadsheet
+    async fn tabs(&self, spreadsheet_id: &str) -> AnyhowResult<Vec<String>> {
+        let mut url = self.url(spreadsheet_id, &[])?;
+        url.set_query(Some("fields=sheets.properties.title"));
+        let request = self.client.get(url).bearer_auth(self.token);
+        let response = check(self.client.send(request).await?).await?;
+        let spreadsheet: Spreadsheet = response
+            .json()
+            .await
+            .context("Failed to parse the spreadsheet as json")?;
+        Ok(spreadsheet
+            .sheets
+            .into_iter()
+            .map(|x| x.properties.title)
+            .collect())
+    }
+
+    /// Cells of `range` of the spreadsheet
+    async fn values(&self, spreadsheet_id: &str, range: &str) -> AnyhowResult<Vec<Vec<String>>> {
+        let request = self
+            .client
+            .get(self.url(spreadsheet_id, &["values", range])?)
+            .bearer_auth(self.token);
+        let response = check(self.client.send(request).await?).await?;
+        let cells: ValueRange = response
+            .json()
+            .await
+            .context("Failed to parse the sheet as json")?;
+        Ok(cells.values)
+    }
+
+    /// Preferred and unavailable ranges marked in `range` of the spreadsheet, by email. Dates are
+    /// whole days in `timezone`
+    #[instrument(skip(self))]
+    pub async fn preferences(
+        &self,
+        spreadsheet_id: &str,
+        range: &str,
+        timezone: Tz,
+    ) -> AnyhowResult<HashMap<String, DeclaredAvailability>> {
+        let values = self
+            .values(spreadsheet_id, range)
+            .await
+            .context("Failed to read the preferences sheet")?;
+        parse_preferences(&values, timezone).context(format!("Invalid preferences in {}", range))
+    }
+
+    /// Swap requests in `range` of the spreadsheet, in the order they were sent
+    #[instrument(skip(self))]
+    pub async fn swap_requests(
+        &self,
+        spreadsheet_id: &str,
+        range: &str,
+    ) -> AnyhowResult<Vec<SwapRequest>> {
+        let values = self
+            .values(spreadsheet_id, range)
+            .await
+            .context("Failed to read the swap requests sheet")?;
+        parse_swap_requests(&values).context(format!("Invalid swap requests in {}", range))
+    }
+
+    /// Write the conflicts and overrides of a run to the `tab` of the spreadsheet, adding the tab
+    /// if it isn't there yet and clearing it if it is
+    #[instrument(skip(self, conflicts, overrides))]
+    pub async fn write_plan(
+        &self,
+        spreadsheet_id: &str,
+        tab: &str,
+        conflicts: &[Conflict],
+        overrides: &[FinalOverride],
+    ) -> AnyhowResult<()> {
+        let request = match self.tabs(spreadsheet_id).await?.iter().any(|x| x == tab) {
+            true => self
+                .client
+                .post(self.url(spreadsheet_id, &["values", &format!("'{}':clear", tab)])?),
+            false => {
+                let url = self.url(spreadsheet_id, &[])?;
+                // batchUpdate is a method of the spreadsheet, written after a colon
+                let url = format!("{}:batchUpdate", url);
+                self.client.post(url).json(&json!({
+                    "requests": [{ "addSheet": { "properties": { "title": tab } } }]
+                }))
+            }
+        };
+        check(self.client.send(request.bearer_auth(self.token)).await?)
+            .await
+            .context(format!("Failed to prepare the tab {}", tab))?;
+
+        let mut url = self.url(spreadsheet_id, &["values", &format!("'{}'!A1", tab)])?;
+        url.set_query(Some("valueInputOption=RAW"));
+        let request = self
+            .client
+            .put(url)
+            .bearer_auth(self.token)
+            .json(&json!({ "values": plan_rows(conflicts, overrides) }));
+        check(self.client.send(request).await?)
+            .await
+            .context(format!("Failed to write the plan to the tab {}", tab))?;
+        Ok(())
+    }
+}
+
+/// Whether `token` may reach `spreadsheet_id`. A token from before the sheets were configured
+/// lacks the scope, and is unauthorised like an expired one so that a new one is asked for
+#[instrument(skip(client, token))]
+pub async fn check_spreadsheet(
+    client: &HttpClient,
+    base_url: &str,
+    token: &str,
+    spreadsheet_id: &str,
+) -> AnyhowResult<()> {
+    let sheets = GoogleSheets {
+        client,
+        base_url,
+        token,
+    };
+    sheets.tabs(spreadsheet_id).await.map(|_| ())
+}
+
+async fn check(response: Response) -> AnyhowResult<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    match status.as_u16() {
+        401 => Err(AuthError::Unauthorized.into()),
+        403 if body.contains("ACCESS_TOKEN_SCOPE_INSUFFICIENT") => {
+            Err(AuthError::Unauthorized.into())
+        }
+        status => Err(anyhow!(
+            "Unexpected status {} from the google sheets api: {}",
+            status,
+            body
+        )),
+    }
+}
+
+/// Rows of email, start, end, preference and an optional reason, found by the names of the header
+/// row. Preferred slots are a soft wish, unavailable ones are conflicts. Dates are written as in
+/// the availability file, YYYY-MM-DD or rfc3339
+fn parse_preferences(
+    rows: &[Vec<String>],
+    timezone: Tz,
+) -> AnyhowResult<HashMap<String, DeclaredAvailability>> {
+    let Some((header, rows)) = rows.split_first() else {
+        return Ok(HashMap::new());
+    };
+    let column = |name: &str| {
+        header
+            .iter()
+            .position(|x| x.trim().eq_ignore_ascii_case(name))
+            .ok_or_else(|| anyhow!("No {} column in the header", name))
+    };
+    let (email, start, end, preference) = (
+        column("email")?,
+        column("start")?,
+        column("end")?,
+        column("preference")?,
+    );
+    let reason = column("reason").ok();
+
+    let mut preferences: HashMap<String, DeclaredAvailability> = HashMap::new();
+    for (i, row) in rows.iter().enumerate() {
+        let cell = |column: usize| row.get(column).map(|x| x.trim()).unwrap_or("");
+        if row.iter().all(|x| x.trim().is_empty()) {
+            continue;
+        }
+        // the header is row 1
+        let line = i + 2;
+        let entry = preferences.entry(cell(email).to_string()).or_default();
+        match cell(preference).to_lowercase().as_str() {
+            "preferred" => entry.preferred.push(
+                preferred_range(cell(start), cell(end), timezone)
+                    .context(format!("Invalid range on row {}", line))?,
+            ),
+            "unavailable" => {
+                let reason = reason
+                    .map(cell)
+                    .filter(|x| !x.is_empty())
+                    .unwrap_or("unavailable in the preferences sheet");
+                entry.unavailable.push(
+                    unavailable_range(cell(start), cell(end), reason, timezone)
+                        .context(format!("Invalid range on row {}", line))?,
+                );
+            }
+            other => bail!(
+                "Unknown preference {:?} on row {}, expected preferred or unavailable",
+                other,
+                line
+            ),
+        }
+    }
+    Ok(preferences)
+}
+
+/// A table of the conflicts followed by one of the overrides, as rows of cells
+fn plan_rows(conflicts: &[Conflict], overrides: &[FinalOverride]) -> Vec<Vec<String>> {
+    let cells = |row: &[&str]| row.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+    let mut rows = vec![
+        cells(&["Conflicts"]),
+        cells(&["Email", "Start", "End", "Soft", "Reasons"]),
+    ];
+    for conflict in conflicts {
+        rows.push(vec![
+            conflict.email.clone(),
+            conflict.start.to_rfc3339(),
+            conflict.end.to_rfc3339(),
+            conflict.soft.to_string(),
+            conflict.reasons.join(", "),
+        ]);
+    }
+    rows.push(Vec::new());
+    rows.push(cells(&["Overrides"]));
+    rows.push(cells(&["Slot", "From", "To", "Start", "End"]));
+    for entry in overrides {
+        rows.push(vec![
+            entry.original_slot.clone(),
+            entry.original_assignee.clone(),
+            entry.final_override.clone(),
+            entry.start_time_iso.clone(),
+            entry.end_time_iso.clone(),
+        ]);
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+    use chrono_tz::Asia::Singapore;
+
+    #[test]
+    fn test_plan_rows() -> AnyhowResult<()> {
+        let conflicts = vec![Conflict {
+            email: "a@x.com".to_string(),
+            start: DateTime::parse_from_rfc3339("2022-08-29T03:00:00+08:00")?,
+            end: DateTime::parse_from_rfc3339("2022-08-30T03:00:00+08:00")?,
+            soft: false,
+            reasons: vec!["Out of office".to_string(), "Dentist".to_string()],
+        }];
+        let overrides = vec![FinalOverride {
+            original_slot: "Mon".to_string(),
+            original_assignee: "a@x.com".to_string(),
+            final_override: "b@x.com".to_string(),
+            start_time_iso: "2022-08-29T03:00:00+08:00".to_string(),
+            end_time_iso: "2022-08-30T03:00:00+08:00".to_string(),
+            pd_user_id: "PB".to_string(),
+        }];
+        let rows = plan_rows(&conflicts, &overrides);
+        assert_eq!(rows.len(), 7);
+        assert_eq!(rows[2][4], "Out of office, Dentist");
+        assert!(rows[3].is_empty());
+        assert_eq!(rows[6][1..3], ["a@x.com", "b@x.com"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_preferences() -> AnyhowResult<()> {
+        let cells: ValueRange = serde_json::from_str(
+            r#"{"range": "Preferences!A1:Z1000", "values": [
+                ["Email", "Preference", "Start", "End", "Reason"],
+                ["a@x.com", "Preferred", "2022-08-30", "2022-08-30"],
+                [],
+                ["a@x.com", "unavailable", "2022-09-01", "2022-09-01", "wedding"],
+                ["b@x.com", "unavailable", "2022-09-01T09:00:00+08:00", "2022-09-01T12:00:00+08:00"]
+            ]}"#,
+        )?;
+        let preferences = parse_preferences(&cells.values, Singapore)?;
+        let a = &preferences["a@x.com"];
+        assert_eq!(a.preferred[0].1.to_rfc3339(), "2022-08-31T00:00:00+08:00");
+        assert_eq!(a.unavailable[0].summary, "wedding");
+        assert_eq!(
+            preferences["b@x.com"].unavailable[0].summary,
+            "unavailable in the preferences sheet"
+        );
+
+        let unknown = vec![
+            vec![
+                "email".to_string(),
+                "start".to_string(),
+                "end".to_string(),
+                "preference".to_string(),
+            ],
+            vec![
+                "a@x.com".to_string(),
+                "2022-08-30".to_string(),
+                "2022-08-30".to_string(),
+                "maybe".to_string(),
+            ],
+        ];
+        assert!(parse_preferences(&unknown, Singapore).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,246 @@
+use crate::http::HttpClient;
+use crate::solver::FinalOverride;
+use crate::templates::{PlanReport, Templates, SLACK_PLAN};
+use crate::watch::Conflict;
+use anyhow::{anyhow, Error, Result as AnyhowResult};
+use chrono::{DateTime, FixedOffset};
+use serde_json::{json, Value};
+
+/// Longest list put in a single message, Slack truncates sections above 3000 characters
+const MAX_LINES: usize = 20;
+
+/// Posts to a Slack incoming webhook, if one is configured. Failing to notify is only a warning,
+/// it never fails the run
+pub struct SlackNotifier<'a> {
+    pub client: &'a HttpClient,
+    pub webhook_url: Option<String>,
+}
+
+impl SlackNotifier<'_> {
+    async fn post(&self, message: Value) {
+        let Some(webhook_url) = &self.webhook_url else {
+            return;
+        };
+        let request = self.client.post(webhook_url).json(&message);
+        let result = match self.client.send(request).await {
+            Ok(response) if response.status().is_success() => Ok(()),
+            Ok(response) => Err(anyhow!("Unexpected status {}", response.status())),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            say!("Warning. Failed to notify slack: {:#}", e);
+        }
+    }
+
+    /// The plan, worded by the slack_plan template if there is one
+    pub async fn plan_ready(&self, report: &PlanReport<'_>, templates: &Templates) {
+        let message = match templates.render(SLACK_PLAN, report) {
+            Some(Ok(text)) => section_message(&text),
+            Some(Err(e)) => {
+                say!("Warning. {:#}, using the built-in slack message", e);
+                plan_message(report)
+            }
+            None => plan_message(report),
+        };
+        self.post(message).await
+    }
+
+    pub async fn applied(&self, schedule_id: &str, run_id: i64, result: &AnyhowResult<()>) {
+        let text = match result {
+            Ok(_) => format!(
+                ":white_check_mark: Applied the overrides of run {} to schedule {}",
+                run_id, schedule_id
+            ),
+            Err(e) => format!(
+                ":x: Failed to apply the overrides of run {} to schedule {}: {:#}",
+                run_id, schedule_id, e
+            ),
+        };
+        self.post(section_message(&text)).await
+    }
+
+    pub async fn new_conflicts(&self, schedule_id: &str, conflicts: &[Conflict]) {
+        if conflicts.is_empty() {
+            return;
+        }
+        let mut message = section_message(&format!(
+            ":warning: New conflicts on schedule {}",
+            schedule_id
+        ));
+        message["blocks"].as_array_mut().unwrap().push(list_block(
+            conflicts.iter().map(describe_conflict).collect(),
+        ));
+        self.post(message).await
+    }
+
+    pub async fn check_failed(&self, error: &Error) {
+        self.post(section_message(&format!(
+            ":x: Schedule check failed: {:#}",
+            error
+        )))
+        .await
+    }
+}
+
+/// Answer to `/oncall check`
+pub fn check_reply(schedule_id: &str, conflicts: &[Conflict]) -> Value {
+    if conflicts.is_empty() {
+        return section_message(&format!(
+            ":white_check_mark: No conflicts coming up on schedule {}",
+            schedule_id
+        ));
+    }
+    let mut message = section_message(&format!(
+        ":warning: {} conflicts coming up on schedule {}",
+        conflicts.len(),
+        schedule_id
+    ));
+    message["blocks"].as_array_mut().unwrap().push(list_block(
+        conflicts.iter().map(describe_conflict).collect(),
+    ));
+    message
+}
+
+/// Answer to `/oncall plan`, a plan that was only solved, not recorded or applied
+pub fn plan_preview_reply(
+    schedule_id: &str,
+    start: DateTime<FixedOffset>,
+    end: DateTime<FixedOffset>,
+    conflicts: &[Conflict],
+    overrides: &[FinalOverride],
+) -> Value {
+    let mut message = section_message(&format!(
+        "Plan for schedule {} from {} to {}: {} conflicts, {} overrides. Run gcal-pagerduty to apply it",
+        schedule_id,
+        start.format("%a %d %b"),
+        end.format("%a %d %b"),
+        conflicts.len(),
+        overrides.len()
+    ));
+    let blocks = message["blocks"].as_array_mut().unwrap();
+    if !conflicts.is_empty() {
+        blocks.push(heading_block("Conflicts"));
+        blocks.push(list_block(
+            conflicts.iter().map(describe_conflict).collect(),
+        ));
+    }
+    if !overrides.is_empty() {
+        blocks.push(heading_block("Overrides"));
+        blocks.push(list_block(
+            overrides.iter().map(describe_override).collect(),
+        ));
+    }
+    message
+}
+
+/// Answer to a slash command that couldn't be carried out
+pub fn failed_reply(error: &Error) -> Value {
+    section_message(&format!(":x: {:#}", error))
+}
+
+fn describe_override(entry: &FinalOverride) -> String {
+    format!(
+        "{} to {}: {} → {}",
+        entry.start_time_iso, entry.end_time_iso, entry.original_assignee, entry.final_override
+    )
+}
+
+fn describe_conflict(conflict: &Conflict) -> String {
+    format!(
+        "{}{} from {} to {}: {}",
+        conflict.email,
+        if conflict.soft { " (soft)" } else { "" },
+        conflict.start.format("%a %d %b %H:%M"),
+        conflict.end.format("%a %d %b %H:%M"),
+        conflict.reasons.join(", ")
+    )
+}
+
+fn section_message(text: &str) -> Value {
+    json!({
+        "text": text,
+        "blocks": [{ "type": "section", "text": { "type": "mrkdwn", "text": text } }],
+    })
+}
+
+fn heading_block(text: &str) -> Value {
+    json!({ "type": "context", "elements": [{ "type": "mrkdwn", "text": format!("*{}*", text) }] })
+}
+
+/// One line per entry, cut off after MAX_LINES
+fn list_block(lines: Vec<String>) -> Value {
+    let mut text: Vec<String> = lines
+        .iter()
+        .take(MAX_LINES)
+        .map(|x| format!("• {}", x))
+        .collect();
+    if lines.len() > MAX_LINES {
+        text.push(format!("_and {} more_", lines.len() - MAX_LINES));
+    }
+    json!({ "type": "section", "text": { "type": "mrkdwn", "text": text.join("\n") } })
+}
+
+/// The conflicts found and the overrides proposed to resolve them
+fn plan_message(report: &PlanReport) -> Value {
+    let title = format!(
+        "Plan for schedule {} (run {}): {} conflicts, {} overrides",
+        report.schedule_id,
+        report.run_id,
+        report.conflicts.len(),
+        report.overrides.len()
+    );
+    let mut blocks = vec![json!({
+        "type": "header",
+        "text": { "type": "plain_text", "text": title },
+    })];
+    if !report.conflicts.is_empty() {
+        blocks.push(heading_block("Conflicts"));
+        blocks.push(list_block(
+            report.conflicts.iter().map(describe_conflict).collect(),
+        ));
+    }
+    if !report.overrides.is_empty() {
+        blocks.push(heading_block("Overrides"));
+        blocks.push(list_block(
+            report.overrides.iter().map(describe_override).collect(),
+        ));
+    }
+    json!({ "text": title, "blocks": blocks })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::tests::test_slot;
+
+    #[test]
+    fn test_plan_message() {
+        let slot = test_slot("2022-08-29T03:00:00+08:00");
+        let conflicts: Vec<Conflict> = (0..25)
+            .map(|i| Conflict {
+                email: format!("{}@x.com", i),
+                start: slot.start_time,
+                end: slot.end_time,
+                soft: false,
+                reasons: vec!["OOO".to_string()],
+            })
+            .collect();
+        let message = plan_message(&PlanReport {
+            schedule_id: "P1",
+            run_id: 2,
+            conflicts: &conflicts,
+            swaps: &[],
+            overrides: &[],
+            diff: &[],
+        });
+        assert_eq!(
+            message["text"],
+            "Plan for schedule P1 (run 2): 25 conflicts, 0 overrides"
+        );
+        let blocks = message["blocks"].as_array().unwrap();
+        assert_eq!(blocks.len(), 3);
+        let listed = blocks[2]["text"]["text"].as_str().unwrap();
+        assert!(listed.starts_with("• 0@x.com from Mon 29 Aug 03:00 to Mon 29 Aug 15:00: OOO"));
+        assert!(listed.ends_with("_and 5 more_"));
+    }
+}
@@ -0,0 +1,205 @@
+use crate::availability::DeclaredAvailability;
+use crate::config::SlackConfig;
+use crate::http::HttpClient;
+use crate::solver::BusyInterval;
+use anyhow::{anyhow, Context, Result as AnyhowResult};
+use chrono::{DateTime, Duration, FixedOffset, TimeZone, Utc};
+use serde::Deserialize;
+use serde_json::Value;
+use tracing::instrument;
+
+/// Root of the slack web api
+pub const SLACK_API_URL: &str = "https://slack.com";
+
+/// How long a status without an expiry counts as away. It is read again on the next run
+const OPEN_ENDED_STATUS_HOURS: i64 = 24;
+
+#[derive(Deserialize, Debug)]
+struct LookupResponse {
+    user: SlackUser,
+}
+
+#[derive(Deserialize, Debug)]
+struct SlackUser {
+    id: String,
+    profile: Profile,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct Profile {
+    status_text: String,
+    status_emoji: String,
+    /// unix timestamp, 0 when the status doesn't expire
+    status_expiration: i64,
+}
+
+/// The slack web api, called with a bot token having users:read, users:read.email and, for the
+/// oncall group, usergroups:write
+pub struct SlackWorkspace<'a> {
+    pub client: &'a HttpClient,
+    /// SLACK_API_URL, unless --base-url points elsewhere
+    pub base_url: &'a str,
+    pub token: &'a str,
+    pub config: &'a SlackConfig,
+}
+
+impl SlackWorkspace<'_> {
+    /// Call `method` with form encoded `params`, which every method accepts. Slack reports most
+    /// errors in the body, with a 200 status
+    async fn call_unchecked(&self, method: &str, params: &[(&str, &str)]) -> AnyhowResult<Value> {
+        let request = self
+            .client
+            .post(format!("{}/api/{}", self.base_url, method))
+            .bearer_auth(self.token)
+            .form(params);
+        let response = self.client.send(request).await?;
+        response
+            .json()
+            .await
+            .context(format!("Failed to parse the response of slack {}", method))
+    }
+
+    async fn call(&self, method: &str, params: &[(&str, &str)]) -> AnyhowResult<Value> {
+        let body = self.call_unchecked(method, params).await?;
+        match body["ok"].as_bool() {
+            Some(true) => Ok(body),
+            _ => Err(anyhow!(
+                "Slack {} failed: {}",
+                method,
+                body["error"].as_str().unwrap_or("no error given")
+            )),
+        }
+    }
+
+    /// The slack user with `email`, or None when nobody in the workspace has it
+    async fn lookup(&self, email: &str) -> AnyhowResult<Option<SlackUser>> {
+        let body = self
+            .call_unchecked("users.lookupByEmail", &[("email", email)])
+            .await?;
+        match (body["ok"].as_bool(), body["error"].as_str()) {
+            (Some(true), _) => {
+                let found: LookupResponse = serde_json::from_value(body)
+                    .context("Failed to parse the slack user as json")?;
+                Ok(Some(found.user))
+            }
+            (_, Some("users_not_found")) => Ok(None),
+            (_, error) => Err(anyhow!(
+                "Slack users.lookupByEmail failed: {}",
+                error.unwrap_or("no error given")
+            )),
+        }
+    }
+
+    /// The time `email` is away according to their slack status, from now until it expires
+    #[instrument(skip(self))]
+    pub async fn away(
+        &self,
+        email: &str,
+        now: DateTime<FixedOffset>,
+    ) -> AnyhowResult<DeclaredAvailability> {
+        let mut availability = DeclaredAvailability::default();
+        if self.config.away_statuses.is_empty() {
+            return Ok(availability);
+        }
+        let Some(user) = self.lookup(email).await? else {
+            return Ok(availability);
+        };
+        if let Some(busy) = away_interval(&user.profile, &self.config.away_statuses, now) {
+            availability.unavailable.push(busy);
+        }
+        Ok(availability)
+    }
+
+    /// Make `emails` the members of the oncall group, when there is one
+    #[instrument(skip(self))]
+    pub async fn set_oncall_group(&self, emails: &[String]) -> AnyhowResult<()> {
+        let Some(group) = &self.config.oncall_group else {
+            return Ok(());
+        };
+        let mut ids = Vec::new();
+        for email in emails {
+            match self.lookup(email).await? {
+                Some(user) => ids.push(user.id),
+                None => say!("Warning. {} isn't in the slack workspace", email),
+            }
+        }
+        // slack refuses to empty a user group
+        if ids.is_empty() {
+            return Err(anyhow!("Nobody oncall is in the slack workspace"));
+        }
+        self.call(
+            "usergroups.users.update",
+            &[("usergroup", group), ("users", &ids.join(","))],
+        )
+        .await
+        .context(format!("Failed to update the slack user group {}", group))?;
+        Ok(())
+    }
+}
+
+/// From `now` until the status expires, if it is one of `away_statuses`
+fn away_interval(
+    profile: &Profile,
+    away_statuses: &[String],
+    now: DateTime<FixedOffset>,
+) -> Option<BusyInterval> {
+    let text = profile.status_text.to_lowercase();
+    let away = away_statuses.iter().any(|status| {
+        *status == profile.status_emoji
+            || (!status.starts_with(':') && text.contains(&status.to_lowercase()))
+    });
+    if !away {
+        return None;
+    }
+    let end = match profile.status_expiration {
+        0 => now + Duration::hours(OPEN_ENDED_STATUS_HOURS),
+        timestamp => Utc
+            .timestamp_opt(timestamp, 0)
+            .single()?
+            .with_timezone(now.offset()),
+    };
+    (end > now).then(|| BusyInterval {
+        summary: format!(
+            "slack status {} {}",
+            profile.status_emoji, profile.status_text
+        )
+        .trim_end()
+        .to_string(),
+        start: now,
+        end,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_away_interval() -> AnyhowResult<()> {
+        let now = DateTime::parse_from_rfc3339("2022-08-29T09:00:00+08:00")?;
+        let statuses = vec![":palm_tree:".to_string(), "PTO".to_string()];
+        let profile = |text: &str, emoji: &str, expiration: i64| Profile {
+            status_text: text.to_string(),
+            status_emoji: emoji.to_string(),
+            status_expiration: expiration,
+        };
+
+        // 2022-08-31T00:00:00Z
+        let busy = away_interval(
+            &profile("Holiday", ":palm_tree:", 1661904000),
+            &statuses,
+            now,
+        )
+        .unwrap();
+        assert_eq!(busy.end.to_rfc3339(), "2022-08-31T08:00:00+08:00");
+        assert_eq!(busy.summary, "slack status :palm_tree: Holiday");
+        let busy = away_interval(&profile("On pto", "", 0), &statuses, now).unwrap();
+        assert_eq!(busy.end, now + Duration::hours(24));
+
+        assert!(away_interval(&profile("In a meeting", ":calendar:", 0), &statuses, now).is_none());
+        // expired already
+        assert!(away_interval(&profile("", ":palm_tree:", 1661731200), &statuses, now).is_none());
+        Ok(())
+    }
+}
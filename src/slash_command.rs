@@ -0,0 +1,188 @@
+use crate::http::HttpClient;
+use crate::webhook::sign;
+use actix_web::dev::Server;
+use actix_web::web::{Bytes, Data, Query};
+use actix_web::{post, App, HttpRequest, HttpResponse, HttpServer};
+use anyhow::{anyhow, Context, Result as AnyhowResult};
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+
+/// Oldest request accepted, in seconds, so that a captured request can't be replayed later
+const MAX_REQUEST_AGE_SECONDS: i64 = 5 * 60;
+
+const USAGE: &str = "Usage: `/oncall check` lists the conflicts coming up, `/oncall plan` \
+    proposes overrides for them and `/oncall plan next-week` does so for next week";
+
+/// What a slash command asks for
+#[derive(Debug, PartialEq, Eq)]
+pub enum SlashCommand {
+    /// the conflicts over the --watch horizon
+    Check,
+    /// a plan resolving the conflicts, over the --watch horizon or, with next_week, from next
+    /// monday for a week. It is only shown, never applied
+    Plan { next_week: bool },
+}
+
+impl SlashCommand {
+    /// The command in the text typed after the slash command, if it is one
+    pub fn parse(text: &str) -> Option<SlashCommand> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        match words.as_slice() {
+            ["check"] => Some(SlashCommand::Check),
+            ["plan"] => Some(SlashCommand::Plan { next_week: false }),
+            ["plan", "next-week"] => Some(SlashCommand::Plan { next_week: true }),
+            _ => None,
+        }
+    }
+}
+
+/// A slash command to carry out, answered at its response url
+pub struct CommandRequest {
+    pub command: SlashCommand,
+    pub response_url: String,
+    /// slack name of who typed it
+    pub user: String,
+}
+
+/// Fields of the form slack posts for a slash command
+#[derive(Deserialize, Debug)]
+struct SlashCommandForm {
+    #[serde(default)]
+    text: String,
+    response_url: String,
+    #[serde(default)]
+    user_name: String,
+}
+
+struct CommandState {
+    signing_secret: String,
+    sender: mpsc::Sender<CommandRequest>,
+}
+
+/// Whether the request was signed by slack with the app's signing secret, recently
+fn verify(request: &HttpRequest, body: &[u8], signing_secret: &str) -> bool {
+    let header = |name: &str| {
+        request
+            .headers()
+            .get(name)
+            .and_then(|x| x.to_str().ok())
+            .unwrap_or("")
+    };
+    let Ok(timestamp) = header("X-Slack-Request-Timestamp").parse::<i64>() else {
+        return false;
+    };
+    if (Utc::now().timestamp() - timestamp).abs() > MAX_REQUEST_AGE_SECONDS {
+        return false;
+    }
+    let Ok(body) = std::str::from_utf8(body) else {
+        return false;
+    };
+    let expected = format!(
+        "v0={}",
+        sign(signing_secret, &format!("v0:{}:{}", timestamp, body))
+    );
+    header("X-Slack-Signature") == expected
+}
+
+/// Slack wants an answer within 3 seconds, so the command is acknowledged here and carried out
+/// by the watch, which replies at the response url
+#[post("/slack/commands")]
+async fn slash_command(
+    request: HttpRequest,
+    body: Bytes,
+    state: Data<CommandState>,
+) -> HttpResponse {
+    if !verify(&request, &body, &state.signing_secret) {
+        return HttpResponse::Unauthorized().body("Invalid slack signature");
+    }
+    let form = match std::str::from_utf8(&body)
+        .ok()
+        .and_then(|x| Query::<SlashCommandForm>::from_query(x).ok())
+    {
+        Some(form) => form.into_inner(),
+        None => return HttpResponse::BadRequest().body("Expected a slash command form"),
+    };
+    let Some(command) = SlashCommand::parse(&form.text) else {
+        return HttpResponse::Ok().json(ephemeral(USAGE));
+    };
+    let text = match command {
+        SlashCommand::Check => "Checking the schedule…",
+        SlashCommand::Plan { .. } => "Planning, this can take a minute…",
+    };
+    let queued = state.sender.try_send(CommandRequest {
+        command,
+        response_url: form.response_url,
+        user: form.user_name,
+    });
+    match queued {
+        Ok(_) => HttpResponse::Ok().json(ephemeral(text)),
+        Err(_) => HttpResponse::Ok().json(ephemeral(
+            "Busy with earlier commands, try again in a minute",
+        )),
+    }
+}
+
+fn ephemeral(text: &str) -> Value {
+    json!({ "response_type": "ephemeral", "text": text })
+}
+
+/// Serve slash commands on every interface, as slack must reach them, until the returned
+/// channel is dropped. Requests without a valid signature are refused
+pub fn start_slash_commands(
+    port: u16,
+    signing_secret: String,
+) -> AnyhowResult<(Server, mpsc::Receiver<CommandRequest>)> {
+    let (sender, receiver) = mpsc::channel(4);
+    let state = Data::new(CommandState {
+        signing_secret,
+        sender,
+    });
+    let server = HttpServer::new(move || App::new().app_data(state.clone()).service(slash_command))
+        .workers(1)
+        .shutdown_timeout(5)
+        .bind(("0.0.0.0", port))
+        .context(format!("Failed to serve slack commands on port {}", port))?
+        .run();
+    Ok((server, receiver))
+}
+
+/// Post `message` in the channel the command was typed in
+pub async fn reply(
+    client: &HttpClient,
+    response_url: &str,
+    mut message: Value,
+) -> AnyhowResult<()> {
+    message["response_type"] = json!("in_channel");
+    let response = client
+        .send(client.post(response_url).json(&message))
+        .await?;
+    match response.status().is_success() {
+        true => Ok(()),
+        false => Err(anyhow!(
+            "Unexpected status {} replying to the slash command",
+            response.status()
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(SlashCommand::parse(" check "), Some(SlashCommand::Check));
+        assert_eq!(
+            SlashCommand::parse("plan next-week"),
+            Some(SlashCommand::Plan { next_week: true })
+        );
+        assert_eq!(
+            SlashCommand::parse("plan"),
+            Some(SlashCommand::Plan { next_week: false })
+        );
+        assert_eq!(SlashCommand::parse("plan tomorrow"), None);
+        assert_eq!(SlashCommand::parse(""), None);
+    }
+}
@@ -0,0 +1,3298 @@
+use crate::calendar::{display_time, CalendarEvent};
+use crate::config::{shift_of, Shift, Weights};
+use crate::oncall::FinalPagerDutySchedule;
+use crate::saved_plan::{ExportedOverride, PlanConstraints, SavedPlan, SavedSlot};
+use anyhow::{anyhow, Result as AnyhowResult};
+use chrono::{DateTime, Datelike, Duration, FixedOffset, NaiveDate, Weekday};
+use chrono_tz::Tz;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use rayon::prelude::*;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::iter::zip;
+use std::sync::OnceLock;
+use std::time::{Duration as StdDuration, Instant};
+use tabled::Tabled;
+use tracing::instrument;
+
+mod constraints;
+
+pub use constraints::validate_plan;
+use constraints::Move;
+
+#[derive(Tabled, Serialize, Debug, Clone)]
+pub struct SimulatedSwap {
+    pub person_with_conflict: String,
+    pub original_slot: String,
+    pub swapped_with: String,
+    pub new_slot: String,
+    #[tabled(skip)]
+    #[serde(skip)]
+    pub original_start: DateTime<FixedOffset>,
+    #[tabled(skip)]
+    #[serde(skip)]
+    pub new_start: DateTime<FixedOffset>,
+}
+
+/// Replay `swaps` and return the fewest sequential swaps with the same net effect, dropping
+/// slots moved several times along the way or moved back to whoever held them first. Slots are
+/// told apart by their start, since their display strings may repeat across a DST change. Swaps
+/// that don't replay consistently are returned as they are
+pub fn compact_swaps(swaps: &[SimulatedSwap]) -> Vec<SimulatedSwap> {
+    let mut slots: Vec<DateTime<FixedOffset>> = Vec::new();
+    let mut labels: BTreeMap<DateTime<FixedOffset>, &str> = BTreeMap::new();
+    let mut original: BTreeMap<DateTime<FixedOffset>, &str> = BTreeMap::new();
+    for swap in swaps {
+        for (slot, label, holder) in [
+            (
+                swap.original_start,
+                &swap.original_slot,
+                &swap.person_with_conflict,
+            ),
+            (swap.new_start, &swap.new_slot, &swap.swapped_with),
+        ] {
+            if let Entry::Vacant(entry) = original.entry(slot) {
+                entry.insert(holder);
+                slots.push(slot);
+                labels.insert(slot, label);
+            }
+        }
+    }
+    let mut holders = original.clone();
+    for swap in swaps {
+        let leaving = holders[&swap.original_start];
+        let arriving = holders[&swap.new_start];
+        holders.insert(swap.original_start, arriving);
+        holders.insert(swap.new_start, leaving);
+    }
+
+    let mut current = original;
+    let mut compacted = Vec::new();
+    for (i, slot) in slots.iter().enumerate() {
+        let wanted = holders[slot];
+        if current[slot] == wanted {
+            continue;
+        }
+        // prefer a direct exchange, which settles both slots at once
+        let pending =
+            |x: &&DateTime<FixedOffset>| current[*x] == wanted && current[*x] != holders[*x];
+        let other = match slots[i + 1..]
+            .iter()
+            .filter(pending)
+            .find(|x| holders[*x] == current[slot])
+            .or_else(|| slots[i + 1..].iter().find(pending))
+        {
+            Some(value) => *value,
+            None => return swaps.to_vec(),
+        };
+        compacted.push(SimulatedSwap {
+            person_with_conflict: current[slot].to_string(),
+            original_slot: labels[slot].to_string(),
+            swapped_with: wanted.to_string(),
+            new_slot: labels[&other].to_string(),
+            original_start: *slot,
+            new_start: other,
+        });
+        let leaving = current[slot];
+        current.insert(*slot, wanted);
+        current.insert(other, leaving);
+    }
+    compacted
+}
+
+#[derive(Tabled, Serialize, Clone)]
+pub struct FinalOverride {
+    pub original_slot: String,
+    pub original_assignee: String,
+    pub final_override: String,
+    pub start_time_iso: String,
+    pub end_time_iso: String,
+    pub pd_user_id: String,
+}
+
+/// Knobs that constrain what the solver is allowed to produce
+#[derive(Debug, Clone)]
+pub struct SolverOptions {
+    /// no one may end up with more slots than this, unless they already had more before solving
+    pub max_shifts_per_person: Option<usize>,
+    /// no one may be oncall more calendar days in a row than this, counting every shift, unless
+    /// they already were before solving
+    pub max_consecutive_days: Option<usize>,
+    /// most people in a rotation when a direct swap isn't possible. Below 2 disables rotations
+    pub max_cycle_length: usize,
+    /// weights of the soft constraints, used to order swap candidates and rank plans
+    pub weights: Weights,
+    /// search budget of a single solver run
+    pub max_swaps: usize,
+    pub max_depth: usize,
+    pub max_duration: Option<StdDuration>,
+    /// number of seeds tried by generate_candidate_plans, 5 per candidate if not set
+    pub attempts: Option<usize>,
+    /// shortest piece when splitting partially conflicting slots. None disables splitting
+    pub min_split_segment: Option<Duration>,
+    /// groups of people who must never be swapped with each other
+    pub blocked_swaps: Vec<Vec<String>>,
+    /// public holidays, whose slots are spread evenly like weekends
+    pub holidays: BTreeSet<NaiveDate>,
+    /// how far from their original slots people may be moved. None allows any slot in the window
+    pub swap_window: Option<Duration>,
+    /// secondary oncall of each primary slot, keyed by slot start, when a secondary schedule is given
+    pub secondaries: BTreeMap<DateTime<FixedOffset>, String>,
+    /// [primary, secondary] pairs that must stay together in the slots they share
+    pub keep_paired: Vec<[String; 2]>,
+    /// [primary, secondary] pairs that must never be oncall together
+    pub never_paired: Vec<[String; 2]>,
+    /// keep running attempts with fresh seeds until this much time has passed, instead of a fixed
+    /// number of attempts, and fall back to the best partial plan if none solves everything
+    pub time_budget: Option<StdDuration>,
+    /// holder of every slot in a previously saved plan, which the solver starts from
+    pub previous_assignments: Option<BTreeMap<DateTime<FixedOffset>, String>>,
+    /// new holder of the slots moved by accepted swap requests, laid over the previous plan
+    pub approved_swaps: BTreeMap<DateTime<FixedOffset>, String>,
+    /// leave conflicts that can't be resolved in place and solve the rest, instead of failing
+    pub allow_unresolved: bool,
+    /// shift definitions of the schedule, naming the shift of each slot
+    pub shifts: Vec<Shift>,
+    /// the only shift each person may be moved into from their own, keyed by email
+    pub preferred_shifts: BTreeMap<String, String>,
+}
+
+impl SolverOptions {
+    /// The hard constraints, as written in exported plans
+    pub fn exported_constraints(&self) -> PlanConstraints {
+        PlanConstraints {
+            max_shifts_per_person: self.max_shifts_per_person,
+            max_consecutive_days: self.max_consecutive_days,
+            swap_window_days: self.swap_window.map(|x| x.num_days()),
+            blocked_swaps: self.blocked_swaps.clone(),
+            keep_paired: self.keep_paired.clone(),
+            never_paired: self.never_paired.clone(),
+            holidays: self.holidays.iter().copied().collect(),
+            allow_unresolved: self.allow_unresolved,
+        }
+    }
+}
+
+impl Default for SolverOptions {
+    fn default() -> Self {
+        SolverOptions {
+            max_shifts_per_person: None,
+            max_consecutive_days: None,
+            max_cycle_length: 4,
+            weights: Weights::default(),
+            max_swaps: 200,
+            max_depth: 1000,
+            max_duration: None,
+            attempts: None,
+            min_split_segment: None,
+            blocked_swaps: Vec::new(),
+            holidays: BTreeSet::new(),
+            secondaries: BTreeMap::new(),
+            keep_paired: Vec::new(),
+            never_paired: Vec::new(),
+            previous_assignments: None,
+            approved_swaps: BTreeMap::new(),
+            time_budget: None,
+            swap_window: None,
+            allow_unresolved: false,
+            shifts: Vec::new(),
+            preferred_shifts: BTreeMap::new(),
+        }
+    }
+}
+
+/// Soft constraint measurements of a schedule. Hard constraints (calendar conflicts) are
+/// enforced by the solver itself and never traded off against these
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SoftScore {
+    pub weekend_imbalance: f64,
+    pub holiday_imbalance: f64,
+    pub preferences_met: usize,
+    pub back_to_back: usize,
+    /// recent load of whoever holds each weekend slot, summed. Lower means recently busy people
+    /// hold fewer weekends
+    pub history_load: f64,
+    /// costs from the cost matrix of every slot's holder, summed
+    pub external_cost: f64,
+    /// fraction of each slot held through soft conflicts covered by them, summed. A short overlap
+    /// costs less than a meeting spanning the whole slot
+    pub soft_conflict_coverage: f64,
+}
+
+impl SoftScore {
+    pub fn of(schedule: &[FinalEntity], holidays: &BTreeSet<NaiveDate>) -> SoftScore {
+        SoftScore {
+            weekend_imbalance: imbalance(&weekend_counts(schedule)),
+            holiday_imbalance: imbalance(&holiday_counts(schedule, holidays)),
+            preferences_met: schedule
+                .iter()
+                .filter(|x| x.prefers(x.pd_schedule.start, x.pd_schedule.end))
+                .count(),
+            back_to_back: back_to_back_count(schedule),
+            history_load: schedule
+                .iter()
+                .filter(|x| is_weekend(x.pd_schedule.start))
+                .map(|x| x.recent_load)
+                .sum(),
+            external_cost: schedule
+                .iter()
+                .map(|x| x.slot_cost(x.pd_schedule.start))
+                .sum(),
+            soft_conflict_coverage: schedule.iter().map(|x| x.soft_conflict_coverage()).sum(),
+        }
+    }
+
+    /// Weighted penalty of the soft constraints, lower is better
+    pub fn penalty(&self, weights: &Weights) -> f64 {
+        weights.weekend * self.weekend_imbalance
+            + weights.holiday * self.holiday_imbalance
+            + weights.back_to_back * self.back_to_back as f64
+            - weights.preference * self.preferences_met as f64
+            + weights.history * self.history_load
+            + weights.cost * self.external_cost
+            + weights.soft_conflict * self.soft_conflict_coverage
+    }
+}
+
+/// Counters of the search, summed over every solver attempt, to compare solver configurations
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SolverStats {
+    pub attempts: usize,
+    pub failed_attempts: usize,
+    /// search steps, one per conflict picked up by the solver
+    pub iterations: usize,
+    /// swap candidates scored for those conflicts
+    pub candidates_evaluated: usize,
+    pub rotations: usize,
+    /// conflicts no swap or rotation could resolve. The solver doesn't backtrack within an
+    /// attempt, so each of these either fails the attempt or is left unresolved
+    pub dead_ends: usize,
+    pub wall_time: StdDuration,
+}
+
+impl SolverStats {
+    fn add(&mut self, other: &SolverStats) {
+        self.attempts += other.attempts;
+        self.failed_attempts += other.failed_attempts;
+        self.iterations += other.iterations;
+        self.candidates_evaluated += other.candidates_evaluated;
+        self.rotations += other.rotations;
+        self.dead_ends += other.dead_ends;
+        self.wall_time += other.wall_time;
+    }
+}
+
+/// A complete plan produced by a single solver run
+pub struct CandidatePlan {
+    pub seed: u64,
+    pub schedule: Vec<FinalEntity>,
+    pub swaps: Vec<SimulatedSwap>,
+    pub overrides: Vec<FinalOverride>,
+    pub splits: Vec<SplitShift>,
+    pub soft_score: SoftScore,
+    /// statistics of the whole solve that produced the plan
+    pub stats: SolverStats,
+}
+
+impl CandidatePlan {
+    /// The plan reached by rescheduling `schedule`. Only the `splits` still needed by the
+    /// rescheduled plan are kept
+    pub fn new(
+        seed: u64,
+        schedule: &[FinalEntity],
+        rescheduled: Vec<FinalEntity>,
+        swaps: Vec<SimulatedSwap>,
+        splits: &[SplitShift],
+        holidays: &BTreeSet<NaiveDate>,
+    ) -> CandidatePlan {
+        let splits = kept_splits(splits, &rescheduled);
+        let overrides = apply_splits(
+            generate_diff_of_shift(schedule.to_vec(), rescheduled.clone()),
+            &splits,
+        );
+        CandidatePlan {
+            seed,
+            overrides,
+            soft_score: SoftScore::of(&rescheduled, holidays),
+            schedule: rescheduled,
+            swaps: compact_swaps(&swaps),
+            splits,
+            stats: SolverStats::default(),
+        }
+    }
+
+    /// The most slots handed over to a single person. Lower means the swaps are spread more evenly
+    pub fn max_overrides_per_person(&self) -> usize {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for entry in &self.overrides {
+            *counts.entry(&entry.final_override).or_default() += 1;
+        }
+        counts.into_values().max().unwrap_or(0)
+    }
+
+    pub fn soft_score(&self) -> SoftScore {
+        self.soft_score
+    }
+
+    /// People the plan leaves in a slot they are unavailable for, see --allow-unresolved
+    pub fn unresolved_conflicts(&self) -> Vec<&FinalEntity> {
+        self.schedule
+            .iter()
+            .filter(|x| has_conflicts(&x.pd_schedule, &x.available_slots))
+            .collect()
+    }
+
+    /// Recent load of the people taking over slots, summed. Lower means the swaps land on people
+    /// who were oncall less recently
+    pub fn absorbed_load(&self) -> f64 {
+        self.overrides
+            .iter()
+            .filter_map(|entry| {
+                self.schedule
+                    .iter()
+                    .find(|x| x.pd_schedule.email == entry.final_override)
+            })
+            .map(|x| x.recent_load)
+            .sum()
+    }
+
+    /// Total weighted score of the plan, lower is better
+    pub fn score(&self, weights: &Weights) -> f64 {
+        weights.overrides * self.overrides.len() as f64
+            + weights.history * self.absorbed_load()
+            + self.soft_score().penalty(weights)
+    }
+
+    /// Identifies the resulting schedule, so plans reached through different swaps are deduplicated
+    pub fn fingerprint(&self) -> Vec<(String, String)> {
+        self.overrides
+            .iter()
+            .map(|x| (x.start_time_iso.clone(), x.final_override.clone()))
+            .collect()
+    }
+}
+
+/// Run the solver with consecutive seeds in parallel and keep the best `candidates` distinct plans,
+/// ranked by unresolved conflicts, then score (overrides and soft constraints), then fairness, then
+/// fewest simulated swaps. Without a time budget, results only depend on the seed, not on how the
+/// attempts were scheduled.
+/// Soft conflicts are treated like any other conflict, unless no plan can avoid them all.
+#[instrument(skip_all, fields(slots = schedule.len(), seed, candidates))]
+pub fn generate_candidate_plans(
+    schedule: &[FinalEntity],
+    seed: u64,
+    candidates: usize,
+    options: &SolverOptions,
+) -> AnyhowResult<Vec<CandidatePlan>> {
+    let (searched_schedule, splits) = prepare_schedule(schedule, options);
+    let has_soft_conflicts = searched_schedule
+        .iter()
+        .any(|x| !x.soft_conflict_slots.is_empty());
+    let mut stats = SolverStats::default();
+    let strict = solve_attempts(
+        schedule,
+        &searched_schedule,
+        &splits,
+        seed,
+        candidates,
+        options,
+        &mut stats,
+    );
+    let fully_resolved = strict
+        .as_ref()
+        .is_ok_and(|plans| plans[0].unresolved_conflicts().is_empty());
+    let mut plans = if fully_resolved || !has_soft_conflicts {
+        strict?
+    } else {
+        say!("No plan resolves every conflict while avoiding soft conflicts. Retrying with soft conflicts allowed");
+        solve_attempts(
+            schedule,
+            &relax_soft_conflicts(&searched_schedule),
+            &splits,
+            seed,
+            candidates,
+            options,
+            &mut stats,
+        )?
+    };
+    for plan in plans.iter_mut() {
+        plan.stats = stats;
+    }
+    Ok(plans)
+}
+
+/// The schedule the solver searches: limited to the swap window, starting from the previous plan
+/// and the approved swaps, with partially conflicting slots offered as splits
+fn prepare_schedule(
+    schedule: &[FinalEntity],
+    options: &SolverOptions,
+) -> (Vec<FinalEntity>, Vec<SplitShift>) {
+    let windowed_schedule = match options.swap_window {
+        Some(window) => restrict_to_swap_window(schedule, window),
+        None => schedule.to_vec(),
+    };
+    let mut seeded_schedule = match &options.previous_assignments {
+        Some(previous_assignments) => apply_previous_plan(&windowed_schedule, previous_assignments),
+        None => windowed_schedule,
+    };
+    if !options.approved_swaps.is_empty() {
+        seeded_schedule = pin_slots(
+            &apply_previous_plan(&seeded_schedule, &options.approved_swaps),
+            &options.approved_swaps,
+        );
+    }
+    match options.min_split_segment {
+        Some(min_segment) => split_partial_conflicts(&seeded_schedule, min_segment, options),
+        None => (seeded_schedule, Vec::new()),
+    }
+}
+
+/// Most subsets of conflicts minimal_removal tries before settling for a larger removal
+const MAX_REMOVAL_CHECKS: usize = 500;
+
+/// The smallest set of conflicting people (in their slots) whose removal from the schedule lets
+/// the solver find a full plan. Subsets of the conflicts are tried smallest first, and when that
+/// gets too expensive the conflicts a best-effort solve leaves unresolved are returned instead.
+/// Like the solver itself this is a heuristic: a subset only counts if some attempt solves it
+pub fn minimal_removal(
+    schedule: &[FinalEntity],
+    seed: u64,
+    options: &SolverOptions,
+) -> Vec<FinalEntity> {
+    let (prepared, _) = prepare_schedule(schedule, options);
+    let prepared = relax_soft_conflicts(&prepared);
+    let strict = SolverOptions {
+        allow_unresolved: false,
+        ..options.clone()
+    };
+    let best_effort = SolverOptions {
+        allow_unresolved: true,
+        ..options.clone()
+    };
+    let conflicts: Vec<&FinalEntity> = prepared
+        .iter()
+        .filter(|x| has_conflicts(&x.pd_schedule, &x.available_slots))
+        .collect();
+    let mut rng = StdRng::seed_from_u64(seed);
+    let unresolved: Vec<FinalEntity> =
+        match recursive_solution(&prepared, Vec::new(), &mut rng, &best_effort) {
+            Ok((rescheduled, _)) => rescheduled
+                .into_iter()
+                .filter(|x| has_conflicts(&x.pd_schedule, &x.available_slots))
+                .collect(),
+            Err(_) => conflicts.iter().map(|x| (*x).clone()).collect(),
+        };
+    let mut checks = 0;
+    for size in 1..unresolved.len().min(conflicts.len() + 1) {
+        checks += binomial(conflicts.len(), size);
+        if checks > MAX_REMOVAL_CHECKS {
+            break;
+        }
+        let found = combinations(conflicts.len(), size)
+            .into_par_iter()
+            .find_first(|removed| {
+                let rest: Vec<FinalEntity> = prepared
+                    .iter()
+                    .filter(|x| !removed.iter().any(|i| conflicts[*i] == *x))
+                    .cloned()
+                    .collect();
+                is_solvable(&rest, seed, &strict)
+            });
+        if let Some(removed) = found {
+            return removed.into_iter().map(|i| conflicts[i].clone()).collect();
+        }
+    }
+    unresolved
+}
+
+/// Whether any of the solver attempts finds a full plan for an already prepared `schedule`
+fn is_solvable(schedule: &[FinalEntity], seed: u64, options: &SolverOptions) -> bool {
+    let attempts = options.attempts.unwrap_or(5).max(1);
+    (0..attempts).any(|attempt| {
+        let mut rng = StdRng::seed_from_u64(seed.wrapping_add(attempt as u64));
+        recursive_solution(schedule, Vec::new(), &mut rng, options).is_ok_and(|(rescheduled, _)| {
+            check_plan_limits(schedule, &rescheduled, options).is_ok()
+        })
+    })
+}
+
+/// Every way of picking `k` of the indices below `n`
+fn combinations(n: usize, k: usize) -> Vec<Vec<usize>> {
+    if k == 0 {
+        return vec![Vec::new()];
+    }
+    (k - 1..n)
+        .flat_map(|last| {
+            combinations(last, k - 1).into_iter().map(move |mut x| {
+                x.push(last);
+                x
+            })
+        })
+        .collect()
+}
+
+fn binomial(n: usize, k: usize) -> usize {
+    (0..k).fold(1, |acc, i| acc * (n - i) / (i + 1))
+}
+
+/// Let everyone be oncall through their soft conflicts. The solver still avoids them through the
+/// soft conflict weight
+fn relax_soft_conflicts(schedule: &[FinalEntity]) -> Vec<FinalEntity> {
+    schedule
+        .iter()
+        .map(|x| {
+            let mut relaxed = x.clone();
+            relaxed
+                .available_slots
+                .extend(x.soft_conflict_slots.iter().cloned());
+            relaxed.available_slots.sort_by_key(|slot| slot.start_time);
+            relaxed
+        })
+        .collect()
+}
+
+/// The attempts of generate_candidate_plans over the prepared `searched_schedule`, with plans
+/// diffed against the original `schedule`
+fn solve_attempts(
+    schedule: &[FinalEntity],
+    searched_schedule: &[FinalEntity],
+    splits: &[SplitShift],
+    seed: u64,
+    candidates: usize,
+    options: &SolverOptions,
+    stats: &mut SolverStats,
+) -> AnyhowResult<Vec<CandidatePlan>> {
+    let started = Instant::now();
+    let candidates = candidates.max(1);
+    // give the randomised solver a few extra tries, since different seeds often land on the same plan
+    let batch_size = options.attempts.unwrap_or(candidates * 5).max(1);
+    let deadline = options.time_budget.map(|x| Instant::now() + x);
+    let to_plan = |attempt_seed, rescheduled: Vec<FinalEntity>, swaps| {
+        CandidatePlan::new(
+            attempt_seed,
+            schedule,
+            rescheduled,
+            swaps,
+            splits,
+            &options.holidays,
+        )
+    };
+    let mut plans: Vec<CandidatePlan> = Vec::new();
+    let mut first_error = None;
+    let mut best_exhausted: Option<(u64, anyhow::Error)> = None;
+    let mut max_attempts = 0;
+    let mut failed_attempts = 0;
+    // without a time budget this is a single batch. With one, batches of fresh seeds keep running
+    // until the budget is spent, no single run may outlast it, and only the best plans are kept
+    loop {
+        let batch_options = match deadline {
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                SolverOptions {
+                    max_duration: Some(
+                        options.max_duration.map_or(remaining, |x| x.min(remaining)),
+                    ),
+                    ..options.clone()
+                }
+            }
+            None => options.clone(),
+        };
+        let solutions: Vec<_> = (max_attempts..max_attempts + batch_size)
+            .into_par_iter()
+            .map(|attempt| {
+                let attempt_seed = seed.wrapping_add(attempt as u64);
+                let mut rng = StdRng::seed_from_u64(attempt_seed);
+                let (solution, attempt_stats) =
+                    solve_once(searched_schedule, Vec::new(), &mut rng, &batch_options);
+                let solution = solution.and_then(|(rescheduled, swaps)| {
+                    check_plan_limits(schedule, &rescheduled, options)?;
+                    Ok((rescheduled, swaps))
+                });
+                (attempt_seed, solution, attempt_stats)
+            })
+            .collect();
+        max_attempts += batch_size;
+        failed_attempts += solutions.iter().filter(|(_, x, _)| x.is_err()).count();
+
+        for (attempt_seed, solution, attempt_stats) in solutions {
+            stats.add(&attempt_stats);
+            match solution {
+                Ok((rescheduled, swaps)) => {
+                    let plan = to_plan(attempt_seed, rescheduled, swaps);
+                    if !plans.iter().any(|x| x.fingerprint() == plan.fingerprint()) {
+                        plans.push(plan);
+                    }
+                }
+                Err(e) => match e.downcast_ref::<SearchExhausted>() {
+                    // keep the partial plan that got the furthest, it's the most useful to report
+                    Some(exhausted)
+                        if best_exhausted.as_ref().is_none_or(|(_, best)| {
+                            let best = best.downcast_ref::<SearchExhausted>().unwrap();
+                            exhausted.best.remaining_conflicts < best.best.remaining_conflicts
+                        }) =>
+                    {
+                        best_exhausted = Some((attempt_seed, e))
+                    }
+                    Some(_) => {}
+                    None if first_error.is_none() => first_error = Some(e),
+                    None => {}
+                },
+            }
+        }
+        rank_plans(&mut plans, &options.weights);
+        plans.truncate(candidates);
+        if deadline.is_none_or(|x| Instant::now() >= x) {
+            break;
+        }
+    }
+    say!(
+        "{} of {} solver attempts failed",
+        failed_attempts,
+        max_attempts
+    );
+    stats.attempts += max_attempts;
+    stats.failed_attempts += failed_attempts;
+    stats.wall_time += started.elapsed();
+
+    if plans.is_empty() {
+        match best_exhausted {
+            // with a time budget, the best partial plan is the answer rather than an error
+            Some((attempt_seed, e)) if deadline.is_some() => {
+                let exhausted = e
+                    .downcast::<SearchExhausted>()
+                    .expect("only SearchExhausted errors are kept");
+                say!("{}", exhausted);
+                plans.push(to_plan(
+                    attempt_seed,
+                    exhausted.best.schedule,
+                    exhausted.best.swaps,
+                ));
+            }
+            best_exhausted => {
+                return Err(best_exhausted
+                    .map(|(_, e)| e)
+                    .or(first_error)
+                    .unwrap_or_else(|| anyhow!("No solution"))
+                    .context(format!(
+                        "No valid plan found after {} attempts",
+                        max_attempts
+                    )));
+            }
+        }
+    }
+    if plans.len() < candidates {
+        say!(
+            "Only found {} distinct plans out of {} requested",
+            plans.len(),
+            candidates
+        );
+    }
+    Ok(plans)
+}
+
+/// Best plan first: fewest unresolved conflicts, then lowest score, then fairness, then fewest
+/// simulated swaps
+fn rank_plans(plans: &mut [CandidatePlan], weights: &Weights) {
+    plans.sort_by(|a, b| {
+        a.unresolved_conflicts()
+            .len()
+            .cmp(&b.unresolved_conflicts().len())
+            .then(a.score(weights).total_cmp(&b.score(weights)))
+            .then(
+                a.max_overrides_per_person()
+                    .cmp(&b.max_overrides_per_person()),
+            )
+            .then(a.swaps.len().cmp(&b.swaps.len()))
+    });
+}
+
+/// Number of slots held by each person, sorted by email
+pub fn shift_counts(schedule: &[FinalEntity]) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for entity in schedule {
+        *counts.entry(entity.pd_schedule.email.clone()).or_default() += 1;
+    }
+    counts
+}
+
+pub fn is_weekend(start: DateTime<FixedOffset>) -> bool {
+    matches!(start.weekday(), Weekday::Sat | Weekday::Sun)
+}
+
+/// Number of weekend slots held by each person in the schedule, including people with none
+pub fn weekend_counts(schedule: &[FinalEntity]) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for entity in schedule {
+        let count = counts.entry(entity.pd_schedule.email.clone()).or_default();
+        if is_weekend(entity.pd_schedule.start) {
+            *count += 1;
+        }
+    }
+    counts
+}
+
+/// Number of slots starting on one of the `holidays` held by each person, including people with none
+pub fn holiday_counts(
+    schedule: &[FinalEntity],
+    holidays: &BTreeSet<NaiveDate>,
+) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for entity in schedule {
+        let count = counts.entry(entity.pd_schedule.email.clone()).or_default();
+        if is_holiday(entity.pd_schedule.start, holidays) {
+            *count += 1;
+        }
+    }
+    counts
+}
+
+pub fn is_holiday(start: DateTime<FixedOffset>, holidays: &BTreeSet<NaiveDate>) -> bool {
+    holidays.contains(&start.naive_local().date())
+}
+
+/// Sum of squared deviations from the mean count. 0 means perfectly balanced
+fn imbalance(counts: &BTreeMap<String, usize>) -> f64 {
+    if counts.is_empty() {
+        return 0.0;
+    }
+    let mean = counts.values().sum::<usize>() as f64 / counts.len() as f64;
+    counts
+        .values()
+        .map(|count| (*count as f64 - mean).powi(2))
+        .sum()
+}
+
+/// Number of times someone holds a slot that starts as (or before) their previous slot ends
+fn back_to_back_count(schedule: &[FinalEntity]) -> usize {
+    let mut slots_per_person: BTreeMap<&str, Vec<&FinalPagerDutySchedule>> = BTreeMap::new();
+    for entity in schedule {
+        slots_per_person
+            .entry(&entity.pd_schedule.email)
+            .or_default()
+            .push(&entity.pd_schedule);
+    }
+    slots_per_person
+        .into_values()
+        .map(|mut slots| {
+            slots.sort_by_key(|x| x.start);
+            slots
+                .windows(2)
+                .filter(|pair| pair[1].start <= pair[0].end)
+                .count()
+        })
+        .sum()
+}
+
+/// Soft constraint penalty of `schedule` if the people at `conflict` and `candidate` exchanged
+/// slots. The exchange is made in place and undone, so scoring a candidate copies nothing
+pub fn swap_penalty(
+    schedule: &mut [FinalEntity],
+    conflict: usize,
+    candidate: usize,
+    options: &SolverOptions,
+) -> f64 {
+    let weights = &options.weights;
+    swap_slots(schedule, conflict, candidate);
+    let penalty = SoftScore::of(schedule, &options.holidays).penalty(weights);
+    swap_slots(schedule, conflict, candidate);
+    // the candidate absorbs the conflicting slot
+    penalty + weights.history * schedule[candidate].recent_load
+}
+
+/// Exchange the slots of the people at `a` and `b`, the same as replacing them with
+/// `schedule[a].moved_to(&schedule[b])` and `schedule[b].moved_to(&schedule[a])`
+fn swap_slots(schedule: &mut [FinalEntity], a: usize, b: usize) {
+    let (start, end) = (schedule[a].pd_schedule.start, schedule[a].pd_schedule.end);
+    schedule[a].pd_schedule.start = schedule[b].pd_schedule.start;
+    schedule[a].pd_schedule.end = schedule[b].pd_schedule.end;
+    schedule[b].pd_schedule.start = start;
+    schedule[b].pd_schedule.end = end;
+}
+
+/// Fail if the rescheduled plan breaks any of the per person limits in `options`
+pub fn check_plan_limits(
+    original: &[FinalEntity],
+    rescheduled: &[FinalEntity],
+    options: &SolverOptions,
+) -> AnyhowResult<()> {
+    check_shift_limit(original, rescheduled, options.max_shifts_per_person)?;
+    check_consecutive_days(original, rescheduled, options.max_consecutive_days)
+}
+
+/// Longest run of consecutive calendar days each person holds a slot on, across all shifts
+pub fn consecutive_days(schedule: &[FinalEntity]) -> BTreeMap<String, usize> {
+    let mut days: BTreeMap<String, BTreeSet<NaiveDate>> = BTreeMap::new();
+    for entity in schedule {
+        days.entry(entity.pd_schedule.email.clone())
+            .or_default()
+            .insert(entity.pd_schedule.start.naive_local().date());
+    }
+    days.into_iter()
+        .map(|(email, days)| {
+            let mut longest = 0;
+            let mut run = 0;
+            let mut previous: Option<NaiveDate> = None;
+            for day in days {
+                run = match previous {
+                    Some(x) if day - x == Duration::days(1) => run + 1,
+                    _ => 1,
+                };
+                longest = longest.max(run);
+                previous = Some(day);
+            }
+            (email, longest)
+        })
+        .collect()
+}
+
+/// Fail if the rescheduled plan has anyone oncall more than `max_consecutive_days` days in a row.
+/// Like check_shift_limit, people already above the limit are only flagged if it got worse
+pub fn check_consecutive_days(
+    original: &[FinalEntity],
+    rescheduled: &[FinalEntity],
+    max_consecutive_days: Option<usize>,
+) -> AnyhowResult<()> {
+    let max_days = match max_consecutive_days {
+        Some(value) => value,
+        None => return Ok(()),
+    };
+    let before = consecutive_days(original);
+    let offenders: Vec<String> = consecutive_days(rescheduled)
+        .into_iter()
+        .filter(|(email, after)| {
+            *after > max_days && *after > before.get(email).copied().unwrap_or(0)
+        })
+        .map(|(email, after)| format!("{} ({} days)", email, after))
+        .collect();
+    if offenders.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Plan exceeds the limit of {} consecutive oncall days for {}",
+            max_days,
+            offenders.join(", ")
+        ))
+    }
+}
+
+/// Fail if the rescheduled plan pushes anyone above `max_shifts_per_person`. People who were
+/// already above the limit in the original schedule are only flagged if they gained slots.
+pub fn check_shift_limit(
+    original: &[FinalEntity],
+    rescheduled: &[FinalEntity],
+    max_shifts_per_person: Option<usize>,
+) -> AnyhowResult<()> {
+    let max_shifts = match max_shifts_per_person {
+        Some(value) => value,
+        None => return Ok(()),
+    };
+    let before = shift_counts(original);
+    let offenders: Vec<String> = shift_counts(rescheduled)
+        .into_iter()
+        .filter(|(email, after)| {
+            *after > max_shifts && *after > before.get(email).copied().unwrap_or(0)
+        })
+        .map(|(email, after)| format!("{} ({} shifts)", email, after))
+        .collect();
+    if offenders.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Plan exceeds the limit of {} shifts per person for {}",
+            max_shifts,
+            offenders.join(", ")
+        ))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FinalEntity {
+    pub pd_schedule: FinalPagerDutySchedule,
+    pub available_slots: Vec<OncallSlot>,
+    /// available slots the person asked to be put oncall for with a prefer-oncall event
+    pub preferred_slots: Vec<OncallSlot>,
+    /// calendar events that make the person unavailable, kept to explain the plan
+    pub busy: Vec<BusyInterval>,
+    /// slots the person is only kept from by soft conflicts. Not in available_slots unless the
+    /// solver has to fall back to allowing soft conflicts
+    pub soft_conflict_slots: Vec<OncallSlot>,
+    /// meetings matching the soft conflict keywords
+    pub soft_busy: Vec<BusyInterval>,
+    /// how much more oncall the person carried than average in the weeks before the window
+    pub recent_load: f64,
+    /// extra cost of the person holding a slot, keyed by slot start, from --cost-matrix
+    pub slot_costs: BTreeMap<DateTime<FixedOffset>, f64>,
+}
+
+/// Whether intervals that only touch, e.g. an event ending at 07:00 and a slot starting at 07:00,
+/// overlap
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overlap {
+    /// intervals include their start but not their end, so touching ones don't overlap
+    #[default]
+    HalfOpen,
+    /// intervals include both ends, so touching ones overlap, with --touching-conflicts
+    Inclusive,
+}
+
+impl Overlap {
+    pub fn overlaps(
+        self,
+        (start, end): (DateTime<FixedOffset>, DateTime<FixedOffset>),
+        (other_start, other_end): (DateTime<FixedOffset>, DateTime<FixedOffset>),
+    ) -> bool {
+        match self {
+            Overlap::HalfOpen => start < other_end && other_start < end,
+            Overlap::Inclusive => start <= other_end && other_start <= end,
+        }
+    }
+}
+
+static OVERLAP: OnceLock<Overlap> = OnceLock::new();
+
+/// Set once from the command line, before anything is compared
+pub fn set_overlap(overlap: Overlap) {
+    let _ = OVERLAP.set(overlap);
+}
+
+/// How busy times are compared with slots everywhere
+pub fn overlap() -> Overlap {
+    *OVERLAP.get().unwrap_or(&Overlap::HalfOpen)
+}
+
+#[derive(Debug, Clone)]
+pub struct BusyInterval {
+    pub summary: String,
+    pub start: DateTime<FixedOffset>,
+    pub end: DateTime<FixedOffset>,
+}
+
+impl BusyInterval {
+    /// All day events last from midnight to midnight in `timezone`, of whoever's calendar they are
+    /// on. Events without valid times are left out
+    pub fn from_events(events: &[CalendarEvent], timezone: Tz) -> Vec<BusyInterval> {
+        events
+            .iter()
+            .filter_map(|event| {
+                let (start, end) = event.times(timezone).ok()?;
+                Some(BusyInterval {
+                    summary: event
+                        .summary
+                        .clone()
+                        .unwrap_or_else(|| "(no title)".to_string()),
+                    start,
+                    end,
+                })
+            })
+            .collect()
+    }
+
+    /// Same overlap check as BusyTimes
+    pub fn overlaps(&self, start: DateTime<FixedOffset>, end: DateTime<FixedOffset>) -> bool {
+        overlap().overlaps((self.start, self.end), (start, end))
+    }
+}
+
+/// Busy times of a calendar sorted by start, so whether any overlaps a slot takes a binary search
+/// rather than a scan of every event. Multi-week windows over busy calendars reach thousands of
+/// events per person
+#[derive(Debug, Clone, Default)]
+pub struct BusyTimes {
+    starts: Vec<DateTime<FixedOffset>>,
+    /// latest end among the times up to each one, in order of start
+    latest_ends: Vec<DateTime<FixedOffset>>,
+}
+
+impl BusyTimes {
+    pub fn new(
+        times: impl IntoIterator<Item = (DateTime<FixedOffset>, DateTime<FixedOffset>)>,
+    ) -> BusyTimes {
+        let mut times: Vec<_> = times.into_iter().collect();
+        times.sort();
+        let mut latest_ends: Vec<DateTime<FixedOffset>> = Vec::with_capacity(times.len());
+        for (_, end) in &times {
+            let latest = latest_ends.last().map_or(*end, |x| (*x).max(*end));
+            latest_ends.push(latest);
+        }
+        BusyTimes {
+            starts: times.into_iter().map(|(start, _)| start).collect(),
+            latest_ends,
+        }
+    }
+
+    /// All day events last from midnight to midnight in `timezone`, of whoever's calendar they are
+    /// on. Events without valid times are left out
+    pub fn from_events(events: &[CalendarEvent], timezone: Tz) -> BusyTimes {
+        BusyTimes::new(events.iter().filter_map(|x| x.times(timezone).ok()))
+    }
+
+    /// Whether any of the times overlaps `start` to `end`
+    pub fn overlaps(&self, start: DateTime<FixedOffset>, end: DateTime<FixedOffset>) -> bool {
+        self.overlaps_with(start, end, overlap())
+    }
+
+    fn overlaps_with(
+        &self,
+        start: DateTime<FixedOffset>,
+        end: DateTime<FixedOffset>,
+        overlap: Overlap,
+    ) -> bool {
+        // of the times starting early enough to overlap, the one ending last decides
+        let candidates = match overlap {
+            Overlap::HalfOpen => self.starts.partition_point(|x| *x < end),
+            Overlap::Inclusive => self.starts.partition_point(|x| *x <= end),
+        };
+        match candidates.checked_sub(1).map(|i| self.latest_ends[i]) {
+            Some(latest) => match overlap {
+                Overlap::HalfOpen => latest > start,
+                Overlap::Inclusive => latest >= start,
+            },
+            None => false,
+        }
+    }
+}
+
+impl FinalEntity {
+    /// The same person, moved into the slot currently held by `other`
+    pub fn moved_to(&self, other: &FinalEntity) -> FinalEntity {
+        FinalEntity {
+            pd_schedule: FinalPagerDutySchedule {
+                pd_user_id: self.pd_schedule.pd_user_id.clone(),
+                start: other.pd_schedule.start,
+                end: other.pd_schedule.end,
+                email: self.pd_schedule.email.clone(),
+            },
+            available_slots: self.available_slots.clone(),
+            preferred_slots: self.preferred_slots.clone(),
+            busy: self.busy.clone(),
+            soft_conflict_slots: self.soft_conflict_slots.clone(),
+            soft_busy: self.soft_busy.clone(),
+            recent_load: self.recent_load,
+            slot_costs: self.slot_costs.clone(),
+        }
+    }
+
+    pub fn is_available_at(
+        &self,
+        start: DateTime<FixedOffset>,
+        end: DateTime<FixedOffset>,
+    ) -> bool {
+        self.available_slots.iter().any(|x| x.covers(start, end))
+    }
+
+    pub fn prefers(&self, start: DateTime<FixedOffset>, end: DateTime<FixedOffset>) -> bool {
+        self.preferred_slots.iter().any(|x| x.covers(start, end))
+    }
+
+    pub fn slot_cost(&self, start: DateTime<FixedOffset>) -> f64 {
+        at_slot(&self.slot_costs, start).copied().unwrap_or(0.0)
+    }
+
+    /// The soft conflict the person is oncall through in their current slot, if any
+    pub fn soft_conflict(&self) -> Option<&BusyInterval> {
+        if !self
+            .soft_conflict_slots
+            .iter()
+            .any(|x| x.covers(self.pd_schedule.start, self.pd_schedule.end))
+        {
+            return None;
+        }
+        self.soft_busy
+            .iter()
+            .find(|x| x.overlaps(self.pd_schedule.start, self.pd_schedule.end))
+    }
+
+    /// Fraction of the current slot covered by soft conflicts, 0 when the person isn't oncall
+    /// through one
+    pub fn soft_conflict_coverage(&self) -> f64 {
+        if self.soft_conflict().is_none() {
+            return 0.0;
+        }
+        covered_fraction(
+            &self.soft_busy,
+            self.pd_schedule.start,
+            self.pd_schedule.end,
+        )
+    }
+}
+
+/// Fraction of the time between `start` and `end` covered by `intervals`, counting time covered
+/// by several of them once
+pub fn covered_fraction(
+    intervals: &[BusyInterval],
+    start: DateTime<FixedOffset>,
+    end: DateTime<FixedOffset>,
+) -> f64 {
+    let length = (end - start).num_seconds();
+    if length <= 0 {
+        return 0.0;
+    }
+    let mut clipped: Vec<(DateTime<FixedOffset>, DateTime<FixedOffset>)> = intervals
+        .iter()
+        .filter(|x| x.start < end && x.end > start)
+        .map(|x| (x.start.max(start), x.end.min(end)))
+        .collect();
+    clipped.sort();
+    let mut covered = 0;
+    let mut covered_until = start;
+    for (from, to) in clipped {
+        let from = from.max(covered_until);
+        if to > from {
+            covered += (to - from).num_seconds();
+            covered_until = to;
+        }
+    }
+    covered as f64 / length as f64
+}
+
+impl PartialEq for FinalEntity {
+    fn eq(&self, other: &Self) -> bool {
+        self.pd_schedule.email == other.pd_schedule.email
+            && self.pd_schedule.start == other.pd_schedule.start
+            && self.pd_schedule.end == other.pd_schedule.end
+    }
+}
+
+/// Reassign the slots of `schedule` to their holders in a previous plan, so the solver only
+/// touches slots that conflict with the refreshed calendars. Slots whose previous holder is no
+/// longer in the schedule keep their current holder
+pub fn apply_previous_plan(
+    schedule: &[FinalEntity],
+    previous_assignments: &BTreeMap<DateTime<FixedOffset>, String>,
+) -> Vec<FinalEntity> {
+    schedule
+        .iter()
+        .map(|entity| {
+            let previous_holder = at_slot(previous_assignments, entity.pd_schedule.start)
+                .and_then(|email| schedule.iter().find(|x| &x.pd_schedule.email == email));
+            match previous_holder {
+                Some(holder) => holder.moved_to(entity),
+                None => entity.clone(),
+            }
+        })
+        .collect()
+}
+
+/// Keep the slots of `assignments` with their holder, by leaving them out of everyone else's
+/// available slots so no swap can move someone into them
+fn pin_slots(
+    schedule: &[FinalEntity],
+    assignments: &BTreeMap<DateTime<FixedOffset>, String>,
+) -> Vec<FinalEntity> {
+    let pinned = |email: &str, slot: &OncallSlot| {
+        at_slot(assignments, slot.start_time).is_some_and(|holder| holder != email)
+    };
+    schedule
+        .iter()
+        .map(|entity| {
+            let email = entity.pd_schedule.email.as_str();
+            let mut entity = entity.clone();
+            entity.available_slots.retain(|x| !pinned(email, x));
+            entity.preferred_slots.retain(|x| !pinned(email, x));
+            entity.soft_conflict_slots.retain(|x| !pinned(email, x));
+            entity
+        })
+        .collect()
+}
+
+/// Keep the slots starting at `frozen` with their holder, who counts as free for them, and out of
+/// everyone else's available slots, so no plan changes them. For slots that have started, whose
+/// overrides the providers reject or cut short, and those about to
+pub fn freeze_slots(
+    schedule: &[FinalEntity],
+    frozen: &BTreeSet<DateTime<FixedOffset>>,
+) -> Vec<FinalEntity> {
+    let holders: BTreeMap<DateTime<FixedOffset>, String> = schedule
+        .iter()
+        .filter(|x| {
+            frozen
+                .iter()
+                .any(|start| same_slot(*start, x.pd_schedule.start))
+        })
+        .map(|x| (x.pd_schedule.start, x.pd_schedule.email.clone()))
+        .collect();
+    pin_slots(schedule, &holders)
+        .into_iter()
+        .map(|mut entity| {
+            let slot = OncallSlot {
+                start_time: entity.pd_schedule.start,
+                end_time: entity.pd_schedule.end,
+            };
+            if holders.contains_key(&slot.start_time)
+                && has_conflicts(&entity.pd_schedule, &entity.available_slots)
+            {
+                entity
+                    .soft_conflict_slots
+                    .retain(|x| !same_slot(x.start_time, slot.start_time));
+                entity.available_slots.push(slot);
+                entity.available_slots.sort_by_key(|x| x.start_time);
+            }
+            entity
+        })
+        .collect()
+}
+
+pub fn to_saved_plan(plan: &CandidatePlan) -> SavedPlan {
+    SavedPlan {
+        seed: plan.seed,
+        slots: plan
+            .schedule
+            .iter()
+            .map(|x| SavedSlot {
+                start: x.pd_schedule.start.to_rfc3339(),
+                end: x.pd_schedule.end.to_rfc3339(),
+                email: x.pd_schedule.email.clone(),
+                pd_user_id: x.pd_schedule.pd_user_id.clone(),
+            })
+            .collect(),
+    }
+}
+
+impl From<&FinalOverride> for ExportedOverride {
+    fn from(entry: &FinalOverride) -> ExportedOverride {
+        ExportedOverride {
+            start: entry.start_time_iso.clone(),
+            end: entry.end_time_iso.clone(),
+            from: entry.original_assignee.clone(),
+            to: entry.final_override.clone(),
+            to_user_id: entry.pd_user_id.clone(),
+        }
+    }
+}
+
+/// Hex sha256 of the slots of `schedule` and of the slots each holder is free and softly busy
+/// for, which is everything a plan is solved from. Any change to the schedule or to someone's
+/// calendar that matters to the solver changes it
+pub fn inputs_hash(schedule: &[FinalEntity]) -> String {
+    let mut entities: Vec<&FinalEntity> = schedule.iter().collect();
+    entities.sort_by(|a, b| {
+        (a.pd_schedule.start, &a.pd_schedule.email)
+            .cmp(&(b.pd_schedule.start, &b.pd_schedule.email))
+    });
+    let mut hasher = Sha256::new();
+    for entity in entities {
+        let slot = &entity.pd_schedule;
+        hasher.update(format!(
+            "slot {} {} {} {}\n",
+            slot.start.to_rfc3339(),
+            slot.end.to_rfc3339(),
+            slot.email,
+            slot.pd_user_id
+        ));
+        for (kind, slots) in [
+            ("available", &entity.available_slots),
+            ("soft", &entity.soft_conflict_slots),
+        ] {
+            let mut slots: Vec<_> = slots.iter().map(|x| (x.start_time, x.end_time)).collect();
+            slots.sort();
+            for (start, end) in slots {
+                hasher.update(format!(
+                    "{} {} {}\n",
+                    kind,
+                    start.to_rfc3339(),
+                    end.to_rfc3339()
+                ));
+            }
+        }
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// Drop every available slot that is further than `window` from all of the person's originally
+/// assigned slots, so swaps stay close to where people planned to be oncall
+fn restrict_to_swap_window(schedule: &[FinalEntity], window: Duration) -> Vec<FinalEntity> {
+    schedule
+        .iter()
+        .map(|entity| {
+            let original_starts: Vec<DateTime<FixedOffset>> = schedule
+                .iter()
+                .filter(|x| x.pd_schedule.email == entity.pd_schedule.email)
+                .map(|x| x.pd_schedule.start)
+                .collect();
+            let near = |slot: &OncallSlot| {
+                original_starts.iter().any(|start| {
+                    (slot.start_time - *start).num_seconds().abs() <= window.num_seconds()
+                })
+            };
+            let mut entity = entity.clone();
+            entity.available_slots.retain(near);
+            entity.preferred_slots.retain(near);
+            entity
+        })
+        .collect()
+}
+
+/// Part of a slot handed to someone else, because its assignee is only busy for part of it
+#[derive(Tabled, Debug, Clone)]
+pub struct SplitShift {
+    pub slot: String,
+    pub assignee: String,
+    pub covered_by: String,
+    pub cover_start: DateTime<FixedOffset>,
+    pub cover_end: DateTime<FixedOffset>,
+    #[tabled(skip)]
+    pub slot_start: DateTime<FixedOffset>,
+    #[tabled(skip)]
+    pub slot_end: DateTime<FixedOffset>,
+    #[tabled(skip)]
+    pub cover_pd_user_id: String,
+}
+
+/// The part of the entity's slot it is busy for, widened so that neither the cover nor what the
+/// assignee keeps is shorter than `min_segment`. None if the whole slot would have to be covered
+fn busy_segment(
+    entity: &FinalEntity,
+    min_segment: Duration,
+) -> Option<(DateTime<FixedOffset>, DateTime<FixedOffset>)> {
+    let (start, end) = (entity.pd_schedule.start, entity.pd_schedule.end);
+    let overlapping: Vec<&BusyInterval> = entity
+        .busy
+        .iter()
+        .filter(|x| x.overlaps(start, end))
+        .collect();
+    let mut cover_start = overlapping.iter().map(|x| x.start).min()?.max(start);
+    let mut cover_end = overlapping.iter().map(|x| x.end).max()?.min(end);
+    if cover_end - cover_start < min_segment {
+        cover_end = cover_start + min_segment;
+    }
+    // pieces left to the assignee that are too short are covered as well
+    if cover_start - start < min_segment {
+        cover_start = start;
+    }
+    if end - cover_end < min_segment {
+        cover_end = end;
+    }
+    if cover_start == start && cover_end == end {
+        return None;
+    }
+    Some((cover_start, cover_end))
+}
+
+/// For every slot whose assignee is only busy for part of it, hand that part to someone who is free
+/// and not oncall at the time, preferring whoever covers the fewest so far. The returned schedule
+/// treats those slots as available to their assignee, so the solver leaves them in place.
+fn split_partial_conflicts(
+    schedule: &[FinalEntity],
+    min_segment: Duration,
+    options: &SolverOptions,
+) -> (Vec<FinalEntity>, Vec<SplitShift>) {
+    let mut people: BTreeMap<&str, &FinalEntity> = BTreeMap::new();
+    for entity in schedule {
+        people.entry(&entity.pd_schedule.email).or_insert(entity);
+    }
+    let mut splits: Vec<SplitShift> = Vec::new();
+    for entity in schedule {
+        if !has_conflicts(&entity.pd_schedule, &entity.available_slots) {
+            continue;
+        }
+        let (cover_start, cover_end) = match busy_segment(entity, min_segment) {
+            Some(value) => value,
+            None => continue,
+        };
+        let helper = people
+            .values()
+            .filter(|x| x.pd_schedule.email != entity.pd_schedule.email)
+            // covering is a move into the slot, held to the same constraints as a swap. The helper
+            // keeps their own slots, so nothing about leaving them applies
+            .filter(|x| {
+                options.allows(&Move {
+                    mover: x,
+                    displaced: entity,
+                    forced: true,
+                })
+            })
+            .filter(|x| !x.busy.iter().any(|b| b.overlaps(cover_start, cover_end)))
+            .filter(|x| {
+                !schedule.iter().any(|slot| {
+                    slot.pd_schedule.email == x.pd_schedule.email
+                        && slot.pd_schedule.start < cover_end
+                        && slot.pd_schedule.end > cover_start
+                })
+            })
+            .min_by_key(|x| {
+                splits
+                    .iter()
+                    .filter(|split| split.covered_by == x.pd_schedule.email)
+                    .count()
+            });
+        if let Some(helper) = helper {
+            splits.push(SplitShift {
+                slot: display_time(entity.pd_schedule.start, Some(&entity.pd_schedule.email)),
+                assignee: entity.pd_schedule.email.clone(),
+                covered_by: helper.pd_schedule.email.clone(),
+                cover_start,
+                cover_end,
+                slot_start: entity.pd_schedule.start,
+                slot_end: entity.pd_schedule.end,
+                cover_pd_user_id: helper.pd_schedule.pd_user_id.clone(),
+            });
+        }
+    }
+    let searched = schedule
+        .iter()
+        .map(|entity| {
+            let mut entity = entity.clone();
+            for split in splits
+                .iter()
+                .filter(|x| x.assignee == entity.pd_schedule.email)
+            {
+                entity.available_slots.push(OncallSlot {
+                    start_time: split.slot_start,
+                    end_time: split.slot_end,
+                });
+            }
+            entity
+        })
+        .collect();
+    (searched, splits)
+}
+
+/// Splits still valid after solving: the assignee still holds the slot and the cover isn't oncall
+/// during it
+fn kept_splits(splits: &[SplitShift], rescheduled: &[FinalEntity]) -> Vec<SplitShift> {
+    splits
+        .iter()
+        .filter(|split| {
+            rescheduled.iter().any(|x| {
+                x.pd_schedule.start == split.slot_start && x.pd_schedule.email == split.assignee
+            }) && !rescheduled.iter().any(|x| {
+                x.pd_schedule.email == split.covered_by
+                    && x.pd_schedule.start < split.cover_end
+                    && x.pd_schedule.end > split.cover_start
+            })
+        })
+        .cloned()
+        .collect()
+}
+
+/// Add an override for the covered part of every split slot. The assignee keeps the rest of the
+/// slot through the existing schedule, so no override is needed for it
+fn apply_splits(mut overrides: Vec<FinalOverride>, splits: &[SplitShift]) -> Vec<FinalOverride> {
+    for split in splits {
+        overrides.push(FinalOverride {
+            original_slot: split.slot.clone(),
+            original_assignee: split.assignee.clone(),
+            final_override: split.covered_by.clone(),
+            start_time_iso: split.cover_start.format("%+").to_string(),
+            end_time_iso: split.cover_end.format("%+").to_string(),
+            pd_user_id: split.cover_pd_user_id.clone(),
+        });
+    }
+    overrides.sort_by(|a, b| a.start_time_iso.cmp(&b.start_time_iso));
+    overrides
+}
+
+/// Solve the schedule within the search budget from `options`. When the budget runs out, the error
+/// is a SearchExhausted carrying the best partial plan found
+pub fn recursive_solution(
+    schedule: &[FinalEntity],
+    swaps: Vec<SimulatedSwap>,
+    rng: &mut StdRng,
+    options: &SolverOptions,
+) -> AnyhowResult<(Vec<FinalEntity>, Vec<SimulatedSwap>)> {
+    solve_once(schedule, swaps, rng, options).0
+}
+
+/// A rescheduled schedule and the simulated swaps that led to it
+type Solution = (Vec<FinalEntity>, Vec<SimulatedSwap>);
+
+/// Like recursive_solution, also returning the statistics of the search
+fn solve_once(
+    schedule: &[FinalEntity],
+    swaps: Vec<SimulatedSwap>,
+    rng: &mut StdRng,
+    options: &SolverOptions,
+) -> (AnyhowResult<Solution>, SolverStats) {
+    let mut budget = SearchBudget::new(options);
+    let solution = recursive_search(schedule.to_vec(), swaps, rng, options, &mut budget);
+    budget.stats.iterations = budget.depth;
+    (solution, budget.stats)
+}
+
+/// Resolve the most restrictive conflict with a swap or rotation, and recurse until none are
+/// left. Swaps exchange the slots of two people in place, so `schedule` is only copied when the
+/// budget keeps a new best partial plan
+fn recursive_search(
+    mut schedule: Vec<FinalEntity>,
+    mut swaps: Vec<SimulatedSwap>,
+    rng: &mut StdRng,
+    options: &SolverOptions,
+    budget: &mut SearchBudget,
+) -> AnyhowResult<(Vec<FinalEntity>, Vec<SimulatedSwap>)> {
+    // if there is no conflict left, it's solved and this is the termination condition
+    let conflict = match find_conflicts(&schedule) {
+        None => return Ok((schedule, swaps)),
+        Some(value) => value,
+    };
+    budget.record(&schedule, &swaps);
+    if let Some(reason) = budget.exhausted_reason(&swaps) {
+        return Err(anyhow!(budget.exhausted(reason)));
+    }
+
+    // find best swap from remaining entries in schedule
+    let best_swap_option = find_potential_swap(
+        &mut schedule,
+        conflict,
+        &swaps,
+        rng,
+        options,
+        &mut budget.stats,
+    );
+    let best_swap = match best_swap_option {
+        None => {
+            // no direct swap left, try rotating the slot through several people instead
+            if let Some(rotation) = find_rotation_cycle(&schedule, conflict, options) {
+                budget.stats.rotations += 1;
+                apply_rotation(&mut schedule, conflict, &rotation, &mut swaps);
+                return recursive_search(schedule, swaps, rng, options, budget);
+            }
+            budget.stats.dead_ends += 1;
+            if options.allow_unresolved {
+                // leave the conflict where it is, out of everyone else's way, and solve the rest
+                let unresolved = schedule.remove(conflict);
+                let (mut rescheduled, swaps) =
+                    recursive_search(schedule, swaps, rng, options, budget)?;
+                rescheduled.push(unresolved);
+                return Ok((rescheduled, swaps));
+            }
+            return Err(anyhow!(
+                "No solution, no swap or rotation resolves the conflict of {}",
+                schedule[conflict].pd_schedule.email
+            ));
+        }
+        Some(value) => value,
+    };
+
+    swaps.push(SimulatedSwap {
+        person_with_conflict: schedule[conflict].pd_schedule.email.clone(),
+        // shown alike for everyone, since one swap involves two people
+        original_slot: display_time(schedule[conflict].pd_schedule.start, None),
+        swapped_with: schedule[best_swap].pd_schedule.email.clone(),
+        new_slot: display_time(schedule[best_swap].pd_schedule.start, None),
+        original_start: schedule[conflict].pd_schedule.start,
+        new_start: schedule[best_swap].pd_schedule.start,
+    });
+    swap_slots(&mut schedule, conflict, best_swap);
+    recursive_search(schedule, swaps, rng, options, budget)
+}
+
+/// A schedule the solver passed through, possibly with conflicts left
+#[derive(Debug, Clone)]
+pub struct PartialPlan {
+    pub schedule: Vec<FinalEntity>,
+    pub swaps: Vec<SimulatedSwap>,
+    pub remaining_conflicts: usize,
+}
+
+/// Returned (inside anyhow) when the search budget runs out before every conflict is resolved
+#[derive(Debug)]
+pub struct SearchExhausted {
+    pub reason: String,
+    pub best: PartialPlan,
+}
+
+impl fmt::Display for SearchExhausted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Search budget exhausted: {}. The best partial plan still has {} unresolved conflicts",
+            self.reason, self.best.remaining_conflicts
+        )
+    }
+}
+
+impl std::error::Error for SearchExhausted {}
+
+/// Tracks how much of the search budget a single solver run has used, and the best state seen
+struct SearchBudget {
+    max_swaps: usize,
+    max_depth: usize,
+    deadline: Option<Instant>,
+    depth: usize,
+    best: Option<PartialPlan>,
+    stats: SolverStats,
+}
+
+impl SearchBudget {
+    fn new(options: &SolverOptions) -> SearchBudget {
+        SearchBudget {
+            max_swaps: options.max_swaps,
+            max_depth: options.max_depth,
+            deadline: options.max_duration.map(|x| Instant::now() + x),
+            depth: 0,
+            best: None,
+            stats: SolverStats::default(),
+        }
+    }
+
+    /// Count one more search step, and keep the schedule if it has the fewest conflicts so far
+    fn record(&mut self, schedule: &[FinalEntity], swaps: &[SimulatedSwap]) {
+        self.depth += 1;
+        let remaining_conflicts = schedule
+            .iter()
+            .filter(|x| has_conflicts(&x.pd_schedule, &x.available_slots))
+            .count();
+        if self
+            .best
+            .as_ref()
+            .is_none_or(|best| remaining_conflicts < best.remaining_conflicts)
+        {
+            self.best = Some(PartialPlan {
+                schedule: schedule.to_vec(),
+                swaps: swaps.to_vec(),
+                remaining_conflicts,
+            });
+        }
+    }
+
+    fn exhausted_reason(&self, swaps: &[SimulatedSwap]) -> Option<String> {
+        if swaps.len() > self.max_swaps {
+            Some(format!("reached the limit of {} swaps", self.max_swaps))
+        } else if self.depth > self.max_depth {
+            Some(format!("reached the search depth of {}", self.max_depth))
+        } else if self.deadline.is_some_and(|x| Instant::now() > x) {
+            Some("ran out of time".to_string())
+        } else {
+            None
+        }
+    }
+
+    fn exhausted(&mut self, reason: String) -> SearchExhausted {
+        SearchExhausted {
+            reason,
+            best: self
+                .best
+                .take()
+                .expect("record is called before the budget runs out"),
+        }
+    }
+}
+
+/// Look for people to rotate through the conflicting slot when no direct swap is possible:
+/// the conflict takes B's slot, B takes C's slot, ... and the last person takes the conflict's slot.
+/// Unlike a direct swap, everyone in the rotation must be available for the slot they move into,
+/// so applying it never creates new conflicts. Returns indices into `schedule` in rotation order.
+fn find_rotation_cycle(
+    schedule: &[FinalEntity],
+    conflict: usize,
+    options: &SolverOptions,
+) -> Option<Vec<usize>> {
+    let mut path = Vec::new();
+    if extend_rotation(schedule, conflict, conflict, options, &mut path) {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// Depth first search for the rest of a rotation, `mover` being the last person added to `path`
+fn extend_rotation(
+    schedule: &[FinalEntity],
+    conflict: usize,
+    mover: usize,
+    options: &SolverOptions,
+    path: &mut Vec<usize>,
+) -> bool {
+    let (conflict_entity, mover_entity) = (&schedule[conflict], &schedule[mover]);
+    // close the cycle by moving into the conflicting slot
+    if !path.is_empty()
+        && mover_entity.is_available_at(
+            conflict_entity.pd_schedule.start,
+            conflict_entity.pd_schedule.end,
+        )
+        && options.allows(&Move {
+            mover: mover_entity,
+            displaced: conflict_entity,
+            forced: false,
+        })
+    {
+        return true;
+    }
+    // the conflicting person plus everyone on the path
+    if path.len() + 1 >= options.max_cycle_length {
+        return false;
+    }
+    for (i, candidate) in schedule.iter().enumerate() {
+        if i == conflict
+            || path.contains(&i)
+            || candidate.pd_schedule.email == mover_entity.pd_schedule.email
+            || !mover_entity.is_available_at(candidate.pd_schedule.start, candidate.pd_schedule.end)
+            || !options.allows(&Move {
+                mover: mover_entity,
+                displaced: candidate,
+                forced: path.is_empty(),
+            })
+        {
+            continue;
+        }
+        path.push(i);
+        if extend_rotation(schedule, conflict, i, options, path) {
+            return true;
+        }
+        path.pop();
+    }
+    false
+}
+
+/// Apply a rotation found by `find_rotation_cycle`, recording it as sequential swaps through the
+/// conflicting slot so it reads the same way as the rest of the simulated swaps
+fn apply_rotation(
+    schedule: &mut [FinalEntity],
+    conflict: usize,
+    rotation: &[usize],
+    swaps: &mut Vec<SimulatedSwap>,
+) {
+    let conflict_start = schedule[conflict].pd_schedule.start;
+    let conflict_slot = display_time(conflict_start, None);
+    let mut mover = conflict;
+    for index in rotation {
+        swaps.push(SimulatedSwap {
+            person_with_conflict: schedule[mover].pd_schedule.email.clone(),
+            original_slot: conflict_slot.clone(),
+            swapped_with: schedule[*index].pd_schedule.email.clone(),
+            new_slot: display_time(schedule[*index].pd_schedule.start, None),
+            original_start: conflict_start,
+            new_start: schedule[*index].pd_schedule.start,
+        });
+        // the mover takes the next slot, handing the conflicting slot they hold along
+        swap_slots(schedule, mover, *index);
+        mover = *index;
+    }
+}
+
+/// Index of the most restrictive conflict: the person with a conflict who has the fewest
+/// available slots, the earliest in `schedule` among equals
+fn find_conflicts(schedule: &[FinalEntity]) -> Option<usize> {
+    schedule
+        .iter()
+        .enumerate()
+        .filter(|(_, x)| has_conflicts(&x.pd_schedule, &x.available_slots))
+        .min_by_key(|(_, x)| x.available_slots.len())
+        .map(|(i, _)| i)
+}
+
+/// Index of the best person in `schedule` to exchange slots with the conflict at `conflict`
+fn find_potential_swap(
+    schedule: &mut [FinalEntity],
+    conflict: usize,
+    swaps: &[SimulatedSwap],
+    rng: &mut StdRng,
+    options: &SolverOptions,
+    stats: &mut SolverStats,
+) -> Option<usize> {
+    let current_slot = &schedule[conflict];
+    let mut potential_swaps: Vec<usize> = current_slot
+        .available_slots
+        .iter()
+        .flat_map(|available_slot| {
+            schedule.iter().enumerate().filter(move |(i, slot)| {
+                *i != conflict
+                    && available_slot.covers(slot.pd_schedule.start, slot.pd_schedule.end)
+            })
+        })
+        // the conflict has to move anyway, the candidate only if no constraint keeps them in place
+        .filter(|(_, slot)| {
+            options.allows(&Move {
+                mover: current_slot,
+                displaced: slot,
+                forced: true,
+            }) && options.allows(&Move {
+                mover: slot,
+                displaced: current_slot,
+                forced: false,
+            })
+        })
+        .map(|(i, _)| i)
+        .collect();
+    stats.candidates_evaluated += potential_swaps.len();
+    // the shuffle only breaks ties between equally good candidates, the sort below is stable
+    potential_swaps.shuffle(rng);
+    let conflict_shift = shift_of(schedule[conflict].pd_schedule.start, &options.shifts);
+    let mut scored_swaps: Vec<(CandidateScore, usize)> = potential_swaps
+        .into_iter()
+        .map(|i| {
+            let score = CandidateScore {
+                cross_shift: shift_of(schedule[i].pd_schedule.start, &options.shifts)
+                    != conflict_shift,
+                penalty: swap_penalty(schedule, conflict, i, options),
+                times_swapped: swaps
+                    .iter()
+                    .filter(|swap| {
+                        swap.person_with_conflict == schedule[i].pd_schedule.email
+                            || swap.swapped_with == schedule[i].pd_schedule.email
+                    })
+                    .count(),
+                alternatives: schedule[i].available_slots.len(),
+            };
+            (score, i)
+        })
+        .collect();
+    scored_swaps.sort_by(|a, b| a.0.cmp(&b.0));
+    // skip whoever had the last two conflicts, to avoid swapping back and forth
+    let recent: Vec<&str> = swaps
+        .iter()
+        .rev()
+        .take(2)
+        .map(|x| x.person_with_conflict.as_str())
+        .collect();
+    scored_swaps
+        .into_iter()
+        .map(|x| x.1)
+        .find(|i| !recent.contains(&schedule[*i].pd_schedule.email.as_str()))
+}
+
+/// Smallest difference between two penalties that makes one swap candidate better than another
+const PENALTY_RESOLUTION: f64 = 1e-9;
+
+/// How good a swap candidate is, compared field by field with the best candidates first
+struct CandidateScore {
+    /// cross-shift swaps are only reached for once same-shift candidates are exhausted
+    cross_shift: bool,
+    /// soft constraint penalty of the schedule after the swap. Compared rounded to
+    /// PENALTY_RESOLUTION, so penalties that only differ by float rounding tie
+    penalty: f64,
+    /// swaps the candidate was already part of in this run, to keep churn down
+    times_swapped: usize,
+    /// slots the candidate is available for. People with more options are less likely to end up
+    /// in a conflict of their own
+    alternatives: usize,
+}
+
+impl CandidateScore {
+    fn cmp(&self, other: &CandidateScore) -> std::cmp::Ordering {
+        let rounded = |penalty: f64| (penalty / PENALTY_RESOLUTION).round() as i64;
+        self.cross_shift
+            .cmp(&other.cross_shift)
+            .then(rounded(self.penalty).cmp(&rounded(other.penalty)))
+            .then(self.times_swapped.cmp(&other.times_swapped))
+            .then(other.alternatives.cmp(&self.alternatives))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OncallSlot {
+    pub start_time: DateTime<FixedOffset>,
+    pub end_time: DateTime<FixedOffset>,
+}
+
+/// How far apart the boundaries of two slots may be, e.g. an entry that starts a minute late, for
+/// them to still be the same slot
+pub const SLOT_TOLERANCE_MINUTES: i64 = 5;
+
+impl OncallSlot {
+    /// Whether this slot spans the whole of `start` to `end`, give or take SLOT_TOLERANCE_MINUTES
+    /// at either end. Matches the same slot with drifted boundaries, and a part of a split or
+    /// merged slot
+    pub fn covers(&self, start: DateTime<FixedOffset>, end: DateTime<FixedOffset>) -> bool {
+        let tolerance = Duration::minutes(SLOT_TOLERANCE_MINUTES);
+        self.start_time <= start + tolerance && self.end_time >= end - tolerance
+    }
+}
+
+/// Whether slots starting at `a` and `b` are the same slot, give or take SLOT_TOLERANCE_MINUTES
+pub fn same_slot(a: DateTime<FixedOffset>, b: DateTime<FixedOffset>) -> bool {
+    (a - b).num_seconds().abs() <= Duration::minutes(SLOT_TOLERANCE_MINUTES).num_seconds()
+}
+
+/// The value kept for the slot starting at `start` in `by_start`, keyed by slot starts that may
+/// be up to SLOT_TOLERANCE_MINUTES off. The closest start wins
+pub fn at_slot<V>(
+    by_start: &BTreeMap<DateTime<FixedOffset>, V>,
+    start: DateTime<FixedOffset>,
+) -> Option<&V> {
+    let tolerance = Duration::minutes(SLOT_TOLERANCE_MINUTES);
+    by_start
+        .range(start - tolerance..=start + tolerance)
+        .min_by_key(|(x, _)| (**x - start).num_seconds().abs())
+        .map(|(_, value)| value)
+}
+
+/// find conflicts. I.e. his initial scheduled slot is not covered by any of the available slots a
+/// person has
+pub fn has_conflicts(
+    current_slot: &FinalPagerDutySchedule,
+    available_slots: &[OncallSlot],
+) -> bool {
+    !available_slots
+        .iter()
+        .any(|slot| slot.covers(current_slot.start, current_slot.end))
+}
+
+/// Get diff a shift. A loop of a loop, pretty inefficient
+/// Can be made better by pre-sorting both and zipping?
+pub fn generate_diff_of_shift(
+    mut initial_shifts: Vec<FinalEntity>,
+    mut final_shifts: Vec<FinalEntity>,
+) -> Vec<FinalOverride> {
+    let mut final_overrides = Vec::new();
+    // say!("\n====Generating final diff against current schedule======");
+    initial_shifts.sort_by_key(|a| a.pd_schedule.start);
+    final_shifts.sort_by_key(|a| a.pd_schedule.start);
+    let zipped = zip(initial_shifts, final_shifts);
+    for pair in zipped {
+        let (original, new) = pair;
+        assert!(original.pd_schedule.start == new.pd_schedule.start);
+        if original.pd_schedule.email != new.pd_schedule.email {
+            final_overrides.push(FinalOverride {
+                original_slot: display_time(
+                    original.pd_schedule.start,
+                    Some(&original.pd_schedule.email),
+                ),
+                original_assignee: original.pd_schedule.email,
+                final_override: new.pd_schedule.email,
+                start_time_iso: original.pd_schedule.start.format("%+").to_string(),
+                end_time_iso: original.pd_schedule.end.format("%+").to_string(),
+                pd_user_id: new.pd_schedule.pd_user_id,
+            });
+        }
+    }
+    final_overrides
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use tabled::Table;
+
+    #[test]
+    fn test_find_conflicts_false() {
+        let current_pd_shift = FinalPagerDutySchedule {
+            pd_user_id: "someid".to_string(),
+            start: DateTime::<FixedOffset>::parse_from_rfc3339("2022-08-30T07:00:00+08:00")
+                .unwrap(),
+            end: DateTime::<FixedOffset>::parse_from_rfc3339("2022-08-30T15:00:00+08:00").unwrap(),
+            email: "random.user@grabtaxi.com".to_string(),
+        };
+        let oncall_slots = vec![
+            OncallSlot {
+                start_time: DateTime::<FixedOffset>::parse_from_rfc3339(
+                    "2022-08-30T07:00:00+08:00",
+                )
+                .unwrap(),
+                end_time: DateTime::<FixedOffset>::parse_from_rfc3339("2022-08-30T15:00:00+08:00")
+                    .unwrap(),
+            },
+            OncallSlot {
+                start_time: DateTime::<FixedOffset>::parse_from_rfc3339(
+                    "2022-08-31T07:00:00+08:00",
+                )
+                .unwrap(),
+                end_time: DateTime::<FixedOffset>::parse_from_rfc3339("2022-08-31T15:00:00+08:00")
+                    .unwrap(),
+            },
+        ];
+        let result = has_conflicts(&current_pd_shift, &oncall_slots);
+        assert!(!result);
+    }
+
+    #[test]
+    fn test_find_conflicts() {
+        let current_pd_shift = FinalPagerDutySchedule {
+            pd_user_id: "someid".to_string(),
+            start: DateTime::<FixedOffset>::parse_from_rfc3339("2022-08-30T07:00:00+08:00")
+                .unwrap(),
+            end: DateTime::<FixedOffset>::parse_from_rfc3339("2022-08-30T15:00:00+08:00").unwrap(),
+            email: "random.user@grabtaxi.com".to_string(),
+        };
+        let oncall_slots = vec![
+            OncallSlot {
+                start_time: DateTime::<FixedOffset>::parse_from_rfc3339(
+                    "2022-08-29T07:00:00+08:00",
+                )
+                .unwrap(),
+                end_time: DateTime::<FixedOffset>::parse_from_rfc3339("2022-08-29T15:00:00+08:00")
+                    .unwrap(),
+            },
+            OncallSlot {
+                start_time: DateTime::<FixedOffset>::parse_from_rfc3339(
+                    "2022-08-31T07:00:00+08:00",
+                )
+                .unwrap(),
+                end_time: DateTime::<FixedOffset>::parse_from_rfc3339("2022-08-31T15:00:00+08:00")
+                    .unwrap(),
+            },
+        ];
+        let result = has_conflicts(&current_pd_shift, &oncall_slots);
+        assert!(result);
+    }
+
+    #[test]
+    fn test_slots_matched_with_tolerance() {
+        let slot = |start: &str, hours: i64| {
+            let slot = test_slot(start);
+            FinalPagerDutySchedule {
+                pd_user_id: "PA".to_string(),
+                start: slot.start_time,
+                end: slot.start_time + Duration::hours(hours),
+                email: "a@x.com".to_string(),
+            }
+        };
+        let available = [test_slot("2022-08-30T03:00:00+08:00")];
+        assert!(!has_conflicts(
+            &slot("2022-08-30T03:00:00+08:00", 12),
+            &available
+        ));
+        // entries a minute off are still the same slot, half an hour off they aren't
+        assert!(!has_conflicts(
+            &slot("2022-08-30T03:01:00+08:00", 12),
+            &available
+        ));
+        assert!(!has_conflicts(
+            &slot("2022-08-30T02:59:00+08:00", 12),
+            &available
+        ));
+        assert!(has_conflicts(
+            &slot("2022-08-30T03:30:00+08:00", 12),
+            &available
+        ));
+        assert!(has_conflicts(
+            &slot("2022-08-30T02:30:00+08:00", 12),
+            &available
+        ));
+        // a part of the available slot, e.g. after a split, is covered, a longer slot isn't
+        assert!(!has_conflicts(
+            &slot("2022-08-30T09:00:00+08:00", 6),
+            &available
+        ));
+        assert!(has_conflicts(
+            &slot("2022-08-30T03:00:00+08:00", 24),
+            &available
+        ));
+
+        // b's entry starts a minute late, a can still take it
+        let days = ["2022-08-29T03:00:00+08:00", "2022-08-30T03:00:00+08:00"];
+        let mut schedule = vec![
+            test_entity("a@x.com", days[0], &days[1..]),
+            test_entity("b@x.com", "2022-08-30T03:01:00+08:00", &days),
+            test_entity("c@x.com", "2022-08-31T03:00:00+08:00", &days),
+        ];
+        let swap = find_potential_swap(
+            &mut schedule,
+            0,
+            &[],
+            &mut StdRng::seed_from_u64(1),
+            &SolverOptions::default(),
+            &mut SolverStats::default(),
+        );
+        assert_eq!(swap, Some(1));
+
+        // c's entry starts a minute late, the rotation a -> b -> c -> a can still close through it
+        let days = [
+            "2022-08-29T03:00:00+08:00",
+            "2022-08-30T03:00:00+08:00",
+            "2022-08-31T03:00:00+08:00",
+        ];
+        let schedule = vec![
+            test_entity("a@x.com", days[0], &[days[1]]),
+            test_entity("b@x.com", days[1], &[days[2]]),
+            test_entity("c@x.com", "2022-08-31T03:01:00+08:00", &[days[0]]),
+        ];
+        assert_eq!(
+            find_rotation_cycle(&schedule, 0, &SolverOptions::default()),
+            Some(vec![1, 2])
+        );
+
+        // a previous plan and secondaries recorded against c's slot before it drifted still apply
+        let on_time = test_slot(days[2]).start_time;
+        let previous = BTreeMap::from([(on_time, "a@x.com".to_string())]);
+        let rescheduled = apply_previous_plan(&schedule, &previous);
+        assert_eq!(rescheduled[2].pd_schedule.email, "a@x.com");
+        assert_eq!(
+            rescheduled[2].pd_schedule.start,
+            schedule[2].pd_schedule.start
+        );
+        let options = SolverOptions {
+            secondaries: BTreeMap::from([(on_time, "mentor@x.com".to_string())]),
+            keep_paired: vec![["c@x.com".to_string(), "mentor@x.com".to_string()]],
+            ..SolverOptions::default()
+        };
+        assert_eq!(
+            options
+                .pairings()
+                .kept_with("c@x.com", schedule[2].pd_schedule.start),
+            Some("mentor@x.com")
+        );
+        // but not one half an hour off
+        let late = BTreeMap::from([(on_time + Duration::minutes(30), "a@x.com".to_string())]);
+        assert_eq!(
+            apply_previous_plan(&schedule, &late)[2].pd_schedule.email,
+            "c@x.com"
+        );
+    }
+
+    #[test]
+    fn test_recursive_solution_base_case() -> AnyhowResult<()> {
+        let schedule = vec![
+            FinalEntity {
+                pd_schedule: FinalPagerDutySchedule {
+                    pd_user_id: "someid".to_string(),
+                    start: DateTime::<FixedOffset>::parse_from_rfc3339("2022-08-30T07:00:00+08:00")
+                        .unwrap(),
+                    end: DateTime::<FixedOffset>::parse_from_rfc3339("2022-08-30T15:00:00+08:00")
+                        .unwrap(),
+                    email: "random.user@grabtaxi.com".to_string(),
+                },
+                available_slots: vec![
+                    OncallSlot {
+                        start_time: DateTime::<FixedOffset>::parse_from_rfc3339(
+                            "2022-08-29T07:00:00+08:00",
+                        )
+                        .unwrap(),
+                        end_time: DateTime::<FixedOffset>::parse_from_rfc3339(
+                            "2022-08-29T15:00:00+08:00",
+                        )
+                        .unwrap(),
+                    },
+                    OncallSlot {
+                        start_time: DateTime::<FixedOffset>::parse_from_rfc3339(
+                            "2022-08-31T07:00:00+08:00",
+                        )
+                        .unwrap(),
+                        end_time: DateTime::<FixedOffset>::parse_from_rfc3339(
+                            "2022-08-31T15:00:00+08:00",
+                        )
+                        .unwrap(),
+                    },
+                ],
+                preferred_slots: Vec::new(),
+                busy: Vec::new(),
+                soft_conflict_slots: Vec::new(),
+                soft_busy: Vec::new(),
+                recent_load: 0.0,
+                slot_costs: BTreeMap::new(),
+            },
+            FinalEntity {
+                pd_schedule: FinalPagerDutySchedule {
+                    pd_user_id: "someid".to_string(),
+                    start: DateTime::<FixedOffset>::parse_from_rfc3339("2022-08-31T07:00:00+08:00")
+                        .unwrap(),
+                    end: DateTime::<FixedOffset>::parse_from_rfc3339("2022-08-31T15:00:00+08:00")
+                        .unwrap(),
+                    email: "random.user2@grabtaxi.com".to_string(),
+                },
+                available_slots: vec![
+                    OncallSlot {
+                        start_time: DateTime::<FixedOffset>::parse_from_rfc3339(
+                            "2022-08-29T07:00:00+08:00",
+                        )
+                        .unwrap(),
+                        end_time: DateTime::<FixedOffset>::parse_from_rfc3339(
+                            "2022-08-29T15:00:00+08:00",
+                        )
+                        .unwrap(),
+                    },
+                    OncallSlot {
+                        start_time: DateTime::<FixedOffset>::parse_from_rfc3339(
+                            "2022-08-30T07:00:00+08:00",
+                        )
+                        .unwrap(),
+                        end_time: DateTime::<FixedOffset>::parse_from_rfc3339(
+                            "2022-08-30T15:00:00+08:00",
+                        )
+                        .unwrap(),
+                    },
+                ],
+                preferred_slots: Vec::new(),
+                busy: Vec::new(),
+                soft_conflict_slots: Vec::new(),
+                soft_busy: Vec::new(),
+                recent_load: 0.0,
+                slot_costs: BTreeMap::new(),
+            },
+        ];
+
+        let mut rng = StdRng::seed_from_u64(42);
+        let (rescheduled, swaps) =
+            recursive_solution(&schedule, Vec::new(), &mut rng, &SolverOptions::default())?;
+        say!("\n========Simulating swaps==============");
+        say!("{}", Table::new(swaps));
+
+        let final_overrides = generate_diff_of_shift(schedule, rescheduled);
+        say!("\n====Generating final diff against current schedule======");
+        say!("{}", Table::new(final_overrides));
+        Ok(())
+    }
+
+    pub fn test_slot(start: &str) -> OncallSlot {
+        let start_time = DateTime::<FixedOffset>::parse_from_rfc3339(start).unwrap();
+        OncallSlot {
+            start_time,
+            end_time: start_time + Duration::hours(12),
+        }
+    }
+
+    pub fn test_entity(email: &str, start: &str, available: &[&str]) -> FinalEntity {
+        let slot = test_slot(start);
+        FinalEntity {
+            pd_schedule: FinalPagerDutySchedule {
+                pd_user_id: format!("id-{}", email),
+                start: slot.start_time,
+                end: slot.end_time,
+                email: email.to_string(),
+            },
+            available_slots: available.iter().map(|x| test_slot(x)).collect(),
+            preferred_slots: Vec::new(),
+            busy: Vec::new(),
+            soft_conflict_slots: Vec::new(),
+            soft_busy: Vec::new(),
+            recent_load: 0.0,
+            slot_costs: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_recursive_solution_is_deterministic_for_seed() -> AnyhowResult<()> {
+        let all_days = [
+            "2022-08-29T03:00:00+08:00",
+            "2022-08-30T03:00:00+08:00",
+            "2022-08-31T03:00:00+08:00",
+            "2022-09-01T03:00:00+08:00",
+        ];
+        let schedule = vec![
+            test_entity("a@x.com", all_days[0], &all_days[1..]),
+            test_entity("b@x.com", all_days[1], &all_days),
+            test_entity("c@x.com", all_days[2], &all_days),
+            test_entity("d@x.com", all_days[3], &all_days),
+        ];
+        let run = |seed: u64| -> AnyhowResult<Vec<String>> {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let (_, swaps) =
+                recursive_solution(&schedule, Vec::new(), &mut rng, &SolverOptions::default())?;
+            Ok(swaps
+                .into_iter()
+                .map(|x| format!("{}->{}", x.swapped_with, x.new_slot))
+                .collect())
+        };
+        for seed in 0..10 {
+            assert_eq!(run(seed)?, run(seed)?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_candidate_plans_are_distinct_and_ranked() -> AnyhowResult<()> {
+        let all_days = [
+            "2022-08-29T03:00:00+08:00",
+            "2022-08-30T03:00:00+08:00",
+            "2022-08-31T03:00:00+08:00",
+            "2022-09-01T03:00:00+08:00",
+        ];
+        let schedule = vec![
+            test_entity("a@x.com", all_days[0], &all_days[1..]),
+            test_entity("b@x.com", all_days[1], &all_days),
+            test_entity("c@x.com", all_days[2], &all_days),
+            test_entity("d@x.com", all_days[3], &all_days),
+        ];
+        let plans = generate_candidate_plans(&schedule, 7, 3, &SolverOptions::default())?;
+        assert!(!plans.is_empty() && plans.len() <= 3);
+        // five attempts per candidate, each picking up a's conflict and scoring b, c and d
+        let stats = plans[0].stats;
+        assert_eq!((stats.attempts, stats.failed_attempts), (15, 0));
+        assert!(stats.iterations >= 15 && stats.candidates_evaluated >= 45);
+        assert_eq!(stats.dead_ends, 0);
+        // attempts run in parallel, but the chosen plans only depend on the seed
+        let again = generate_candidate_plans(&schedule, 7, 3, &SolverOptions::default())?;
+        assert_eq!(
+            plans.iter().map(|x| x.seed).collect::<Vec<_>>(),
+            again.iter().map(|x| x.seed).collect::<Vec<_>>()
+        );
+        for (i, plan) in plans.iter().enumerate() {
+            for other in &plans[i + 1..] {
+                assert_ne!(plan.fingerprint(), other.fingerprint());
+                assert!(plan.overrides.len() <= other.overrides.len());
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_candidate_plans_without_conflicts() -> AnyhowResult<()> {
+        let schedule = vec![test_entity(
+            "a@x.com",
+            "2022-08-29T03:00:00+08:00",
+            &["2022-08-29T03:00:00+08:00"],
+        )];
+        let plans = generate_candidate_plans(&schedule, 7, 2, &SolverOptions::default())?;
+        assert_eq!(plans.len(), 1);
+        assert!(plans[0].overrides.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_swap_penalty_prefers_weekend_balancing_swap() {
+        // 2022-09-03 is a Saturday, 2022-09-05 a Monday
+        let saturday = "2022-09-03T03:00:00+08:00";
+        let sunday = "2022-09-04T03:00:00+08:00";
+        let next_sunday = "2022-09-11T03:00:00+08:00";
+        let monday = "2022-09-05T03:00:00+08:00";
+        let tuesday = "2022-09-06T03:00:00+08:00";
+        let conflict = test_entity("a@x.com", saturday, &[monday, tuesday]);
+        let no_weekends = test_entity("c@x.com", monday, &[]);
+        let has_weekend = test_entity("d@x.com", tuesday, &[]);
+        let mut schedule = vec![
+            conflict,
+            test_entity("a@x.com", sunday, &[]),
+            no_weekends,
+            has_weekend,
+            test_entity("d@x.com", next_sunday, &[]),
+        ];
+        let counts = weekend_counts(&schedule);
+        assert_eq!(counts.get("a@x.com"), Some(&2));
+        assert_eq!(counts.get("c@x.com"), Some(&0));
+        assert_eq!(counts.get("d@x.com"), Some(&1));
+        // handing a's saturday to c evens things out, handing it to d just moves the imbalance
+        let weights = Weights {
+            weekend: 1.0,
+            back_to_back: 0.0,
+            ..Weights::default()
+        };
+        let options = SolverOptions {
+            weights,
+            ..SolverOptions::default()
+        };
+        assert!(
+            swap_penalty(&mut schedule, 0, 2, &options)
+                < swap_penalty(&mut schedule, 0, 3, &options)
+        );
+        assert!((imbalance(&counts) - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_find_rotation_cycle() {
+        let days = [
+            "2022-08-29T03:00:00+08:00",
+            "2022-08-30T03:00:00+08:00",
+            "2022-08-31T03:00:00+08:00",
+            "2022-09-01T03:00:00+08:00",
+        ];
+        let mut schedule = vec![
+            test_entity("a@x.com", days[0], &[days[1]]),
+            test_entity("d@x.com", days[3], &[days[3]]),
+            test_entity("b@x.com", days[1], &[days[2]]),
+            test_entity("c@x.com", days[2], &[days[0]]),
+        ];
+        let with_cycle_length = |max_cycle_length| SolverOptions {
+            max_cycle_length,
+            ..SolverOptions::default()
+        };
+        assert_eq!(
+            find_rotation_cycle(&schedule, 0, &with_cycle_length(2)),
+            None
+        );
+        assert_eq!(
+            find_rotation_cycle(&schedule, 0, &with_cycle_length(3)),
+            Some(vec![2, 3])
+        );
+        // c may not take a's slot, so the rotation is no longer possible
+        let blocked = SolverOptions {
+            blocked_swaps: vec![vec!["a@x.com".to_string(), "c@x.com".to_string()]],
+            ..with_cycle_length(3)
+        };
+        assert_eq!(find_rotation_cycle(&schedule, 0, &blocked), None);
+
+        let mut swaps = Vec::new();
+        apply_rotation(&mut schedule, 0, &[2, 3], &mut swaps);
+        assert_eq!(swaps.len(), 2);
+        assert!(schedule
+            .iter()
+            .all(|x| !has_conflicts(&x.pd_schedule, &x.available_slots)));
+    }
+
+    #[test]
+    fn test_recursive_solution_falls_back_to_rotation() -> AnyhowResult<()> {
+        let days = [
+            "2022-08-29T03:00:00+08:00",
+            "2022-08-30T03:00:00+08:00",
+            "2022-08-31T03:00:00+08:00",
+        ];
+        let schedule = vec![
+            test_entity("a@x.com", days[0], &[days[1]]),
+            test_entity("b@x.com", days[1], &[days[2]]),
+            test_entity("c@x.com", days[2], &[days[0], days[2]]),
+        ];
+        // b was just swapped, so a direct swap with b is ruled out
+        let previous_swaps = vec![SimulatedSwap {
+            person_with_conflict: "b@x.com".to_string(),
+            original_slot: "".to_string(),
+            swapped_with: "".to_string(),
+            new_slot: "".to_string(),
+            original_start: test_slot("2022-08-29T03:00:00+08:00").start_time,
+            new_start: test_slot("2022-08-29T03:00:00+08:00").start_time,
+        }];
+        let options = SolverOptions {
+            max_cycle_length: 3,
+            ..SolverOptions::default()
+        };
+        let mut rng = StdRng::seed_from_u64(1);
+        let (rescheduled, swaps) =
+            recursive_solution(&schedule, previous_swaps.clone(), &mut rng, &options)?;
+        assert_eq!(swaps.len(), 3);
+        assert!(rescheduled
+            .iter()
+            .all(|x| !has_conflicts(&x.pd_schedule, &x.available_slots)));
+
+        let without_rotations = SolverOptions {
+            max_cycle_length: 0,
+            ..SolverOptions::default()
+        };
+        assert!(
+            recursive_solution(&schedule, previous_swaps, &mut rng, &without_rotations).is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_potential_swap_prefers_same_shift() {
+        let am = "2022-08-30T03:00:00+08:00";
+        let pm = "2022-08-30T15:00:00+08:00";
+        let mut schedule = vec![
+            test_entity("a@x.com", "2022-08-29T03:00:00+08:00", &[am, pm]),
+            test_entity("b@x.com", pm, &[]),
+            test_entity("c@x.com", am, &[]),
+        ];
+        for seed in 0..10 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let best = find_potential_swap(
+                &mut schedule,
+                0,
+                &[],
+                &mut rng,
+                &SolverOptions::default(),
+                &mut SolverStats::default(),
+            );
+            assert_eq!(schedule[best.unwrap()].pd_schedule.email, "c@x.com");
+        }
+    }
+
+    #[test]
+    fn test_find_potential_swap_prefers_requested_slots() {
+        let days = [
+            "2022-08-30T03:00:00+08:00",
+            "2022-08-31T03:00:00+08:00",
+            "2022-09-01T03:00:00+08:00",
+        ];
+        let mut conflict = test_entity("a@x.com", "2022-08-29T03:00:00+08:00", &days);
+        conflict.preferred_slots = vec![test_slot(days[1])];
+        let mut schedule: Vec<FinalEntity> = days
+            .iter()
+            .enumerate()
+            .map(|(i, day)| test_entity(&format!("{}@x.com", i), day, &[]))
+            .collect();
+        schedule.insert(0, conflict);
+        let options = SolverOptions {
+            weights: Weights {
+                preference: 1.0,
+                ..Weights::default()
+            },
+            ..SolverOptions::default()
+        };
+        for seed in 0..10 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let best = find_potential_swap(
+                &mut schedule,
+                0,
+                &[],
+                &mut rng,
+                &options,
+                &mut SolverStats::default(),
+            );
+            assert_eq!(
+                schedule[best.unwrap()].pd_schedule.start,
+                test_slot(days[1]).start_time
+            );
+        }
+    }
+
+    #[test]
+    fn test_back_to_back_count() {
+        let schedule = vec![
+            test_entity("a@x.com", "2022-08-29T03:00:00+08:00", &[]),
+            test_entity("a@x.com", "2022-08-29T15:00:00+08:00", &[]),
+            test_entity("a@x.com", "2022-08-31T03:00:00+08:00", &[]),
+            test_entity("b@x.com", "2022-08-30T03:00:00+08:00", &[]),
+        ];
+        assert_eq!(back_to_back_count(&schedule), 1);
+    }
+
+    #[test]
+    fn test_recursive_solution_returns_best_partial_plan_when_exhausted() {
+        let (a, b) = ("2022-08-29T03:00:00+08:00", "2022-08-30T03:00:00+08:00");
+        let schedule = vec![
+            test_entity("a@x.com", a, &[b]),
+            test_entity("b@x.com", b, &[a]),
+        ];
+        let options = SolverOptions {
+            max_depth: 0,
+            ..SolverOptions::default()
+        };
+        let mut rng = StdRng::seed_from_u64(1);
+        let error = recursive_solution(&schedule, Vec::new(), &mut rng, &options).unwrap_err();
+        let exhausted = error.downcast_ref::<SearchExhausted>().unwrap();
+        assert_eq!(exhausted.best.remaining_conflicts, 2);
+        assert!(exhausted.best.swaps.is_empty());
+        // the same schedule solves with the default budget
+        assert!(
+            recursive_solution(&schedule, Vec::new(), &mut rng, &SolverOptions::default()).is_ok()
+        );
+    }
+
+    #[test]
+    fn test_split_partial_conflicts() -> AnyhowResult<()> {
+        let days = ["2022-08-29T03:00:00+08:00", "2022-08-30T03:00:00+08:00"];
+        let parse = |x: &str| DateTime::parse_from_rfc3339(x).unwrap();
+        let mut partly_busy = test_entity("a@x.com", days[0], &[days[1]]);
+        partly_busy.busy = vec![BusyInterval {
+            summary: "Dentist".to_string(),
+            start: parse("2022-08-29T05:00:00+08:00"),
+            end: parse("2022-08-29T08:00:00+08:00"),
+        }];
+        let schedule = vec![partly_busy, test_entity("b@x.com", days[1], &days)];
+        let options = SolverOptions {
+            min_split_segment: Some(Duration::hours(3)),
+            ..SolverOptions::default()
+        };
+        let plans = generate_candidate_plans(&schedule, 1, 1, &options)?;
+        let plan = &plans[0];
+        assert!(plan.swaps.is_empty());
+        assert_eq!(plan.splits.len(), 1);
+        // the 2 hours before the dentist are too short to keep, so b covers them too
+        assert_eq!(
+            plan.splits[0].cover_start,
+            parse("2022-08-29T03:00:00+08:00")
+        );
+        assert_eq!(plan.splits[0].cover_end, parse("2022-08-29T08:00:00+08:00"));
+        assert_eq!(plan.overrides.len(), 1);
+        assert_eq!(plan.overrides[0].final_override, "b@x.com");
+
+        // b may not cover for a when the two may not be swapped
+        let blocked = SolverOptions {
+            blocked_swaps: vec![vec!["a@x.com".to_string(), "b@x.com".to_string()]],
+            ..options.clone()
+        };
+        let (_, splits) = split_partial_conflicts(&schedule, Duration::hours(3), &blocked);
+        assert!(splits.is_empty());
+
+        // a slot that is busy throughout can't be split
+        let mut fully_busy = schedule[0].clone();
+        fully_busy.busy[0].end = parse("2022-08-29T13:00:00+08:00");
+        assert!(busy_segment(&fully_busy, Duration::hours(3)).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_restrict_to_swap_window() {
+        let days = [
+            "2022-08-29T03:00:00+08:00",
+            "2022-08-30T03:00:00+08:00",
+            "2022-09-05T03:00:00+08:00",
+        ];
+        let schedule = vec![
+            test_entity("a@x.com", days[0], &days[1..]),
+            test_entity("b@x.com", days[1], &days),
+            test_entity("c@x.com", days[2], &days),
+        ];
+        let restricted = restrict_to_swap_window(&schedule, Duration::days(2));
+        let starts = |entity: &FinalEntity| -> Vec<DateTime<FixedOffset>> {
+            entity
+                .available_slots
+                .iter()
+                .map(|x| x.start_time)
+                .collect()
+        };
+        // a's conflict can only be swapped with the next day, not a week later
+        assert_eq!(starts(&restricted[0]), vec![test_slot(days[1]).start_time]);
+        assert_eq!(starts(&restricted[1]).len(), 2);
+        assert_eq!(starts(&restricted[2]), vec![test_slot(days[2]).start_time]);
+    }
+
+    #[test]
+    fn test_approved_swaps_are_kept() -> AnyhowResult<()> {
+        let days = [
+            "2022-08-29T03:00:00+08:00",
+            "2022-08-30T03:00:00+08:00",
+            "2022-08-31T03:00:00+08:00",
+        ];
+        let schedule = vec![
+            test_entity("a@x.com", days[0], &days[1..]),
+            test_entity("b@x.com", days[1], &days),
+            test_entity("c@x.com", days[2], &days),
+        ];
+        // b gave their slot to c, so a's conflict can only go to the last slot
+        let options = SolverOptions {
+            approved_swaps: BTreeMap::from([(test_slot(days[1]).start_time, "c@x.com".into())]),
+            ..SolverOptions::default()
+        };
+        for seed in 0..5 {
+            let plan = generate_candidate_plans(&schedule, seed, 1, &options)?.remove(0);
+            let mut holders: Vec<(DateTime<FixedOffset>, &str)> = plan
+                .schedule
+                .iter()
+                .map(|x| (x.pd_schedule.start, x.pd_schedule.email.as_str()))
+                .collect();
+            holders.sort();
+            let holders: Vec<&str> = holders.into_iter().map(|x| x.1).collect();
+            assert_eq!(holders, ["c@x.com", "c@x.com", "a@x.com"]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_frozen_slots_keep_their_holder() -> AnyhowResult<()> {
+        let days = [
+            "2022-08-29T03:00:00+08:00",
+            "2022-08-30T03:00:00+08:00",
+            "2022-08-31T03:00:00+08:00",
+        ];
+        // a is busy in the slot under way, and b in the one after it
+        let schedule = vec![
+            test_entity("a@x.com", days[0], &days[1..]),
+            test_entity("b@x.com", days[1], &[days[0], days[2]]),
+            test_entity("c@x.com", days[2], &days),
+        ];
+        let started = test_slot(days[0]).start_time;
+        let fixed = freeze_slots(&schedule, &BTreeSet::from([started]));
+        assert!(!has_conflicts(
+            &fixed[0].pd_schedule,
+            &fixed[0].available_slots
+        ));
+        assert!(fixed[1..]
+            .iter()
+            .all(|x| x.available_slots.iter().all(|y| y.start_time != started)));
+        for seed in 0..5 {
+            let plan =
+                generate_candidate_plans(&fixed, seed, 1, &SolverOptions::default())?.remove(0);
+            let holder = plan
+                .schedule
+                .iter()
+                .find(|x| x.pd_schedule.start == started)
+                .map(|x| x.pd_schedule.email.as_str());
+            assert_eq!(holder, Some("a@x.com"));
+            assert!(plan.overrides.iter().all(|x| x.start_time_iso != days[0]));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_swap_penalty_spreads_holidays() {
+        // all weekdays, the first three are holidays and a holds two of them
+        let days = [
+            "2022-08-29T03:00:00+08:00",
+            "2022-08-30T03:00:00+08:00",
+            "2022-08-31T03:00:00+08:00",
+            "2022-09-01T03:00:00+08:00",
+        ];
+        let holidays: BTreeSet<NaiveDate> = [
+            NaiveDate::from_ymd(2022, 8, 29),
+            NaiveDate::from_ymd(2022, 8, 30),
+            NaiveDate::from_ymd(2022, 8, 31),
+        ]
+        .into_iter()
+        .collect();
+        let conflict = test_entity("a@x.com", days[1], &days);
+        let no_holidays = test_entity("b@x.com", days[3], &days);
+        let has_holiday = test_entity("c@x.com", days[2], &days);
+        let mut schedule = vec![
+            test_entity("a@x.com", days[0], &days),
+            conflict,
+            no_holidays,
+            has_holiday,
+        ];
+        assert_eq!(
+            holiday_counts(&schedule, &holidays).get("a@x.com"),
+            Some(&2)
+        );
+        let options = SolverOptions {
+            holidays,
+            weights: Weights {
+                back_to_back: 0.0,
+                ..Weights::default()
+            },
+            ..SolverOptions::default()
+        };
+        assert!(
+            swap_penalty(&mut schedule, 1, 2, &options)
+                < swap_penalty(&mut schedule, 1, 3, &options)
+        );
+    }
+
+    #[test]
+    fn test_find_potential_swap_respects_pairings() {
+        let days = [
+            "2022-08-29T03:00:00+08:00",
+            "2022-08-30T03:00:00+08:00",
+            "2022-08-31T03:00:00+08:00",
+        ];
+        let mut schedule = vec![
+            test_entity("a@x.com", days[0], &days[1..]),
+            test_entity("b@x.com", days[1], &days),
+            test_entity("c@x.com", days[2], &days),
+        ];
+        let secondaries: BTreeMap<DateTime<FixedOffset>, String> = [
+            (test_slot(days[1]).start_time, "mentor@x.com".to_string()),
+            (test_slot(days[2]).start_time, "rival@x.com".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        let mut rng = StdRng::seed_from_u64(1);
+        let keep_only = SolverOptions {
+            secondaries: secondaries.clone(),
+            keep_paired: vec![["b@x.com".to_string(), "mentor@x.com".to_string()]],
+            ..SolverOptions::default()
+        };
+        // b stays with their mentor, so c is the only option
+        let swap = find_potential_swap(
+            &mut schedule,
+            0,
+            &[],
+            &mut rng,
+            &keep_only,
+            &mut SolverStats::default(),
+        );
+        assert_eq!(schedule[swap.unwrap()].pd_schedule.email, "c@x.com");
+
+        let keep_and_never = SolverOptions {
+            never_paired: vec![["a@x.com".to_string(), "rival@x.com".to_string()]],
+            ..keep_only
+        };
+        let swap = find_potential_swap(
+            &mut schedule,
+            0,
+            &[],
+            &mut rng,
+            &keep_and_never,
+            &mut SolverStats::default(),
+        );
+        assert!(swap.is_none());
+    }
+
+    #[test]
+    fn test_incremental_solve_keeps_previous_plan() -> AnyhowResult<()> {
+        let days = [
+            "2022-08-29T03:00:00+08:00",
+            "2022-08-30T03:00:00+08:00",
+            "2022-08-31T03:00:00+08:00",
+        ];
+        // the previous plan swapped a and b. Since then c has become busy on their slot
+        let schedule = vec![
+            test_entity("a@x.com", days[0], &days),
+            test_entity("b@x.com", days[1], &days),
+            test_entity("c@x.com", days[2], &days[..2]),
+        ];
+        let previous = CandidatePlan {
+            seed: 1,
+            schedule: vec![
+                schedule[0].moved_to(&schedule[1]),
+                schedule[1].moved_to(&schedule[0]),
+                schedule[2].clone(),
+            ],
+            swaps: Vec::new(),
+            overrides: Vec::new(),
+            splits: Vec::new(),
+            soft_score: SoftScore::default(),
+            stats: SolverStats::default(),
+        };
+        let options = SolverOptions {
+            previous_assignments: Some(to_saved_plan(&previous).assignments()?),
+            ..SolverOptions::default()
+        };
+        let plans = generate_candidate_plans(&schedule, 3, 1, &options)?;
+        // only c's slot changes hands compared to the previous plan
+        let delta = generate_diff_of_shift(previous.schedule, plans[0].schedule.clone());
+        assert_eq!(delta.len(), 2);
+        assert!(delta.iter().any(|x| x.original_assignee == "c@x.com"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_candidate_plans_falls_back_to_soft_conflicts() -> AnyhowResult<()> {
+        let days = ["2022-08-29T03:00:00+08:00", "2022-08-30T03:00:00+08:00"];
+        // a is out of office on the first day, and only has a standup on the second
+        let mut a = test_entity("a@x.com", days[0], &[]);
+        a.soft_conflict_slots = vec![test_slot(days[1])];
+        a.soft_busy = vec![BusyInterval {
+            summary: "standup".to_string(),
+            start: test_slot(days[1]).start_time + Duration::hours(6),
+            end: test_slot(days[1]).start_time + Duration::hours(7),
+        }];
+        let schedule = vec![a, test_entity("b@x.com", days[1], &days)];
+        let plans = generate_candidate_plans(&schedule, 7, 1, &SolverOptions::default())?;
+        assert_eq!(plans[0].overrides.len(), 2);
+        // the hour long standup covers a twelfth of the slot
+        assert!((plans[0].soft_score().soft_conflict_coverage - 1.0 / 12.0).abs() < 1e-9);
+        let moved = plans[0]
+            .schedule
+            .iter()
+            .find(|x| x.pd_schedule.email == "a@x.com")
+            .unwrap();
+        assert_eq!(moved.soft_conflict().unwrap().summary, "standup");
+
+        // without the fallback there is no plan at all
+        let strict = recursive_solution(
+            &schedule,
+            Vec::new(),
+            &mut StdRng::seed_from_u64(7),
+            &SolverOptions::default(),
+        );
+        assert!(strict.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_allow_unresolved_solves_the_rest() -> AnyhowResult<()> {
+        let days = [
+            "2022-08-29T03:00:00+08:00",
+            "2022-08-30T03:00:00+08:00",
+            "2022-08-31T03:00:00+08:00",
+        ];
+        // a can't take any slot, b and c can swap
+        let schedule = vec![
+            test_entity("a@x.com", days[0], &[]),
+            test_entity("b@x.com", days[1], &[days[2]]),
+            test_entity("c@x.com", days[2], &[days[1], days[2]]),
+        ];
+        assert!(generate_candidate_plans(&schedule, 7, 1, &SolverOptions::default()).is_err());
+
+        let options = SolverOptions {
+            allow_unresolved: true,
+            ..SolverOptions::default()
+        };
+        let plans = generate_candidate_plans(&schedule, 7, 1, &options)?;
+        assert_eq!(plans[0].overrides.len(), 2);
+        let unresolved = plans[0].unresolved_conflicts();
+        assert_eq!(unresolved.len(), 1);
+        assert_eq!(unresolved[0], &schedule[0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_minimal_removal() {
+        let days = [
+            "2022-08-29T03:00:00+08:00",
+            "2022-08-30T03:00:00+08:00",
+            "2022-08-31T03:00:00+08:00",
+            "2022-09-01T03:00:00+08:00",
+        ];
+        // a and d can't take any slot, b and c can swap
+        let schedule = vec![
+            test_entity("a@x.com", days[0], &[]),
+            test_entity("b@x.com", days[1], &[days[2]]),
+            test_entity("c@x.com", days[2], &[days[1], days[2]]),
+            test_entity("d@x.com", days[3], &[]),
+        ];
+        let removal = minimal_removal(&schedule, 7, &SolverOptions::default());
+        let mut emails: Vec<&str> = removal
+            .iter()
+            .map(|x| x.pd_schedule.email.as_str())
+            .collect();
+        emails.sort();
+        assert_eq!(emails, vec!["a@x.com", "d@x.com"]);
+
+        // b can only hold their own slot, so one of them has to go
+        let schedule = vec![
+            test_entity("a@x.com", days[0], &[days[1]]),
+            test_entity("b@x.com", days[1], &[days[1]]),
+        ];
+        let removal = minimal_removal(&schedule, 7, &SolverOptions::default());
+        assert_eq!(removal.len(), 1);
+        assert_eq!(combinations(4, 2).len(), binomial(4, 2));
+    }
+
+    #[test]
+    fn test_time_budget_returns_best_partial_plan() -> AnyhowResult<()> {
+        let (a, b) = ("2022-08-29T03:00:00+08:00", "2022-08-30T03:00:00+08:00");
+        let schedule = vec![
+            test_entity("a@x.com", a, &[b]),
+            test_entity("b@x.com", b, &[a]),
+        ];
+        let exhausted_options = SolverOptions {
+            max_depth: 0,
+            ..SolverOptions::default()
+        };
+        assert!(generate_candidate_plans(&schedule, 7, 1, &exhausted_options).is_err());
+
+        // a spent budget runs a single batch, whose attempts all stop at the depth limit
+        let time_boxed = SolverOptions {
+            time_budget: Some(StdDuration::ZERO),
+            attempts: Some(3),
+            ..exhausted_options
+        };
+        let plans = generate_candidate_plans(&schedule, 7, 1, &time_boxed)?;
+        assert_eq!(plans.len(), 1);
+        assert_eq!(plans[0].unresolved_conflicts().len(), 2);
+        assert_eq!(plans[0].stats.attempts, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_budget_exhausted_reason() {
+        let mut budget = SearchBudget::new(&SolverOptions {
+            max_depth: 1,
+            max_duration: Some(StdDuration::from_secs(3600)),
+            ..SolverOptions::default()
+        });
+        assert_eq!(budget.exhausted_reason(&[]), None);
+        budget.depth = 2;
+        assert_eq!(
+            budget.exhausted_reason(&[]).as_deref(),
+            Some("reached the search depth of 1")
+        );
+        budget.depth = 0;
+        budget.deadline = Some(Instant::now() - StdDuration::from_secs(1));
+        assert_eq!(
+            budget.exhausted_reason(&[]).as_deref(),
+            Some("ran out of time")
+        );
+    }
+
+    #[test]
+    fn test_check_consecutive_days() {
+        let all = [
+            "2022-08-29T03:00:00+08:00",
+            "2022-08-30T15:00:00+08:00",
+            "2022-08-31T03:00:00+08:00",
+            "2022-09-01T15:00:00+08:00",
+            "2022-09-03T03:00:00+08:00",
+        ];
+        let original = vec![
+            test_entity("a@x.com", all[0], &all),
+            test_entity("a@x.com", all[1], &all),
+            test_entity("a@x.com", all[2], &all),
+            test_entity("b@x.com", all[3], &all),
+            test_entity("a@x.com", all[4], &all),
+        ];
+        assert_eq!(consecutive_days(&original)["a@x.com"], 3);
+        // a takes b's slot, making it 4 days in a row across the AM and PM shifts
+        let mut rescheduled = original.clone();
+        rescheduled[3] = original[4].moved_to(&original[3]);
+        rescheduled[4] = original[3].moved_to(&original[4]);
+        assert_eq!(consecutive_days(&rescheduled)["a@x.com"], 4);
+        assert!(check_consecutive_days(&original, &rescheduled, Some(3)).is_err());
+        assert!(check_consecutive_days(&original, &rescheduled, Some(4)).is_ok());
+        assert!(check_consecutive_days(&original, &rescheduled, None).is_ok());
+        // already above the limit, but no worse
+        assert!(check_consecutive_days(&original, &original, Some(2)).is_ok());
+    }
+
+    #[test]
+    fn test_find_potential_swap_prefers_flexible_and_unswapped_candidates() {
+        let days = [
+            "2022-08-29T03:00:00+08:00",
+            "2022-08-30T03:00:00+08:00",
+            "2022-08-31T03:00:00+08:00",
+            "2022-09-01T03:00:00+08:00",
+        ];
+        let mut schedule = vec![
+            test_entity("a@x.com", days[0], &[days[1], days[2]]),
+            test_entity("b@x.com", days[1], &[days[0], days[1]]),
+            test_entity("c@x.com", days[2], &days),
+        ];
+        let mut best_for = |swaps: Vec<SimulatedSwap>| {
+            (0..10)
+                .map(|seed| {
+                    let mut rng = StdRng::seed_from_u64(seed);
+                    let best = find_potential_swap(
+                        &mut schedule,
+                        0,
+                        &swaps,
+                        &mut rng,
+                        &SolverOptions::default(),
+                        &mut SolverStats::default(),
+                    );
+                    schedule[best.unwrap()].pd_schedule.email.clone()
+                })
+                .collect::<BTreeSet<String>>()
+        };
+        // c can take any slot, so swapping with c is less likely to cause a new conflict
+        assert_eq!(
+            best_for(Vec::new()),
+            BTreeSet::from(["c@x.com".to_string()])
+        );
+        // unless c was already moved around earlier in the run
+        let earlier = SimulatedSwap {
+            person_with_conflict: "d@x.com".to_string(),
+            original_slot: "".to_string(),
+            swapped_with: "c@x.com".to_string(),
+            new_slot: "".to_string(),
+            original_start: test_slot("2022-08-29T03:00:00+08:00").start_time,
+            new_start: test_slot("2022-08-29T03:00:00+08:00").start_time,
+        };
+        let filler = SimulatedSwap {
+            person_with_conflict: "e@x.com".to_string(),
+            swapped_with: "f@x.com".to_string(),
+            ..earlier.clone()
+        };
+        assert_eq!(
+            best_for(vec![earlier, filler.clone(), filler]),
+            BTreeSet::from(["b@x.com".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_covered_fraction() {
+        let slot = test_slot("2022-08-29T03:00:00+08:00");
+        let interval = |start: &str, end: &str| BusyInterval {
+            summary: "standup".to_string(),
+            start: DateTime::parse_from_rfc3339(start).unwrap(),
+            end: DateTime::parse_from_rfc3339(end).unwrap(),
+        };
+        // overlapping meetings count once, and meetings are clipped to the slot
+        let meetings = [
+            interval("2022-08-29T00:00:00+08:00", "2022-08-29T09:00:00+08:00"),
+            interval("2022-08-29T06:00:00+08:00", "2022-08-29T09:00:00+08:00"),
+            interval("2022-08-30T01:00:00+08:00", "2022-08-30T07:00:00+08:00"),
+        ];
+        let fraction = covered_fraction(&meetings, slot.start_time, slot.end_time);
+        assert!((fraction - 0.5).abs() < 1e-9);
+        assert_eq!(
+            covered_fraction(&meetings[..1], slot.end_time, slot.end_time),
+            0.0
+        );
+    }
+
+    #[test]
+    fn test_overlap() {
+        let slot = test_slot("2022-08-29T07:00:00+08:00");
+        let time = |x: &str| DateTime::parse_from_rfc3339(x).unwrap();
+        let slot = (slot.start_time, slot.end_time);
+        // ends when the slot starts
+        let before = (
+            time("2022-08-29T06:00:00+08:00"),
+            time("2022-08-29T07:00:00+08:00"),
+        );
+        let within = (
+            time("2022-08-29T06:00:00+08:00"),
+            time("2022-08-29T07:01:00+08:00"),
+        );
+        assert!(!Overlap::HalfOpen.overlaps(before, slot));
+        assert!(Overlap::Inclusive.overlaps(before, slot));
+        assert!(Overlap::HalfOpen.overlaps(within, slot));
+        // starts when the slot ends
+        let after = (slot.1, slot.1 + Duration::hours(1));
+        assert!(!Overlap::HalfOpen.overlaps(slot, after));
+        assert!(Overlap::Inclusive.overlaps(slot, after));
+        assert_eq!(overlap(), Overlap::HalfOpen);
+    }
+
+    #[test]
+    fn test_busy_times() {
+        use rand::Rng;
+        let midnight = DateTime::parse_from_rfc3339("2022-08-29T00:00:00+08:00").unwrap();
+        let hour = |x: i64| midnight + Duration::hours(x);
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut interval = || {
+            let start = rng.gen_range(0..500);
+            (hour(start), hour(start + rng.gen_range(0..30)))
+        };
+        let times: Vec<_> = (0..200).map(|_| interval()).collect();
+        let busy = BusyTimes::new(times.clone());
+        // same answers as checking every time, touching ones included
+        for _ in 0..500 {
+            let (start, end) = interval();
+            for mode in [Overlap::HalfOpen, Overlap::Inclusive] {
+                let scanned = times.iter().any(|x| mode.overlaps(*x, (start, end)));
+                assert_eq!(busy.overlaps_with(start, end, mode), scanned);
+            }
+        }
+
+        // a long event hidden behind shorter ones that start later
+        let busy = BusyTimes::new([(hour(0), hour(48)), (hour(1), hour(2)), (hour(3), hour(4))]);
+        assert!(busy.overlaps(hour(24), hour(36)));
+        assert!(!busy.overlaps(hour(48), hour(60)));
+        assert!(!BusyTimes::default().overlaps(hour(0), hour(1)));
+    }
+
+    #[test]
+    fn test_compact_swaps() {
+        let start = |slot: &str| {
+            let day = match slot {
+                "mon" => "2022-08-29T03:00:00+08:00",
+                "tue" => "2022-08-30T03:00:00+08:00",
+                _ => "2022-08-31T03:00:00+08:00",
+            };
+            test_slot(day).start_time
+        };
+        let swap = |person: &str, slot: &str, other: &str, new_slot: &str| SimulatedSwap {
+            person_with_conflict: person.to_string(),
+            original_slot: slot.to_string(),
+            swapped_with: other.to_string(),
+            new_slot: new_slot.to_string(),
+            original_start: start(slot),
+            new_start: start(new_slot),
+        };
+        // a swap undone later leaves nothing to do
+        let undone = [swap("a", "mon", "b", "tue"), swap("b", "mon", "a", "tue")];
+        assert!(compact_swaps(&undone).is_empty());
+
+        // c ends up back in wed after passing through mon and tue, so only a and b really move
+        let chain = [
+            swap("a", "mon", "c", "wed"),
+            swap("c", "mon", "b", "tue"),
+            swap("b", "mon", "c", "tue"),
+            swap("c", "mon", "a", "wed"),
+            swap("a", "mon", "b", "tue"),
+        ];
+        let compacted = compact_swaps(&chain);
+        assert_eq!(compacted.len(), 1);
+        assert_eq!(compacted[0].person_with_conflict, "a");
+        assert_eq!(compacted[0].swapped_with, "b");
+        assert_eq!(compacted[0].new_slot, "tue");
+
+        // a three way rotation needs two swaps
+        let rotation = [swap("a", "mon", "b", "tue"), swap("b", "mon", "c", "wed")];
+        assert_eq!(compact_swaps(&rotation).len(), 2);
+
+        // the hour repeated when the clocks go back shows the same for both slots
+        let (first, repeated) = (
+            test_slot("2022-11-06T01:30:00-04:00").start_time,
+            test_slot("2022-11-06T01:30:00-05:00").start_time,
+        );
+        let across_dst = [SimulatedSwap {
+            original_start: first,
+            new_start: repeated,
+            ..swap("a", "Sun 01:30", "b", "Sun 01:30")
+        }];
+        let compacted = compact_swaps(&across_dst);
+        assert_eq!(compacted.len(), 1);
+        assert_eq!(
+            (compacted[0].original_start, compacted[0].new_start),
+            (first, repeated)
+        );
+    }
+
+    #[test]
+    fn test_inputs_hash() {
+        let days = ["2022-08-29T03:00:00+08:00", "2022-08-30T03:00:00+08:00"];
+        let schedule = vec![
+            test_entity("a@x.com", days[0], &days),
+            test_entity("b@x.com", days[1], &days),
+        ];
+        let hash = inputs_hash(&schedule);
+        assert_eq!(hash.len(), 64);
+        // the order the schedule was fetched in doesn't matter
+        let reversed: Vec<FinalEntity> = schedule.iter().rev().cloned().collect();
+        assert_eq!(inputs_hash(&reversed), hash);
+        // someone becoming busy does
+        let mut busy = schedule.clone();
+        busy[1].available_slots.remove(0);
+        assert_ne!(inputs_hash(&busy), hash);
+    }
+
+    #[test]
+    fn test_busy_intervals_skip_events_without_times() {
+        let time = |x: &str| {
+            Some(crate::calendar::TimeWrapper {
+                date_string: None,
+                date_time_string: Some(x.to_string()),
+            })
+        };
+        let event = |summary: Option<&str>, start, end| CalendarEvent {
+            visibility: None,
+            status: None,
+            summary: summary.map(|x| x.to_string()),
+            start,
+            end,
+            event_type: None,
+            pagerduty: None,
+        };
+        let events = vec![
+            event(
+                None,
+                time("2022-08-29T09:00:00+08:00"),
+                time("2022-08-29T10:00:00+08:00"),
+            ),
+            event(Some("No start"), None, time("2022-08-29T10:00:00+08:00")),
+            event(Some("No end"), time("2022-08-29T09:00:00+08:00"), None),
+        ];
+        let intervals = BusyInterval::from_events(&events, chrono_tz::Asia::Singapore);
+        assert_eq!(intervals.len(), 1);
+        assert_eq!(intervals[0].summary, "(no title)");
+        assert_eq!(intervals[0].start.to_rfc3339(), "2022-08-29T09:00:00+08:00");
+    }
+
+    #[test]
+    fn test_candidate_score_ties_rounded_penalties() {
+        let score = |penalty: f64, times_swapped: usize| CandidateScore {
+            cross_shift: false,
+            penalty,
+            times_swapped,
+            alternatives: 1,
+        };
+        // 0.1 + 0.2 is 0.30000000000000004, the candidate swapped less often still wins
+        assert_eq!(
+            score(0.1 + 0.2, 0).cmp(&score(0.3, 1)),
+            std::cmp::Ordering::Less
+        );
+        assert_eq!(score(0.3, 0).cmp(&score(0.4, 0)), std::cmp::Ordering::Less);
+    }
+
+    #[test]
+    fn test_cross_shift_swaps_follow_shift_names() {
+        // 09:00 in Berlin is 16:00 in Singapore before the DST change and 15:00 after it
+        let (winter, summer) = ("2022-03-25T16:00:00+08:00", "2022-03-28T15:00:00+08:00");
+        let night = "2022-03-28T03:00:00+08:00";
+        let mut schedule = vec![
+            test_entity("a@x.com", winter, &[summer, night]),
+            test_entity("b@x.com", summer, &[summer]),
+            test_entity("c@x.com", night, &[winter, summer, night]),
+        ];
+        let best = |schedule: &mut [FinalEntity], shifts: Vec<Shift>| {
+            let options = SolverOptions {
+                shifts,
+                ..SolverOptions::default()
+            };
+            find_potential_swap(
+                schedule,
+                0,
+                &[],
+                &mut StdRng::seed_from_u64(1),
+                &options,
+                &mut SolverStats::default(),
+            )
+        };
+        // by time of day, both swaps change shift and c, with more alternatives, is preferred
+        assert_eq!(best(&mut schedule, Vec::new()), Some(2));
+        let eu = Shift {
+            name: "EU".to_string(),
+            start: chrono::NaiveTime::from_hms(9, 0, 0),
+            timezone: chrono_tz::Europe::Berlin,
+        };
+        // b's slot is in a's EU shift across the DST change
+        assert_eq!(best(&mut schedule, vec![eu]), Some(1));
+    }
+
+    #[test]
+    fn test_slot_costs_change_the_swap_candidate() {
+        let days = [
+            "2022-08-29T03:00:00+08:00",
+            "2022-08-30T03:00:00+08:00",
+            "2022-08-31T03:00:00+08:00",
+        ];
+        let schedule = vec![
+            test_entity("a@x.com", days[0], &days[1..]),
+            test_entity("b@x.com", days[1], &days),
+            test_entity("c@x.com", days[2], &days),
+        ];
+        // whoever takes a's slot pays what it costs them, so the other one is picked
+        let best_with_cost_for = |costly: usize| {
+            let mut schedule = schedule.clone();
+            schedule[costly]
+                .slot_costs
+                .insert(test_slot(days[0]).start_time, 5.0);
+            find_potential_swap(
+                &mut schedule,
+                0,
+                &[],
+                &mut StdRng::seed_from_u64(1),
+                &SolverOptions::default(),
+                &mut SolverStats::default(),
+            )
+        };
+        assert_eq!(best_with_cost_for(1), Some(2));
+        assert_eq!(best_with_cost_for(2), Some(1));
+    }
+}
@@ -0,0 +1,373 @@
+use super::{
+    at_slot, check_consecutive_days, check_shift_limit, has_conflicts, same_slot, FinalEntity,
+    OncallSlot, SolverOptions,
+};
+use crate::calendar::display_time;
+use crate::config::{shift_of, Shift};
+use chrono::{DateTime, FixedOffset};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Someone moving into the slot currently held by `displaced`
+pub struct Move<'a> {
+    pub mover: &'a FinalEntity,
+    pub displaced: &'a FinalEntity,
+    /// the mover has a conflict in their own slot, so they have to leave it whatever happens
+    pub forced: bool,
+}
+
+/// Why a constraint rejects a move
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub constraint: &'static str,
+    pub reason: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.constraint, self.reason)
+    }
+}
+
+/// A hard constraint checked before the solver moves anyone. Calendar availability is not one of
+/// these, since the search itself is built around resolving it
+pub trait Constraint {
+    fn violates(&self, assignment: &Move) -> Option<Violation>;
+}
+
+/// People in the same group never exchange slots
+pub struct BlockedSwaps<'a> {
+    pub groups: &'a [Vec<String>],
+}
+
+impl Constraint for BlockedSwaps<'_> {
+    fn violates(&self, assignment: &Move) -> Option<Violation> {
+        let (a, b) = (
+            &assignment.mover.pd_schedule.email,
+            &assignment.displaced.pd_schedule.email,
+        );
+        self.groups
+            .iter()
+            .any(|group| group.contains(a) && group.contains(b))
+            .then(|| Violation {
+                constraint: "blocked swaps",
+                reason: format!("{} and {} may not be swapped", a, b),
+            })
+    }
+}
+
+/// Primary and secondary pairings: kept pairs stay in the slots they share, and never pairs are
+/// not put oncall together
+pub struct Pairings<'a> {
+    pub secondaries: &'a BTreeMap<DateTime<FixedOffset>, String>,
+    pub keep: &'a [[String; 2]],
+    pub never: &'a [[String; 2]],
+}
+
+impl Pairings<'_> {
+    /// The secondary `email` must stay with in the slot starting at `start`, if any
+    pub fn kept_with(&self, email: &str, start: DateTime<FixedOffset>) -> Option<&str> {
+        self.paired_in(self.keep, email, start)
+    }
+
+    fn paired_in(
+        &self,
+        pairs: &[[String; 2]],
+        email: &str,
+        start: DateTime<FixedOffset>,
+    ) -> Option<&str> {
+        let secondary = at_slot(self.secondaries, start)?;
+        pairs
+            .iter()
+            .any(|[x, y]| x == email && y == secondary)
+            .then_some(secondary.as_str())
+    }
+}
+
+impl Constraint for Pairings<'_> {
+    fn violates(&self, assignment: &Move) -> Option<Violation> {
+        let email = &assignment.mover.pd_schedule.email;
+        if let Some(secondary) =
+            self.paired_in(self.never, email, assignment.displaced.pd_schedule.start)
+        {
+            return Some(Violation {
+                constraint: "pairings",
+                reason: format!("{} may never be paired with {}", email, secondary),
+            });
+        }
+        if assignment.forced {
+            return None;
+        }
+        self.kept_with(email, assignment.mover.pd_schedule.start)
+            .map(|secondary| Violation {
+                constraint: "pairings",
+                reason: format!("{} stays paired with {}", email, secondary),
+            })
+    }
+}
+
+/// People with a preferred shift are only moved out of their own shift into that one. Checked for
+/// both people of a swap, so nobody is displaced into a shift they didn't opt into either
+pub struct ShiftPreferences<'a> {
+    pub shifts: &'a [Shift],
+    /// preferred shift name, keyed by email
+    pub preferred: &'a BTreeMap<String, String>,
+}
+
+impl Constraint for ShiftPreferences<'_> {
+    fn violates(&self, assignment: &Move) -> Option<Violation> {
+        let email = &assignment.mover.pd_schedule.email;
+        let preferred = self.preferred.get(email)?;
+        let from = shift_of(assignment.mover.pd_schedule.start, self.shifts);
+        let to = shift_of(assignment.displaced.pd_schedule.start, self.shifts);
+        (to != from && to != *preferred).then(|| Violation {
+            constraint: "shift preferences",
+            reason: format!(
+                "{} only moves into the {} shift, not {}",
+                email, preferred, to
+            ),
+        })
+    }
+}
+
+impl SolverOptions {
+    pub fn pairings(&self) -> Pairings<'_> {
+        Pairings {
+            secondaries: &self.secondaries,
+            keep: &self.keep_paired,
+            never: &self.never_paired,
+        }
+    }
+
+    /// Every hard constraint configured in these options
+    pub fn constraints(&self) -> Vec<Box<dyn Constraint + '_>> {
+        vec![
+            Box::new(BlockedSwaps {
+                groups: &self.blocked_swaps,
+            }),
+            Box::new(self.pairings()),
+            Box::new(ShiftPreferences {
+                shifts: &self.shifts,
+                preferred: &self.preferred_shifts,
+            }),
+        ]
+    }
+
+    /// The first constraint the move violates, if any
+    pub fn violation(&self, assignment: &Move) -> Option<Violation> {
+        self.constraints()
+            .iter()
+            .find_map(|constraint| constraint.violates(assignment))
+    }
+
+    pub fn allows(&self, assignment: &Move) -> bool {
+        self.violation(assignment).is_none()
+    }
+}
+
+/// Check a proposed plan, e.g. a hand-edited saved plan, against every active constraint. Each
+/// slot that changed hands is checked as a move of its new holder into it, like the solver would
+/// have made it. Its new holder must be free for it, through a soft conflict at worst, unless
+/// conflicts may be left unresolved
+pub fn validate_plan(
+    schedule: &[FinalEntity],
+    plan: &[FinalEntity],
+    options: &SolverOptions,
+) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    for entity in plan {
+        let (email, start) = (&entity.pd_schedule.email, entity.pd_schedule.start);
+        let displaced = match schedule
+            .iter()
+            .find(|x| same_slot(x.pd_schedule.start, start))
+        {
+            Some(value) if &value.pd_schedule.email != email => value,
+            _ => continue,
+        };
+        let allowed_slots: Vec<OncallSlot> = entity
+            .available_slots
+            .iter()
+            .chain(&entity.soft_conflict_slots)
+            .cloned()
+            .collect();
+        if !options.allow_unresolved && has_conflicts(&entity.pd_schedule, &allowed_slots) {
+            violations.push(Violation {
+                constraint: "availability",
+                reason: format!(
+                    "{} is unavailable on {}",
+                    email,
+                    display_time(start, Some(email))
+                ),
+            });
+        }
+        let original_slots: Vec<&FinalEntity> = schedule
+            .iter()
+            .filter(|x| &x.pd_schedule.email == email)
+            .collect();
+        let mover = match original_slots.first() {
+            Some(value) => *value,
+            None => continue,
+        };
+        if let Some(window) = options.swap_window {
+            if !original_slots
+                .iter()
+                .any(|x| (start - x.pd_schedule.start).num_seconds().abs() <= window.num_seconds())
+            {
+                violations.push(Violation {
+                    constraint: "swap window",
+                    reason: format!(
+                        "{} is moved more than {} days from their slots",
+                        email,
+                        window.num_days()
+                    ),
+                });
+            }
+        }
+        let forced = original_slots
+            .iter()
+            .any(|x| has_conflicts(&x.pd_schedule, &x.available_slots));
+        violations.extend(options.violation(&Move {
+            mover,
+            displaced,
+            forced,
+        }));
+    }
+    if let Err(e) = check_shift_limit(schedule, plan, options.max_shifts_per_person) {
+        violations.push(Violation {
+            constraint: "shift limit",
+            reason: e.to_string(),
+        });
+    }
+    if let Err(e) = check_consecutive_days(schedule, plan, options.max_consecutive_days) {
+        violations.push(Violation {
+            constraint: "consecutive days",
+            reason: e.to_string(),
+        });
+    }
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::tests::{test_entity, test_slot};
+
+    #[test]
+    fn test_constraints() {
+        let days = ["2022-08-29T03:00:00+08:00", "2022-08-30T03:00:00+08:00"];
+        let a = test_entity("a@x.com", days[0], &days);
+        let b = test_entity("b@x.com", days[1], &days);
+        let secondaries = [(test_slot(days[0]).start_time, "mentor@x.com".to_string())]
+            .into_iter()
+            .collect();
+        let options = SolverOptions {
+            blocked_swaps: vec![vec!["a@x.com".to_string(), "c@x.com".to_string()]],
+            secondaries,
+            keep_paired: vec![["a@x.com".to_string(), "mentor@x.com".to_string()]],
+            never_paired: vec![["b@x.com".to_string(), "mentor@x.com".to_string()]],
+            ..SolverOptions::default()
+        };
+        let a_to_b = |forced| Move {
+            mover: &a,
+            displaced: &b,
+            forced,
+        };
+        // a stays with their mentor, unless a conflict forces them out
+        assert_eq!(
+            options.violation(&a_to_b(false)).unwrap().constraint,
+            "pairings"
+        );
+        assert!(options.allows(&a_to_b(true)));
+        // b may never be paired with a's mentor
+        let b_to_a = Move {
+            mover: &b,
+            displaced: &a,
+            forced: true,
+        };
+        assert!(!options.allows(&b_to_a));
+
+        let c = test_entity("c@x.com", days[1], &days);
+        let a_to_c = Move {
+            mover: &a,
+            displaced: &c,
+            forced: true,
+        };
+        assert_eq!(
+            options.violation(&a_to_c).unwrap().constraint,
+            "blocked swaps"
+        );
+    }
+
+    #[test]
+    fn test_shift_preferences() {
+        let (am, pm) = ("2022-08-29T03:00:00+08:00", "2022-08-29T15:00:00+08:00");
+        let a = test_entity("a@x.com", am, &[am, pm]);
+        let b = test_entity("b@x.com", pm, &[am, pm]);
+        let c = test_entity("c@x.com", "2022-08-30T03:00:00+08:00", &[am]);
+        let options = SolverOptions {
+            preferred_shifts: [("b@x.com".to_string(), "15:00".to_string())]
+                .into_iter()
+                .collect(),
+            ..SolverOptions::default()
+        };
+        let swap = |mover, displaced| Move {
+            mover,
+            displaced,
+            forced: false,
+        };
+        // a has no preference, but b, displaced into a's AM slot, only takes PM slots
+        assert!(options.allows(&swap(&a, &b)));
+        assert_eq!(
+            options.violation(&swap(&b, &a)).unwrap().reason,
+            "b@x.com only moves into the 15:00 shift, not 03:00"
+        );
+        // moves within their own shift are always fine
+        assert!(options.allows(&swap(&a, &c)));
+    }
+
+    #[test]
+    fn test_validate_plan() {
+        let days = [
+            "2022-08-29T03:00:00+08:00",
+            "2022-08-30T03:00:00+08:00",
+            "2022-08-31T03:00:00+08:00",
+        ];
+        let schedule = vec![
+            test_entity("a@x.com", days[0], &[days[1]]),
+            test_entity("b@x.com", days[1], &days),
+            test_entity("c@x.com", days[2], &days),
+        ];
+        let swap = |x: usize, y: usize| {
+            let mut plan = schedule.clone();
+            plan[x] = schedule[x].moved_to(&schedule[y]);
+            plan[y] = schedule[y].moved_to(&schedule[x]);
+            plan
+        };
+        let options = SolverOptions::default();
+        assert!(validate_plan(&schedule, &swap(0, 1), &options).is_empty());
+
+        // a can't take c's slot, but the conflict a is left in by the original schedule is fine
+        let violations = validate_plan(&schedule, &swap(0, 2), &options);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].constraint, "availability");
+        assert!(validate_plan(&schedule, &schedule, &options).is_empty());
+        // unless conflicts may be left unresolved
+        let allow_unresolved = SolverOptions {
+            allow_unresolved: true,
+            ..SolverOptions::default()
+        };
+        assert!(validate_plan(&schedule, &swap(0, 2), &allow_unresolved).is_empty());
+        // and a soft conflict doesn't make anyone unavailable
+        let mut soft = swap(0, 2);
+        soft[0].soft_conflict_slots.push(test_slot(days[2]));
+        assert!(validate_plan(&schedule, &soft, &options).is_empty());
+
+        let blocked = SolverOptions {
+            blocked_swaps: vec![vec!["a@x.com".to_string(), "b@x.com".to_string()]],
+            ..SolverOptions::default()
+        };
+        let violations = validate_plan(&schedule, &swap(0, 1), &blocked);
+        assert!(violations.iter().all(|x| x.constraint == "blocked swaps"));
+        assert!(!violations.is_empty());
+    }
+}
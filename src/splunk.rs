@@ -0,0 +1,342 @@
+use crate::cache::{Cache, Namespace};
+use crate::calendar::local_time;
+use crate::http::HttpClient;
+use crate::oncall::{
+    AppliedOverride, FinalPagerDutySchedule, OncallProvider, SkippedUser, SkippedUsers,
+};
+use crate::provider::{check, error_kind, ProviderErrorKind};
+use crate::solver::FinalOverride;
+
+use anyhow::{anyhow, Context, Result as AnyhowResult};
+use chrono::{DateTime, FixedOffset};
+use chrono_tz::Tz;
+use reqwest::RequestBuilder;
+use serde::Deserialize;
+use serde_json::json;
+use tracing::instrument;
+
+#[derive(Deserialize, Debug)]
+struct PolicySchedule {
+    schedule: Vec<Rotation>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Rotation {
+    #[serde(default)]
+    rolls: Vec<Roll>,
+}
+
+/// One handover of a rotation, with the username of who is oncall until the next
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Roll {
+    change: String,
+    until: String,
+    on_call: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct User {
+    username: String,
+    email: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct UserList {
+    users: Vec<User>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct CreatedOverride {
+    public_id: String,
+}
+
+/// Root of the splunk on-call (formerly victorops) public api
+pub const SPLUNK_ONCALL_API_URL: &str = "https://api.victorops.com";
+
+/// Splunk On-Call, accessed with an api id and key. Schedules are escalation policies, passed by
+/// their slug, and users are known by username
+pub struct SplunkOncall<'a> {
+    pub client: &'a HttpClient,
+    /// SPLUNK_ONCALL_API_URL, unless the config file or --base-url point elsewhere
+    pub base_url: &'a str,
+    pub api_id: &'a str,
+    pub api_key: &'a str,
+    pub cache: &'a Cache,
+    /// of the schedule, slots are given the offset it has at their start
+    pub timezone: Tz,
+    /// users that couldn't be looked up, whose slots are left out
+    pub skipped: &'a SkippedUsers,
+}
+
+impl OncallProvider for SplunkOncall<'_> {
+    #[instrument(skip_all, fields(%schedule_id, %start, %end))]
+    async fn fetch_schedule(
+        &self,
+        schedule_id: &str,
+        start: DateTime<FixedOffset>,
+        end: DateTime<FixedOffset>,
+    ) -> AnyhowResult<Vec<FinalPagerDutySchedule>> {
+        say!(
+            "Retrieving splunk on-call schedule from {} to {}",
+            start,
+            end
+        );
+        // the schedule starts today, rolls before the window are dropped below
+        let days_forward = (end - local_time(chrono::Utc::now(), self.timezone))
+            .num_days()
+            .max(0)
+            + 1;
+        let url = format!(
+            "{}/api-public/v1/policies/{}/oncall/schedule?daysForward={}",
+            self.base_url, schedule_id, days_forward
+        );
+        let response_text = match self.cache.get(Namespace::Schedules, &url) {
+            Some(value) => value,
+            None => {
+                let response = self
+                    .client
+                    .send(self.authorized(self.client.get(&url)))
+                    .await
+                    .context("Failed to call splunk on-call api")?;
+                check("splunk on-call", &response)?;
+                let text = response
+                    .text()
+                    .await
+                    .context("Failed to get text response from splunk on-call api call")?;
+                self.cache.put(Namespace::Schedules, &url, &text);
+                text
+            }
+        };
+        let schedule: PolicySchedule = serde_json::from_str(&response_text)
+            .context("Failed to parse json from splunk on-call api response")?;
+
+        let mut entries = Vec::new();
+        for roll in schedule.schedule.into_iter().flat_map(|x| x.rolls) {
+            let entry_start = DateTime::parse_from_rfc3339(&roll.change)
+                .context("Failed to parse change as rfc3339")?;
+            let entry_end = DateTime::parse_from_rfc3339(&roll.until)
+                .context("Failed to parse until as rfc3339")?;
+            if entry_start >= end || entry_end <= start {
+                continue;
+            }
+            match self.resolve_user(&roll.on_call).await {
+                Ok(email) => entries.push(FinalPagerDutySchedule {
+                    pd_user_id: roll.on_call,
+                    start: local_time(entry_start, self.timezone),
+                    end: local_time(entry_end, self.timezone),
+                    email,
+                }),
+                Err(e) if error_kind(&e) == Some(ProviderErrorKind::RateLimited) => {
+                    return Err(e.context("Failed to look up the users of the splunk schedule"));
+                }
+                Err(e) => self.skipped.push(SkippedUser {
+                    user: roll.on_call,
+                    start: roll.change,
+                    end: roll.until,
+                    reason: format!("{:#}", e),
+                }),
+            }
+        }
+        entries.sort_by_key(|x| x.start);
+        Ok(entries)
+    }
+
+    /// Splunk overrides are made for the person handing over their shift, then assigned to the
+    /// one taking it on the escalation policy
+    #[instrument(skip_all, fields(%schedule_id, overrides = overrides.len()))]
+    async fn apply_overrides(
+        &self,
+        schedule_id: &str,
+        overrides: &[FinalOverride],
+    ) -> AnyhowResult<Vec<AppliedOverride>> {
+        let users = self.users().await?;
+        let mut applied = Vec::new();
+        for entry in overrides {
+            let Some(original) = users.iter().find(|x| x.email == entry.original_assignee) else {
+                say!(
+                    "Warning. No splunk on-call user has the email {}, skipping the override from {} to {}",
+                    entry.original_assignee,
+                    entry.start_time_iso,
+                    entry.end_time_iso
+                );
+                continue;
+            };
+            match self
+                .create_override(schedule_id, &original.username, entry)
+                .await
+            {
+                Ok(id) => applied.push(AppliedOverride {
+                    id,
+                    start: entry.start_time_iso.clone(),
+                    end: entry.end_time_iso.clone(),
+                    pd_user_id: entry.pd_user_id.clone(),
+                }),
+                Err(e)
+                    if matches!(
+                        error_kind(&e),
+                        Some(ProviderErrorKind::Unauthorized | ProviderErrorKind::RateLimited)
+                    ) =>
+                {
+                    return Err(e.context("Failed to override splunk on-call schedule"));
+                }
+                Err(e) => say!(
+                    "Warning. Splunk on-call rejected the override from {} to {}: {:#}",
+                    entry.start_time_iso,
+                    entry.end_time_iso,
+                    e
+                ),
+            }
+        }
+        Ok(applied)
+    }
+
+    #[instrument(skip(self))]
+    async fn remove_override(&self, _schedule_id: &str, override_id: &str) -> AnyhowResult<()> {
+        let request = self.authorized(self.client.delete(format!(
+            "{}/api-public/v1/overrides/{}",
+            self.base_url, override_id
+        )));
+        let response = self.client.send(request).await?;
+        check("splunk on-call", &response).context(format!(
+            "Failed to delete splunk on-call override {}",
+            override_id
+        ))
+    }
+
+    #[instrument(skip(self), fields(email))]
+    async fn resolve_user(&self, user_id: &str) -> AnyhowResult<String> {
+        let url = format!("{}/api-public/v1/user/{}", self.base_url, user_id);
+        if let Some(email) = self.cache.get(Namespace::UserEmails, &url) {
+            return Ok(email);
+        }
+        let response = self
+            .client
+            .send(self.authorized(self.client.get(&url)))
+            .await
+            .context("Failed to call splunk on-call api to get user email")?;
+        check("splunk on-call", &response)?;
+        let user: User = response
+            .json()
+            .await
+            .context("Failed to parse the splunk on-call user as json")?;
+        self.cache.put(Namespace::UserEmails, &url, &user.email);
+        tracing::Span::current().record("email", &user.email);
+        Ok(user.email)
+    }
+}
+
+impl SplunkOncall<'_> {
+    fn authorized(&self, request: RequestBuilder) -> RequestBuilder {
+        request
+            .header("X-VO-Api-Id", self.api_id)
+            .header("X-VO-Api-Key", self.api_key)
+    }
+
+    /// Whether the api key can read the escalation policy
+    #[instrument(skip(self))]
+    pub async fn check_schedule(&self, schedule_id: &str) -> AnyhowResult<()> {
+        let request = self.authorized(self.client.get(format!(
+            "{}/api-public/v1/policies/{}/oncall/schedule?daysForward=1",
+            self.base_url, schedule_id
+        )));
+        let response = self
+            .client
+            .send(request)
+            .await
+            .context("Failed to call splunk on-call api")?;
+        check("splunk on-call", &response)?;
+        Ok(())
+    }
+
+    /// Every user of the organisation, to find the username of an email
+    async fn users(&self) -> AnyhowResult<Vec<User>> {
+        let request = self.authorized(
+            self.client
+                .get(format!("{}/api-public/v1/user", self.base_url)),
+        );
+        let response = self
+            .client
+            .send(request)
+            .await
+            .context("Failed to call splunk on-call api to list users")?;
+        check("splunk on-call", &response)?;
+        let list: UserList = response
+            .json()
+            .await
+            .context("Failed to parse the splunk on-call users as json")?;
+        Ok(list.users)
+    }
+
+    /// Create an override of `original`'s shifts and assign it to the `final_override` person on
+    /// the policy, returning its public id
+    async fn create_override(
+        &self,
+        schedule_id: &str,
+        original: &str,
+        entry: &FinalOverride,
+    ) -> AnyhowResult<String> {
+        let request = self
+            .authorized(
+                self.client
+                    .post(format!("{}/api-public/v1/overrides", self.base_url)),
+            )
+            .json(&json!({
+                "username": original,
+                "timezone": "Etc/UTC",
+                "start": entry.start_time_iso,
+                "end": entry.end_time_iso,
+            }));
+        let response = self.client.send(request).await?;
+        check("splunk on-call", &response)?;
+        let created: CreatedOverride = response
+            .json()
+            .await
+            .context("Failed to parse the override created by splunk on-call")?;
+
+        let request = self
+            .authorized(self.client.put(format!(
+                "{}/api-public/v1/overrides/{}/assignments/{}",
+                self.base_url, created.public_id, schedule_id
+            )))
+            .json(&json!({ "username": entry.pd_user_id }));
+        let assigned = match self.client.send(request).await {
+            Ok(response) => check("splunk on-call", &response).map_err(|e| anyhow!(e)),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = assigned {
+            // an unassigned override leaves nobody oncall, so it is taken back
+            if let Err(removed) = self.remove_override(schedule_id, &created.public_id).await {
+                say!("Warning. {:#}", removed);
+            }
+            return Err(e.context(format!(
+                "Failed to assign splunk on-call override {}",
+                created.public_id
+            )));
+        }
+        Ok(created.public_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_policy_schedule() -> AnyhowResult<()> {
+        let schedule: PolicySchedule = serde_json::from_str(
+            r#"{"policy": {"name": "Primary", "slug": "pol-primary"}, "schedule": [
+                {"onCall": "alice", "rotationName": "Daily", "rolls": [
+                    {"change": "2022-08-28T19:00:00Z", "until": "2022-08-29T19:00:00Z",
+                     "onCall": "alice", "isRoll": true}
+                ]},
+                {"onCall": "bob", "rotationName": "Empty"}
+            ], "overrides": []}"#,
+        )?;
+        assert_eq!(schedule.schedule.len(), 2);
+        assert!(schedule.schedule[1].rolls.is_empty());
+        assert_eq!(schedule.schedule[0].rolls[0].on_call, "alice");
+        Ok(())
+    }
+}
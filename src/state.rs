@@ -0,0 +1,319 @@
+use crate::oncall::AppliedOverride;
+use crate::saved_plan::SavedPlan;
+use crate::solver::FinalOverride;
+use anyhow::{Context, Result as AnyhowResult};
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection};
+use std::path::Path;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    started_at TEXT NOT NULL,
+    command_line TEXT NOT NULL,
+    schedule_id TEXT NOT NULL,
+    window_start TEXT NOT NULL,
+    window_end TEXT NOT NULL,
+    seed INTEGER NOT NULL,
+    plan TEXT NOT NULL,
+    outcome TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS applied_overrides (
+    override_id TEXT NOT NULL,
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    schedule_id TEXT NOT NULL,
+    start TEXT NOT NULL,
+    end TEXT NOT NULL,
+    pd_user_id TEXT NOT NULL,
+    undone_at TEXT,
+    PRIMARY KEY (schedule_id, override_id)
+);
+";
+
+/// How a run ended
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RunOutcome {
+    /// solved, not applied (yet)
+    Planned,
+    Applied,
+    /// some overrides were rejected or failed to apply
+    PartiallyApplied,
+    Declined,
+    Failed,
+    Undone,
+}
+
+impl RunOutcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            RunOutcome::Planned => "planned",
+            RunOutcome::Applied => "applied",
+            RunOutcome::PartiallyApplied => "partially applied",
+            RunOutcome::Declined => "declined",
+            RunOutcome::Failed => "failed",
+            RunOutcome::Undone => "undone",
+        }
+    }
+}
+
+/// The inputs of a run and the plan it computed
+pub struct NewRun<'a> {
+    pub command_line: String,
+    pub schedule_id: &'a str,
+    pub window_start: String,
+    pub window_end: String,
+    pub seed: u64,
+    pub plan: &'a SavedPlan,
+}
+
+/// A run as recorded in the store
+#[derive(Debug)]
+pub struct StoredRun {
+    pub id: i64,
+    pub started_at: String,
+    pub schedule_id: String,
+    pub window_start: String,
+    pub window_end: String,
+    pub seed: u64,
+    pub outcome: String,
+    /// overrides of the run still in place
+    pub active_overrides: usize,
+}
+
+/// Runs and the overrides they applied, kept in a local sqlite database so any shell can tell
+/// what was already applied, and undo it
+pub struct StateStore {
+    conn: Connection,
+}
+
+impl StateStore {
+    pub fn open(path: &Path) -> AnyhowResult<StateStore> {
+        let conn = Connection::open(path)
+            .context(format!("Failed to open state database {}", path.display()))?;
+        StateStore::with_connection(conn)
+    }
+
+    /// Where the database is kept, for messages
+    pub fn location(&self) -> String {
+        match self.conn.path() {
+            Some(path) if !path.as_os_str().is_empty() => path.display().to_string(),
+            _ => "memory".to_string(),
+        }
+    }
+
+    /// A store that is gone once the run ends
+    pub fn in_memory() -> AnyhowResult<StateStore> {
+        StateStore::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(conn: Connection) -> AnyhowResult<StateStore> {
+        conn.execute_batch(SCHEMA)
+            .context("Failed to create the state database tables")?;
+        Ok(StateStore { conn })
+    }
+
+    /// Record a run with the plan it computed, returning its id
+    pub fn start_run(&self, run: &NewRun) -> AnyhowResult<i64> {
+        let plan = serde_json::to_string(run.plan).context("Failed to serialise plan")?;
+        self.conn
+            .execute(
+                "INSERT INTO runs (started_at, command_line, schedule_id, window_start, window_end, seed, plan, outcome)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    Utc::now().to_rfc3339(),
+                    run.command_line,
+                    run.schedule_id,
+                    run.window_start,
+                    run.window_end,
+                    run.seed as i64,
+                    plan,
+                    RunOutcome::Planned.as_str(),
+                ],
+            )
+            .context("Failed to record run")?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    pub fn set_outcome(&self, run_id: i64, outcome: RunOutcome) -> AnyhowResult<()> {
+        self.conn
+            .execute(
+                "UPDATE runs SET outcome = ?1 WHERE id = ?2",
+                params![outcome.as_str(), run_id],
+            )
+            .context(format!("Failed to update outcome of run {}", run_id))?;
+        Ok(())
+    }
+
+    pub fn record_applied(
+        &self,
+        run_id: i64,
+        schedule_id: &str,
+        applied: &[AppliedOverride],
+    ) -> AnyhowResult<()> {
+        for entry in applied {
+            self.conn
+                .execute(
+                    "INSERT INTO applied_overrides (override_id, run_id, schedule_id, start, end, pd_user_id)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    params![
+                        entry.id,
+                        run_id,
+                        schedule_id,
+                        entry.start,
+                        entry.end,
+                        entry.pd_user_id
+                    ],
+                )
+                .context(format!("Failed to record override {}", entry.id))?;
+        }
+        Ok(())
+    }
+
+    /// Id of the run that already applied this exact override, if it is still in place. The times
+    /// recorded are those pd echoed back, so they are compared as instants
+    pub fn applied_by(
+        &self,
+        schedule_id: &str,
+        entry: &FinalOverride,
+    ) -> AnyhowResult<Option<i64>> {
+        let mut statement = self.conn.prepare(
+            "SELECT run_id, start, end FROM applied_overrides
+             WHERE schedule_id = ?1 AND pd_user_id = ?2 AND undone_at IS NULL",
+        )?;
+        let rows = statement
+            .query_map(params![schedule_id, entry.pd_user_id], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()
+            .context("Failed to look up applied overrides")?;
+        Ok(rows.into_iter().find_map(|(run_id, start, end)| {
+            (same_instant(&start, &entry.start_time_iso) && same_instant(&end, &entry.end_time_iso))
+                .then_some(run_id)
+        }))
+    }
+
+    /// Overrides of the run still in place, with the id of their schedule
+    pub fn active_overrides(&self, run_id: i64) -> AnyhowResult<Vec<(String, AppliedOverride)>> {
+        let mut statement = self.conn.prepare(
+            "SELECT schedule_id, override_id, start, end, pd_user_id FROM applied_overrides
+             WHERE run_id = ?1 AND undone_at IS NULL",
+        )?;
+        let rows = statement.query_map(params![run_id], |row| {
+            Ok((
+                row.get(0)?,
+                AppliedOverride {
+                    id: row.get(1)?,
+                    start: row.get(2)?,
+                    end: row.get(3)?,
+                    pd_user_id: row.get(4)?,
+                },
+            ))
+        })?;
+        rows.collect::<Result<_, _>>()
+            .context(format!("Failed to read overrides of run {}", run_id))
+    }
+
+    pub fn mark_undone(&self, schedule_id: &str, override_id: &str) -> AnyhowResult<()> {
+        self.conn
+            .execute(
+                "UPDATE applied_overrides SET undone_at = ?1 WHERE schedule_id = ?2 AND override_id = ?3",
+                params![Utc::now().to_rfc3339(), schedule_id, override_id],
+            )
+            .context(format!("Failed to mark override {} as undone", override_id))?;
+        Ok(())
+    }
+
+    /// The most recent runs, newest first
+    pub fn runs(&self, limit: usize) -> AnyhowResult<Vec<StoredRun>> {
+        let mut statement = self.conn.prepare(
+            "SELECT id, started_at, schedule_id, window_start, window_end, seed, outcome,
+                 (SELECT COUNT(*) FROM applied_overrides a WHERE a.run_id = runs.id AND a.undone_at IS NULL)
+             FROM runs ORDER BY id DESC LIMIT ?1",
+        )?;
+        let rows = statement.query_map(params![limit as i64], |row| {
+            Ok(StoredRun {
+                id: row.get(0)?,
+                started_at: row.get(1)?,
+                schedule_id: row.get(2)?,
+                window_start: row.get(3)?,
+                window_end: row.get(4)?,
+                seed: row.get::<_, i64>(5)? as u64,
+                outcome: row.get(6)?,
+                active_overrides: row.get(7)?,
+            })
+        })?;
+        rows.collect::<Result<_, _>>()
+            .context("Failed to read past runs")
+    }
+}
+
+/// Whether two RFC 3339 times are the same instant, whatever their offset or precision
+pub fn same_instant(a: &str, b: &str) -> bool {
+    match (
+        DateTime::parse_from_rfc3339(a),
+        DateTime::parse_from_rfc3339(b),
+    ) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_store() -> AnyhowResult<()> {
+        let store = StateStore::with_connection(Connection::open_in_memory()?)?;
+        let plan = SavedPlan {
+            seed: u64::MAX,
+            slots: Vec::new(),
+        };
+        let run_id = store.start_run(&NewRun {
+            command_line: "gcal-pagerduty --pd-schedule P1".to_string(),
+            schedule_id: "P1",
+            window_start: "2022-08-29T00:00:00+08:00".to_string(),
+            window_end: "2022-09-02T00:00:00+08:00".to_string(),
+            seed: u64::MAX,
+            plan: &plan,
+        })?;
+        let entry = FinalOverride {
+            original_slot: "".to_string(),
+            original_assignee: "a@x.com".to_string(),
+            final_override: "b@x.com".to_string(),
+            start_time_iso: "2022-08-29T03:00:00+08:00".to_string(),
+            end_time_iso: "2022-08-30T03:00:00+08:00".to_string(),
+            pd_user_id: "PB".to_string(),
+        };
+        assert_eq!(store.applied_by("P1", &entry)?, None);
+
+        // pd echoes the override back in UTC
+        let applied = AppliedOverride {
+            id: "Q1".to_string(),
+            start: "2022-08-28T19:00:00.000Z".to_string(),
+            end: "2022-08-29T19:00:00Z".to_string(),
+            pd_user_id: entry.pd_user_id.clone(),
+        };
+        store.record_applied(run_id, "P1", std::slice::from_ref(&applied))?;
+        store.set_outcome(run_id, RunOutcome::Applied)?;
+        assert_eq!(store.applied_by("P1", &entry)?, Some(run_id));
+        assert_eq!(store.applied_by("P2", &entry)?, None);
+        assert_eq!(
+            store.active_overrides(run_id)?,
+            vec![("P1".to_string(), applied)]
+        );
+        let runs = store.runs(10)?;
+        assert_eq!(runs[0].outcome, "applied");
+        assert_eq!(runs[0].seed, u64::MAX);
+        assert_eq!(runs[0].active_overrides, 1);
+
+        store.mark_undone("P1", "Q1")?;
+        assert_eq!(store.applied_by("P1", &entry)?, None);
+        assert!(store.active_overrides(run_id)?.is_empty());
+        Ok(())
+    }
+}
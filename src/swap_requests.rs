@@ -0,0 +1,255 @@
+use crate::solver::FinalEntity;
+use anyhow::{anyhow, Result as AnyhowResult};
+use chrono::{DateTime, FixedOffset, NaiveDate};
+use std::collections::BTreeMap;
+use tabled::Tabled;
+
+/// Someone asking to give their slot away, e.g. through a google form
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SwapRequest {
+    /// row of the sheet, to point people at their request
+    pub row: usize,
+    /// who gives the slot away
+    pub email: String,
+    /// the slot given away, as its date or a time within it
+    pub slot: String,
+    /// who takes the slot
+    pub taken_by: String,
+    /// a slot of `taken_by` taken in return, making it a swap rather than a give away
+    pub in_return: Option<String>,
+}
+
+/// A swap request checked against the schedule and both parties' calendars
+#[derive(Tabled, Debug)]
+pub struct ReviewedSwap {
+    pub row: usize,
+    pub from: String,
+    pub to: String,
+    pub slot: String,
+    pub in_return: String,
+    pub outcome: String,
+}
+
+/// Rows of email address, slot, taken by and an optional in return, found by the names of the
+/// header row like the questions of a google form. Slots are written as YYYY-MM-DD or rfc3339
+pub fn parse_swap_requests(rows: &[Vec<String>]) -> AnyhowResult<Vec<SwapRequest>> {
+    let Some((header, rows)) = rows.split_first() else {
+        return Ok(Vec::new());
+    };
+    let column = |name: &str| {
+        header
+            .iter()
+            .position(|x| x.trim().eq_ignore_ascii_case(name))
+            .ok_or_else(|| anyhow!("No {} column in the header", name))
+    };
+    let (email, slot, taken_by) = (
+        column("email address")?,
+        column("slot")?,
+        column("taken by")?,
+    );
+    let in_return = column("in return").ok();
+    let mut requests = Vec::new();
+    for (i, row) in rows.iter().enumerate() {
+        let cell = |column: usize| row.get(column).map(|x| x.trim()).unwrap_or("");
+        if row.iter().all(|x| x.trim().is_empty()) {
+            continue;
+        }
+        requests.push(SwapRequest {
+            // the header is row 1
+            row: i + 2,
+            email: cell(email).to_lowercase(),
+            slot: cell(slot).to_string(),
+            taken_by: cell(taken_by).to_lowercase(),
+            in_return: in_return
+                .map(cell)
+                .filter(|x| !x.is_empty())
+                .map(|x| x.to_string()),
+        });
+    }
+    Ok(requests)
+}
+
+/// The slot `email` holds on the date `value`, or that contains the time `value`
+fn find_slot<'a>(
+    schedule: &'a [FinalEntity],
+    email: &str,
+    value: &str,
+) -> Result<&'a FinalEntity, String> {
+    let held = schedule.iter().filter(|x| x.pd_schedule.email == email);
+    let found: Vec<&FinalEntity> = match NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        Ok(date) => held
+            .filter(|x| x.pd_schedule.start.date_naive() == date)
+            .collect(),
+        Err(_) => {
+            let time = DateTime::parse_from_rfc3339(value).map_err(|_| {
+                format!(
+                    "invalid slot {}, expected YYYY-MM-DD or an rfc3339 time",
+                    value
+                )
+            })?;
+            held.filter(|x| x.pd_schedule.start <= time && time < x.pd_schedule.end)
+                .collect()
+        }
+    };
+    match found.as_slice() {
+        [entity] => Ok(entity),
+        [] => Err(format!("{} isn't oncall on {}", email, value)),
+        _ => Err(format!(
+            "{} has several slots on {}, give the start time",
+            email, value
+        )),
+    }
+}
+
+/// Why `request` can't be taken as is, or the slots it moves with their new holders
+fn check_request(
+    schedule: &[FinalEntity],
+    request: &SwapRequest,
+) -> Result<Vec<(DateTime<FixedOffset>, String)>, String> {
+    if request.email == request.taken_by {
+        return Err("gives the slot to the same person".to_string());
+    }
+    if !schedule
+        .iter()
+        .any(|x| x.pd_schedule.email == request.taken_by)
+    {
+        return Err(format!("{} isn't on the schedule", request.taken_by));
+    }
+    let free = |email: &str, slot: &FinalEntity| {
+        schedule.iter().any(|x| {
+            x.pd_schedule.email == email
+                && x.is_available_at(slot.pd_schedule.start, slot.pd_schedule.end)
+        })
+    };
+    let given = find_slot(schedule, &request.email, &request.slot)?;
+    let mut moves = vec![(given.pd_schedule.start, request.taken_by.clone())];
+    if !free(&request.taken_by, given) {
+        return Err(format!("{} is busy then", request.taken_by));
+    }
+    if let Some(value) = &request.in_return {
+        let taken = find_slot(schedule, &request.taken_by, value)?;
+        if !free(&request.email, taken) {
+            return Err(format!("{} is busy for the slot in return", request.email));
+        }
+        moves.push((taken.pd_schedule.start, request.email.clone()));
+    }
+    Ok(moves)
+}
+
+/// Check `requests` in order against `schedule`, whose availability comes from everyone's
+/// calendars. Returns the review of each request and the new holder of each slot moved by the
+/// accepted ones. A slot is only moved by the first request that asks for it
+pub fn review_swap_requests(
+    schedule: &[FinalEntity],
+    requests: &[SwapRequest],
+) -> (Vec<ReviewedSwap>, BTreeMap<DateTime<FixedOffset>, String>) {
+    let mut approved: BTreeMap<DateTime<FixedOffset>, String> = BTreeMap::new();
+    // row of the request that moved each slot
+    let mut moved_by: BTreeMap<DateTime<FixedOffset>, usize> = BTreeMap::new();
+    let mut reviews = Vec::new();
+    for request in requests {
+        let checked = check_request(schedule, request).and_then(|moves| {
+            match moves.iter().find_map(|(start, _)| moved_by.get(start)) {
+                Some(row) => Err(format!("the slot was already swapped on row {}", row)),
+                None => Ok(moves),
+            }
+        });
+        let outcome = match checked {
+            Ok(moves) => {
+                for (start, email) in moves {
+                    moved_by.insert(start, request.row);
+                    approved.insert(start, email);
+                }
+                "accepted".to_string()
+            }
+            Err(reason) => format!("rejected: {}", reason),
+        };
+        reviews.push(ReviewedSwap {
+            row: request.row,
+            from: request.email.clone(),
+            to: request.taken_by.clone(),
+            slot: request.slot.clone(),
+            in_return: request.in_return.clone().unwrap_or_default(),
+            outcome,
+        });
+    }
+    (reviews, approved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::tests::test_entity;
+
+    #[test]
+    fn test_review_swap_requests() -> AnyhowResult<()> {
+        let days = [
+            "2022-08-29T03:00:00+08:00",
+            "2022-08-30T03:00:00+08:00",
+            "2022-08-31T03:00:00+08:00",
+        ];
+        // c can't take the first slot
+        let schedule = vec![
+            test_entity("a@x.com", days[0], &days),
+            test_entity("b@x.com", days[1], &days),
+            test_entity("c@x.com", days[2], &days[1..]),
+        ];
+        let cells = |row: &[&str]| row.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        let requests = parse_swap_requests(&[
+            cells(&[
+                "Timestamp",
+                "Email Address",
+                "Slot",
+                "Taken by",
+                "In return",
+            ]),
+            cells(&[
+                "8/25/2022",
+                "A@x.com",
+                "2022-08-29",
+                "b@x.com",
+                "2022-08-30",
+            ]),
+            cells(&[
+                "8/25/2022",
+                "a@x.com",
+                "2022-08-29T12:00:00+08:00",
+                "c@x.com",
+            ]),
+            cells(&[]),
+            cells(&["8/26/2022", "b@x.com", "2022-08-30", "c@x.com"]),
+            cells(&["8/26/2022", "c@x.com", "2022-08-31", "z@x.com"]),
+            cells(&["8/26/2022", "c@x.com", "2022-09-01", "a@x.com"]),
+        ])?;
+        assert_eq!(requests.len(), 5);
+        assert_eq!(requests[0].email, "a@x.com");
+        assert_eq!(requests[2].row, 5);
+
+        let (reviews, approved) = review_swap_requests(&schedule, &requests);
+        let outcomes: Vec<&str> = reviews.iter().map(|x| x.outcome.as_str()).collect();
+        assert_eq!(
+            outcomes,
+            [
+                "accepted",
+                "rejected: c@x.com is busy then",
+                "rejected: the slot was already swapped on row 2",
+                "rejected: z@x.com isn't on the schedule",
+                "rejected: c@x.com isn't oncall on 2022-09-01",
+            ]
+        );
+        let approved: Vec<(String, &str)> = approved
+            .iter()
+            .map(|(start, email)| (start.to_rfc3339(), email.as_str()))
+            .collect();
+        assert_eq!(
+            approved,
+            [
+                (days[0].to_string(), "b@x.com"),
+                (days[1].to_string(), "a@x.com")
+            ]
+        );
+
+        assert!(parse_swap_requests(&[cells(&["Email Address", "Slot"])]).is_err());
+        Ok(())
+    }
+}
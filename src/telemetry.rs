@@ -0,0 +1,49 @@
+use anyhow::{Context, Result as AnyhowResult};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::trace::TracerProvider;
+use opentelemetry_sdk::{runtime, Resource};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+/// Exports the spans around every api call, with the user, schedule and latency of each, to an
+/// OTLP collector over http. Without one the spans go nowhere and cost next to nothing
+pub struct Telemetry {
+    provider: TracerProvider,
+}
+
+impl Telemetry {
+    /// Send spans to `endpoint`, e.g. http://localhost:4318/v1/traces
+    pub fn init(endpoint: &str) -> AnyhowResult<Telemetry> {
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint)
+            .build()
+            .context("Failed to build the OTLP exporter")?;
+        let provider = TracerProvider::builder()
+            .with_batch_exporter(exporter, runtime::Tokio)
+            .with_resource(Resource::new(vec![KeyValue::new(
+                "service.name",
+                env!("CARGO_PKG_NAME"),
+            )]))
+            .build();
+        tracing_subscriber::registry()
+            .with(
+                tracing_opentelemetry::layer().with_tracer(provider.tracer(env!("CARGO_PKG_NAME"))),
+            )
+            .try_init()
+            .context("Failed to set up tracing")?;
+        Ok(Telemetry { provider })
+    }
+
+    /// Send the spans still buffered, before the process exits
+    pub async fn shutdown(self) {
+        let provider = self.provider;
+        // flushing blocks until the exporter, running on the runtime, is done
+        let flushed = tokio::task::spawn_blocking(move || provider.shutdown()).await;
+        if let Ok(Err(e)) = flushed {
+            eprintln!("Warning. Failed to export traces: {}", e);
+        }
+    }
+}
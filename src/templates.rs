@@ -0,0 +1,111 @@
+use crate::config::TemplateFiles;
+use crate::solver::{FinalOverride, SimulatedSwap};
+use crate::watch::Conflict;
+use anyhow::{Context as _, Result as AnyhowResult};
+use serde::Serialize;
+use tera::{Context, Tera};
+
+pub const SLACK_PLAN: &str = "slack_plan";
+pub const EMAIL: &str = "email";
+pub const MARKDOWN: &str = "markdown";
+
+/// Used for --markdown-report when the config file has no markdown template of its own
+const DEFAULT_MARKDOWN: &str = include_str!("../templates/report.md.tera");
+
+/// What a plan report is rendered from. Times are rfc3339 strings
+#[derive(Serialize)]
+pub struct PlanReport<'a> {
+    pub schedule_id: &'a str,
+    pub run_id: i64,
+    pub conflicts: &'a [Conflict],
+    /// in the order the solver made them, each on top of the ones before
+    pub swaps: &'a [SimulatedSwap],
+    pub overrides: &'a [FinalOverride],
+    /// changes from the plan given with --previous-plan, empty without one
+    pub diff: &'a [FinalOverride],
+}
+
+/// The Tera templates of the `[templates]` section of the config file. Reports without a template
+/// keep their built-in wording
+pub struct Templates {
+    tera: Tera,
+}
+
+impl Templates {
+    pub fn load(files: &TemplateFiles) -> AnyhowResult<Templates> {
+        let mut tera = Tera::default();
+        tera.add_raw_template(MARKDOWN, DEFAULT_MARKDOWN)
+            .context("Failed to parse the built-in markdown template")?;
+        for (name, path) in [
+            (SLACK_PLAN, &files.slack_plan),
+            (EMAIL, &files.email),
+            (MARKDOWN, &files.markdown),
+        ] {
+            if let Some(path) = path {
+                tera.add_template_file(path, Some(name)).context(format!(
+                    "Failed to load the {} template {}",
+                    name,
+                    path.display()
+                ))?;
+            }
+        }
+        Ok(Templates { tera })
+    }
+
+    /// `context` rendered with the template `name`, or None when there is no such template
+    pub fn render(&self, name: &str, context: &impl Serialize) -> Option<AnyhowResult<String>> {
+        self.tera.get_template_names().find(|x| *x == name)?;
+        Some(
+            Context::from_serialize(context)
+                .and_then(|context| self.tera.render(name, &context))
+                .context(format!("Failed to render the {} template", name)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+
+    #[test]
+    fn test_default_markdown() -> AnyhowResult<()> {
+        let conflicts = [Conflict {
+            email: "a@x.com".to_string(),
+            start: DateTime::parse_from_rfc3339("2022-08-29T03:00:00+08:00")?,
+            end: DateTime::parse_from_rfc3339("2022-08-29T15:00:00+08:00")?,
+            soft: false,
+            reasons: vec!["Out of office".to_string()],
+        }];
+        let overrides = [FinalOverride {
+            original_slot: "".to_string(),
+            original_assignee: "a@x.com".to_string(),
+            final_override: "b@x.com".to_string(),
+            start_time_iso: "2022-08-29T03:00:00+08:00".to_string(),
+            end_time_iso: "2022-08-29T15:00:00+08:00".to_string(),
+            pd_user_id: "PB".to_string(),
+        }];
+        let report = PlanReport {
+            schedule_id: "P1",
+            run_id: 3,
+            conflicts: &conflicts,
+            swaps: &[],
+            overrides: &overrides,
+            diff: &[],
+        };
+        let templates = Templates::load(&TemplateFiles::default())?;
+        let markdown = templates.render(MARKDOWN, &report).unwrap()?;
+        assert!(
+            markdown.starts_with("# Plan for schedule P1 (run 3)\n\n1 conflicts, 1 overrides.\n")
+        );
+        assert!(markdown.contains(
+            "| a@x.com | 2022-08-29T03:00:00+08:00 | 2022-08-29T15:00:00+08:00 | Out of office |\n"
+        ));
+        assert!(markdown.contains(
+            "| 2022-08-29T03:00:00+08:00 | 2022-08-29T15:00:00+08:00 | a@x.com | b@x.com |\n"
+        ));
+        assert!(!markdown.contains("## Swaps"));
+        assert!(templates.render(SLACK_PLAN, &report).is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,105 @@
+use crate::solver::{has_conflicts, BusyInterval, FinalEntity};
+use chrono::{DateTime, FixedOffset};
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+/// A slot whose holder is busy for it
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Conflict {
+    pub email: String,
+    pub start: DateTime<FixedOffset>,
+    pub end: DateTime<FixedOffset>,
+    /// only soft conflicts (meetings matching soft_conflict_keywords) overlap the slot
+    pub soft: bool,
+    /// summaries of the events overlapping the slot
+    pub reasons: Vec<String>,
+}
+
+pub fn find_conflicts(entities: &[FinalEntity]) -> Vec<Conflict> {
+    entities
+        .iter()
+        .filter(|x| has_conflicts(&x.pd_schedule, &x.available_slots))
+        .map(|entity| {
+            let slot = &entity.pd_schedule;
+            let soft = entity
+                .soft_conflict_slots
+                .iter()
+                .any(|x| x.covers(slot.start, slot.end));
+            let busy: &[BusyInterval] = if soft {
+                &entity.soft_busy
+            } else {
+                &entity.busy
+            };
+            Conflict {
+                email: slot.email.clone(),
+                start: slot.start,
+                end: slot.end,
+                soft,
+                reasons: busy
+                    .iter()
+                    .filter(|x| x.overlaps(slot.start, slot.end))
+                    .map(|x| x.summary.clone())
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+/// Conflicts found by the previous check, so each is only reported once
+#[derive(Debug, Default)]
+pub struct ConflictWatch {
+    seen: BTreeSet<(String, DateTime<FixedOffset>)>,
+}
+
+impl ConflictWatch {
+    /// Conflicts that weren't there on the previous check. Ones that went away are forgotten, and
+    /// reported again if they come back
+    pub fn update(&mut self, conflicts: Vec<Conflict>) -> Vec<Conflict> {
+        let current = conflicts
+            .iter()
+            .map(|x| (x.email.clone(), x.start))
+            .collect();
+        let new_conflicts = conflicts
+            .into_iter()
+            .filter(|x| !self.seen.contains(&(x.email.clone(), x.start)))
+            .collect();
+        self.seen = current;
+        new_conflicts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::tests::test_entity;
+
+    #[test]
+    fn test_conflict_watch() {
+        let days = ["2022-08-29T03:00:00+08:00", "2022-08-30T03:00:00+08:00"];
+        let mut a = test_entity("a@x.com", days[0], &days[1..]);
+        a.busy.push(BusyInterval {
+            summary: "OOO".to_string(),
+            start: a.pd_schedule.start,
+            end: a.pd_schedule.end,
+        });
+        let b = test_entity("b@x.com", days[1], &days);
+        let mut watch = ConflictWatch::default();
+
+        let first = watch.update(find_conflicts(&[a.clone(), b.clone()]));
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].email, "a@x.com");
+        assert_eq!(first[0].reasons, vec!["OOO"]);
+        assert!(!first[0].soft);
+        assert!(watch
+            .update(find_conflicts(&[a.clone(), b.clone()]))
+            .is_empty());
+
+        // resolved, then back again
+        let mut resolved = a.clone();
+        resolved.available_slots = b.available_slots.clone();
+        assert!(watch
+            .update(find_conflicts(&[resolved, b.clone()]))
+            .is_empty());
+        assert_eq!(watch.update(find_conflicts(&[a, b])).len(), 1);
+    }
+}
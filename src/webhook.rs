@@ -0,0 +1,143 @@
+use crate::http::HttpClient;
+use crate::solver::FinalOverride;
+use crate::watch::Conflict;
+use anyhow::{anyhow, Result as AnyhowResult};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value};
+use sha2::Sha256;
+
+/// Header carrying the hex HMAC-SHA256 of the body, keyed with the webhook secret
+pub const SIGNATURE_HEADER: &str = "X-Gcal-Pagerduty-Signature";
+
+/// Posts a signed json description of what a run found and did to the `[webhook]` url of the
+/// config file, for audit pipelines or chatops to react to. Like slack, failing to deliver is only
+/// a warning
+pub struct WebhookNotifier<'a> {
+    pub client: &'a HttpClient,
+    pub url: Option<String>,
+    /// from WEBHOOK_SECRET. Without one, requests are sent unsigned
+    pub secret: Option<String>,
+}
+
+impl WebhookNotifier<'_> {
+    async fn post(&self, event: &str, mut payload: Value) {
+        let Some(url) = &self.url else {
+            return;
+        };
+        payload["event"] = json!(event);
+        payload["timestamp"] = json!(Utc::now().to_rfc3339());
+        let body = payload.to_string();
+        let mut request = self
+            .client
+            .post(url)
+            .header("Content-Type", "application/json");
+        if let Some(secret) = &self.secret {
+            request = request.header(SIGNATURE_HEADER, format!("sha256={}", sign(secret, &body)));
+        }
+        let result = match self.client.send(request.body(body)).await {
+            Ok(response) if response.status().is_success() => Ok(()),
+            Ok(response) => Err(anyhow!("Unexpected status {}", response.status())),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            say!("Warning. Failed to deliver the {} webhook: {:#}", event, e);
+        }
+    }
+
+    /// Conflicts found by --check
+    pub async fn conflicts_found(&self, schedule_id: &str, conflicts: &[Conflict]) {
+        self.post(
+            "conflicts_found",
+            json!({
+                "schedule_id": schedule_id,
+                "conflicts": conflicts.iter().map(conflict_json).collect::<Vec<_>>(),
+            }),
+        )
+        .await
+    }
+
+    pub async fn plan_ready(
+        &self,
+        schedule_id: &str,
+        run_id: i64,
+        conflicts: &[Conflict],
+        overrides: &[FinalOverride],
+    ) {
+        self.post(
+            "plan_ready",
+            json!({
+                "schedule_id": schedule_id,
+                "run_id": run_id,
+                "conflicts": conflicts.iter().map(conflict_json).collect::<Vec<_>>(),
+                "overrides": overrides.iter().map(override_json).collect::<Vec<_>>(),
+            }),
+        )
+        .await
+    }
+
+    pub async fn applied(
+        &self,
+        schedules: &[(&str, &[FinalOverride])],
+        run_id: i64,
+        result: &AnyhowResult<()>,
+    ) {
+        self.post(
+            "applied",
+            json!({
+                "run_id": run_id,
+                "schedules": schedules
+                    .iter()
+                    .map(|(schedule_id, overrides)| json!({
+                        "schedule_id": schedule_id,
+                        "overrides": overrides.iter().map(override_json).collect::<Vec<_>>(),
+                    }))
+                    .collect::<Vec<_>>(),
+                "error": result.as_ref().err().map(|e| format!("{:#}", e)),
+            }),
+        )
+        .await
+    }
+}
+
+/// Hex HMAC-SHA256 of `body`, for receivers to check the request came from a holder of `secret`
+pub fn sign(secret: &str, body: &str) -> String {
+    // HMAC takes keys of any length
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(body.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+fn conflict_json(conflict: &Conflict) -> Value {
+    json!({
+        "email": conflict.email,
+        "start": conflict.start.to_rfc3339(),
+        "end": conflict.end.to_rfc3339(),
+        "soft": conflict.soft,
+        "reasons": conflict.reasons,
+    })
+}
+
+fn override_json(entry: &FinalOverride) -> Value {
+    json!({
+        "start": entry.start_time_iso,
+        "end": entry.end_time_iso,
+        "original_assignee": entry.original_assignee,
+        "final_override": entry.final_override,
+        "pd_user_id": entry.pd_user_id,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            sign("Jefe", "what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
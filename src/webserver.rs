@@ -13,7 +13,7 @@ pub struct AppState {
 // Have to use a channel to pass the response back to main thread
 // oneshot channel?
 pub async fn start_webserver(sender: Sender<Callback>) -> actix_web::dev::Server {
-    println!("Starting local callback webserver");
+    say!("Starting local callback webserver");
 
     let server = HttpServer::new(move || {
         let app_state = Data::new(AppState {
@@ -31,6 +31,7 @@ pub struct Callback {
 }
 
 #[get("/oauth_callback")]
+#[allow(clippy::needless_return, clippy::to_string_in_format_args)]
 async fn oauth_callback(req_body: web::Query<Callback>, app_state: web::Data<AppState>) -> String {
     let sender = &app_state.sender_channel;
     match sender.send(req_body.into_inner()).await {
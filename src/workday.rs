@@ -0,0 +1,125 @@
+use crate::availability::{unavailable_range, DeclaredAvailability};
+use crate::config::WorkdayConfig;
+use crate::http::HttpClient;
+use anyhow::{anyhow, ensure, Context, Result as AnyhowResult};
+use chrono_tz::Tz;
+use serde_json::Value;
+use std::collections::HashMap;
+use tracing::instrument;
+
+/// Absences from a Workday custom report, read as json through Reports as a Service (RaaS), for
+/// companies whose leave is only in Workday
+pub struct Workday<'a> {
+    pub client: &'a HttpClient,
+    pub config: &'a WorkdayConfig,
+    /// of the integration system user in `config`, from WORKDAY_PASSWORD
+    pub password: &'a str,
+}
+
+impl Workday<'_> {
+    /// Absences in the report, by email. Their dates are whole days in `timezone`
+    #[instrument(skip(self))]
+    pub async fn absences(
+        &self,
+        timezone: Tz,
+    ) -> AnyhowResult<HashMap<String, DeclaredAvailability>> {
+        let request = self
+            .client
+            .get(&self.config.report_url)
+            .basic_auth(&self.config.username, Some(self.password));
+        let response = self.client.send(request).await?;
+        ensure!(
+            response.status().is_success(),
+            "Unexpected status {} from the workday report",
+            response.status()
+        );
+        let report: Value = response
+            .json()
+            .await
+            .context("Failed to parse the workday report as json, is format=json in its url?")?;
+        parse_report(&report, self.config, timezone).context("Failed to read the workday report")
+    }
+}
+
+/// Rows of the report are under Report_Entry, with columns named as in the config file
+fn parse_report(
+    report: &Value,
+    config: &WorkdayConfig,
+    timezone: Tz,
+) -> AnyhowResult<HashMap<String, DeclaredAvailability>> {
+    let rows = report["Report_Entry"]
+        .as_array()
+        .ok_or_else(|| anyhow!("Expected a Report_Entry array"))?;
+    let mut absences: HashMap<String, DeclaredAvailability> = HashMap::new();
+    for (i, row) in rows.iter().enumerate() {
+        let field = |name: &str| {
+            row[name]
+                .as_str()
+                .ok_or_else(|| anyhow!("Row {} has no {}", i + 1, name))
+        };
+        let email = field(&config.email_field)?;
+        let reason = row[&config.reason_field]
+            .as_str()
+            .map(|x| format!("{} in workday", x.to_lowercase()))
+            .unwrap_or_else(|| "absence in workday".to_string());
+        let busy = unavailable_range(
+            date(field(&config.start_field)?),
+            date(field(&config.end_field)?),
+            &reason,
+            timezone,
+        )
+        .context(format!("Invalid absence on row {}", i + 1))?;
+        absences
+            .entry(email.to_string())
+            .or_default()
+            .unavailable
+            .push(busy);
+    }
+    Ok(absences)
+}
+
+/// Workday writes dates with the tenant's offset, e.g. 2022-09-01-07:00, which is the day alone
+fn date(value: &str) -> &str {
+    match value.get(..10) {
+        Some(day) if value.len() > 10 && (value[10..].starts_with(['-', '+', 'Z'])) => day,
+        _ => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono_tz::Asia::Singapore;
+
+    #[test]
+    fn test_parse_report() -> AnyhowResult<()> {
+        let config: WorkdayConfig = toml::from_str(
+            r#"
+            report_url = "https://workday/report"
+            username = "ISU"
+            email_field = "Work_Email"
+            "#,
+        )?;
+        let report: Value = serde_json::from_str(
+            r#"{"Report_Entry": [
+                {"Work_Email": "a@x.com", "Start_Date": "2022-08-30-07:00",
+                 "End_Date": "2022-08-31-07:00", "Absence_Type": "Sick Leave"},
+                {"Work_Email": "b@x.com", "Start_Date": "2022-09-01", "End_Date": "2022-09-01"}
+            ]}"#,
+        )?;
+        let absences = parse_report(&report, &config, Singapore)?;
+        let busy = &absences["a@x.com"].unavailable[0];
+        assert_eq!(busy.summary, "sick leave in workday");
+        assert_eq!(busy.start.to_rfc3339(), "2022-08-30T00:00:00+08:00");
+        assert_eq!(busy.end.to_rfc3339(), "2022-09-01T00:00:00+08:00");
+        assert_eq!(
+            absences["b@x.com"].unavailable[0].summary,
+            "absence in workday"
+        );
+
+        let missing: Value =
+            serde_json::from_str(r#"{"Report_Entry": [{"Work_Email": "a@x.com"}]}"#)?;
+        assert!(parse_report(&missing, &config, Singapore).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,54 @@
+mod common;
+
+use common::cli::plan_with_config;
+
+#[actix_web::test]
+async fn test_audit_record_uploaded_after_apply() {
+    let (fixtures, stdout) = plan_with_config(
+        "audit-log",
+        |port| {
+            format!(
+                "[audit_log]\nurl = \"s3://audit-log/oncall\"\nendpoint = \"http://127.0.0.1:{}\"\n",
+                port
+            )
+        },
+        &[
+            ("AUDIT_LOG_ACCESS_KEY_ID", "AKIDFIXTURE"),
+            ("AUDIT_LOG_SECRET_ACCESS_KEY", "fixture"),
+            ("GCAL_PAGERDUTY_OPERATOR", "alice@example.com"),
+        ],
+        b"y\n",
+    )
+    .await;
+
+    let objects = fixtures.audit_log.lock().unwrap();
+    assert_eq!(objects.len(), 1);
+    let (key, record) = &objects[0];
+    assert!(
+        key.starts_with("oncall/year=") && key.contains("/PPRIMARY-run-1-"),
+        "{}",
+        key
+    );
+    assert!(
+        stdout.contains(&format!(
+            "Recorded the run in the audit log as s3://audit-log/{}",
+            key
+        )),
+        "{}",
+        stdout
+    );
+    assert_eq!(record["operator"], "alice@example.com");
+    assert_eq!(record["outcome"], "applied");
+    assert_eq!(record["inputs"]["seed"], 1);
+    assert_eq!(
+        record["schedules"][0]["overrides"]
+            .as_array()
+            .unwrap()
+            .len(),
+        2
+    );
+    // the ids pagerduty gave the overrides
+    let applied = record["applied"].as_array().unwrap();
+    assert_eq!(applied.len(), 2);
+    assert!(applied[0]["id"].as_str().unwrap().starts_with('Q'));
+}
@@ -0,0 +1,19 @@
+mod common;
+
+use common::cli::{assert_conflicts, plan_with_config};
+
+#[actix_web::test]
+async fn test_plan_with_bamboohr_time_off() {
+    // don't apply
+    let (fixtures, stdout) = plan_with_config(
+        "bamboohr",
+        |_| "[bamboohr]\ncompany = \"acme\"\n".to_string(),
+        &[("BAMBOOHR_API_KEY", "fixture")],
+        b"n\n",
+    )
+    .await;
+
+    // dave's approved vacation is a conflict on top of alice's out of office, carol's denied one isn't
+    assert_conflicts(&fixtures, 2);
+    assert!(stdout.contains("vacation in bamboohr"), "{}", stdout);
+}
@@ -0,0 +1,33 @@
+mod common;
+
+use common::cli::{assert_conflicts, plan_with_config};
+
+#[actix_web::test]
+async fn test_plan_with_external_bookings() {
+    // don't apply
+    let (fixtures, stdout) = plan_with_config(
+        "bookings",
+        |port| {
+            format!(
+                "[users.\"bob@example.com\"]\ncalendly_user = \"https://api.calendly.com/users/BOB\"\n\n[users.\"dave@example.com\"]\nbookings_feed = \"http://127.0.0.1:{port}/bookings/dave@example.com.ics\"\n",
+            )
+        },
+        &[("CALENDLY_API_TOKEN", "fixture")],
+        b"n\n",
+    )
+    .await;
+
+    // bob's call booked in calendly and dave's booked through the feed are conflicts on top of
+    // alice's out of office
+    assert_conflicts(&fixtures, 3);
+    assert!(
+        stdout.contains("Intro call (booked in calendly)"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("Onboarding call (external booking)"),
+        "{}",
+        stdout
+    );
+}
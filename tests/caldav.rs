@@ -0,0 +1,45 @@
+mod common;
+
+use common::cli::{run_cli, serve, stdout, workdir};
+use common::Fixtures;
+use std::fs;
+
+#[actix_web::test]
+async fn test_plan_from_caldav_calendars() {
+    let (fixtures, port) = serve(Fixtures::load());
+
+    // no google token and no google credentials, every calendar is read over CalDAV
+    let mut config = "caldav_only = true\n".to_string();
+    for name in ["alice", "bob", "carol", "dave"] {
+        config.push_str(&format!(
+            "[users.\"{name}@example.com\"]\n\
+             caldav = {{ url = \"http://127.0.0.1:{port}/caldav/{name}@example.com/\", \
+             username = \"{name}\", password_env = \"CALDAV_PASSWORD\" }}\n"
+        ));
+    }
+    let workdir = workdir("caldav", &config);
+    fs::remove_file(workdir.join("google_oidc_token")).unwrap();
+
+    // don't apply
+    let output = run_cli(
+        &workdir,
+        port,
+        &[],
+        &[
+            ("CALDAV_PASSWORD", "fixture"),
+            ("GOOGLE_CLIENT_ID", ""),
+            ("GOOGLE_CLIENT_SECRET", ""),
+        ],
+        b"n\n",
+    )
+    .await;
+    fs::remove_dir_all(&workdir).unwrap();
+    stdout(&output);
+
+    // alice's out of office comes from her CalDAV calendar, as in the google calendar fixtures
+    let slack = fixtures.slack.lock().unwrap();
+    assert_eq!(
+        slack[0]["text"],
+        "Plan for schedule PPRIMARY (run 1): 1 conflicts, 2 overrides"
+    );
+}
@@ -0,0 +1,140 @@
+//! Running the binary against the fixture server, each test in a directory of its own holding its
+//! config, cache, state and google token
+#![allow(dead_code)]
+
+use super::{start_fixture_server, Fixtures};
+use actix_web::web::Data;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Output, Stdio};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+/// Options planning the fixture schedule, added to those of a run that doesn't set them
+const PLAN_ARGS: [(&str, &str); 2] = [("--pd-schedule", "PPRIMARY"), ("--seed", "1")];
+
+/// The window of the fixture schedule. Its slots are all in 2022, so they are planned with
+/// --force-past, unless a run sets a window of its own
+const WINDOW_ARGS: [&str; 5] = [
+    "--start-date",
+    "2022-08-29",
+    "--duration-days",
+    "4",
+    "--force-past",
+];
+
+/// Serve `fixtures` on a free port, for the rest of the test
+pub fn serve(fixtures: Fixtures) -> (Data<Fixtures>, u16) {
+    let fixtures = Data::new(fixtures);
+    let (server, port) = start_fixture_server(0, fixtures.clone()).unwrap();
+    actix_web::rt::spawn(server);
+    (fixtures, port)
+}
+
+/// An empty directory for the test called `name`, with a google token the fixture server accepts,
+/// and `config` as the config file unless it's empty
+pub fn workdir(name: &str, config: &str) -> PathBuf {
+    let workdir =
+        std::env::temp_dir().join(format!("gcal-pagerduty-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&workdir);
+    fs::create_dir_all(&workdir).unwrap();
+    fs::write(workdir.join("google_oidc_token"), "fixture-token").unwrap();
+    if !config.is_empty() {
+        fs::write(workdir.join("gcal-pagerduty.toml"), config).unwrap();
+    }
+    workdir
+}
+
+/// The binary run in `workdir` against the fixture server on `port`, with `args` after the options
+/// of [PLAN_ARGS] and [WINDOW_ARGS] they don't set. The pagerduty key, google client and slack
+/// webhook are set, unless `env` gives them another value, an empty one leaving the variable out
+pub fn cli(workdir: &Path, port: u16, args: &[&str], env: &[(&str, &str)]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_gcal-pagerduty"));
+    if !args.contains(&"--start-date") {
+        command.args(WINDOW_ARGS);
+    }
+    for (flag, value) in PLAN_ARGS {
+        if !args.contains(&flag) {
+            command.args([flag, value]);
+        }
+    }
+    command
+        .args(["--base-url", &format!("http://127.0.0.1:{}", port)])
+        .args(args)
+        .env("PD_API_KEY", "fixture")
+        .env("GOOGLE_CLIENT_ID", "fixture")
+        .env("GOOGLE_CLIENT_SECRET", "fixture")
+        .env(
+            "SLACK_WEBHOOK_URL",
+            format!("http://127.0.0.1:{}/slack", port),
+        )
+        .env("GCAL_PAGERDUTY_CONFIG_DIR", workdir)
+        .env("GCAL_PAGERDUTY_CACHE_DIR", workdir)
+        .env("GCAL_PAGERDUTY_STATE_DIR", workdir)
+        .current_dir(workdir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    for (name, value) in env {
+        match value.is_empty() {
+            true => command.env_remove(name),
+            false => command.env(name, value),
+        };
+    }
+    command
+}
+
+/// Run [cli] to the end, answering `stdin` to its prompts
+pub async fn run_cli(
+    workdir: &Path,
+    port: u16,
+    args: &[&str],
+    env: &[(&str, &str)],
+    stdin: &[u8],
+) -> Output {
+    let mut child = cli(workdir, port, args, env).spawn().unwrap();
+    child.stdin.take().unwrap().write_all(stdin).await.unwrap();
+    child.wait_with_output().await.unwrap()
+}
+
+/// The stdout of a run that must have succeeded
+pub fn stdout(output: &Output) -> String {
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    assert!(
+        output.status.success(),
+        "{}\n{}",
+        stdout,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    stdout
+}
+
+/// Plan the fixture window in `workdir` and apply the plan, with `env` like [cli]. Returns what was
+/// printed
+pub async fn plan_and_apply(workdir: &Path, port: u16, env: &[(&str, &str)]) -> String {
+    stdout(&run_cli(workdir, port, &[], env, b"y\n").await)
+}
+
+/// Plan the fixture window of a test called `name`, with the config file `config` gives for the
+/// port of the fixture server, answering `stdin` to the prompt. Returns the fixtures and the stdout
+/// of the run
+pub async fn plan_with_config(
+    name: &str,
+    config: impl FnOnce(u16) -> String,
+    env: &[(&str, &str)],
+    stdin: &[u8],
+) -> (Data<Fixtures>, String) {
+    let (fixtures, port) = serve(Fixtures::load());
+    let workdir = workdir(name, &config(port));
+    let output = run_cli(&workdir, port, &[], env, stdin).await;
+    fs::remove_dir_all(&workdir).unwrap();
+    (fixtures, stdout(&output))
+}
+
+/// Check the plan posted to slack counts `count` conflicts
+pub fn assert_conflicts(fixtures: &Fixtures, count: usize) {
+    let slack = fixtures.slack.lock().unwrap();
+    let text = slack[0]["text"].as_str().unwrap();
+    let expected = format!("Plan for schedule PPRIMARY (run 1): {} conflicts", count);
+    assert!(text.starts_with(&expected), "{}", text);
+}
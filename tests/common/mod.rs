@@ -0,0 +1,840 @@
+//! A fixture server standing in for the google calendar, CalDAV, pagerduty, opsgenie, grafana
+//! oncall, splunk on-call, bamboohr, workday, google sheets, jira, confluence, grafana annotation and calendly apis, holiday and booking feeds and an S3 bucket, serving the json files in tests/fixtures. Used by the integration tests and by the demo
+//! (examples/fixture_server.rs)
+
+#[cfg(test)]
+pub mod cli;
+
+use actix_web::dev::Server;
+use actix_web::http::Method;
+use actix_web::web::{self, Data, Json, Path};
+use actix_web::{delete, get, post, put, App, HttpRequest, HttpResponse, HttpServer};
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+pub struct Fixtures {
+    schedule: Value,
+    users: HashMap<String, String>,
+    /// events of the google calendar of each person, by email
+    pub calendars: HashMap<String, Value>,
+    /// bodies of the override requests received, with the id of their schedule
+    pub overrides: Mutex<Vec<(String, Value)>>,
+    /// ids of the overrides deleted, with the id of their schedule
+    pub removed: Mutex<Vec<(String, String)>>,
+    /// messages posted to the slack webhook
+    pub slack: Mutex<Vec<Value>>,
+    /// grafana oncall shifts and splunk on-call overrides created, by id, until they are added to
+    /// a schedule
+    pending: Mutex<HashMap<String, Value>>,
+    /// cells written to the tabs of the google sheet, by tab name
+    pub sheets: Mutex<HashMap<String, Value>>,
+    /// jira issues created
+    pub jira: Mutex<Vec<Value>>,
+    /// confluence pages, as last saved. The id of a page is its position
+    pub confluence: Mutex<Vec<Value>>,
+    /// grafana annotations created
+    pub annotations: Mutex<Vec<Value>>,
+    /// objects put in the audit-log bucket, with their key
+    pub audit_log: Mutex<Vec<(String, Value)>>,
+    /// email, timeMin and timeMax of each google calendar query
+    pub calendar_queries: Mutex<Vec<(String, String, String)>>,
+    /// reasons of the 403s the next google calendar queries are answered with, e.g.
+    /// rateLimitExceeded
+    pub calendar_errors: Mutex<Vec<&'static str>>,
+}
+
+impl Fixtures {
+    pub fn load() -> Fixtures {
+        Fixtures {
+            schedule: serde_json::from_str(include_str!("../fixtures/pagerduty_schedule.json"))
+                .unwrap(),
+            users: serde_json::from_str(include_str!("../fixtures/pagerduty_users.json")).unwrap(),
+            calendars: serde_json::from_str(include_str!("../fixtures/calendars.json")).unwrap(),
+            overrides: Mutex::new(Vec::new()),
+            removed: Mutex::new(Vec::new()),
+            slack: Mutex::new(Vec::new()),
+            pending: Mutex::new(HashMap::new()),
+            sheets: Mutex::new(HashMap::new()),
+            jira: Mutex::new(Vec::new()),
+            confluence: Mutex::new(Vec::new()),
+            annotations: Mutex::new(Vec::new()),
+            audit_log: Mutex::new(Vec::new()),
+            calendar_queries: Mutex::new(Vec::new()),
+            calendar_errors: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+/// The schedule, or one nobody is oncall in for PEMPTY, one with dave's account deleted since for
+/// PDELETED, or one with dave under an old address for PALIASED. Like pagerduty, the overrides
+/// received and not deleted since are rendered on top
+#[get("/schedules/{id}")]
+async fn schedule(id: Path<String>, fixtures: Data<Fixtures>) -> HttpResponse {
+    let mut schedule = fixtures.schedule.clone();
+    let entries = &mut schedule["schedule"]["final_schedule"]["rendered_schedule_entries"];
+    match id.as_str() {
+        "PEMPTY" => *entries = json!([]),
+        "PDELETED" => entries[3]["user"] = json!({ "id": "PDAVE", "summary": "Dave" }),
+        "PALIASED" => entries[3]["user"]["id"] = json!("PDAVEOLD"),
+        _ => {}
+    }
+    let removed = fixtures.removed.lock().unwrap();
+    for (n, (schedule_id, body)) in fixtures.overrides.lock().unwrap().iter().enumerate() {
+        for (i, entry) in body["overrides"].as_array().unwrap().iter().enumerate() {
+            let deleted = removed.contains(&(schedule_id.clone(), format!("Q{}-{}", n, i)));
+            if schedule_id == id.as_str() && !deleted {
+                *entries = render_override(entries.as_array().unwrap(), entry);
+            }
+        }
+    }
+    HttpResponse::Ok().json(schedule)
+}
+
+/// `entries` with whatever `entry` overlaps of them handed to the user of the override, splitting
+/// entries it only covers part of
+fn render_override(entries: &[Value], entry: &Value) -> Value {
+    let time = |value: &Value| DateTime::parse_from_rfc3339(value.as_str().unwrap()).unwrap();
+    let (start, end) = (&entry["start"], &entry["end"]);
+    let id = entry["user"]["id"].as_str().unwrap();
+    let holder = json!({
+        "id": id,
+        "summary": id,
+        "self": format!("https://api.pagerduty.com/users/{}", id),
+    });
+    let mut rendered = Vec::new();
+    for existing in entries {
+        let (existing_start, existing_end) = (&existing["start"], &existing["end"]);
+        if time(start) >= time(existing_end) || time(end) <= time(existing_start) {
+            rendered.push(existing.clone());
+            continue;
+        }
+        if time(existing_start) < time(start) {
+            rendered
+                .push(json!({ "start": existing_start, "end": start, "user": existing["user"] }));
+        }
+        let covered_start = match time(existing_start) < time(start) {
+            true => start,
+            false => existing_start,
+        };
+        let covered_end = match time(existing_end) > time(end) {
+            true => end,
+            false => existing_end,
+        };
+        rendered.push(json!({ "start": covered_start, "end": covered_end, "user": holder }));
+        if time(existing_end) > time(end) {
+            rendered.push(json!({ "start": end, "end": existing_end, "user": existing["user"] }));
+        }
+    }
+    Value::Array(rendered)
+}
+
+#[post("/schedules/{id}/overrides")]
+async fn overrides(id: Path<String>, body: Json<Value>, fixtures: Data<Fixtures>) -> HttpResponse {
+    let mut received = fixtures.overrides.lock().unwrap();
+    // like pagerduty, answer with the status and id of every override created
+    let created: Vec<Value> = body["overrides"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            let mut created = entry.clone();
+            created["id"] = json!(format!("Q{}-{}", received.len(), i));
+            json!({ "status": 201, "override": created })
+        })
+        .collect();
+    received.push((id.into_inner(), body.into_inner()));
+    HttpResponse::Created().json(created)
+}
+
+#[delete("/schedules/{id}/overrides/{override_id}")]
+async fn remove_override(ids: Path<(String, String)>, fixtures: Data<Fixtures>) -> HttpResponse {
+    fixtures.removed.lock().unwrap().push(ids.into_inner());
+    HttpResponse::NoContent().finish()
+}
+
+#[post("/slack")]
+async fn slack(body: Json<Value>, fixtures: Data<Fixtures>) -> HttpResponse {
+    fixtures.slack.lock().unwrap().push(body.into_inner());
+    HttpResponse::Ok().body("ok")
+}
+
+#[get("/users/{id}")]
+async fn user(id: Path<String>, fixtures: Data<Fixtures>) -> HttpResponse {
+    match fixtures.users.get(id.as_str()) {
+        Some(email) => HttpResponse::Ok().json(json!({ "user": { "email": email } })),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+/// The pagerduty schedule as an opsgenie timeline, with everyone in a single rotation
+#[get("/v2/schedules/{id}/timeline")]
+async fn opsgenie_timeline(fixtures: Data<Fixtures>) -> HttpResponse {
+    let periods: Vec<Value> = fixtures.schedule["schedule"]["final_schedule"]
+        ["rendered_schedule_entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| {
+            let id = entry["user"]["id"].as_str().unwrap();
+            json!({
+                "startDate": entry["start"],
+                "endDate": entry["end"],
+                "type": "default",
+                "recipient": { "id": id, "type": "user", "name": fixtures.users[id] },
+            })
+        })
+        .collect();
+    HttpResponse::Ok().json(json!({
+        "data": { "finalTimeline": { "rotations": [{ "id": "rotation", "periods": periods }] } }
+    }))
+}
+
+#[get("/v2/schedules/{id}")]
+async fn opsgenie_schedule(id: Path<String>) -> HttpResponse {
+    HttpResponse::Ok().json(json!({ "data": { "id": id.into_inner() } }))
+}
+
+/// Opsgenie takes one override per request, and names it with an alias
+#[post("/v2/schedules/{id}/overrides")]
+async fn opsgenie_override(
+    id: Path<String>,
+    body: Json<Value>,
+    fixtures: Data<Fixtures>,
+) -> HttpResponse {
+    let mut received = fixtures.overrides.lock().unwrap();
+    let alias = format!("A{}", received.len());
+    received.push((id.into_inner(), body.into_inner()));
+    HttpResponse::Created().json(json!({ "data": { "alias": alias } }))
+}
+
+#[delete("/v2/schedules/{id}/overrides/{alias}")]
+async fn opsgenie_remove_override(
+    ids: Path<(String, String)>,
+    fixtures: Data<Fixtures>,
+) -> HttpResponse {
+    fixtures.removed.lock().unwrap().push(ids.into_inner());
+    HttpResponse::Ok().json(json!({ "result": "Deleted" }))
+}
+
+/// The pagerduty schedule as grafana oncall final shifts in utc, split over two pages
+#[get("/api/v1/schedules/{id}/final_shifts")]
+async fn grafana_final_shifts(request: HttpRequest, fixtures: Data<Fixtures>) -> HttpResponse {
+    let shifts: Vec<Value> = fixtures.schedule["schedule"]["final_schedule"]
+        ["rendered_schedule_entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| {
+            let id = entry["user"]["id"].as_str().unwrap();
+            let utc = |value: &Value| {
+                DateTime::parse_from_rfc3339(value.as_str().unwrap())
+                    .unwrap()
+                    .with_timezone(&Utc)
+                    .to_rfc3339()
+            };
+            json!({
+                "user_pk": id,
+                "user_email": fixtures.users[id],
+                "shift_start": utc(&entry["start"]),
+                "shift_end": utc(&entry["end"]),
+            })
+        })
+        .collect();
+    let (first, second) = shifts.split_at(shifts.len() / 2);
+    if request.query_string().contains("page=2") {
+        return HttpResponse::Ok().json(json!({ "next": null, "results": second }));
+    }
+    let next = format!(
+        "http://{}{}?{}&page=2",
+        request.connection_info().host(),
+        request.path(),
+        request.query_string()
+    );
+    HttpResponse::Ok().json(json!({ "next": next, "results": first }))
+}
+
+#[get("/api/v1/schedules/{id}")]
+async fn grafana_schedule(id: Path<String>) -> HttpResponse {
+    HttpResponse::Ok()
+        .json(json!({ "id": id.into_inner(), "type": "calendar", "shifts": ["OEXISTING"] }))
+}
+
+#[post("/api/v1/on_call_shifts/")]
+async fn grafana_create_shift(body: Json<Value>, fixtures: Data<Fixtures>) -> HttpResponse {
+    let mut shifts = fixtures.pending.lock().unwrap();
+    let id = format!("O{}", shifts.len());
+    shifts.insert(id.clone(), body.into_inner());
+    HttpResponse::Created().json(json!({ "id": id }))
+}
+
+/// Shifts created earlier and added to the schedule are the overrides received. The shifts the
+/// schedule already had must be kept
+#[put("/api/v1/schedules/{id}")]
+async fn grafana_update_schedule(
+    id: Path<String>,
+    body: Json<Value>,
+    fixtures: Data<Fixtures>,
+) -> HttpResponse {
+    let shifts: Vec<&str> = body["shifts"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|x| x.as_str().unwrap())
+        .collect();
+    if !shifts.contains(&"OEXISTING") {
+        return HttpResponse::BadRequest().finish();
+    }
+    let created = fixtures.pending.lock().unwrap();
+    let mut received = fixtures.overrides.lock().unwrap();
+    for shift in shifts {
+        if let Some(entry) = created.get(shift) {
+            received.push((id.to_string(), entry.clone()));
+        }
+    }
+    HttpResponse::Ok().json(body.into_inner())
+}
+
+/// Shifts are deleted by id alone, so they are recorded without a schedule
+#[delete("/api/v1/on_call_shifts/{id}")]
+async fn grafana_delete_shift(id: Path<String>, fixtures: Data<Fixtures>) -> HttpResponse {
+    fixtures
+        .removed
+        .lock()
+        .unwrap()
+        .push((String::new(), id.into_inner()));
+    HttpResponse::NoContent().finish()
+}
+
+/// The pagerduty schedule as the rolls of a splunk on-call escalation policy, with the pagerduty ids
+/// as usernames
+#[get("/api-public/v1/policies/{policy}/oncall/schedule")]
+async fn splunk_schedule(fixtures: Data<Fixtures>) -> HttpResponse {
+    let rolls: Vec<Value> = fixtures.schedule["schedule"]["final_schedule"]
+        ["rendered_schedule_entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| {
+            json!({
+                "change": entry["start"],
+                "until": entry["end"],
+                "onCall": entry["user"]["id"],
+                "isRoll": true,
+            })
+        })
+        .collect();
+    HttpResponse::Ok().json(json!({ "schedule": [{ "rotationName": "Daily", "rolls": rolls }] }))
+}
+
+#[get("/api-public/v1/user")]
+async fn splunk_users(fixtures: Data<Fixtures>) -> HttpResponse {
+    let users: Vec<Value> = fixtures
+        .users
+        .iter()
+        .map(|(username, email)| json!({ "username": username, "email": email }))
+        .collect();
+    HttpResponse::Ok().json(json!({ "users": users }))
+}
+
+#[get("/api-public/v1/user/{username}")]
+async fn splunk_user(username: Path<String>, fixtures: Data<Fixtures>) -> HttpResponse {
+    match fixtures.users.get(username.as_str()) {
+        Some(email) => HttpResponse::Ok().json(json!({ "username": *username, "email": email })),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+#[post("/api-public/v1/overrides")]
+async fn splunk_create_override(body: Json<Value>, fixtures: Data<Fixtures>) -> HttpResponse {
+    let mut pending = fixtures.pending.lock().unwrap();
+    let id = format!("V{}", pending.len());
+    pending.insert(id.clone(), body.into_inner());
+    HttpResponse::Ok().json(json!({ "publicId": id }))
+}
+
+/// Overrides assigned to a policy are the overrides received, with the username taking them on
+/// as `assignee`
+#[put("/api-public/v1/overrides/{id}/assignments/{policy}")]
+async fn splunk_assign_override(
+    ids: Path<(String, String)>,
+    body: Json<Value>,
+    fixtures: Data<Fixtures>,
+) -> HttpResponse {
+    let (id, policy) = ids.into_inner();
+    let Some(mut entry) = fixtures.pending.lock().unwrap().get(&id).cloned() else {
+        return HttpResponse::NotFound().finish();
+    };
+    entry["assignee"] = body["username"].clone();
+    fixtures.overrides.lock().unwrap().push((policy, entry));
+    HttpResponse::Ok().json(body.into_inner())
+}
+
+/// Overrides are deleted by id alone, so they are recorded without a schedule
+#[delete("/api-public/v1/overrides/{id}")]
+async fn splunk_delete_override(id: Path<String>, fixtures: Data<Fixtures>) -> HttpResponse {
+    fixtures
+        .removed
+        .lock()
+        .unwrap()
+        .push((String::new(), id.into_inner()));
+    HttpResponse::Ok().finish()
+}
+
+/// Everyone in the pagerduty users fixture, with their pagerduty id as employee id
+#[get("/api/gateway.php/{company}/v1/employees/directory")]
+async fn bamboohr_directory(fixtures: Data<Fixtures>) -> HttpResponse {
+    let employees: Vec<Value> = fixtures
+        .users
+        .iter()
+        .map(|(id, email)| json!({ "id": id, "workEmail": email }))
+        .collect();
+    HttpResponse::Ok().json(json!({ "employees": employees }))
+}
+
+/// Dave's approved day off on 2022-09-01, and a request of carol's that was denied
+#[get("/api/gateway.php/{company}/v1/time_off/requests/")]
+async fn bamboohr_time_off() -> HttpResponse {
+    HttpResponse::Ok().json(json!([
+        {
+            "employeeId": "PDAVE",
+            "status": { "status": "approved" },
+            "start": "2022-09-01",
+            "end": "2022-09-01",
+            "type": { "name": "Vacation" },
+        },
+        {
+            "employeeId": "PCAROL",
+            "status": { "status": "denied" },
+            "start": "2022-08-30",
+            "end": "2022-08-31",
+            "type": { "name": "Vacation" },
+        },
+    ]))
+}
+
+/// A workday absence report with dave off on 2022-09-01, needing basic auth
+#[get("/ccx/service/customreport2/{tenant}/{user}/{report}")]
+async fn workday_report(request: HttpRequest) -> HttpResponse {
+    let authorized = request
+        .headers()
+        .get("Authorization")
+        .and_then(|x| x.to_str().ok())
+        .is_some_and(|x| x.starts_with("Basic "));
+    if !authorized {
+        return HttpResponse::Unauthorized().finish();
+    }
+    HttpResponse::Ok().json(json!({
+        "Report_Entry": [
+            {
+                "Email": "dave@example.com",
+                "Start_Date": "2022-09-01-07:00",
+                "End_Date": "2022-09-01-07:00",
+                "Absence_Type": "Sick Leave",
+            },
+        ]
+    }))
+}
+
+/// Tab names of a range such as 'PPRIMARY run 1'!A1
+fn sheet_tab(range: &str) -> String {
+    let range = range.trim_end_matches(":clear");
+    let tab = range.split('!').next().unwrap();
+    tab.trim_matches('\'').to_string()
+}
+
+#[get("/v4/spreadsheets/{id}")]
+async fn spreadsheet(fixtures: Data<Fixtures>) -> HttpResponse {
+    let sheets: Vec<Value> = fixtures
+        .sheets
+        .lock()
+        .unwrap()
+        .keys()
+        .map(|title| json!({ "properties": { "title": title } }))
+        .collect();
+    HttpResponse::Ok().json(json!({ "sheets": sheets }))
+}
+
+/// Preferences marked by bob and carol, or the swap requests sent through a google form
+#[get("/v4/spreadsheets/{id}/values/{range}")]
+async fn sheet_read(path: Path<(String, String)>) -> HttpResponse {
+    if path.1 == "Form Responses 1" {
+        return HttpResponse::Ok().json(json!({
+            "range": "'Form Responses 1'!A1:Z1000",
+            "values": [
+                ["Timestamp", "Email Address", "Slot", "Taken by", "In return"],
+                ["8/25/2022 10:12:00", "bob@example.com", "2022-08-30", "dave@example.com"],
+                ["8/25/2022 11:40:00", "carol@example.com", "2022-08-31", "dave@example.com", "2022-09-01"],
+                ["8/26/2022 09:05:00", "dave@example.com", "2022-09-01", "erin@example.com"],
+            ]
+        }));
+    }
+    HttpResponse::Ok().json(json!({
+        "range": "Preferences!A1:Z1000",
+        "values": [
+            ["Email", "Start", "End", "Preference", "Reason"],
+            ["bob@example.com", "2022-08-29", "2022-08-29", "preferred"],
+            ["carol@example.com", "2022-08-31", "2022-08-31", "unavailable", "moving house"],
+        ]
+    }))
+}
+
+#[post("/v4/spreadsheets/{id}:batchUpdate")]
+async fn spreadsheet_update(body: Json<Value>, fixtures: Data<Fixtures>) -> HttpResponse {
+    let mut sheets = fixtures.sheets.lock().unwrap();
+    for request in body["requests"].as_array().unwrap() {
+        let title = request["addSheet"]["properties"]["title"].as_str().unwrap();
+        sheets.insert(title.to_string(), json!([]));
+    }
+    HttpResponse::Ok().json(json!({ "replies": [] }))
+}
+
+#[post("/v4/spreadsheets/{id}/values/{range}")]
+async fn sheet_clear(path: Path<(String, String)>, fixtures: Data<Fixtures>) -> HttpResponse {
+    let tab = sheet_tab(&path.1);
+    match fixtures.sheets.lock().unwrap().get_mut(&tab) {
+        Some(values) => {
+            *values = json!([]);
+            HttpResponse::Ok().json(json!({ "clearedRange": path.1 }))
+        }
+        None => HttpResponse::BadRequest().finish(),
+    }
+}
+
+#[put("/v4/spreadsheets/{id}/values/{range}")]
+async fn sheet_write(
+    path: Path<(String, String)>,
+    body: Json<Value>,
+    fixtures: Data<Fixtures>,
+) -> HttpResponse {
+    let tab = sheet_tab(&path.1);
+    match fixtures.sheets.lock().unwrap().get_mut(&tab) {
+        Some(values) => {
+            *values = body["values"].clone();
+            HttpResponse::Ok().json(json!({ "updatedRange": path.1 }))
+        }
+        None => HttpResponse::BadRequest().finish(),
+    }
+}
+
+#[post("/rest/api/2/issue")]
+async fn jira_issue(body: Json<Value>, fixtures: Data<Fixtures>) -> HttpResponse {
+    let mut issues = fixtures.jira.lock().unwrap();
+    issues.push(body.into_inner());
+    HttpResponse::Created().json(json!({
+        "id": format!("{}", 10000 + issues.len()),
+        "key": format!("OPS-{}", issues.len()),
+    }))
+}
+
+#[get("/rest/api/content")]
+async fn confluence_search(
+    query: web::Query<HashMap<String, String>>,
+    fixtures: Data<Fixtures>,
+) -> HttpResponse {
+    let pages = fixtures.confluence.lock().unwrap();
+    let results: Vec<&Value> = pages
+        .iter()
+        .filter(|x| {
+            x["space"]["key"].as_str() == query.get("spaceKey").map(String::as_str)
+                && x["title"].as_str() == query.get("title").map(String::as_str)
+        })
+        .collect();
+    HttpResponse::Ok().json(json!({ "results": results }))
+}
+
+#[post("/rest/api/content")]
+async fn confluence_create(body: Json<Value>, fixtures: Data<Fixtures>) -> HttpResponse {
+    let mut pages = fixtures.confluence.lock().unwrap();
+    let mut page = body.into_inner();
+    page["id"] = json!(pages.len().to_string());
+    page["version"] = json!({ "number": 1 });
+    pages.push(page.clone());
+    HttpResponse::Ok().json(page)
+}
+
+#[put("/rest/api/content/{id}")]
+async fn confluence_update(
+    id: Path<usize>,
+    body: Json<Value>,
+    fixtures: Data<Fixtures>,
+) -> HttpResponse {
+    let mut pages = fixtures.confluence.lock().unwrap();
+    let Some(page) = pages.get_mut(id.into_inner()) else {
+        return HttpResponse::NotFound().finish();
+    };
+    // like confluence, only the next version is accepted
+    let next = page["version"]["number"].as_u64().unwrap() + 1;
+    if body["version"]["number"] != json!(next) {
+        return HttpResponse::Conflict().finish();
+    }
+    let id = page["id"].clone();
+    *page = body.into_inner();
+    page["id"] = id;
+    HttpResponse::Ok().json(&*page)
+}
+
+#[post("/api/annotations")]
+async fn grafana_annotation(body: Json<Value>, fixtures: Data<Fixtures>) -> HttpResponse {
+    let mut annotations = fixtures.annotations.lock().unwrap();
+    annotations.push(body.into_inner());
+    HttpResponse::Ok().json(json!({ "id": annotations.len(), "message": "Annotation added" }))
+}
+
+/// Public holidays of SG and MY around the fixture schedule, as ICS feeds. Malaysia's national
+/// day falls on carol's slot
+#[get("/holidays/{region}.ics")]
+async fn holiday_feed(region: Path<String>) -> HttpResponse {
+    let (summary, date) = match region.as_str() {
+        "SG" => ("National Day", "20220809"),
+        "MY" => ("Hari Kebangsaan", "20220831"),
+        _ => return HttpResponse::NotFound().finish(),
+    };
+    HttpResponse::Ok()
+        .content_type("text/calendar; charset=utf-8")
+        .body(format!(
+            "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nBEGIN:VEVENT\r\nSUMMARY:{}\r\n\
+             DTSTART;VALUE=DATE:{}\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n",
+            summary, date
+        ))
+}
+
+/// Dave's external bookings, as an ICS feed
+#[get("/bookings/{email}.ics")]
+async fn bookings_feed(email: Path<String>) -> HttpResponse {
+    if email.as_str() != "dave@example.com" {
+        return HttpResponse::NotFound().finish();
+    }
+    HttpResponse::Ok()
+        .content_type("text/calendar; charset=utf-8")
+        .body(
+            "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nBEGIN:VEVENT\r\nSUMMARY:Onboarding call\r\n\
+             DTSTART:20220901T060000Z\r\nDTEND:20220901T070000Z\r\nEND:VEVENT\r\n\
+             END:VCALENDAR\r\n",
+        )
+}
+
+/// Bob's calendly bookings, over two pages, needing a bearer token
+#[get("/scheduled_events")]
+async fn calendly_events(request: HttpRequest) -> HttpResponse {
+    let authorized = request
+        .headers()
+        .get("Authorization")
+        .is_some_and(|x| x.as_bytes().starts_with(b"Bearer "));
+    if !authorized {
+        return HttpResponse::Unauthorized().finish();
+    }
+    if !request
+        .query_string()
+        .contains("user=https%3A%2F%2Fapi.calendly.com%2Fusers%2FBOB")
+    {
+        return HttpResponse::Ok().json(json!({ "collection": [], "pagination": {} }));
+    }
+    let (name, start, end, next_page) = match request.query_string().contains("page_token=2") {
+        false => (
+            "Intro call",
+            "2022-08-30T02:00:00.000000Z",
+            "2022-08-30T02:30:00.000000Z",
+            json!(format!(
+                "http://{}/scheduled_events?{}&page_token=2",
+                request.connection_info().host(),
+                request.query_string()
+            )),
+        ),
+        true => (
+            "Quarterly review",
+            "2022-08-31T01:00:00.000000Z",
+            "2022-08-31T02:00:00.000000Z",
+            Value::Null,
+        ),
+    };
+    HttpResponse::Ok().json(json!({
+        "collection": [{ "name": name, "start_time": start, "end_time": end, "status": "active" }],
+        "pagination": { "next_page": next_page },
+    }))
+}
+
+/// An S3 bucket named audit-log, taking objects signed with an access key
+#[put("/audit-log/{key:.*}")]
+async fn bucket_put(
+    request: HttpRequest,
+    key: Path<String>,
+    body: Json<Value>,
+    fixtures: Data<Fixtures>,
+) -> HttpResponse {
+    let signed = request
+        .headers()
+        .get("Authorization")
+        .is_some_and(|x| x.as_bytes().starts_with(b"AWS4-HMAC-SHA256 Credential="));
+    if !signed || request.headers().get("x-amz-content-sha256").is_none() {
+        return HttpResponse::Forbidden().finish();
+    }
+    let mut objects = fixtures.audit_log.lock().unwrap();
+    objects.push((key.into_inner(), body.into_inner()));
+    HttpResponse::Ok().finish()
+}
+
+#[get("/calendar/v3/users/me/calendarList")]
+async fn calendar_list() -> HttpResponse {
+    HttpResponse::Ok().json(json!({ "items": [] }))
+}
+
+/// Like google, only the events overlapping timeMin to timeMax, when given
+#[get("/calendar/v3/calendars/{email}/events")]
+async fn events(
+    email: Path<String>,
+    query: web::Query<HashMap<String, String>>,
+    fixtures: Data<Fixtures>,
+) -> HttpResponse {
+    let error = {
+        let mut errors = fixtures.calendar_errors.lock().unwrap();
+        (!errors.is_empty()).then(|| errors.remove(0))
+    };
+    if let Some(reason) = error {
+        return HttpResponse::Forbidden().json(json!({
+            "error": { "code": 403, "errors": [{ "domain": "usageLimits", "reason": reason }] }
+        }));
+    }
+    let Some(items) = fixtures.calendars.get(email.as_str()) else {
+        return HttpResponse::NotFound().finish();
+    };
+    let (Some(min), Some(max)) = (query.get("timeMin"), query.get("timeMax")) else {
+        return HttpResponse::Ok().json(json!({ "items": items }));
+    };
+    fixtures
+        .calendar_queries
+        .lock()
+        .unwrap()
+        .push((email.into_inner(), min.clone(), max.clone()));
+    let (min, max) = (
+        DateTime::parse_from_rfc3339(min).unwrap(),
+        DateTime::parse_from_rfc3339(max).unwrap(),
+    );
+    // all day events of the fixtures are in +08:00
+    let time = |x: &Value| match (x["dateTime"].as_str(), x["date"].as_str()) {
+        (Some(value), _) => DateTime::parse_from_rfc3339(value).unwrap(),
+        (None, Some(date)) => {
+            DateTime::parse_from_rfc3339(&format!("{}T00:00:00+08:00", date)).unwrap()
+        }
+        _ => panic!("Event without a time: {}", x),
+    };
+    let overlapping: Vec<&Value> = items
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|x| time(&x["end"]) > min && time(&x["start"]) < max)
+        .collect();
+    HttpResponse::Ok().json(json!({ "items": overlapping }))
+}
+
+/// The same calendars as the google api, as a CalDAV calendar-query response. Any credentials will
+/// do, but there must be some
+async fn caldav(
+    request: HttpRequest,
+    email: Path<String>,
+    fixtures: Data<Fixtures>,
+) -> HttpResponse {
+    let authorized = request
+        .headers()
+        .get("Authorization")
+        .is_some_and(|x| x.as_bytes().starts_with(b"Basic "));
+    let Some(items) = fixtures
+        .calendars
+        .get(email.as_str())
+        .filter(|_| authorized)
+    else {
+        return HttpResponse::Unauthorized().finish();
+    };
+    let time = |value: &Value| match (value["date"].as_str(), value["dateTime"].as_str()) {
+        (Some(date), _) => format!(";VALUE=DATE:{}", date.replace('-', "")),
+        (_, Some(time)) => format!(
+            ":{}",
+            DateTime::parse_from_rfc3339(time)
+                .unwrap()
+                .with_timezone(&Utc)
+                .format("%Y%m%dT%H%M%SZ")
+        ),
+        _ => panic!("Event without a time: {}", value),
+    };
+    let responses: Vec<String> = items
+        .as_array()
+        .unwrap()
+        .iter()
+        .enumerate()
+        .map(|(i, event)| {
+            format!(
+                "<d:response><d:href>/caldav/{email}/{i}.ics</d:href><d:propstat><d:prop>\
+                 <c:calendar-data><![CDATA[BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nSUMMARY:{}\r\n\
+                 DTSTART{}\r\nDTEND{}\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n]]></c:calendar-data>\
+                 </d:prop></d:propstat></d:response>",
+                event["summary"].as_str().unwrap(),
+                time(&event["start"]),
+                time(&event["end"]),
+            )
+        })
+        .collect();
+    HttpResponse::build(actix_web::http::StatusCode::MULTI_STATUS)
+        .content_type("application/xml; charset=utf-8")
+        .body(format!(
+            r#"<?xml version="1.0" encoding="utf-8"?><d:multistatus xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">{}</d:multistatus>"#,
+            responses.join("")
+        ))
+}
+
+/// Serve the fixtures on localhost, port 0 picking a free one. Returns the server, to be awaited or
+/// spawned, and the port it listens on
+pub fn start_fixture_server(port: u16, fixtures: Data<Fixtures>) -> std::io::Result<(Server, u16)> {
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(fixtures.clone())
+            .service(schedule)
+            .service(overrides)
+            .service(remove_override)
+            .service(slack)
+            .service(user)
+            .service(calendar_list)
+            .service(events)
+            .service(opsgenie_timeline)
+            .service(opsgenie_schedule)
+            .service(opsgenie_override)
+            .service(opsgenie_remove_override)
+            .service(grafana_final_shifts)
+            .service(grafana_schedule)
+            .service(grafana_create_shift)
+            .service(grafana_update_schedule)
+            .service(grafana_delete_shift)
+            .service(splunk_schedule)
+            .service(splunk_users)
+            .service(splunk_user)
+            .service(splunk_create_override)
+            .service(splunk_assign_override)
+            .service(splunk_delete_override)
+            .service(bamboohr_directory)
+            .service(bamboohr_time_off)
+            .service(workday_report)
+            .service(spreadsheet)
+            .service(spreadsheet_update)
+            .service(sheet_clear)
+            .service(sheet_read)
+            .service(sheet_write)
+            .service(jira_issue)
+            .service(confluence_search)
+            .service(confluence_create)
+            .service(confluence_update)
+            .service(grafana_annotation)
+            .service(holiday_feed)
+            .service(bookings_feed)
+            .service(calendly_events)
+            .service(bucket_put)
+            .route(
+                "/caldav/{email}/",
+                web::method(Method::from_bytes(b"REPORT").unwrap()).to(caldav),
+            )
+    })
+    .workers(1)
+    .bind(("127.0.0.1", port))?;
+    let port = server.addrs()[0].port();
+    Ok((server.run(), port))
+}
@@ -0,0 +1,47 @@
+mod common;
+
+use common::cli::{plan_and_apply, run_cli, serve, stdout, workdir};
+use common::Fixtures;
+use std::fs;
+
+#[actix_web::test]
+async fn test_confluence_page_after_apply() {
+    let (fixtures, port) = serve(Fixtures::load());
+    let workdir = workdir(
+        "confluence",
+        &format!(
+            "[confluence]\nurl = \"http://127.0.0.1:{}\"\nemail = \"bot@example.com\"\nspace = \"OPS\"\n",
+            port
+        ),
+    );
+
+    let env = [("CONFLUENCE_API_TOKEN", "fixture")];
+    // the first apply creates the page, the next one updates it. The overrides of the first are
+    // part of the schedule until undone, leaving the next plan nothing to apply otherwise
+    let printed = plan_and_apply(&workdir, port, &env).await;
+    assert!(printed.contains("Updated confluence page 0"), "{}", printed);
+    stdout(&run_cli(&workdir, port, &["--undo", "1"], &[], b"").await);
+    plan_and_apply(&workdir, port, &env).await;
+    fs::remove_dir_all(&workdir).unwrap();
+
+    let pages = fixtures.confluence.lock().unwrap();
+    assert_eq!(pages.len(), 1);
+    let page = &pages[0];
+    assert_eq!(page["title"], "Oncall rotation");
+    assert_eq!(page["version"]["number"], 2);
+    let body = page["body"]["storage"]["value"].as_str().unwrap();
+    assert!(
+        body.contains("last by run 2 of schedule PPRIMARY"),
+        "{}",
+        body
+    );
+    // alice is out of office on the first day, so the rotation no longer starts with her
+    assert!(
+        body.contains("<td>Mon 29 Aug 03:00</td><td>Tue 30 Aug 03:00</td>")
+            && !body.contains("<td>Mon 29 Aug 03:00</td><td>Tue 30 Aug 03:00</td><td>alice"),
+        "{}",
+        body
+    );
+    assert!(body.contains("<td>1</td>") && body.contains("<td>2</td>"));
+    assert!(body.contains("Shifts per person"));
+}
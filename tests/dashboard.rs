@@ -0,0 +1,63 @@
+mod common;
+
+use common::cli::{cli, serve, workdir};
+use common::Fixtures;
+use std::fs;
+use std::net::TcpListener;
+use std::time::Duration;
+
+#[actix_web::test]
+async fn test_apply_from_dashboard() {
+    let (fixtures, port) = serve(Fixtures::load());
+    let dashboard_port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let workdir = workdir("dashboard", "");
+
+    let child = cli(
+        &workdir,
+        port,
+        &["--serve", &dashboard_port.to_string()],
+        &[],
+    )
+    .kill_on_drop(true)
+    .spawn()
+    .unwrap();
+
+    // the page is up once the plan is solved
+    let client = reqwest::Client::new();
+    let url = format!("http://127.0.0.1:{}", dashboard_port);
+    let mut page = None;
+    for _ in 0..100 {
+        if let Ok(response) = client.get(&url).send().await {
+            page = Some(response.text().await.unwrap());
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+    let page = page.expect("dashboard never came up");
+    assert!(page.contains("alice@example.com"));
+    assert!(page.contains("class=\"conflict\""));
+
+    let applied = client
+        .post(format!("{}/apply", url))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(
+        applied.contains("Applied the overrides of run 1"),
+        "{}",
+        applied
+    );
+    // close the kept alive connection, so the dashboard shuts down right away
+    drop(client);
+    let output = child.wait_with_output().await.unwrap();
+    fs::remove_dir_all(&workdir).unwrap();
+    assert!(output.status.success());
+    assert_eq!(fixtures.overrides.lock().unwrap().len(), 1);
+}
@@ -0,0 +1,52 @@
+mod common;
+
+use common::cli::{run_cli, serve, stdout, workdir};
+use common::Fixtures;
+use std::fs;
+
+#[actix_web::test]
+async fn test_times_shown_in_the_display_timezone() {
+    let (_, port) = serve(Fixtures::load());
+    let workdir = workdir(
+        "display",
+        r#"
+        display_timezone = "Europe/Berlin"
+
+        [users."alice@example.com"]
+        timezone = "Europe/Berlin"
+
+        [users."dave@example.com"]
+        timezone = "America/New_York"
+        "#,
+    );
+
+    // everyone's own timezone, over the one of the config file. Don't apply the plan
+    let output = run_cli(&workdir, port, &["--display-timezone", "user"], &[], b"n\n").await;
+    fs::remove_dir_all(&workdir).unwrap();
+    let stdout = stdout(&output);
+    // slots starting at 03:00 in singapore, in the timezone of whoever held them
+    assert!(
+        stdout.contains("| Sun Aug 28 21:00:00 2022 CEST | alice@example.com |"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("| Wed Aug 31 15:00:00 2022 EDT  | dave@example.com  |"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains(
+            "needed: alice@example.com is busy with \"Out of office\" \
+            (Mon Aug 29 00:00:00 2022 CEST to Tue Aug 30 00:00:00 2022 CEST)"
+        ),
+        "{}",
+        stdout
+    );
+    // swaps are about two people, so their slots stay in the timezone of the schedule
+    assert!(
+        stdout.contains("| alice@example.com    | Mon Aug 29 03:00:00 2022 +08 |"),
+        "{}",
+        stdout
+    );
+}
@@ -0,0 +1,50 @@
+mod common;
+
+use common::cli::{run_cli, serve, workdir};
+use common::Fixtures;
+use std::fs;
+
+#[actix_web::test]
+async fn test_calendars_read_under_normalised_emails() {
+    let (fixtures, port) = serve(Fixtures::load());
+    let workdir = workdir(
+        "aliases",
+        r#"
+        [email_aliases]
+        domains = { "oldcorp.com" = "example.com" }
+
+        [users."carl@example.com"]
+        timezone = "Asia/Singapore"
+        "#,
+    );
+
+    // dave is still under his old address in the oncall provider
+    let output = run_cli(
+        &workdir,
+        port,
+        &["--pd-schedule", "PALIASED", "--check"],
+        &[],
+        b"",
+    )
+    .await;
+    fs::remove_dir_all(&workdir).unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(10), "{}", stdout);
+    assert!(
+        stdout.contains(
+            "users.\"carl@example.com\" in the config file matches nobody on the schedule, \
+            did you mean carol@example.com?"
+        ),
+        "{}",
+        stdout
+    );
+
+    let queries = fixtures.calendar_queries.lock().unwrap();
+    let emails: Vec<&str> = queries.iter().map(|x| x.0.as_str()).collect();
+    assert!(emails.contains(&"dave@example.com"), "{:?}", emails);
+    assert!(
+        !emails.iter().any(|x| x.contains("oldcorp")),
+        "{:?}",
+        emails
+    );
+}
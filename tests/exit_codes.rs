@@ -0,0 +1,114 @@
+mod common;
+
+use common::cli::{run_cli, serve, workdir};
+use common::Fixtures;
+use std::fs;
+use tokio::process::Command;
+
+#[actix_web::test]
+async fn test_check_exits_with_conflicts_found() {
+    let (fixtures, port) = serve(Fixtures::load());
+    let workdir = workdir("check", "");
+
+    let output = run_cli(&workdir, port, &["--check"], &[], b"").await;
+    fs::remove_dir_all(&workdir).unwrap();
+    // alice is out of office on the first day
+    assert_eq!(output.status.code(), Some(10));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Found conflict: alice@example.com"),
+        "{}",
+        stdout
+    );
+    assert!(fixtures.overrides.lock().unwrap().is_empty());
+}
+
+#[actix_web::test]
+async fn test_empty_schedule_exits_with_guidance() {
+    let (_, port) = serve(Fixtures::load());
+    let workdir = workdir("empty", "");
+
+    let output = run_cli(
+        &workdir,
+        port,
+        &["--pd-schedule", "PEMPTY", "--check"],
+        &[],
+        b"",
+    )
+    .await;
+    fs::remove_dir_all(&workdir).unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+    assert!(
+        stderr.contains("Schedule PEMPTY has nobody oncall from 2022-08-29 00:00:00 +08:00"),
+        "{}",
+        stderr
+    );
+}
+
+#[actix_web::test]
+async fn test_skipped_users_are_listed_and_fail_the_run() {
+    let (_, port) = serve(Fixtures::load());
+    let workdir = workdir("skipped", "");
+
+    // dave's account was deleted, so his slot can't be planned
+    let args = ["--pd-schedule", "PDELETED", "--check"];
+    let output = run_cli(&workdir, port, &args, &[], b"").await;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(50), "{}", stdout);
+    assert!(stdout.contains("Skipped users"), "{}", stdout);
+    assert!(
+        stdout.contains("Possible invalid user in pagerduty: Dave"),
+        "{}",
+        stdout
+    );
+    assert!(!stdout.contains("Found conflict"), "{}", stdout);
+
+    // the rest of the schedule is still checked
+    let args = [
+        "--pd-schedule",
+        "PDELETED",
+        "--check",
+        "--allow-skipped-users",
+    ];
+    let output = run_cli(&workdir, port, &args, &[], b"").await;
+    fs::remove_dir_all(&workdir).unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(10), "{}", stdout);
+    assert!(
+        stdout.contains("Found conflict: alice@example.com"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("Skipped users"), "{}", stdout);
+}
+
+#[tokio::test]
+async fn test_invalid_inputs_exit_before_anything_runs() {
+    let run = |args: &'static [&'static str]| async move {
+        Command::new(env!("CARGO_BIN_EXE_gcal-pagerduty"))
+            .args(args)
+            .env("PD_API_KEY", "fixture")
+            .output()
+            .await
+            .unwrap()
+    };
+    let output = run(&["--start-date", "2022/08/29", "--duration-days", "4"]).await;
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("expected a date as YYYY-MM-DD, e.g. 2022-08-22, got 2022/08/29"),
+        "{}",
+        stderr
+    );
+
+    let output = run(&["--start-date", "2022-08-29", "--duration-days", "0"]).await;
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("'--duration-days <DURATION_DAYS>': expected a number above zero, got 0"),
+        "{}",
+        stderr
+    );
+}
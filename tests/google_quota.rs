@@ -0,0 +1,54 @@
+mod common;
+
+use common::cli::{run_cli, serve, workdir};
+use common::Fixtures;
+use std::fs;
+use std::process::Output;
+
+/// Check the fixture schedule, with google answering the first calendar reads with 403s of
+/// `reasons`
+async fn check_with_errors(name: &str, reasons: Vec<&'static str>) -> Output {
+    let (fixtures, port) = serve(Fixtures::load());
+    *fixtures.calendar_errors.lock().unwrap() = reasons;
+    let workdir = workdir(name, "");
+
+    let output = run_cli(&workdir, port, &["--check", "--no-cache"], &[], b"").await;
+    fs::remove_dir_all(&workdir).unwrap();
+    output
+}
+
+#[actix_web::test]
+async fn test_reads_over_the_quota_are_retried() {
+    let output =
+        check_with_errors("quota", vec!["rateLimitExceeded", "userRateLimitExceeded"]).await;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // the check gets through, and finds alice's out of office
+    assert_eq!(output.status.code(), Some(10), "{}", stdout);
+    assert!(
+        stdout.contains("Found conflict: alice@example.com"),
+        "{}",
+        stdout
+    );
+    assert_eq!(
+        stdout
+            .matches("Warning. Over the google calendar quota")
+            .count(),
+        2,
+        "{}",
+        stdout
+    );
+}
+
+#[actix_web::test]
+async fn test_unshared_calendars_fail_right_away() {
+    let output = check_with_errors("forbidden", vec!["forbidden"]).await;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1), "{}\n{}", stdout, stderr);
+    assert!(!stdout.contains("retrying"), "{}", stdout);
+    assert!(
+        stderr.contains("isn't shared with whoever runs the tool"),
+        "{}",
+        stderr
+    );
+}
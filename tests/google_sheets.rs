@@ -0,0 +1,81 @@
+mod common;
+
+use common::cli::{assert_conflicts, plan_with_config, run_cli, serve, stdout, workdir};
+use common::Fixtures;
+use std::fs;
+
+#[actix_web::test]
+async fn test_plan_written_to_google_sheets() {
+    // don't apply
+    let (fixtures, stdout) = plan_with_config(
+        "sheets",
+        |_| "[sheets]\nplan_spreadsheet = \"SHEET\"\n".to_string(),
+        &[],
+        b"n\n",
+    )
+    .await;
+
+    let sheets = fixtures.sheets.lock().unwrap();
+    let rows = sheets["PPRIMARY run 1"].as_array().unwrap();
+    assert_eq!(rows[0][0], "Conflicts");
+    assert_eq!(rows[2][0], "alice@example.com");
+    // the conflict and the two overrides resolving it
+    assert_eq!(rows.len(), 8, "{:?}", rows);
+    assert!(
+        stdout.contains("Wrote the plan to the tab PPRIMARY run 1"),
+        "{}",
+        stdout
+    );
+}
+
+#[actix_web::test]
+async fn test_plan_with_sheet_preferences() {
+    // don't apply
+    let (fixtures, stdout) = plan_with_config(
+        "sheet-preferences",
+        |_| "[sheets]\npreferences_spreadsheet = \"PREFS\"\n".to_string(),
+        &[],
+        b"n\n",
+    )
+    .await;
+
+    // carol being unavailable is a conflict on top of alice's out of office
+    assert_conflicts(&fixtures, 2);
+    assert!(stdout.contains("moving house"), "{}", stdout);
+}
+
+#[actix_web::test]
+async fn test_plan_with_swap_requests() {
+    let (fixtures, port) = serve(Fixtures::load());
+    let workdir = workdir(
+        "swap-requests",
+        "[sheets]\nswap_requests_spreadsheet = \"FORM\"\n",
+    );
+
+    // outcomes are checked whole, so keep them on one line
+    let args = ["--max-column-width", "0"];
+    let output = run_cli(&workdir, port, &args, &[], b"y\n").await;
+    fs::remove_dir_all(&workdir).unwrap();
+    let stdout = stdout(&output);
+
+    // carol is xoncall for dave's slot, and erin isn't on the schedule
+    assert!(
+        stdout.contains("rejected: carol@example.com is busy for the slot in return"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("rejected: erin@example.com isn't on the schedule"),
+        "{}",
+        stdout
+    );
+    // bob's slot goes to dave as asked, next to the overrides resolving alice's conflict
+    let received = fixtures.overrides.lock().unwrap();
+    let overrides = received[0].1["overrides"].as_array().unwrap();
+    assert!(
+        overrides.iter().any(|x| x["user"]["id"] == "PDAVE"
+            && x["start"].as_str().unwrap().starts_with("2022-08-30")),
+        "{:?}",
+        overrides
+    );
+}
@@ -0,0 +1,45 @@
+mod common;
+
+use common::cli::{plan_and_apply, serve, workdir};
+use common::Fixtures;
+use std::fs;
+
+#[actix_web::test]
+async fn test_grafana_annotations_after_apply() {
+    let (fixtures, port) = serve(Fixtures::load());
+    let workdir = workdir(
+        "grafana-annotations",
+        &format!(
+            "[grafana_annotations]\nurl = \"http://127.0.0.1:{}\"\ndashboard_uid = \"oncall\"\ntags = [\"pager\"]\n",
+            port
+        ),
+    );
+
+    let env = [("GRAFANA_API_TOKEN", "fixture")];
+    let stdout = plan_and_apply(&workdir, port, &env).await;
+    assert!(
+        stdout.contains("Annotated 2 overrides of schedule PPRIMARY in grafana"),
+        "{}",
+        stdout
+    );
+    // the same plan again applies nothing new, so nothing is annotated twice
+    plan_and_apply(&workdir, port, &env).await;
+    fs::remove_dir_all(&workdir).unwrap();
+
+    let annotations = fixtures.annotations.lock().unwrap();
+    assert_eq!(annotations.len(), 2);
+    // alice's out of office day, 2022-08-29T03:00:00+08:00
+    let first = annotations
+        .iter()
+        .find(|x| x["time"] == 1661713200000i64)
+        .unwrap();
+    assert_eq!(first["timeEnd"], 1661799600000i64);
+    assert_eq!(first["dashboardUID"], "oncall");
+    assert_eq!(first["tags"], serde_json::json!(["pager", "PPRIMARY"]));
+    let text = first["text"].as_str().unwrap();
+    assert!(
+        text.ends_with("is oncall on PPRIMARY instead of alice@example.com (gcal-pagerduty run 1)"),
+        "{}",
+        text
+    );
+}
@@ -0,0 +1,43 @@
+mod common;
+
+use common::cli::{run_cli, serve, stdout, workdir};
+use common::Fixtures;
+use std::fs;
+
+#[actix_web::test]
+async fn test_plan_and_apply_on_grafana_oncall() {
+    let (fixtures, port) = serve(Fixtures::load());
+    let workdir = workdir("grafana", "oncall_provider = \"grafana\"\n");
+    // the pagerduty key is left out, the provider has its own
+    let env = [("PD_API_KEY", ""), ("GRAFANA_ONCALL_TOKEN", "fixture")];
+
+    stdout(&run_cli(&workdir, port, &[], &env, b"y\n").await);
+    {
+        let slack = fixtures.slack.lock().unwrap();
+        assert_eq!(
+            slack[0]["text"],
+            "Plan for schedule PPRIMARY (run 1): 1 conflicts, 2 overrides"
+        );
+    }
+
+    // an override shift per override, added to the schedule, alice handing over her first day
+    {
+        let received = fixtures.overrides.lock().unwrap();
+        assert_eq!(received.len(), 2);
+        let first_day = received
+            .iter()
+            .find(|(_, body)| body["start"] == "2022-08-28T19:00:00")
+            .unwrap();
+        assert_eq!(first_day.0, "PPRIMARY");
+        assert_eq!(first_day.1["type"], "override");
+        assert_eq!(first_day.1["duration"], 24 * 60 * 60);
+        assert_ne!(first_day.1["users"][0], "PALICE");
+    }
+
+    stdout(&run_cli(&workdir, port, &["--undo", "1"], &env, b"").await);
+    fs::remove_dir_all(&workdir).unwrap();
+    let removed = fixtures.removed.lock().unwrap();
+    let mut shifts: Vec<&str> = removed.iter().map(|(_, id)| id.as_str()).collect();
+    shifts.sort();
+    assert_eq!(shifts, ["O0", "O1"]);
+}
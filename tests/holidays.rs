@@ -0,0 +1,27 @@
+mod common;
+
+use common::cli::{assert_conflicts, plan_with_config};
+
+#[actix_web::test]
+async fn test_plan_with_regional_holidays() {
+    // don't apply
+    let (fixtures, stdout) = plan_with_config(
+        "holidays",
+        |port| {
+            format!(
+                "region = \"SG\"\n\n[holiday_feeds]\nSG = \"http://127.0.0.1:{port}/holidays/SG.ics\"\nMY = \"http://127.0.0.1:{port}/holidays/MY.ics\"\n\n[users.\"carol@example.com\"]\nregion = \"MY\"\n",
+            )
+        },
+        &[],
+        b"n\n",
+    )
+    .await;
+
+    // carol's national day is a conflict on top of alice's out of office, singapore's was weeks ago
+    assert_conflicts(&fixtures, 2);
+    assert!(
+        stdout.contains("Hari Kebangsaan (MY public holiday)"),
+        "{}",
+        stdout
+    );
+}
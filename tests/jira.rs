@@ -0,0 +1,55 @@
+mod common;
+
+use common::cli::{run_cli, serve, stdout, workdir};
+use common::Fixtures;
+use std::fs;
+
+#[actix_web::test]
+async fn test_jira_ticket_for_unresolved_conflicts() {
+    let (fixtures, port) = serve(Fixtures::load());
+    let workdir = workdir(
+        "jira",
+        &format!(
+            "[jira]\nurl = \"http://127.0.0.1:{}\"\nemail = \"bot@example.com\"\nproject = \"OPS\"\n",
+            port
+        ),
+    );
+    // nobody is free on alice's out of office day
+    fs::write(
+        workdir.join("availability.csv"),
+        "email,start,end,reason\n\
+         bob@example.com,2022-08-29,2022-08-29,offsite\n\
+         carol@example.com,2022-08-29,2022-08-29,offsite\n\
+         dave@example.com,2022-08-29,2022-08-29,offsite\n",
+    )
+    .unwrap();
+
+    // don't apply
+    let args = [
+        "--availability-file",
+        "availability.csv",
+        "--allow-unresolved",
+    ];
+    let env = [("JIRA_API_TOKEN", "fixture")];
+    let output = run_cli(&workdir, port, &args, &env, b"n\n").await;
+    fs::remove_dir_all(&workdir).unwrap();
+    let stdout = stdout(&output);
+
+    let issues = fixtures.jira.lock().unwrap();
+    assert_eq!(issues.len(), 1);
+    let fields = &issues[0]["fields"];
+    assert_eq!(fields["project"]["key"], "OPS");
+    assert_eq!(
+        fields["summary"],
+        "1 unresolved oncall conflicts on PPRIMARY from 2022-08-29 to 2022-09-02"
+    );
+    assert!(
+        fields["description"]
+            .as_str()
+            .unwrap()
+            .contains("|Mon 29 Aug 03:00|Tue 30 Aug 03:00|offsite|"),
+        "{}",
+        fields["description"]
+    );
+    assert!(stdout.contains("Opened OPS-1"), "{}", stdout);
+}
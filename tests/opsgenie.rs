@@ -0,0 +1,47 @@
+mod common;
+
+use common::cli::{run_cli, serve, stdout, workdir};
+use common::Fixtures;
+use std::fs;
+
+#[actix_web::test]
+async fn test_plan_and_apply_on_opsgenie() {
+    let (fixtures, port) = serve(Fixtures::load());
+    let workdir = workdir("opsgenie", "oncall_provider = \"opsgenie\"\n");
+    // the pagerduty key is left out, the provider has its own
+    let env = [("PD_API_KEY", ""), ("OPSGENIE_API_KEY", "fixture")];
+
+    stdout(&run_cli(&workdir, port, &[], &env, b"y\n").await);
+    {
+        let slack = fixtures.slack.lock().unwrap();
+        assert_eq!(
+            slack[0]["text"],
+            "Plan for schedule PPRIMARY (run 1): 1 conflicts, 2 overrides"
+        );
+    }
+
+    // one request per override, alice handing over her first day
+    {
+        let received = fixtures.overrides.lock().unwrap();
+        assert_eq!(received.len(), 2);
+        let first_day = received
+            .iter()
+            .find(|(_, body)| {
+                body["startDate"]
+                    .as_str()
+                    .unwrap()
+                    .starts_with("2022-08-29T03:00:00")
+            })
+            .unwrap();
+        assert_eq!(first_day.0, "PPRIMARY");
+        assert_eq!(first_day.1["user"]["type"], "user");
+        assert_ne!(first_day.1["user"]["id"], "PALICE");
+    }
+
+    stdout(&run_cli(&workdir, port, &["--undo", "1"], &env, b"").await);
+    fs::remove_dir_all(&workdir).unwrap();
+    let removed = fixtures.removed.lock().unwrap();
+    let mut aliases: Vec<&str> = removed.iter().map(|(_, alias)| alias.as_str()).collect();
+    aliases.sort();
+    assert_eq!(aliases, ["A0", "A1"]);
+}
@@ -0,0 +1,55 @@
+mod common;
+
+use actix_web::web::{Bytes, Data};
+use actix_web::{post, App, HttpResponse, HttpServer};
+use common::cli::{run_cli, serve, workdir};
+use common::Fixtures;
+use std::fs;
+use std::sync::Mutex;
+
+/// Bodies of the export requests received
+#[derive(Default)]
+struct Collector {
+    exports: Mutex<Vec<Bytes>>,
+}
+
+#[post("/v1/traces")]
+async fn traces(body: Bytes, collector: Data<Collector>) -> HttpResponse {
+    collector.exports.lock().unwrap().push(body);
+    HttpResponse::Ok().finish()
+}
+
+#[actix_web::test]
+async fn test_spans_are_exported() {
+    let (_, port) = serve(Fixtures::load());
+    let collector = Data::new(Collector::default());
+    let collector_data = collector.clone();
+    let collector_server =
+        HttpServer::new(move || App::new().app_data(collector_data.clone()).service(traces))
+            .workers(1)
+            .bind(("127.0.0.1", 0))
+            .unwrap();
+    let collector_port = collector_server.addrs()[0].port();
+    actix_web::rt::spawn(collector_server.run());
+
+    let workdir = workdir("otlp", "");
+
+    let endpoint = format!("http://127.0.0.1:{}/v1/traces", collector_port);
+    let args = ["--check", "--otlp-endpoint", &endpoint];
+    let output = run_cli(&workdir, port, &args, &[], b"").await;
+    fs::remove_dir_all(&workdir).unwrap();
+    assert_eq!(output.status.code(), Some(10));
+    // spans are protobuf encoded, with their names and attributes as plain strings
+    let exports = collector.exports.lock().unwrap();
+    let exported: Vec<u8> = exports.iter().flat_map(|x| x.to_vec()).collect();
+    let exported = String::from_utf8_lossy(&exported);
+    for expected in [
+        "fetch_schedule",
+        "PPRIMARY",
+        "fetch_events",
+        "alice@example.com",
+        "latency_ms",
+    ] {
+        assert!(exported.contains(expected), "{} not exported", expected);
+    }
+}
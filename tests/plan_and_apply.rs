@@ -0,0 +1,174 @@
+mod common;
+
+use common::cli::{run_cli, serve, stdout, workdir};
+use common::Fixtures;
+use std::fs;
+
+#[actix_web::test]
+async fn test_plan_and_apply_against_fixtures() {
+    let (fixtures, port) = serve(Fixtures::load());
+    // any token will do, the fixture server accepts everything. It's where earlier versions kept it
+    let workdir = workdir("test", "");
+    fs::rename(
+        workdir.join("google_oidc_token"),
+        workdir.join(".google_oidc_token"),
+    )
+    .unwrap();
+
+    // apply the overrides when asked
+    let printed = stdout(&run_cli(&workdir, port, &[], &[], b"y\n").await);
+    // the token left in the current directory by earlier versions is moved to the cache directory
+    assert!(printed.contains("Moved .google_oidc_token to"));
+    assert!(workdir.join("google_oidc_token").exists());
+    assert!(printed.contains("Scheduling overrides..."));
+    assert!(printed.contains("Recorded as run 1"));
+    {
+        let slack = fixtures.slack.lock().unwrap();
+        assert_eq!(slack.len(), 2);
+        assert_eq!(
+            slack[0]["text"],
+            "Plan for schedule PPRIMARY (run 1): 1 conflicts, 2 overrides"
+        );
+        assert!(slack[1]["text"]
+            .as_str()
+            .unwrap()
+            .contains("Applied the overrides of run 1"));
+    }
+
+    // alice is out of office on the first day, so she swaps with someone free that day
+    {
+        let received = fixtures.overrides.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        let (schedule_id, body) = &received[0];
+        assert_eq!(schedule_id, "PPRIMARY");
+        let overrides = body["overrides"].as_array().unwrap();
+        assert_eq!(overrides.len(), 2);
+        let holder_of = |start: &str| {
+            overrides
+                .iter()
+                .find(|x| x["start"].as_str().unwrap().starts_with(start))
+                .map(|x| x["user"]["id"].as_str().unwrap())
+        };
+        let first_day = holder_of("2022-08-29T03:00:00").unwrap();
+        assert_ne!(first_day, "PALICE");
+        assert!(overrides.iter().any(|x| x["user"]["id"] == "PALICE"));
+    }
+
+    // the schedule has the overrides now, so there is nothing left to send
+    let printed = stdout(&run_cli(&workdir, port, &[], &[], b"").await);
+    assert!(printed.contains("Nothing to apply"));
+    assert_eq!(fixtures.overrides.lock().unwrap().len(), 1);
+
+    let history = stdout(&run_cli(&workdir, port, &["--history"], &[], b"").await);
+    assert!(history.contains("applied"), "{}", history);
+
+    stdout(&run_cli(&workdir, port, &["--undo", "1"], &[], b"").await);
+    fs::remove_dir_all(&workdir).unwrap();
+    let removed = fixtures.removed.lock().unwrap();
+    assert_eq!(removed.len(), 2);
+    assert!(removed
+        .iter()
+        .all(|(schedule_id, _)| schedule_id == "PPRIMARY"));
+}
+
+/// Planning the same window again after an apply finds the overrides in the schedule, even with the
+/// schedule of the first run still in the cache, and has nothing left to change. Once undone, the
+/// conflict is back
+#[actix_web::test]
+async fn test_rerun_after_apply_has_nothing_to_apply() {
+    let (fixtures, port) = serve(Fixtures::load());
+    let workdir = workdir("rerun", "");
+
+    stdout(&run_cli(&workdir, port, &[], &[], b"y\n").await);
+    assert_eq!(fixtures.overrides.lock().unwrap().len(), 1);
+
+    // no input, the run must not ask whether to apply
+    let printed = stdout(&run_cli(&workdir, port, &[], &[], b"").await);
+    assert!(!printed.contains("Found conflict"), "{}", printed);
+    assert!(
+        printed.contains("Nothing to apply, the schedule already matches the plan"),
+        "{}",
+        printed
+    );
+    // nor record a run or announce a plan
+    assert!(!printed.contains("Recorded as run"), "{}", printed);
+    assert_eq!(fixtures.slack.lock().unwrap().len(), 2);
+    assert_eq!(fixtures.overrides.lock().unwrap().len(), 1);
+
+    stdout(&run_cli(&workdir, port, &["--undo", "1"], &[], b"").await);
+    let printed = stdout(&run_cli(&workdir, port, &[], &[], b"n\n").await);
+    fs::remove_dir_all(&workdir).unwrap();
+    assert!(printed.contains("Found conflict"), "{}", printed);
+    assert!(
+        printed.contains("Skipping scheduling of overrides"),
+        "{}",
+        printed
+    );
+}
+
+/// A plan saved by an --allow-unresolved run, leaving a conflict in place, is applied as it is
+/// with --plan
+#[actix_web::test]
+async fn test_unresolved_plan_reloads_with_plan() {
+    let (fixtures, port) = serve(Fixtures::load());
+    let workdir = workdir("unresolved-plan", "");
+    // nobody is free on dave's day, while alice can still swap hers
+    fs::write(
+        workdir.join("availability.csv"),
+        "email,start,end,reason\n\
+         alice@example.com,2022-09-01,2022-09-01,offsite\n\
+         bob@example.com,2022-09-01,2022-09-01,offsite\n\
+         carol@example.com,2022-09-01,2022-09-01,offsite\n\
+         dave@example.com,2022-09-01,2022-09-01,offsite\n",
+    )
+    .unwrap();
+    let args = [
+        "--availability-file",
+        "availability.csv",
+        "--allow-unresolved",
+    ];
+
+    let save: Vec<&str> = args
+        .iter()
+        .copied()
+        .chain(["--save-plan", "plan.json"])
+        .collect();
+    stdout(&run_cli(&workdir, port, &save, &[], b"n\n").await);
+    assert!(fixtures.overrides.lock().unwrap().is_empty());
+
+    // a plan whose slots moved since is refused rather than applied in part
+    let saved = fs::read_to_string(workdir.join("plan.json")).unwrap();
+    fs::write(
+        workdir.join("moved.json"),
+        saved.replace("2022-08-29T03:00:00", "2022-08-29T04:00:00"),
+    )
+    .unwrap();
+    let moved: Vec<&str> = args
+        .iter()
+        .copied()
+        .chain(["--plan", "moved.json"])
+        .collect();
+    let output = run_cli(&workdir, port, &moved, &[], b"y\n").await;
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("not in the schedule"), "{}", stderr);
+    assert!(fixtures.overrides.lock().unwrap().is_empty());
+
+    let reload: Vec<&str> = args
+        .iter()
+        .copied()
+        .chain(["--plan", "plan.json"])
+        .collect();
+    let printed = stdout(&run_cli(&workdir, port, &reload, &[], b"y\n").await);
+    fs::remove_dir_all(&workdir).unwrap();
+    assert!(printed.contains("Scheduling overrides..."), "{}", printed);
+    let received = fixtures.overrides.lock().unwrap();
+    assert_eq!(received.len(), 1);
+    // alice's conflict is resolved, and dave is left in his
+    let overrides = received[0].1["overrides"].as_array().unwrap();
+    assert!(overrides.iter().any(|x| x["user"]["id"] == "PALICE"));
+    assert!(!overrides.iter().any(|x| x["start"]
+        .as_str()
+        .unwrap()
+        .starts_with("2022-09-01T03:00:00")));
+}
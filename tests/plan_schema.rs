@@ -0,0 +1,58 @@
+mod common;
+
+use common::cli::{run_cli, serve, workdir};
+use common::Fixtures;
+use serde_json::Value;
+use std::fs;
+
+#[actix_web::test]
+async fn test_export_and_apply_schema_json_plan() {
+    let (fixtures, port) = serve(Fixtures::load());
+    let workdir = workdir("plan-schema", "");
+
+    let export = ["--save-plan", "plan.json", "--plan-format", "schema-json"];
+    let output = run_cli(&workdir, port, &export, &[], b"n\n").await;
+    assert!(output.status.success(), "{:?}", output);
+    let plan: Value =
+        serde_json::from_str(&fs::read_to_string(workdir.join("plan.json")).unwrap()).unwrap();
+    assert_eq!(plan["schema_version"], 1);
+    assert_eq!(plan["schedule_id"], "PPRIMARY");
+    assert_eq!(plan["inputs_hash"].as_str().unwrap().len(), 64);
+    assert_eq!(plan["provenance"]["seed"], 1);
+    assert_eq!(plan["constraints"]["allow_unresolved"], false);
+    let overrides = plan["overrides"].as_array().unwrap();
+    assert_eq!(overrides.len(), 2);
+    assert!(overrides.iter().any(|x| x["from"] == "alice@example.com"));
+
+    // an override edited after approval is refused
+    let mut edited = plan.clone();
+    edited["overrides"][0]["to_user_id"] = "PDAVE".into();
+    fs::write(workdir.join("edited.json"), edited.to_string()).unwrap();
+    let output = run_cli(&workdir, port, &["--plan", "edited.json"], &[], b"y\n").await;
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("don't match its slots"), "{}", stderr);
+
+    // and so is a plan solved from other calendars
+    let mut stale = plan.clone();
+    stale["inputs_hash"] = "0".repeat(64).into();
+    fs::write(workdir.join("stale.json"), stale.to_string()).unwrap();
+    let output = run_cli(&workdir, port, &["--plan", "stale.json"], &[], b"y\n").await;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("changed since"), "{}", stderr);
+    assert!(fixtures.overrides.lock().unwrap().is_empty());
+
+    // the approved plan is applied as it is
+    let output = run_cli(&workdir, port, &["--plan", "plan.json"], &[], b"y\n").await;
+    fs::remove_dir_all(&workdir).unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let received = fixtures.overrides.lock().unwrap();
+    assert_eq!(received.len(), 1);
+    let applied = received[0].1["overrides"].as_array().unwrap();
+    assert_eq!(applied.len(), 2);
+    for entry in overrides {
+        assert!(applied
+            .iter()
+            .any(|x| x["user"]["id"] == entry["to_user_id"] && x["start"] == entry["start"]));
+    }
+}
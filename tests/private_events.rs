@@ -0,0 +1,54 @@
+mod common;
+
+use common::cli::{run_cli, serve, workdir};
+use common::Fixtures;
+use serde_json::json;
+use std::fs;
+use std::process::Output;
+
+/// Check the fixture schedule, with dave at the dentist, privately, during his slot
+async fn check_with_private_event(name: &str, extra_args: &[&str]) -> Output {
+    let mut fixtures = Fixtures::load();
+    fixtures.calendars.insert(
+        "dave@example.com".to_string(),
+        json!([{
+            "visibility": "private",
+            "summary": "Dentist",
+            "start": {"dateTime": "2022-09-01T02:00:00+08:00"},
+            "end": {"dateTime": "2022-09-01T05:00:00+08:00"}
+        }]),
+    );
+    let (_, port) = serve(fixtures);
+    let workdir = workdir(name, "");
+
+    let args = [&["--check", "--no-cache"], extra_args].concat();
+    let output = run_cli(&workdir, port, &args, &[], b"").await;
+    fs::remove_dir_all(&workdir).unwrap();
+    output
+}
+
+#[actix_web::test]
+async fn test_private_events_left_out() {
+    let output = check_with_private_event("private-default", &[]).await;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(10), "{}", stdout);
+    assert!(
+        !stdout.contains("Found conflict: dave@example.com"),
+        "{}",
+        stdout
+    );
+}
+
+#[actix_web::test]
+async fn test_private_events_busy() {
+    let output = check_with_private_event("private-busy", &["--private-events-busy"]).await;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(10), "{}", stdout);
+    assert!(
+        stdout.contains("Found conflict: dave@example.com"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("Private event"), "{}", stdout);
+    assert!(!stdout.contains("Dentist"), "{}", stdout);
+}
@@ -0,0 +1,41 @@
+mod common;
+
+use common::cli::{run_cli, serve, stdout, workdir};
+use common::Fixtures;
+use std::fs;
+
+#[actix_web::test]
+async fn test_replay_a_recorded_run() {
+    let (fixtures, port) = serve(Fixtures::load());
+    let workdir = workdir("replay", "");
+
+    let args = ["--record", "recording"];
+    let env = [
+        ("PD_API_KEY", "pd-api-key-for-recording"),
+        ("SLACK_WEBHOOK_URL", ""),
+    ];
+    let recorded = stdout(&run_cli(&workdir, port, &args, &env, b"y\n").await);
+    assert_eq!(fixtures.overrides.lock().unwrap().len(), 1);
+    let exchanges = fs::read_to_string(workdir.join("recording/exchanges.jsonl")).unwrap();
+    assert!(exchanges.contains("/schedules/PPRIMARY/overrides"));
+    assert!(!exchanges.contains("pd-api-key-for-recording"));
+
+    // without credentials, a token file or the apis, the same plan comes out and is applied again
+    fs::remove_file(workdir.join("google_oidc_token")).unwrap();
+    let args = ["--replay", "recording"];
+    let env = [
+        ("PD_API_KEY", ""),
+        ("GOOGLE_CLIENT_ID", ""),
+        ("GOOGLE_CLIENT_SECRET", ""),
+        ("SLACK_WEBHOOK_URL", ""),
+    ];
+    let replayed = stdout(&run_cli(&workdir, port, &args, &env, b"y\n").await);
+    fs::remove_dir_all(&workdir).unwrap();
+    assert_eq!(fixtures.overrides.lock().unwrap().len(), 1);
+    let table = |text: &str| {
+        let start = text.find("====Generating final diff").unwrap();
+        let end = text.find("Recorded as run").unwrap();
+        text[start..end].to_string()
+    };
+    assert_eq!(table(&recorded), table(&replayed));
+}
@@ -0,0 +1,43 @@
+mod common;
+
+use common::cli::{run_cli, serve, stdout, workdir};
+use common::Fixtures;
+use std::fs;
+
+#[actix_web::test]
+async fn test_templated_reports() {
+    let (fixtures, port) = serve(Fixtures::load());
+    let workdir = workdir(
+        "templates",
+        r#"
+        [templates]
+        slack_plan = "slack.tera"
+        "#,
+    );
+    fs::write(
+        workdir.join("slack.tera"),
+        "Run {{ run_id }} moves {{ overrides | length }} slots:\
+         {% for x in overrides %} {{ x.final_override }}{% endfor %}",
+    )
+    .unwrap();
+
+    // don't apply
+    let args = ["--markdown-report", "report.md"];
+    let output = run_cli(&workdir, port, &args, &[], b"n\n").await;
+    let markdown = fs::read_to_string(workdir.join("report.md"));
+    fs::remove_dir_all(&workdir).unwrap();
+    stdout(&output);
+
+    let markdown = markdown.unwrap();
+    assert!(
+        markdown.starts_with("# Plan for schedule PPRIMARY (run 1)\n\n1 conflicts, 2 overrides.\n"),
+        "{}",
+        markdown
+    );
+    assert!(markdown.contains("## Swaps"), "{}", markdown);
+
+    let slack = fixtures.slack.lock().unwrap();
+    let text = slack[0]["text"].as_str().unwrap();
+    assert!(text.starts_with("Run 1 moves 2 slots: "), "{}", text);
+    assert!(text.contains("alice@"), "{}", text);
+}
@@ -0,0 +1,121 @@
+mod common;
+
+use common::cli::{cli, serve, workdir};
+use common::Fixtures;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fs;
+use std::net::TcpListener;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A request signed like slack signs them, with the signing secret "fixture"
+fn slash_command(
+    client: &reqwest::Client,
+    url: &str,
+    text: &str,
+    response_url: &str,
+) -> reqwest::RequestBuilder {
+    let body = format!(
+        "command=%2Foncall&text={}&user_name=alice&response_url={}",
+        text.replace(' ', "+"),
+        response_url.replace(':', "%3A").replace('/', "%2F")
+    );
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        .to_string();
+    let mut mac = Hmac::<Sha256>::new_from_slice(b"fixture").unwrap();
+    mac.update(format!("v0:{}:{}", timestamp, body).as_bytes());
+    client
+        .post(url)
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("X-Slack-Request-Timestamp", timestamp)
+        .header(
+            "X-Slack-Signature",
+            format!("v0={}", hex::encode(mac.finalize().into_bytes())),
+        )
+        .body(body)
+}
+
+#[actix_web::test]
+async fn test_slash_commands() {
+    let (fixtures, port) = serve(Fixtures::load());
+    let commands_port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let workdir = workdir("slack-commands", "");
+
+    // the fixture slack only sees the replies to the commands
+    let args = ["--watch", "--slack-commands", &commands_port.to_string()];
+    let env = [
+        ("SLACK_SIGNING_SECRET", "fixture"),
+        ("SLACK_WEBHOOK_URL", ""),
+    ];
+    let _child = cli(&workdir, port, &args, &env)
+        .kill_on_drop(true)
+        .spawn()
+        .unwrap();
+
+    let client = reqwest::Client::new();
+    let url = format!("http://127.0.0.1:{}/slack/commands", commands_port);
+    let response_url = format!("http://127.0.0.1:{}/slack", port);
+    let mut acknowledged = None;
+    for _ in 0..100 {
+        if let Ok(response) = slash_command(&client, &url, "check", &response_url)
+            .send()
+            .await
+        {
+            acknowledged = Some(response);
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+    let acknowledged = acknowledged.expect("slack commands were never served");
+    assert_eq!(acknowledged.status(), 200);
+    let body: serde_json::Value = acknowledged.json().await.unwrap();
+    assert_eq!(body["response_type"], "ephemeral");
+
+    // unsigned requests and unknown commands
+    let unsigned = client.post(&url).body("text=check").send().await.unwrap();
+    assert_eq!(unsigned.status(), 401);
+    let usage: serde_json::Value = slash_command(&client, &url, "dance", &response_url)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(usage["text"].as_str().unwrap().starts_with("Usage"));
+
+    slash_command(&client, &url, "plan", &response_url)
+        .send()
+        .await
+        .unwrap();
+    // the fixtures ignore the requested window, so alice's out of office day is always upcoming
+    let mut replies = Vec::new();
+    for _ in 0..150 {
+        replies = fixtures.slack.lock().unwrap().clone();
+        if replies.len() == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+    fs::remove_dir_all(&workdir).unwrap();
+    assert_eq!(replies.len(), 2, "{:?}", replies);
+    assert_eq!(replies[0]["response_type"], "in_channel");
+    let check = replies[0]["text"].as_str().unwrap();
+    assert!(
+        check.starts_with(":warning: 1 conflicts coming up on schedule PPRIMARY"),
+        "{}",
+        check
+    );
+    let plan = replies[1]["text"].as_str().unwrap();
+    assert!(
+        plan.starts_with("Plan for schedule PPRIMARY from") && plan.contains("1 conflicts"),
+        "{}",
+        plan
+    );
+}
@@ -0,0 +1,45 @@
+mod common;
+
+use common::cli::{run_cli, serve, stdout, workdir};
+use common::Fixtures;
+use std::fs;
+
+#[actix_web::test]
+async fn test_plan_and_apply_on_splunk_oncall() {
+    let (fixtures, port) = serve(Fixtures::load());
+    let workdir = workdir(
+        "splunk",
+        "oncall_provider = \"splunk\"\noncall_api_id = \"fixture\"\n",
+    );
+    // the pagerduty key is left out, the provider has its own
+    let env = [("PD_API_KEY", ""), ("SPLUNK_ONCALL_API_KEY", "fixture")];
+
+    stdout(&run_cli(&workdir, port, &[], &env, b"y\n").await);
+    {
+        let slack = fixtures.slack.lock().unwrap();
+        assert_eq!(
+            slack[0]["text"],
+            "Plan for schedule PPRIMARY (run 1): 1 conflicts, 2 overrides"
+        );
+    }
+
+    // overrides are made for alice, who hands over her first day, and assigned on the policy
+    {
+        let received = fixtures.overrides.lock().unwrap();
+        assert_eq!(received.len(), 2);
+        let first_day = received
+            .iter()
+            .find(|(_, body)| body["start"] == "2022-08-29T03:00:00+08:00")
+            .unwrap();
+        assert_eq!(first_day.0, "PPRIMARY");
+        assert_eq!(first_day.1["username"], "PALICE");
+        assert_ne!(first_day.1["assignee"], "PALICE");
+    }
+
+    stdout(&run_cli(&workdir, port, &["--undo", "1"], &env, b"").await);
+    fs::remove_dir_all(&workdir).unwrap();
+    let removed = fixtures.removed.lock().unwrap();
+    let mut ids: Vec<&str> = removed.iter().map(|(_, id)| id.as_str()).collect();
+    ids.sort();
+    assert_eq!(ids, ["V0", "V1"]);
+}
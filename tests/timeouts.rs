@@ -0,0 +1,28 @@
+// no fixture server, the api never answers
+#[allow(dead_code)]
+mod common;
+
+use common::cli::{run_cli, workdir};
+use std::net::TcpListener;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[tokio::test]
+async fn test_unresponsive_api_times_out() {
+    // accepts connections but never answers, like a black-holed network
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        let _held: Vec<_> = listener.incoming().collect();
+    });
+    let workdir = workdir("timeout", "");
+
+    let started = Instant::now();
+    let args = ["--http-timeout-seconds", "1"];
+    let output = run_cli(&workdir, port, &args, &[], b"").await;
+    std::fs::remove_dir_all(&workdir).unwrap();
+    assert!(!output.status.success());
+    assert!(started.elapsed() < Duration::from_secs(20));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("timed out"), "{}", stderr);
+}
@@ -0,0 +1,34 @@
+mod common;
+
+use common::cli::{run_cli, serve, workdir};
+use common::Fixtures;
+use std::fs;
+
+#[actix_web::test]
+async fn test_config_validate_reports_problems() {
+    let (fixtures, port) = serve(Fixtures::load());
+    let workdir = workdir(
+        "validate",
+        r#"
+        schedule = "PPRIMARY"
+        shifts = [{ name = "EU", start = "09:00", timezone = "Europe/Atlantis" }]
+        "#,
+    );
+
+    let output = run_cli(&workdir, port, &["config", "validate"], &[], b"").await;
+    fs::remove_dir_all(&workdir).unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(1), "{}", stdout);
+    assert!(
+        stdout.contains("Pagerduty schedule PPRIMARY is readable"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("The google token is valid"), "{}", stdout);
+    assert!(
+        stdout.contains("Problem: Invalid timezone Europe/Atlantis of shift EU"),
+        "{}",
+        stdout
+    );
+    assert!(fixtures.overrides.lock().unwrap().is_empty());
+}
@@ -0,0 +1,34 @@
+mod common;
+
+use common::cli::{cli, serve, workdir};
+use common::Fixtures;
+use std::fs;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::time::timeout;
+
+#[actix_web::test]
+async fn test_watch_reports_conflicts() {
+    let (_, port) = serve(Fixtures::load());
+    let workdir = workdir("watch", "");
+
+    let mut child = cli(&workdir, port, &["--watch"], &[])
+        .kill_on_drop(true)
+        .spawn()
+        .unwrap();
+    // the fixtures ignore the requested window, so alice's out of office day is always upcoming
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    let conflict = timeout(Duration::from_secs(30), async {
+        while let Some(line) = lines.next_line().await.unwrap() {
+            if line.contains("New conflict") {
+                return line;
+            }
+        }
+        panic!("watch exited without reporting a conflict");
+    })
+    .await
+    .unwrap();
+    child.kill().await.unwrap();
+    fs::remove_dir_all(&workdir).unwrap();
+    assert!(conflict.contains("alice@example.com"), "{}", conflict);
+}
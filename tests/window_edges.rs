@@ -0,0 +1,65 @@
+mod common;
+
+use common::cli::{run_cli, serve, workdir};
+use common::Fixtures;
+use std::fs;
+
+#[actix_web::test]
+async fn test_busy_times_in_slots_straddling_the_window() {
+    let (fixtures, port) = serve(Fixtures::load());
+    let workdir = workdir("edges", "");
+
+    // alice's slot runs from 03:00 the day before the window, and her day out of office ends
+    // when the window starts
+    let args = [
+        "--start-date",
+        "2022-08-30",
+        "--duration-days",
+        "1",
+        "--check",
+    ];
+    let output = run_cli(&workdir, port, &args, &[], b"").await;
+    fs::remove_dir_all(&workdir).unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(10), "{}", stdout);
+    assert!(
+        stdout.contains("Found conflict: alice@example.com"),
+        "{}",
+        stdout
+    );
+
+    // calendars are read from the start of the first slot to the end of the last one
+    let queries = fixtures.calendar_queries.lock().unwrap();
+    assert!(!queries.is_empty());
+    for (email, min, max) in queries.iter() {
+        assert_eq!(min, "2022-08-29T03:00:00+08:00", "{}", email);
+        assert_eq!(max, "2022-09-02T03:00:00+08:00", "{}", email);
+    }
+}
+
+#[actix_web::test]
+async fn test_started_slots_keep_their_holder() {
+    let (fixtures, port) = serve(Fixtures::load());
+    let workdir = workdir("started", "");
+
+    // every slot of the fixtures started in 2022, so without --force-past alice's conflict is
+    // left alone
+    let args = ["--start-date", "2022-08-29", "--duration-days", "4"];
+    let output = run_cli(&workdir, port, &args, &[], b"y\n").await;
+    fs::remove_dir_all(&workdir).unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    assert!(
+        stdout.contains("4 slots have started and keep their holder"),
+        "{}",
+        stdout
+    );
+    let overrides = fixtures.overrides.lock().unwrap();
+    assert!(
+        overrides
+            .iter()
+            .all(|(_, body)| body["overrides"].as_array().unwrap().is_empty()),
+        "{:?}",
+        overrides
+    );
+}
@@ -0,0 +1,24 @@
+mod common;
+
+use common::cli::{assert_conflicts, plan_with_config};
+
+#[actix_web::test]
+async fn test_plan_with_workday_absences() {
+    // don't apply
+    let (fixtures, stdout) = plan_with_config(
+        "workday",
+        |port| {
+            format!(
+                "[workday]\nreport_url = \"http://127.0.0.1:{}/ccx/service/customreport2/acme/isu/Absences?format=json\"\nusername = \"isu\"\n",
+                port
+            )
+        },
+        &[("WORKDAY_PASSWORD", "fixture")],
+        b"n\n",
+    )
+    .await;
+
+    // dave's sick leave is a conflict on top of alice's out of office
+    assert_conflicts(&fixtures, 2);
+    assert!(stdout.contains("sick leave in workday"), "{}", stdout);
+}